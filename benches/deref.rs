@@ -0,0 +1,145 @@
+//! Timings of dereferencing large generated schemas. Run with `cargo bench`.
+
+use polywrap_jsonref::JsonRef;
+use serde_json::{json, Map, Value};
+use std::time::{Duration, Instant};
+
+/// A schema of `count` definitions, each with a long description and, but for every fourth,
+/// a property referring to the next one, and a root referring to every one of them.
+fn wide_schema(count: usize) -> Value {
+    let mut definitions = Map::new();
+    for i in 0..count {
+        let mut properties = Map::new();
+        if (i + 1) % 4 != 0 {
+            let next = json!({"$ref": format!("#/definitions/d{}", i + 1)});
+            properties.insert("next".to_owned(), next);
+        }
+        properties.insert("name".to_owned(), json!({"type": "string"}));
+        definitions.insert(
+            format!("d{}", i),
+            json!({
+                "type": "object",
+                "description": "x".repeat(200),
+                "properties": properties,
+                "required": ["name"],
+            }),
+        );
+    }
+    let properties: Map<String, Value> = (0..count)
+        .map(|i| {
+            let reference = json!({"$ref": format!("#/definitions/d{}", i)});
+            (format!("p{}", i), reference)
+        })
+        .collect();
+    json!({"definitions": definitions, "properties": properties})
+}
+
+/// A schema whose properties each refer to a small part of one large external document.
+fn fragments_of_large_document(count: usize) -> (Value, Value) {
+    let definitions: Map<String, Value> = (0..count)
+        .map(|i| {
+            let definition = json!({"type": "string", "description": "y".repeat(500)});
+            (format!("d{}", i), definition)
+        })
+        .collect();
+    let properties: Map<String, Value> = (0..count)
+        .step_by(10)
+        .map(|i| {
+            let reference = format!("https://example.com/large.json#/definitions/d{}", i);
+            (format!("p{}", i), json!({ "$ref": reference }))
+        })
+        .collect();
+    (
+        json!({ "properties": properties }),
+        json!({ "definitions": definitions }),
+    )
+}
+
+/// A schema of `depth` definitions each referring to the next, so that every ref is expanded
+/// within all the ones before it, repeated under `copies` root properties.
+fn deep_schema(depth: usize, copies: usize) -> Value {
+    let mut definitions = Map::new();
+    for i in 0..depth {
+        let mut properties = Map::new();
+        if i + 1 < depth {
+            let next = json!({"$ref": format!("#/definitions/d{}", i + 1)});
+            properties.insert("next".to_owned(), next);
+        }
+        definitions.insert(format!("d{}", i), json!({ "properties": properties }));
+    }
+    let properties: Map<String, Value> = (0..copies)
+        .map(|i| (format!("p{}", i), json!({"$ref": "#/definitions/d0"})))
+        .collect();
+    json!({"definitions": definitions, "properties": properties})
+}
+
+/// A schema with one object of `count` properties, every tenth a ref to the same definition.
+fn flat_schema(count: usize) -> Value {
+    let properties: Map<String, Value> = (0..count)
+        .map(|i| {
+            let property = if i % 10 == 0 {
+                json!({"$ref": "#/definitions/name"})
+            } else {
+                json!({"type": "integer"})
+            };
+            (format!("p{}", i), property)
+        })
+        .collect();
+    json!({"definitions": {"name": {"type": "string"}}, "properties": properties})
+}
+
+/// The fastest of `runs` runs of `run`.
+fn fastest(runs: usize, mut run: impl FnMut()) -> Duration {
+    (0..runs)
+        .map(|_| {
+            let started = Instant::now();
+            run();
+            started.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    // `cargo test --benches` runs benches with `--bench`-less arguments; keep that quick.
+    let runs = if std::env::args().any(|arg| arg == "--bench") {
+        10
+    } else {
+        1
+    };
+
+    let schema = wide_schema(2_000);
+    let time = fastest(runs, || {
+        let mut jsonref = JsonRef::new();
+        let mut schema = schema.clone();
+        jsonref.deref_value(&mut schema).unwrap();
+    });
+    println!("wide schema, 2000 definitions: {:?}", time);
+
+    let schema = deep_schema(400, 20);
+    let time = fastest(runs, || {
+        let mut jsonref = JsonRef::new();
+        let mut schema = schema.clone();
+        jsonref.deref_value(&mut schema).unwrap();
+    });
+    println!("20 chains of 400 nested refs: {:?}", time);
+
+    let schema = flat_schema(1_000_000);
+    let time = fastest(runs, || {
+        let mut jsonref = JsonRef::new();
+        let mut schema = schema.clone();
+        jsonref.deref_value(&mut schema).unwrap();
+    });
+    println!("one object of 1000000 properties: {:?}", time);
+
+    let (schema, large) = fragments_of_large_document(20_000);
+    let mut jsonref = JsonRef::new();
+    jsonref
+        .add_schema("https://example.com/large.json", large)
+        .unwrap();
+    let time = fastest(runs, || {
+        let mut schema = schema.clone();
+        jsonref.deref_value(&mut schema).unwrap();
+    });
+    println!("2000 fragments of a 20000 definition document: {:?}", time);
+}
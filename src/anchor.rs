@@ -0,0 +1,189 @@
+//! Plain-name fragments, e.g. `other.json#address`, naming the subschema that declares them.
+
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+
+use crate::explain::push_pointer;
+use crate::suggest::pointer_not_found;
+use crate::{Draft, Error, JsonRef, Result, DATA_KEYWORDS};
+
+/// The anchors of one document.
+#[derive(Debug, Default)]
+pub(crate) struct Anchors {
+    /// Every anchor, by name, with the pointer of the subschema declaring it.
+    pointers: HashMap<String, String>,
+    /// The names declared with `$dynamicAnchor`.
+    dynamic: HashSet<String>,
+}
+
+/// The anchors of the documents looked up so far, by the address of their `Value`, with the
+/// document itself so that one dropped and another allocated in its place is not mistaken
+/// for it.
+pub(crate) type AnchorIndex = HashMap<usize, (Weak<Value>, Arc<Anchors>)>;
+
+impl JsonRef {
+    /// The JSON pointer within `document` of what the fragment of `reference` names: the
+    /// fragment itself if it is a JSON pointer, else the subschema declaring it as anchor.
+    pub(crate) fn fragment_pointer(
+        &mut self,
+        reference: &str,
+        document: &Arc<Value>,
+        fragment: &str,
+    ) -> Result<String> {
+        if fragment.is_empty() || fragment.starts_with('/') {
+            return match document.pointer(fragment) {
+                Some(_) => Ok(fragment.to_owned()),
+                None => Err(pointer_not_found(reference, document, fragment)),
+            };
+        }
+        let anchors = self.anchors(document);
+        match anchors.pointers.get(fragment) {
+            Some(pointer) => Ok(pointer.clone()),
+            None => Err(Error::JsonPointerNotFound {
+                pointer: format!(
+                    "ref `{}` can not be resolved as anchor `{}` is not declared in the schema",
+                    reference, fragment
+                ),
+            }),
+        }
+    }
+
+    /// Whether `document` declares `name` with a `$dynamicAnchor`.
+    pub(crate) fn declares_dynamic_anchor(&mut self, document: &Arc<Value>, name: &str) -> bool {
+        self.anchors(document).dynamic.contains(name)
+    }
+
+    /// The anchors of `document`, indexed the first time one is looked up in it.
+    fn anchors(&mut self, document: &Arc<Value>) -> Arc<Anchors> {
+        let id = Arc::as_ptr(document) as usize;
+        if let Some((indexed, anchors)) = self.anchors.get(&id) {
+            if indexed
+                .upgrade()
+                .is_some_and(|indexed| Arc::ptr_eq(&indexed, document))
+            {
+                return anchors.clone();
+            }
+        }
+        let draft = self.document_draft(document, None);
+        let mut anchors = Anchors::default();
+        collect_anchors(document, "", draft, true, &mut anchors);
+        let anchors = Arc::new(anchors);
+        self.anchors
+            .retain(|_, (indexed, _)| indexed.strong_count() > 0);
+        self.anchors
+            .insert(id, (Arc::downgrade(document), anchors.clone()));
+        anchors
+    }
+}
+
+/// Add the anchors declared in `value`, at `pointer` of its document, to `anchors`: those of
+/// `$anchor` and `$dynamicAnchor`, and before 2019-09 of an `$id` (or `id`) like `#foo`. The
+/// first declaration of a name wins. Subschemas with an `$id` of their own are other
+/// resources, whose anchors are not the document's.
+fn collect_anchors(
+    value: &Value,
+    pointer: &str,
+    draft: Option<Draft>,
+    top: bool,
+    anchors: &mut Anchors,
+) {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                let pointer = push_pointer(pointer, &i.to_string());
+                collect_anchors(item, &pointer, draft, false, anchors);
+            }
+            return;
+        }
+        _ => return,
+    };
+    let draft = obj
+        .get("$schema")
+        .and_then(Value::as_str)
+        .and_then(Draft::from_schema_uri)
+        .or(draft);
+    let id_keyword = draft.map_or("$id", Draft::id_keyword);
+    let mut names = vec![];
+    match obj.get(id_keyword).and_then(Value::as_str) {
+        Some(id) if id.starts_with('#') => names.push(&id[1..]),
+        Some(_) if !top => return,
+        _ => {}
+    }
+    if let Some(name) = obj.get("$anchor").and_then(Value::as_str) {
+        names.push(name);
+    }
+    if let Some(name) = obj.get("$dynamicAnchor").and_then(Value::as_str) {
+        names.push(name);
+        anchors.dynamic.insert(name.to_owned());
+    }
+    for name in names {
+        if !name.is_empty() && !name.starts_with('/') {
+            anchors
+                .pointers
+                .entry(name.to_owned())
+                .or_insert_with(|| pointer.to_owned());
+        }
+    }
+    for (key, child) in obj {
+        if !DATA_KEYWORDS.contains(&key.as_str()) {
+            collect_anchors(child, &push_pointer(pointer, key), draft, false, anchors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, JsonRef};
+    use serde_json::json;
+
+    #[test]
+    fn plain_name_fragments_resolve_to_anchors() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/other.json",
+                json!({
+                    "$defs": {
+                        "address": {"$anchor": "address", "properties": {
+                            "street": {"$ref": "#street"}
+                        }},
+                        "street": {"$anchor": "street", "type": "string"},
+                        "legacy": {"$id": "#legacy", "type": "integer"},
+                        "embedded": {"$id": "embedded.json", "$defs": {
+                            "hidden": {"$anchor": "hidden"}
+                        }},
+                        "data": {"const": {"$anchor": "data"}}
+                    }
+                }),
+            )
+            .unwrap();
+        let mut input = json!({"properties": {
+            "home": {"$ref": "https://example.com/other.json#address"},
+            "age": {"$ref": "https://example.com/other.json#legacy"}
+        }});
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(
+            input["properties"]["home"],
+            json!({"$anchor": "address", "properties": {
+                "street": {"$anchor": "street", "type": "string"}
+            }})
+        );
+        assert_eq!(input["properties"]["age"]["type"], "integer");
+        assert_eq!(
+            jsonref.explain("/properties/home/properties/street").steps[1].pointer,
+            "/properties/home/properties/street"
+        );
+
+        for missing in ["hidden", "data", "nowhere"] {
+            let mut input = json!({"$ref": format!("https://example.com/other.json#{}", missing)});
+            match jsonref.deref_value(&mut input).unwrap_err().root_cause() {
+                Error::JsonPointerNotFound { pointer } => {
+                    assert!(pointer.contains(&format!("anchor `{}`", missing)))
+                }
+                other => panic!("unexpected error {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,147 @@
+//! Project configuration read from `jsonref.toml` or `.jsonrefrc`.
+//!
+//! `jsonref.toml` is TOML, `.jsonrefrc` is JSON; both accept the same keys:
+//!
+//! ```toml
+//! base_url = "https://schemas.example.com/"
+//! reference_key = "__reference__"
+//! format = "json"
+//! pretty = true
+//! allowed_hosts = ["schemas.example.com", "*.json-schema.org"]
+//!
+//! [catalog]
+//! "https://schemas.example.com/" = "vendor/schemas/"
+//! ```
+
+use polywrap_jsonref::{BoxError, JsonRef};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Names searched for, in order, in the working directory and its parents.
+const CONFIG_FILES: &[&str] = &["jsonref.toml", ".jsonrefrc"];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Base URL that relative inputs are resolved against.
+    pub base_url: Option<String>,
+    /// Key to store the data that a `$ref` replaced.
+    pub reference_key: Option<String>,
+    /// Output format.
+    pub format: Option<String>,
+    /// Pretty-print JSON output.
+    pub pretty: Option<bool>,
+    /// Hosts that remote refs may be fetched from, as for `JsonRef::set_allowed_hosts`. All
+    /// hosts are allowed when unset.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// URL prefixes mapped to local directories or files, relative to the config file.
+    pub catalog: BTreeMap<String, String>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+impl Config {
+    /// Read the config at `path`, inferring the format from its name.
+    pub fn read(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut config: Config = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        } else {
+            serde_json::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        };
+        config.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(config)
+    }
+
+    /// Find the nearest config file from `start` upwards, if any.
+    pub fn discover(start: &Path) -> Result<Option<Config>, String> {
+        for dir in start.ancestors() {
+            for name in CONFIG_FILES {
+                let candidate = dir.join(name);
+                if candidate.is_file() {
+                    return Config::read(&candidate).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Resolve a command line input against `base_url`.
+    ///
+    /// Inputs that are URLs already, or that exist on disk, are returned unchanged.
+    pub fn resolve_input(&self, input: &str) -> Result<String, String> {
+        match &self.base_url {
+            Some(base) if Url::parse(input).is_err() && !Path::new(input).exists() => {
+                let base = Url::parse(base).map_err(|e| format!("base_url {}: {}", base, e))?;
+                base.join(input)
+                    .map(|url| url.to_string())
+                    .map_err(|e| format!("{}: {}", input, e))
+            }
+            _ => Ok(input.to_owned()),
+        }
+    }
+
+    /// Install the catalog and host allowlist on `jsonref`.
+    pub fn apply(&self, jsonref: &mut JsonRef) {
+        if let Some(key) = &self.reference_key {
+            jsonref.set_reference_key(key);
+        }
+        #[cfg(feature = "network")]
+        if let Some(hosts) = &self.allowed_hosts {
+            jsonref.set_allowed_hosts(hosts);
+        }
+        if self.catalog.is_empty() {
+            return;
+        }
+        let catalog: Vec<(String, PathBuf)> = self
+            .catalog
+            .iter()
+            .map(|(prefix, path)| (prefix.clone(), self.dir.join(path)))
+            .collect();
+        jsonref.set_resolver(Box::new(move |url: &Url| {
+            resolve_from_catalog(url, &catalog)
+        }));
+    }
+}
+
+fn resolve_from_catalog(
+    url: &Url,
+    catalog: &[(String, PathBuf)],
+) -> Result<Option<Value>, BoxError> {
+    for (prefix, path) in catalog {
+        if let Some(rest) = url.as_str().strip_prefix(prefix.as_str()) {
+            let path = if rest.is_empty() {
+                path.clone()
+            } else {
+                path.join(rest)
+            };
+            let file = fs::File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            return Ok(Some(serde_json::from_reader(file)?));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn relative_inputs_use_base_url() {
+        let config: Config =
+            toml::from_str(r#"base_url = "https://schemas.example.com/v1/""#).unwrap();
+        assert_eq!(
+            config.resolve_input("pets.json").unwrap(),
+            "https://schemas.example.com/v1/pets.json"
+        );
+        assert_eq!(
+            config.resolve_input("fixtures/cli/catalog.json").unwrap(),
+            "fixtures/cli/catalog.json"
+        );
+        assert!(toml::from_str::<Config>("unknown_key = 1").is_err());
+    }
+}
@@ -0,0 +1,744 @@
+//! `jsonref` command line tool.
+
+mod config;
+mod report;
+
+use config::Config;
+use polywrap_jsonref::diff::diff;
+use polywrap_jsonref::profile;
+use polywrap_jsonref::{Error, JsonRef, RefSelection, ResolutionMode, UriIndex};
+use report::{Failure, FailureClass, Report};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "\
+Usage:
+    jsonref [deref] <input> [--output <path>] [--reference-key <key>] [--format json|jcs|yaml] [--pretty]
+                    [--bundle] [--origins] [--profile form|typescript] [--rust-type-names]
+                    [--internal-only | --external-only]
+    jsonref explain <input> <pointer> [--reference-key <key>]
+    jsonref diff <old> <new>
+    jsonref get <input> <pointer> [--resolve] [--reference-key <key>] [--format json|jcs|yaml] [--pretty]
+    jsonref index <dir> [--format json|jcs|yaml] [--pretty]
+
+Commands:
+    deref      Dereference <input> and write it to stdout or --output (the default command).
+               With --bundle, schemas from other documents are copied into $defs instead of
+               being inlined. With --origins, an x-origins list of the documents embedded,
+               with their SHA-256 and fetch time, is added to the output. With --profile
+               form, the output is shaped for form generators: allOf merged, and ui:order,
+               ui:required and default titles added. With --profile typescript, it is
+               bundled for type generators: definitions kept, other refs inlined, and an
+               x-type-names map of each definition's ref to its type name added. With
+               --rust-type-names, an x-rust-type-names object is added with a Rust
+               identifier for each definition and the names that collided. With
+               --internal-only, only refs within <input> are resolved, and no other
+               document is read or fetched. With --external-only, only refs to other
+               documents are, those within <input> are kept.
+    explain    Show the chain of $refs that produced the value at <pointer>
+    diff       Dereference two schemas and list the differences between them
+    get        Print the subschema at <pointer>, dereferenced with --resolve
+    index      Map every $id, anchor and definition URI of the schemas under <dir> to its
+               file and pointer
+
+Every command accepts, before or after the command:
+    --config <path>     Config file to use. Without it the nearest jsonref.toml or .jsonrefrc
+                        in the working directory or its parents is used.
+    --report json       Print a JSON report with the output, errors, warnings, fetched URLs
+                        and stats instead of the plain output.
+    --deny-warnings     Fail when dereferencing produced warnings.
+
+<input>, <old> and <new> are paths to schema files or http(s) URLs.
+
+Exit codes:
+    0    success
+    1    warnings were produced and --deny-warnings was set
+    2    bad arguments or config file
+    3    a document could not be read or fetched
+    4    a document is not valid JSON (or YAML)
+    5    a $ref is malformed or points at nothing
+    6    the requested pointer is not in the output
+    7    the output could not be written";
+
+/// Options every command accepts.
+const GLOBAL_OPTIONS: &[&str] = &["--config", "--report"];
+/// Switches every command accepts.
+const GLOBAL_SWITCHES: &[&str] = &["--deny-warnings"];
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut report = Report::default();
+    let result = run(&args, &mut report);
+    let result = report.check(result, args.iter().any(|arg| arg == "--deny-warnings"));
+    let exit_code = result.as_ref().map_or_else(|f| f.class.exit_code(), |_| 0);
+
+    if report_format(&args) == Some("json") {
+        println!("{}", report.to_json(&result));
+    } else {
+        match &result {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => println!("{}", output),
+            Err(failure) => {
+                if failure.class == FailureClass::Warnings {
+                    for warning in &report.warnings {
+                        eprintln!("jsonref: warning: {}: {}", warning.pointer, warning.message);
+                    }
+                }
+                eprintln!("jsonref: {}", failure);
+            }
+        }
+    }
+    process::exit(exit_code);
+}
+
+/// The value of `--report`, if given.
+fn report_format(args: &[String]) -> Option<&str> {
+    let position = args.iter().position(|arg| arg == "--report")?;
+    args.get(position + 1).map(String::as_str)
+}
+
+fn run(args: &[String], report: &mut Report) -> Result<String, Failure> {
+    match report_format(args) {
+        None | Some("json") => {}
+        Some(other) => {
+            return Err(Failure::usage(format!(
+                "unsupported report format `{}`",
+                other
+            )))
+        }
+    }
+    let args = &command_first(args);
+    match args.first().map(String::as_str) {
+        Some("deref") => deref(&args[1..], report),
+        Some("explain") => explain(&args[1..], report),
+        Some("diff") => diff_command(&args[1..], report),
+        Some("get") => get(&args[1..], report),
+        Some("index") => index(&args[1..]),
+        Some("-h") | Some("--help") | None => Ok(USAGE.to_owned()),
+        Some(input) if is_url(input) || Path::new(input).exists() => deref(args, report),
+        Some(other) => Err(Failure::usage(format!(
+            "unknown command `{}`\n\n{}",
+            other, USAGE
+        ))),
+    }
+}
+
+/// `args` with the options every command accepts moved from before the command to after it,
+/// so `jsonref --report json schema.json` runs `deref` like `jsonref schema.json --report json`.
+fn command_first(args: &[String]) -> Vec<String> {
+    let mut start = 0;
+    while let Some(arg) = args.get(start) {
+        if GLOBAL_OPTIONS.contains(&arg.as_str()) {
+            start += 2;
+        } else if GLOBAL_SWITCHES.contains(&arg.as_str()) {
+            start += 1;
+        } else {
+            break;
+        }
+    }
+    let (globals, rest) = args.split_at(start.min(args.len()));
+    rest.iter().chain(globals).cloned().collect()
+}
+
+/// Command line arguments split into positionals, `--option value` pairs and `--switch`es,
+/// along with the project config they are applied on top of.
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
+    switches: HashSet<String>,
+    config: Config,
+}
+
+impl Args {
+    fn parse(args: &[String], options: &[&str], switches: &[&str]) -> Result<Args, Failure> {
+        let mut parsed = Args {
+            positional: vec![],
+            options: HashMap::new(),
+            switches: HashSet::new(),
+            config: Config::default(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = arg.as_str();
+            if GLOBAL_OPTIONS.contains(&name) || options.contains(&name) {
+                let value = args
+                    .next()
+                    .ok_or_else(|| Failure::usage(format!("{} needs a value", name)))?;
+                parsed.options.insert(name.to_owned(), value.clone());
+            } else if GLOBAL_SWITCHES.contains(&name) || switches.contains(&name) {
+                parsed.switches.insert(name.to_owned());
+            } else if name.starts_with("--") {
+                return Err(Failure::usage(format!(
+                    "unknown option `{}`\n\n{}",
+                    name, USAGE
+                )));
+            } else {
+                parsed.positional.push(arg.clone());
+            }
+        }
+
+        parsed.config = match parsed.option("--config") {
+            Some(path) => Config::read(Path::new(path)).map_err(Failure::usage)?,
+            None => {
+                let cwd = env::current_dir().map_err(|e| Failure::usage(e.to_string()))?;
+                Config::discover(&cwd)
+                    .map_err(Failure::usage)?
+                    .unwrap_or_default()
+            }
+        };
+        Ok(parsed)
+    }
+
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(String::as_str)
+    }
+
+    fn switch(&self, name: &str) -> bool {
+        self.switches.contains(name)
+    }
+
+    /// A `JsonRef` configured from the config file, then the command line.
+    fn jsonref(&self) -> JsonRef {
+        let mut jsonref = JsonRef::new();
+        self.config.apply(&mut jsonref);
+        if let Some(key) = self.option("--reference-key") {
+            jsonref.set_reference_key(key);
+        }
+        jsonref
+    }
+
+    /// Serialize `value` in the requested output format.
+    fn render(&self, value: &Value) -> Result<String, Failure> {
+        let format = self
+            .option("--format")
+            .or(self.config.format.as_deref())
+            .unwrap_or("json");
+        let pretty = self.switch("--pretty") || self.config.pretty.unwrap_or(false);
+        let rendered = match format {
+            "json" if pretty => serde_json::to_string_pretty(value),
+            "json" => serde_json::to_string(value),
+            "jcs" => return Ok(polywrap_jsonref::to_canonical_string(value)),
+            "yaml" => return polywrap_jsonref::to_yaml_string(value).map_err(Failure::from),
+            other => {
+                return Err(Failure::usage(format!(
+                    "unsupported output format `{}`",
+                    other
+                )))
+            }
+        };
+        rendered.map_err(|e| Failure::new(FailureClass::InvalidDocument, e.to_string()))
+    }
+
+    /// `input` resolved against the config's `base_url`.
+    fn input(&self, input: &str) -> Result<String, Failure> {
+        self.config.resolve_input(input).map_err(Failure::usage)
+    }
+
+    /// Dereference `input` with `jsonref`, recording what happened in `report`.
+    fn load(
+        &self,
+        jsonref: &mut JsonRef,
+        input: &str,
+        report: &mut Report,
+    ) -> Result<Value, Failure> {
+        let result = load(jsonref, &self.input(input)?);
+        report.record(jsonref);
+        Ok(result?)
+    }
+}
+
+fn deref(args: &[String], report: &mut Report) -> Result<String, Failure> {
+    let args = Args::parse(
+        args,
+        &["--output", "--reference-key", "--format", "--profile"],
+        &[
+            "--pretty",
+            "--bundle",
+            "--origins",
+            "--rust-type-names",
+            "--internal-only",
+            "--external-only",
+        ],
+    )?;
+    let input = match args.positional.as_slice() {
+        [input] => input,
+        _ => return Err(Failure::usage(format!("deref takes <input>\n\n{}", USAGE))),
+    };
+
+    let mut jsonref = args.jsonref();
+    let profile = args.option("--profile");
+    if args.switch("--bundle") || profile == Some("typescript") {
+        jsonref.set_resolution_mode(ResolutionMode::Bundle);
+    }
+    jsonref.set_record_origins(args.switch("--origins"));
+    match (
+        args.switch("--internal-only"),
+        args.switch("--external-only"),
+    ) {
+        (true, true) => {
+            return Err(Failure::usage(
+                "--internal-only and --external-only can not be used together",
+            ))
+        }
+        (true, false) => jsonref.set_ref_selection(RefSelection::InternalOnly),
+        (false, true) => jsonref.set_ref_selection(RefSelection::ExternalOnly),
+        (false, false) => {}
+    }
+    let mut output = args.load(&mut jsonref, input, report)?;
+    match profile {
+        Some("form") => profile::form(&mut output),
+        Some("typescript") => {
+            let names = profile::typescript(&mut output);
+            if let Value::Object(root) = &mut output {
+                let names = names
+                    .into_iter()
+                    .map(|(local_ref, name)| (local_ref, name.into()));
+                root.insert("x-type-names".to_owned(), Value::Object(names.collect()));
+            }
+        }
+        Some(other) => return Err(Failure::usage(format!("unknown profile `{}`", other))),
+        None => {}
+    }
+    if args.switch("--rust-type-names") {
+        let names = profile::rust_type_names(&output);
+        if let Value::Object(root) = &mut output {
+            let names = serde_json::to_value(names).unwrap_or_default();
+            root.insert("x-rust-type-names".to_owned(), names);
+        }
+    }
+    let output = args.render(&output)?;
+    match args.option("--output") {
+        Some(path) => {
+            fs::write(path, output + "\n")
+                .map_err(|e| Failure::new(FailureClass::Output, format!("{}: {}", path, e)))?;
+            Ok(String::new())
+        }
+        None => Ok(output),
+    }
+}
+
+fn explain(args: &[String], report: &mut Report) -> Result<String, Failure> {
+    let args = Args::parse(args, &["--reference-key"], &[])?;
+    let mut jsonref = args.jsonref();
+    let (input, pointer) = match args.positional.as_slice() {
+        [input, pointer] => (input, pointer),
+        _ => {
+            return Err(Failure::usage(format!(
+                "explain takes <input> <pointer>\n\n{}",
+                USAGE
+            )))
+        }
+    };
+
+    let output = args.load(&mut jsonref, input, report)?;
+    if output.pointer(pointer).is_none() {
+        return Err(Failure::new(
+            FailureClass::PointerNotFound,
+            format!("pointer `{}` not found in the output", pointer),
+        ));
+    }
+    Ok(jsonref.explain(pointer).to_string())
+}
+
+fn diff_command(args: &[String], report: &mut Report) -> Result<String, Failure> {
+    let args = Args::parse(args, &[], &[])?;
+    let (old, new) = match args.positional.as_slice() {
+        [old, new] => (old, new),
+        _ => {
+            return Err(Failure::usage(format!(
+                "diff takes <old> <new>\n\n{}",
+                USAGE
+            )))
+        }
+    };
+    let old = args.load(&mut args.jsonref(), old, report)?;
+    let new = args.load(&mut args.jsonref(), new, report)?;
+
+    let changes = diff(&old, &new);
+    if changes.is_empty() {
+        return Ok("no differences".to_owned());
+    }
+    let lines: Vec<String> = changes.iter().map(|change| change.to_string()).collect();
+    Ok(lines.join("\n"))
+}
+
+fn get(args: &[String], report: &mut Report) -> Result<String, Failure> {
+    let args = Args::parse(
+        args,
+        &["--reference-key", "--format"],
+        &["--resolve", "--pretty"],
+    )?;
+    let (input, pointer) = match args.positional.as_slice() {
+        [input, pointer] => (input, pointer),
+        _ => {
+            return Err(Failure::usage(format!(
+                "get takes <input> <pointer>\n\n{}",
+                USAGE
+            )))
+        }
+    };
+
+    let mut jsonref = args.jsonref();
+    if !args.switch("--resolve") {
+        // Loaded as any input is, but with nothing in it dereferenced.
+        jsonref.skip_under("");
+    }
+    let document = args.load(&mut jsonref, input, report)?;
+    let value = document.pointer(pointer).ok_or_else(|| {
+        Failure::new(
+            FailureClass::PointerNotFound,
+            format!("pointer `{}` not found in {}", pointer, input),
+        )
+    })?;
+    args.render(value)
+}
+
+fn index(args: &[String]) -> Result<String, Failure> {
+    let args = Args::parse(args, &["--format"], &["--pretty"])?;
+    let dir = match args.positional.as_slice() {
+        [dir] => dir,
+        _ => return Err(Failure::usage(format!("index takes <dir>\n\n{}", USAGE))),
+    };
+    let index = UriIndex::from_directory(dir)?;
+    let value = serde_json::to_value(&index)
+        .map_err(|e| Failure::new(FailureClass::InvalidDocument, e.to_string()))?;
+    args.render(&value)
+}
+
+fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Dereference `input`, treating it as a URL when it looks like one.
+fn load(jsonref: &mut JsonRef, input: &str) -> Result<Value, Error> {
+    if is_url(input) {
+        jsonref.deref_url(input)
+    } else {
+        jsonref.deref_file(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::report::{Failure, FailureClass, Report};
+
+    const BASE: &str = "fixtures/nested_relative/base.json";
+
+    fn run(args: &[&str]) -> Result<String, Failure> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        super::run(&args, &mut Report::default())
+    }
+
+    /// The JSON `jsonref deref BASE` prints with `options`.
+    fn deref_json(options: &[&str]) -> serde_json::Value {
+        let args: Vec<&str> = ["deref", BASE].iter().chain(options).copied().collect();
+        serde_json::from_str(&run(&args).unwrap()).unwrap()
+    }
+
+    fn expected() -> serde_json::Value {
+        serde_json::from_str(
+            &std::fs::read_to_string("fixtures/nested_relative/expected.json").unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn deref_to_stdout() {
+        let output = run(&[BASE, "--reference-key", "__reference__"]).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&output).unwrap(),
+            expected()
+        );
+    }
+
+    #[test]
+    fn deref_to_output_file() {
+        let path = std::env::temp_dir().join("jsonref-deref-output.json");
+        let path = path.to_str().unwrap();
+        let printed = run(&[
+            "deref",
+            BASE,
+            "--reference-key",
+            "__reference__",
+            "--pretty",
+            "--output",
+            path,
+        ])
+        .unwrap();
+        assert!(printed.is_empty());
+        let written = std::fs::read_to_string(path).unwrap();
+        assert!(written.ends_with("}\n"));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&written).unwrap(),
+            expected()
+        );
+    }
+
+    #[test]
+    fn deref_bundle() {
+        let bundled = run(&["deref", BASE, "--bundle"]).unwrap();
+        assert!(bundled.contains(r##""$ref":"#/$defs/other_json""##));
+    }
+
+    #[test]
+    fn deref_origins() {
+        let with_origins = deref_json(&["--origins"]);
+        assert!(with_origins["x-origins"][0]["url"]
+            .as_str()
+            .unwrap()
+            .ends_with("/other.json"));
+    }
+
+    #[test]
+    fn deref_profiles() {
+        assert!(deref_json(&["--profile", "form"])["ui:order"].is_array());
+        let typed = deref_json(&["--profile", "typescript"]);
+        assert_eq!(typed["x-type-names"]["#/$defs/other_json"], "OtherJson");
+        assert_eq!(
+            run(&["deref", BASE, "--profile", "html"])
+                .unwrap_err()
+                .class,
+            FailureClass::Usage
+        );
+    }
+
+    #[test]
+    fn deref_rust_type_names() {
+        let rust = deref_json(&["--bundle", "--rust-type-names"]);
+        assert_eq!(
+            rust["x-rust-type-names"],
+            serde_json::json!({
+                "names": {
+                    "#/$defs/other_json": "OtherJson",
+                    "#/$defs/other_json_properties_prop1": "OtherJsonPropertiesProp1",
+                    "#/$defs/other_json_properties_prop2": "OtherJsonPropertiesProp2"
+                },
+                "collisions": []
+            })
+        );
+    }
+
+    #[test]
+    fn deref_internal_only() {
+        let internal = deref_json(&["--internal-only"]);
+        assert_eq!(
+            internal["properties"]["prop2"],
+            serde_json::json!({"title": "sub property title in base.json"})
+        );
+        assert_eq!(
+            internal["properties"]["prop3"],
+            serde_json::json!({"$ref": "other.json"})
+        );
+    }
+
+    #[test]
+    fn deref_external_only() {
+        let external = deref_json(&["--external-only"]);
+        assert_eq!(
+            external["properties"]["prop2"],
+            serde_json::json!({"$ref": "#/properties/prop1"})
+        );
+        assert_eq!(external["properties"]["prop3"]["title"], "title from url");
+    }
+
+    #[test]
+    fn deref_usage_errors() {
+        assert_eq!(
+            run(&["deref", BASE, "--internal-only", "--external-only"])
+                .unwrap_err()
+                .class,
+            FailureClass::Usage
+        );
+        assert_eq!(
+            run(&["deref", BASE, BASE]).unwrap_err().class,
+            FailureClass::Usage
+        );
+    }
+
+    #[test]
+    fn global_options_before_the_command() {
+        let deref = run(&[BASE]).unwrap();
+        assert_eq!(run(&["--report", "json", BASE]).unwrap(), deref);
+        assert_eq!(run(&["--deny-warnings", "deref", BASE]).unwrap(), deref);
+        let title = run(&[
+            "--config",
+            "fixtures/cli/jsonref.toml",
+            "--report",
+            "json",
+            "get",
+            BASE,
+            "/title",
+        ])
+        .unwrap();
+        assert_eq!(title, "\"title from file\"");
+        assert_eq!(
+            run(&["--report", "json"]).unwrap_err().class,
+            FailureClass::Usage
+        );
+    }
+
+    #[test]
+    fn index_directory() {
+        let output = run(&["index", "fixtures/index"]).unwrap();
+        let index: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            index["https://example.com/pet.json#tag"]["pointer"],
+            "/properties/tag"
+        );
+        assert_eq!(index["https://example.com/pet.json#tag"]["kind"], "anchor");
+    }
+
+    #[test]
+    fn explain_fixture() {
+        let output = run(&["explain", BASE, "/properties/prop3/properties/prop2"]).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("1. /properties/prop3: `other.json` -> file://"));
+        assert!(lines[1].ends_with("other.json at `/properties/prop1`"));
+
+        assert!(run(&["explain", BASE, "/nope"]).is_err());
+        assert!(run(&["frobnicate"]).is_err());
+    }
+
+    #[test]
+    fn diff_fixtures() {
+        let output = run(&["diff", BASE, "fixtures/nested_relative/other.json"]).unwrap();
+        assert!(output.contains("- /properties/prop3: property `prop3` removed"));
+        assert!(output.contains("~ /title: \"title from file\" -> \"title from url\""));
+
+        let same = run(&[
+            "diff",
+            "fixtures/nested_relative/other.json",
+            "fixtures/nested_relative/other.json",
+        ])
+        .unwrap();
+        assert_eq!(same, "no differences");
+    }
+
+    #[test]
+    fn get_subschema() {
+        let raw = run(&["get", BASE, "/properties/prop3"]).unwrap();
+        assert_eq!(raw, r#"{"$ref":"other.json"}"#);
+        let canonical = run(&["get", BASE, "/properties/prop3", "--format", "jcs"]).unwrap();
+        assert_eq!(canonical, raw);
+
+        let resolved = run(&["get", BASE, "/properties/prop3/title", "--resolve"]).unwrap();
+        assert_eq!(resolved, "\"title from url\"");
+
+        assert!(run(&["get", BASE, "/properties/prop3/title"]).is_err());
+        assert!(run(&["get", BASE, "/title", "--bogus"]).is_err());
+        assert!(run(&["get", BASE, "/title", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn config_file_catalog() {
+        let output = run(&[
+            "get",
+            "fixtures/cli/catalog.json",
+            "/properties",
+            "--resolve",
+            "--config",
+            "fixtures/cli/jsonref.toml",
+        ])
+        .unwrap();
+        let properties: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            properties["mapped"],
+            serde_json::json!({"title": "sub property title in other.json", "__reference__": {}})
+        );
+        assert!(output.contains("\n"), "pretty output expected from config");
+    }
+
+    #[test]
+    // Without `network` there is nothing to check hosts for.
+    #[cfg(feature = "network")]
+    fn config_file_allowed_hosts() {
+        let config = "fixtures/cli/jsonref.toml";
+        let err = run(&[
+            "get",
+            "fixtures/cli/blocked.json",
+            "/properties",
+            "--resolve",
+            "--config",
+            config,
+        ])
+        .unwrap_err();
+        assert!(err
+            .message
+            .contains("host `blocked.example.com` is not allowed"));
+        assert_eq!(err.class, FailureClass::Fetch);
+
+        // The root document is fetched through the same checks as the refs in it.
+        let err = run(&[
+            "get",
+            "https://blocked.example.com/schema.json",
+            "/properties",
+            "--config",
+            config,
+        ])
+        .unwrap_err();
+        assert!(err
+            .message
+            .contains("host `blocked.example.com` is not allowed"));
+    }
+
+    #[test]
+    fn failure_classes() {
+        let class = |args: &[&str]| run(args).unwrap_err().class;
+        assert_eq!(class(&["get", BASE]), FailureClass::Usage);
+        assert_eq!(
+            class(&["get", "missing.json", "/title"]),
+            FailureClass::Fetch
+        );
+        assert_eq!(
+            class(&["get", BASE, "/nope"]),
+            FailureClass::PointerNotFound
+        );
+        assert_eq!(
+            class(&["get", BASE, "/title", "--report", "xml"]),
+            FailureClass::Usage
+        );
+    }
+
+    #[test]
+    fn json_report() {
+        let mut report = Report::default();
+        let args: Vec<String> = ["get", BASE, "/title", "--resolve", "--report", "json"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        let result = super::run(&args, &mut report);
+        let json = report.to_json(&result);
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["output"], "\"title from file\"");
+        assert_eq!(json["stats"]["documents_loaded"], 2);
+        assert_eq!(json["fetched"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn yaml_input() {
+        let output = run(&[
+            "get",
+            "fixtures/yaml/base.yaml",
+            "/properties/prop1",
+            "--resolve",
+            "--format",
+            "yaml",
+        ])
+        .unwrap();
+        assert_eq!(output, "title: title from other.yml\ntype: string\n");
+    }
+
+    #[test]
+    fn yaml_output() {
+        // Every command that takes --format writes YAML in the default build.
+        let output = run(&["deref", BASE, "--format", "yaml"]).unwrap();
+        assert!(output.lines().any(|line| line == "title: title from file"));
+        let output = run(&["index", "fixtures/index", "--format", "yaml"]).unwrap();
+        assert!(output.contains("pointer: /properties/tag"));
+    }
+}
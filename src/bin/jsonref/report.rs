@@ -0,0 +1,283 @@
+//! Failure classes, exit codes and the `--report json` output.
+//!
+//! | exit code | class              | meaning                                               |
+//! |-----------|--------------------|-------------------------------------------------------|
+//! | 0         |                    | success                                               |
+//! | 1         | `warnings`         | warnings were produced and `--deny-warnings` was set  |
+//! | 2         | `usage`            | bad arguments or config file                          |
+//! | 3         | `fetch`            | a document could not be read or fetched               |
+//! | 4         | `invalid_document` | a document is not valid JSON (or YAML)                |
+//! | 5         | `unresolved_ref`   | a `$ref` is malformed or points at nothing            |
+//! | 6         | `pointer_not_found`| the requested pointer is not in the output            |
+//! | 7         | `output`           | the output could not be written                       |
+
+use polywrap_jsonref::{Error, JsonRef, Rule, Warning as JsonRefWarning};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// Why a command failed, which decides its exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    Warnings,
+    Usage,
+    Fetch,
+    InvalidDocument,
+    UnresolvedRef,
+    PointerNotFound,
+    Output,
+}
+
+impl FailureClass {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailureClass::Warnings => 1,
+            FailureClass::Usage => 2,
+            FailureClass::Fetch => 3,
+            FailureClass::InvalidDocument => 4,
+            FailureClass::UnresolvedRef => 5,
+            FailureClass::PointerNotFound => 6,
+            FailureClass::Output => 7,
+        }
+    }
+
+    /// The class of failure `error` causes.
+    fn of(error: &Error) -> FailureClass {
+        match error {
+            #[cfg(feature = "network")]
+            Error::SchemaFromUrl { .. } | Error::InvalidProxy { .. } => FailureClass::Fetch,
+            Error::SchemaFromFile { .. }
+            | Error::HttpStatus { .. }
+            | Error::HostNotAllowed { .. }
+            | Error::TooManyFetches { .. }
+            | Error::TenantQuotaExceeded { .. }
+            | Error::JSONRefError { .. }
+            | Error::ResolverFailed { .. }
+            | Error::RemoteFetchDisabled { .. }
+            | Error::UnsupportedScheme { .. } => FailureClass::Fetch,
+            Error::SchemaNotJson { .. }
+            | Error::SchemaNotJsonSerde { .. }
+            | Error::InvalidDataUrl { .. }
+            | Error::InvalidDirective { .. }
+            | Error::InvalidSnapshot { .. }
+            | Error::InvalidRepro { .. }
+            | Error::DocumentTooLarge { .. }
+            | Error::DefinitionCollision { .. } => FailureClass::InvalidDocument,
+            Error::SchemaNotYaml { .. } | Error::YamlOutput { .. } => FailureClass::InvalidDocument,
+            #[cfg(feature = "signing")]
+            Error::InvalidSigningKey { .. } => FailureClass::Usage,
+            #[cfg(feature = "signing")]
+            Error::InvalidSignature { .. } => FailureClass::InvalidDocument,
+            Error::WriteOutput { .. } | Error::WriteRepro { .. } => FailureClass::Output,
+            Error::UrlParseError { .. }
+            | Error::JsonPointerNotFound { .. }
+            | Error::RecursiveRef { .. }
+            | Error::DocumentBudgetExceeded { .. }
+            | Error::ExpansionTooLarge { .. }
+            | Error::DeadlineExceeded { .. } => FailureClass::UnresolvedRef,
+            Error::RefFailed { source, .. } => FailureClass::of(source),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Failure {
+    pub class: FailureClass,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(class: FailureClass, message: impl Into<String>) -> Failure {
+        Failure {
+            class,
+            message: message.into(),
+        }
+    }
+
+    pub fn usage(message: impl Into<String>) -> Failure {
+        Failure::new(FailureClass::Usage, message)
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Failure {
+        Failure::new(FailureClass::of(&error), error.to_string())
+    }
+}
+
+/// Something worth a look that did not stop the command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    /// Output pointer of the `$ref` the warning is about, or pointer within its document of
+    /// the schema using a keyword that is not supported; empty for warnings about a whole
+    /// document. The message names the document.
+    pub pointer: String,
+    pub message: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Stats {
+    /// `$ref`s replaced with their target or bundled, not those left in place.
+    pub refs_resolved: usize,
+    /// Documents loaded from disk, the network or a resolver.
+    pub documents_loaded: usize,
+    /// `$ref`s not expanded because they were recursive.
+    pub cycles_truncated: usize,
+}
+
+/// What the commands did, collected from every `JsonRef` they ran.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub warnings: Vec<Warning>,
+    pub fetched: Vec<String>,
+    pub stats: Stats,
+}
+
+impl Report {
+    /// Add the trace, warnings and loaded documents of the last deref `jsonref` ran.
+    pub fn record(&mut self, jsonref: &JsonRef) {
+        for step in jsonref.trace() {
+            if step.rules.iter().all(|rule| !left_in_place(rule)) {
+                self.stats.refs_resolved += 1;
+            }
+            for rule in &step.rules {
+                let message = match rule {
+                    Rule::CycleTruncated | Rule::CycleKept | Rule::CycleStubbed => {
+                        self.stats.cycles_truncated += 1;
+                        format!("recursive `$ref` `{}`: {}", step.reference, rule)
+                    }
+                    Rule::SiblingsDiscarded => {
+                        format!("keywords next to `$ref` `{}` discarded", step.reference)
+                    }
+                    Rule::Unresolved { error } => {
+                        format!("`$ref` `{}` left unresolved: {}", step.reference, error)
+                    }
+                    _ => continue,
+                };
+                self.warnings.push(Warning {
+                    pointer: step.pointer.clone(),
+                    message,
+                });
+            }
+        }
+        for warning in jsonref.warnings() {
+            let pointer = match warning {
+                JsonRefWarning::UnsupportedKeyword { pointer, .. } => pointer.clone(),
+                _ => String::new(),
+            };
+            self.warnings.push(Warning {
+                pointer,
+                message: warning.to_string(),
+            });
+        }
+        self.stats.documents_loaded += jsonref.loaded_documents().len();
+        self.fetched
+            .extend(jsonref.loaded_documents().iter().cloned());
+    }
+
+    /// Turn warnings into a failure when `deny_warnings` is set.
+    pub fn check(
+        &self,
+        result: Result<String, Failure>,
+        deny_warnings: bool,
+    ) -> Result<String, Failure> {
+        match result {
+            Ok(_) if deny_warnings && !self.warnings.is_empty() => Err(Failure::new(
+                FailureClass::Warnings,
+                format!("{} warning(s) and --deny-warnings set", self.warnings.len()),
+            )),
+            result => result,
+        }
+    }
+
+    /// The machine-readable report for a command that ended with `result`.
+    pub fn to_json(&self, result: &Result<String, Failure>) -> Value {
+        let (status, exit_code, output, errors) = match result {
+            Ok(output) => ("ok", 0, Some(output), vec![]),
+            Err(failure) => ("failed", failure.class.exit_code(), None, vec![failure]),
+        };
+        json!({
+            "status": status,
+            "exit_code": exit_code,
+            "output": output,
+            "errors": errors,
+            "warnings": self.warnings,
+            "fetched": self.fetched,
+            "stats": self.stats,
+        })
+    }
+}
+
+/// Whether `rule` means the `$ref` was left in the output rather than resolved.
+fn left_in_place(rule: &Rule) -> bool {
+    matches!(
+        rule,
+        Rule::CycleTruncated
+            | Rule::CycleKept
+            | Rule::CycleStubbed
+            | Rule::Unresolved { .. }
+            | Rule::KeptByDirective
+            | Rule::KeptBySelection
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Failure, FailureClass, Report};
+    use polywrap_jsonref::{JsonRef, OnMissing};
+    use serde_json::json;
+
+    #[test]
+    fn records_warnings_and_denies_them() {
+        let mut input = json!(
+            {"properties": {"prop1": {"$ref": "#/properties/prop1"},
+                            "prop2": {"$ref": "#/definitions/a", "title": "dropped"}},
+             "definitions": {"a": {"type": "string"}},
+             "discriminator": {"propertyName": "kind"}}
+        );
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut input).unwrap();
+
+        let mut report = Report::default();
+        report.record(&jsonref);
+        assert_eq!(report.stats.refs_resolved, 2);
+        assert_eq!(report.stats.cycles_truncated, 1);
+        assert_eq!(report.warnings.len(), 3);
+        assert_eq!(report.warnings[2].pointer, "");
+        assert!(report.warnings[2].message.contains("`discriminator`"));
+
+        assert!(report.check(Ok(String::new()), false).is_ok());
+        let denied = report.check(Ok(String::new()), true).unwrap_err();
+        assert_eq!(denied.class.exit_code(), 1);
+
+        let failed = report.to_json(&Err(Failure::usage("bad")));
+        assert_eq!(failed["exit_code"], 2);
+        assert_eq!(failed["errors"][0]["class"], "usage");
+        assert_eq!(FailureClass::PointerNotFound.exit_code(), 6);
+    }
+
+    #[test]
+    fn refs_left_in_place_are_not_resolved() {
+        let mut input = json!(
+            {"properties": {"kept": {"$ref": "#/definitions/a", "x-jsonref": "keep"},
+                            "missing": {"$ref": "#/definitions/missing"},
+                            "inlined": {"$ref": "#/definitions/a"}},
+             "definitions": {"a": {"type": "string"}}}
+        );
+        let mut jsonref = JsonRef::new();
+        jsonref.set_on_missing(OnMissing::Warn);
+        jsonref.deref_value(&mut input).unwrap();
+
+        let mut report = Report::default();
+        report.record(&jsonref);
+        assert_eq!(jsonref.trace().len(), 3);
+        assert_eq!(report.stats.refs_resolved, 1);
+    }
+}
@@ -0,0 +1,204 @@
+//! Configuring a `JsonRef` in one expression, e.g. for application state shared by handlers.
+
+use url::Url;
+
+use crate::{
+    ConcurrencyLimits, DefinitionCollisions, DefinitionsMode, Draft, JsonRef, OnMissing,
+    RecursionStrategy, RefResolver, RefSelection, ResolutionMode, ResourceLimits, SchemaCache,
+    SiblingStrategy,
+};
+
+/// Builds a [`JsonRef`], with a method for each of its `set_` methods that are most often
+/// needed in services.
+///
+/// `JsonRef` is `Send + Sync` and cheap to clone, and clones share its cache, so one built
+/// at startup can be kept in application state and cloned for each request:
+///
+/// ```
+/// # use polywrap_jsonref::{JsonRef, ResourceLimits};
+/// use serde_json::json;
+/// use std::sync::Arc;
+/// use std::thread;
+/// use url::Url;
+///
+/// let jsonref = JsonRef::builder()
+///     .base_url(Url::parse("https://schemas.example.com/api/").unwrap())
+///     .offline(true)
+///     .resource_limits(ResourceLimits { max_fetches: Some(20), ..ResourceLimits::default() })
+///     .build();
+/// let state = Arc::new(jsonref);
+///
+/// let handler = {
+///     let state = Arc::clone(&state);
+///     thread::spawn(move || {
+///         let mut jsonref = JsonRef::clone(&state);
+///         let mut schema = json!({"items": {"$ref": "#/$defs/a"}, "$defs": {"a": {}}});
+///         jsonref.deref_value(&mut schema).map(|_| schema)
+///     })
+/// };
+/// let schema = handler.join().unwrap().unwrap();
+/// assert_eq!(schema["items"], json!({}));
+/// ```
+#[derive(Debug, Default)]
+pub struct JsonRefBuilder {
+    jsonref: JsonRef,
+}
+
+impl JsonRef {
+    /// Start building a `JsonRef`.
+    pub fn builder() -> JsonRefBuilder {
+        JsonRefBuilder::default()
+    }
+}
+
+impl JsonRefBuilder {
+    /// As [`JsonRef::set_reference_key`].
+    pub fn reference_key(mut self, reference_key: &str) -> Self {
+        self.jsonref.set_reference_key(reference_key);
+        self
+    }
+
+    /// As [`JsonRef::set_base_url`].
+    pub fn base_url(mut self, base_url: Url) -> Self {
+        self.jsonref.set_base_url(base_url);
+        self
+    }
+
+    /// As [`JsonRef::set_concurrency_limits`].
+    pub fn concurrency_limits(mut self, limits: ConcurrencyLimits) -> Self {
+        self.jsonref.set_concurrency_limits(limits);
+        self
+    }
+
+    /// As [`JsonRef::set_resource_limits`].
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.jsonref.set_resource_limits(limits);
+        self
+    }
+
+    /// As [`JsonRef::set_resolver`].
+    pub fn resolver(mut self, resolver: Box<dyn RefResolver>) -> Self {
+        self.jsonref.set_resolver(resolver);
+        self
+    }
+
+    /// Keep loaded documents in `cache`, as [`JsonRef::new_with_cache`] does.
+    pub fn cache(mut self, cache: impl SchemaCache + 'static) -> Self {
+        self.jsonref.schema_cache = crate::cache::CacheHandle::new(cache);
+        self
+    }
+
+    /// As [`JsonRef::set_resolution_mode`].
+    pub fn resolution_mode(mut self, mode: ResolutionMode) -> Self {
+        self.jsonref.set_resolution_mode(mode);
+        self
+    }
+
+    /// As [`JsonRef::set_definitions_mode`].
+    pub fn definitions_mode(mut self, mode: DefinitionsMode) -> Self {
+        self.jsonref.set_definitions_mode(mode);
+        self
+    }
+
+    /// As [`JsonRef::set_definition_collisions`].
+    pub fn definition_collisions(mut self, collisions: DefinitionCollisions) -> Self {
+        self.jsonref.set_definition_collisions(collisions);
+        self
+    }
+
+    /// As [`JsonRef::set_sibling_strategy`].
+    pub fn sibling_strategy(mut self, strategy: SiblingStrategy) -> Self {
+        self.jsonref.set_sibling_strategy(strategy);
+        self
+    }
+
+    /// As [`JsonRef::set_recursion_strategy`].
+    pub fn recursion_strategy(mut self, strategy: RecursionStrategy) -> Self {
+        self.jsonref.set_recursion_strategy(strategy);
+        self
+    }
+
+    /// As [`JsonRef::set_max_inline_depth`].
+    pub fn max_inline_depth(mut self, depth: Option<usize>) -> Self {
+        self.jsonref.set_max_inline_depth(depth);
+        self
+    }
+
+    /// As [`JsonRef::set_expand_dynamic_refs`].
+    pub fn expand_dynamic_refs(mut self, expand: bool) -> Self {
+        self.jsonref.set_expand_dynamic_refs(expand);
+        self
+    }
+
+    /// As [`JsonRef::set_on_missing`].
+    pub fn on_missing(mut self, on_missing: OnMissing) -> Self {
+        self.jsonref.set_on_missing(on_missing);
+        self
+    }
+
+    /// As [`JsonRef::set_ref_selection`].
+    pub fn ref_selection(mut self, selection: RefSelection) -> Self {
+        self.jsonref.set_ref_selection(selection);
+        self
+    }
+
+    /// As [`JsonRef::set_offline`].
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.jsonref.set_offline(offline);
+        self
+    }
+
+    /// As [`JsonRef::set_draft`].
+    pub fn draft(mut self, draft: Draft) -> Self {
+        self.jsonref.set_draft(draft);
+        self
+    }
+
+    /// As [`JsonRef::set_default_draft`].
+    pub fn default_draft(mut self, draft: Draft) -> Self {
+        self.jsonref.set_default_draft(draft);
+        self
+    }
+
+    /// The configured `JsonRef`.
+    pub fn build(self) -> JsonRef {
+        self.jsonref
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{JsonRef, SharedCache};
+    use serde_json::json;
+    use url::Url;
+
+    fn assert_shareable<T: Send + Sync + Clone + Default>() {}
+
+    #[test]
+    fn built_instances_are_shareable() {
+        assert_shareable::<JsonRef>();
+
+        let cache = SharedCache::new();
+        let jsonref = JsonRef::builder()
+            .base_url(Url::parse("https://example.com/schemas/root.json").unwrap())
+            .reference_key("__reference__")
+            .cache(cache)
+            .offline(true)
+            .build();
+        let mut setup = jsonref.clone();
+        setup
+            .add_schema(
+                "https://example.com/schemas/pet.json",
+                json!({"title": "pet"}),
+            )
+            .unwrap();
+
+        // The clone sees what was added through the other one.
+        let mut schema = json!({"items": {"$ref": "pet.json", "title": "item"}});
+        jsonref.clone().deref_value(&mut schema).unwrap();
+        assert_eq!(
+            schema,
+            json!({"items": {"title": "pet", "__reference__": {"title": "item"}}})
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! Bundling: copying external schemas into the root document instead of inlining them.
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use url::Url;
+
+use crate::{shift_remove, DEFINITIONS_KEYWORDS};
+
+/// How a `$ref` is replaced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ResolutionMode {
+    /// Replace every `$ref` with a copy of the schema it points to.
+    #[default]
+    Inline,
+    /// Leave refs within the root document alone, and copy each schema from another document
+    /// once into the root's `$defs` (or `definitions` before 2019-09), rewriting the `$ref`s
+    /// to point there, e.g. `other.json#/definitions/Address` becomes
+    /// `#/$defs/other_json_Address`.
+    Bundle,
+}
+
+/// Schemas copied into the root document during one deref, when bundling or when moving
+/// recursive schemas into definitions.
+#[derive(Debug, Default)]
+pub(crate) struct Bundle {
+    /// Root keyword the bundled schemas go under.
+    pub keyword: &'static str,
+    /// Local name given to each bundled ref URL.
+    names: IndexMap<String, String>,
+    taken: HashSet<String>,
+    pub schemas: Map<String, Value>,
+}
+
+impl Bundle {
+    /// Start bundling into the root `document`, keeping clear of the names already under
+    /// `keyword`.
+    pub fn new(keyword: &'static str, document: &Value) -> Bundle {
+        let taken = document
+            .get(keyword)
+            .and_then(Value::as_object)
+            .map(|defs| defs.keys().cloned().collect())
+            .unwrap_or_default();
+        Bundle {
+            keyword,
+            taken,
+            ..Bundle::default()
+        }
+    }
+
+    /// The local `$ref` for `url`, and whether it was newly named (so still needs bundling).
+    pub fn local_ref(&mut self, url: &Url) -> (String, bool) {
+        let key = url.to_string();
+        let new = !self.names.contains_key(&key);
+        if new {
+            let name = self.unique(definition_name(url));
+            self.taken.insert(name.clone());
+            self.names.insert(key.clone(), name);
+        }
+        (format!("#/{}/{}", self.keyword, self.names[&key]), new)
+    }
+
+    /// The local `$ref` for `url`, if [`Bundle::local_ref`] named it.
+    pub fn named_ref(&self, url: &Url) -> Option<String> {
+        self.names
+            .get(url.as_str())
+            .map(|name| format!("#/{}/{}", self.keyword, name))
+    }
+
+    /// Whether the schema for `url` has been stored.
+    pub fn has_schema(&self, url: &Url) -> bool {
+        self.names
+            .get(url.as_str())
+            .is_some_and(|name| self.schemas.contains_key(name))
+    }
+
+    /// Store the dereferenced schema for a ref URL named by [`Bundle::local_ref`].
+    pub fn add(&mut self, url: &Url, schema: Value) {
+        let name = self.names[url.as_str()].clone();
+        self.schemas.insert(name, schema);
+    }
+
+    /// The bundled schemas, in the order their refs were first reached rather than the
+    /// order they were done in, which puts the schemas refs lead to before those they are in.
+    pub fn into_schemas(mut self) -> Map<String, Value> {
+        let names = std::mem::take(&mut self.names);
+        names
+            .into_values()
+            .filter_map(|name| {
+                let schema = shift_remove(&mut self.schemas, &name)?;
+                Some((name, schema))
+            })
+            .collect()
+    }
+
+    fn unique(&self, name: String) -> String {
+        if !self.taken.contains(&name) {
+            return name;
+        }
+        (2..)
+            .map(|i| format!("{}_{}", name, i))
+            .find(|candidate| !self.taken.contains(candidate))
+            .unwrap()
+    }
+}
+
+/// A definition name for `url` made from its file name and fragment, e.g. `other_json_Address`
+/// for `other.json#/definitions/Address`.
+pub(crate) fn definition_name(url: &Url) -> String {
+    let file = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .unwrap_or("schema");
+    let mut parts = vec![sanitize(file)];
+    if let Some(fragment) = url.fragment() {
+        parts.extend(
+            fragment
+                .split('/')
+                .filter(|segment| !segment.is_empty() && !DEFINITIONS_KEYWORDS.contains(segment))
+                .map(sanitize),
+        );
+    }
+    parts.join("_")
+}
+
+fn sanitize(segment: &str) -> String {
+    segment
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bundle;
+    use crate::{JsonRef, ResolutionMode};
+    use serde_json::json;
+    use url::Url;
+
+    #[test]
+    fn names_are_unique() {
+        let mut bundle = Bundle::new("$defs", &json!({"$defs": {"other_json_Address": {}}}));
+        let url = Url::parse("file:///schemas/other.json#/definitions/Address").unwrap();
+        assert_eq!(
+            bundle.local_ref(&url),
+            ("#/$defs/other_json_Address_2".to_owned(), true)
+        );
+        assert_eq!(
+            bundle.local_ref(&url),
+            ("#/$defs/other_json_Address_2".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn root_definitions_are_kept() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolution_mode(ResolutionMode::Bundle);
+        jsonref
+            .add_schema("https://example.com/a.json", json!({"title": "a"}))
+            .unwrap();
+        let mut schema = json!({
+            "$defs": {"mine": {"title": "mine"}},
+            "properties": {
+                "a": {"$ref": "https://example.com/a.json"},
+                "b": {"$ref": "#/$defs/mine"}
+            }
+        });
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(
+            schema["$defs"],
+            json!({"a_json": {"title": "a"}, "mine": {"title": "mine"}})
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "preserve_order")]
+    fn definitions_are_in_first_seen_order() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolution_mode(ResolutionMode::Bundle);
+        for (name, schema) in [
+            ("a", json!({"title": "a"})),
+            ("b", json!({"items": {"$ref": "c.json"}})),
+            ("c", json!({"title": "c"})),
+        ] {
+            jsonref
+                .add_schema(&format!("https://example.com/{}.json", name), schema)
+                .unwrap();
+        }
+        let mut schema = json!({
+            "properties": {
+                "z": {"$ref": "https://example.com/b.json"},
+                "y": {"$ref": "https://example.com/a.json"}
+            },
+            "$defs": {"mine": {"title": "mine"}}
+        });
+        jsonref.deref_value(&mut schema).unwrap();
+        let keys: Vec<&String> = schema.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["properties", "$defs"]);
+        let names: Vec<&String> = schema["$defs"].as_object().unwrap().keys().collect();
+        assert_eq!(names, ["mine", "b_json", "c_json", "a_json"]);
+    }
+
+    #[test]
+    fn bundle_nested_relative() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolution_mode(ResolutionMode::Bundle);
+        let bundled = jsonref
+            .deref_file("fixtures/nested_relative/base.json")
+            .unwrap();
+        assert_eq!(
+            bundled,
+            json!({
+                "title": "title from file",
+                "properties": {"prop1": {"title": "sub property title in base.json"},
+                               "prop2": {"$ref": "#/properties/prop1"},
+                               "prop3": {"$ref": "#/$defs/other_json"},
+                               "prop4": {"$ref": "#/$defs/other_json_properties_prop1"},
+                               "prop5": {"$ref": "#/$defs/other_json_properties_prop2"}},
+                "$defs": {
+                    "other_json": {
+                        "title": "title from url",
+                        "properties": {
+                            "prop1": {"title": "sub property title in other.json"},
+                            "prop2": {"$ref": "#/$defs/other_json_properties_prop1"}
+                        }
+                    },
+                    "other_json_properties_prop1": {"title": "sub property title in other.json"},
+                    "other_json_properties_prop2": {"$ref": "#/$defs/other_json_properties_prop1"}
+                }
+            })
+        );
+    }
+}
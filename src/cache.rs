@@ -0,0 +1,411 @@
+//! Where loaded documents are kept between refs and between dereferences.
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::JsonRef;
+
+/// Storage for the documents a [`JsonRef`] has loaded, set with [`JsonRef::new_with_cache`].
+///
+/// Keys are the URLs of the documents without fragment, in the canonical form every lookup
+/// uses. A document missing from the cache is loaded again, so a cache is free to evict.
+///
+/// ```
+/// # use polywrap_jsonref::{JsonRef, SchemaCache};
+/// use serde_json::{json, Value};
+/// use std::collections::VecDeque;
+/// use std::sync::Arc;
+///
+/// /// Keeps the last `capacity` documents.
+/// struct Recent {
+///     capacity: usize,
+///     documents: VecDeque<(String, Arc<Value>)>,
+/// }
+///
+/// impl SchemaCache for Recent {
+///     fn get(&mut self, key: &str) -> Option<Arc<Value>> {
+///         let found = self.documents.iter().find(|(cached, _)| cached == key)?;
+///         Some(found.1.clone())
+///     }
+///     fn put(&mut self, key: String, document: Arc<Value>) {
+///         self.remove(&key);
+///         if self.documents.len() == self.capacity {
+///             self.documents.pop_front();
+///         }
+///         self.documents.push_back((key, document));
+///     }
+///     fn contains(&self, key: &str) -> bool {
+///         self.documents.iter().any(|(cached, _)| cached == key)
+///     }
+///     fn remove(&mut self, key: &str) {
+///         self.documents.retain(|(cached, _)| cached != key);
+///     }
+///     fn entries(&self) -> Vec<(String, Arc<Value>)> {
+///         self.documents.iter().cloned().collect()
+///     }
+/// }
+///
+/// let mut jsonref = JsonRef::new_with_cache(Recent { capacity: 8, documents: VecDeque::new() });
+/// let mut schema = json!({"items": {"$ref": "data:application/json,%7B%7D"}});
+/// jsonref.deref_value(&mut schema).unwrap();
+/// assert_eq!(schema, json!({"items": {}}));
+/// ```
+pub trait SchemaCache: Send + Sync {
+    /// The document cached under `key`, if any.
+    fn get(&mut self, key: &str) -> Option<Arc<Value>>;
+    /// Cache `document` under `key`, replacing any document cached under it.
+    fn put(&mut self, key: String, document: Arc<Value>);
+    /// Whether a document is cached under `key`.
+    fn contains(&self, key: &str) -> bool;
+    /// Drop the document cached under `key`, if any.
+    fn remove(&mut self, key: &str);
+    /// Every cached document with its key, e.g. for [`JsonRef::write_snapshot`]. The caches
+    /// of this crate list them in the order they were first cached.
+    fn entries(&self) -> Vec<(String, Arc<Value>)>;
+}
+
+/// What the memory tier keeps, given to [`MemoryCache::with_policy`] or
+/// [`SharedCache::with_policy`]. `None` means no bound, which is the default for both.
+///
+/// A document dropped from memory is loaded again when next referenced, from the disk cache
+/// if one is set and its copy is still current, else from where it came from. Documents
+/// added with [`JsonRef::add_schema`] can not be loaded again, so give a bounded cache only
+/// documents that can.
+///
+/// ```
+/// # use polywrap_jsonref::{MemoryCache, MemoryPolicy, SchemaCache};
+/// use serde_json::json;
+/// use std::sync::Arc;
+///
+/// let mut cache = MemoryCache::with_policy(MemoryPolicy {
+///     max_documents: Some(2),
+///     ..MemoryPolicy::default()
+/// });
+/// cache.put("a".to_owned(), Arc::new(json!("a")));
+/// cache.put("b".to_owned(), Arc::new(json!("b")));
+/// cache.get("a");
+/// cache.put("c".to_owned(), Arc::new(json!("c")));
+/// assert!(cache.contains("a") && !cache.contains("b"));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MemoryPolicy {
+    /// Most documents kept. Past it, the one used longest ago is dropped.
+    pub max_documents: Option<usize>,
+    /// Longest a document is kept after it was cached.
+    pub max_age: Option<Duration>,
+}
+
+/// A document in a [`MemoryCache`].
+#[derive(Debug, Clone)]
+struct Cached {
+    document: Arc<Value>,
+    cached_at: Instant,
+    /// When it was last put or got, counted in uses of the cache.
+    used: u64,
+}
+
+/// The default [`SchemaCache`]: every document, in memory, until the `JsonRef` and its clones
+/// are dropped, or as long as its [`MemoryPolicy`] allows.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCache {
+    documents: IndexMap<String, Cached>,
+    policy: MemoryPolicy,
+    uses: u64,
+}
+
+impl MemoryCache {
+    /// An empty cache.
+    pub fn new() -> MemoryCache {
+        MemoryCache::default()
+    }
+
+    /// An empty cache that keeps documents as `policy` allows.
+    pub fn with_policy(policy: MemoryPolicy) -> MemoryCache {
+        MemoryCache {
+            policy,
+            ..MemoryCache::default()
+        }
+    }
+
+    /// Whether `cached` is past the age the policy allows.
+    fn expired(&self, cached: &Cached) -> bool {
+        self.policy
+            .max_age
+            .is_some_and(|max_age| cached.cached_at.elapsed() >= max_age)
+    }
+
+    /// The document cached under `key`, without counting it as used.
+    fn peek(&self, key: &str) -> Option<Arc<Value>> {
+        let cached = self.documents.get(key)?;
+        (!self.expired(cached)).then(|| cached.document.clone())
+    }
+
+    /// Whether getting a document changes what the cache keeps.
+    fn tracks_use(&self) -> bool {
+        self.policy.max_documents.is_some() || self.policy.max_age.is_some()
+    }
+}
+
+impl SchemaCache for MemoryCache {
+    fn get(&mut self, key: &str) -> Option<Arc<Value>> {
+        let cached = self.documents.get(key)?;
+        if self.expired(cached) {
+            self.documents.shift_remove(key);
+            return None;
+        }
+        self.uses += 1;
+        let cached = self.documents.get_mut(key)?;
+        cached.used = self.uses;
+        Some(cached.document.clone())
+    }
+
+    fn put(&mut self, key: String, document: Arc<Value>) {
+        self.uses += 1;
+        self.documents.insert(
+            key,
+            Cached {
+                document,
+                cached_at: Instant::now(),
+                used: self.uses,
+            },
+        );
+        if let Some(max_documents) = self.policy.max_documents {
+            while self.documents.len() > max_documents {
+                let oldest = self
+                    .documents
+                    .values()
+                    .enumerate()
+                    .min_by_key(|(_, cached)| cached.used)
+                    .map(|(index, _)| index);
+                match oldest {
+                    Some(index) => self.documents.shift_remove_index(index),
+                    None => break,
+                };
+            }
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.peek(key).is_some()
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.documents.shift_remove(key);
+    }
+
+    fn entries(&self) -> Vec<(String, Arc<Value>)> {
+        self.documents
+            .iter()
+            .filter(|(_, cached)| !self.expired(cached))
+            .map(|(key, cached)| (key.clone(), cached.document.clone()))
+            .collect()
+    }
+}
+
+/// A [`SchemaCache`] shared by every `JsonRef` given a clone of it, e.g. by the handlers of
+/// a web service, so each upstream document is fetched and parsed once for all of them.
+///
+/// ```
+/// # use polywrap_jsonref::{JsonRef, SharedCache};
+/// use serde_json::json;
+/// use std::thread;
+///
+/// let cache = SharedCache::new();
+/// let mut setup = JsonRef::new_with_cache(cache.clone());
+/// setup.add_schema("https://example.com/pet.json", json!({"title": "pet"})).unwrap();
+///
+/// let handlers: Vec<_> = (0..4)
+///     .map(|_| {
+///         let cache = cache.clone();
+///         thread::spawn(move || {
+///             let mut jsonref = JsonRef::new_with_cache(cache);
+///             jsonref.set_offline(true);
+///             let mut schema = json!({"items": {"$ref": "https://example.com/pet.json"}});
+///             jsonref.deref_value(&mut schema).unwrap();
+///             schema
+///         })
+///     })
+///     .collect();
+/// for handler in handlers {
+///     assert_eq!(handler.join().unwrap(), json!({"items": {"title": "pet"}}));
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SharedCache {
+    documents: Arc<RwLock<MemoryCache>>,
+}
+
+impl SharedCache {
+    /// An empty cache.
+    pub fn new() -> SharedCache {
+        SharedCache::default()
+    }
+
+    /// An empty cache that keeps documents as `policy` allows, whichever `JsonRef` uses
+    /// them.
+    pub fn with_policy(policy: MemoryPolicy) -> SharedCache {
+        SharedCache {
+            documents: Arc::new(RwLock::new(MemoryCache::with_policy(policy))),
+        }
+    }
+}
+
+// A panic while holding the lock can not leave the map half updated, so the lock is used
+// even if poisoned.
+impl SchemaCache for SharedCache {
+    fn get(&mut self, key: &str) -> Option<Arc<Value>> {
+        let documents = self
+            .documents
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        // Without a policy, lookups need not wait for each other.
+        if !documents.tracks_use() {
+            return documents.peek(key);
+        }
+        drop(documents);
+        self.documents
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key)
+    }
+
+    fn put(&mut self, key: String, document: Arc<Value>) {
+        let mut documents = self
+            .documents
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        documents.put(key, document);
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        let documents = self
+            .documents
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        documents.contains(key)
+    }
+
+    fn remove(&mut self, key: &str) {
+        let mut documents = self
+            .documents
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        documents.remove(key);
+    }
+
+    fn entries(&self) -> Vec<(String, Arc<Value>)> {
+        let documents = self
+            .documents
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        documents.entries()
+    }
+}
+
+/// The cache of a `JsonRef`, shared with its clones.
+#[derive(Clone)]
+pub(crate) struct CacheHandle(Arc<Mutex<Box<dyn SchemaCache>>>);
+
+impl CacheHandle {
+    pub(crate) fn new(cache: impl SchemaCache + 'static) -> CacheHandle {
+        CacheHandle(Arc::new(Mutex::new(Box::new(cache))))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Box<dyn SchemaCache>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<Arc<Value>> {
+        self.lock().get(key)
+    }
+
+    pub(crate) fn put(&self, key: String, document: Arc<Value>) {
+        self.lock().put(key, document)
+    }
+
+    pub(crate) fn remove(&self, key: &str) {
+        self.lock().remove(key)
+    }
+
+    pub(crate) fn entries(&self) -> Vec<(String, Arc<Value>)> {
+        self.lock().entries()
+    }
+}
+
+impl JsonRef {
+    /// Create a new instance of JsonRef that keeps loaded documents in `cache`.
+    pub fn new_with_cache(cache: impl SchemaCache + 'static) -> JsonRef {
+        let mut jsonref = JsonRef::new();
+        jsonref.schema_cache = CacheHandle::new(cache);
+        jsonref
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MemoryCache, MemoryPolicy, SchemaCache, SharedCache};
+    use crate::JsonRef;
+    use serde_json::json;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn documents_go_through_the_cache() {
+        let shared = SharedCache::new();
+        let mut jsonref = JsonRef::new_with_cache(shared.clone());
+        jsonref
+            .add_schema("https://example.com/pet.json", json!({"title": "pet"}))
+            .unwrap();
+        assert!(shared.contains("https://example.com/pet.json"));
+
+        let mut other = JsonRef::new_with_cache(shared.clone());
+        other.set_offline(true);
+        let mut schema = json!({"items": {"$ref": "https://example.com/pet.json"}});
+        other.deref_value(&mut schema).unwrap();
+        assert_eq!(schema, json!({"items": {"title": "pet"}}));
+
+        // Roots are not cached, or concurrent derefs of different values would mix them up.
+        assert_eq!(shared.entries().len(), 1);
+    }
+
+    #[test]
+    fn entries_are_listed_in_the_order_first_cached() {
+        let mut cache = MemoryCache::new();
+        for key in ["c", "a", "d", "b"] {
+            cache.put(key.to_owned(), Arc::new(json!(key)));
+        }
+        cache.put("a".to_owned(), Arc::new(json!("again")));
+        cache.remove("d");
+        let entries = cache.entries();
+        let keys: Vec<&str> = entries.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["c", "a", "b"]);
+        assert_eq!(*entries[1].1, "again");
+    }
+
+    #[test]
+    fn policies_bound_what_is_kept() {
+        let mut shared = SharedCache::with_policy(MemoryPolicy {
+            max_documents: Some(2),
+            ..MemoryPolicy::default()
+        });
+        for key in ["a", "b", "c"] {
+            shared.put(key.to_owned(), Arc::new(json!(key)));
+            shared.get("a");
+        }
+        let entries = shared.entries();
+        let keys: Vec<&str> = entries.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["a", "c"]);
+
+        let mut aging = MemoryCache::with_policy(MemoryPolicy {
+            max_age: Some(Duration::ZERO),
+            ..MemoryPolicy::default()
+        });
+        aging.put("a".to_owned(), Arc::new(json!("a")));
+        assert!(!aging.contains("a"));
+        assert_eq!(aging.get("a"), None);
+        assert!(aging.entries().is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+//! Canonical forms of document URLs, so equivalent spellings share one cache entry.
+
+use url::Url;
+
+/// The key `url` is cached under.
+///
+/// Parsing already drops default ports, lowercases the scheme and host and removes dot
+/// segments. On top of that, percent-escapes are uppercased and escaped unreserved characters
+/// are decoded, so `https://Host:443/a/../b%7e.json` and `https://host/b~.json` are one
+/// document. A trailing `/` is kept, as `https://host/dir/` and `https://host/dir` may be
+/// different documents. Only the cache key changes: documents are still fetched from, and
+/// resolve relative refs against, the URL as written.
+pub(crate) fn cache_key(url: &Url) -> String {
+    let mut url = url.clone();
+    let path = normalize_escapes(url.path());
+    url.set_path(&path);
+    if let Some(query) = url.query().map(normalize_escapes) {
+        url.set_query(Some(&query));
+    }
+    if let Some(fragment) = url.fragment().map(normalize_escapes) {
+        url.set_fragment(Some(&fragment));
+    }
+    url.to_string()
+}
+
+/// `text` with escapes of unreserved characters decoded and other escapes uppercased.
+fn normalize_escapes(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('%') {
+        normalized.push_str(&rest[..start]);
+        let escape = &rest[start..];
+        let byte = escape
+            .get(1..3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match byte {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                normalized.push(byte as char)
+            }
+            Some(_) => normalized.push_str(&escape[..3].to_ascii_uppercase()),
+            None => {
+                normalized.push('%');
+                rest = &escape[1..];
+                continue;
+            }
+        }
+        rest = &escape[3..];
+    }
+    normalized.push_str(rest);
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cache_key;
+    use crate::JsonRef;
+    use serde_json::json;
+    use url::Url;
+
+    #[test]
+    fn equivalent_urls_share_a_key() {
+        let key = |url: &str| cache_key(&Url::parse(url).unwrap());
+        assert_eq!(
+            key("HTTPS://Example.com:443/a/../schemas/pet%7e1.json"),
+            "https://example.com/schemas/pet~1.json"
+        );
+        assert_eq!(
+            key("https://example.com/a%2fb?q=%c3%a9#/x%2Dy"),
+            "https://example.com/a%2Fb?q=%C3%A9#/x-y"
+        );
+        assert_eq!(key("https://example.com/"), "https://example.com/");
+        assert_ne!(
+            key("https://example.com/dir/"),
+            key("https://example.com/dir")
+        );
+        assert_eq!(key("file:///tmp/100%/x"), "file:///tmp/100%/x");
+
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/schemas/pet.json",
+                json!({"title": "pet"}),
+            )
+            .unwrap();
+        let mut input =
+            json!({"items": {"$ref": "https://example.com:443/a/../schemas/p%65t.json"}});
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input, json!({"items": {"title": "pet"}}));
+    }
+}
@@ -0,0 +1,132 @@
+//! CBOR output, behind the `cbor` feature.
+
+use serde_json::Value;
+use snafu::ResultExt;
+use std::io::{self, Write};
+
+use crate::{Result, WriteOutput};
+
+/// Serialize a (dereferenced) schema as CBOR ([RFC 8949](https://www.rfc-editor.org/rfc/rfc8949)),
+/// for consumers where JSON is too bulky.
+///
+/// Lengths and integers take the fewest bytes they fit in, and floats are written as single
+/// precision when that loses nothing. Object keys keep the order of the `Value`.
+///
+/// ```
+/// use polywrap_jsonref::to_cbor_vec;
+/// use serde_json::json;
+///
+/// assert_eq!(to_cbor_vec(&json!({"type": "string"})).unwrap().len(), 13);
+/// ```
+pub fn to_cbor_vec(value: &Value) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    to_cbor_writer(value, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Serialize a (dereferenced) schema as CBOR into `writer`, as [`to_cbor_vec`].
+pub fn to_cbor_writer(value: &Value, writer: impl Write) -> Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    write_value(&mut writer, value)
+        .and_then(|()| writer.flush())
+        .map_err(serde_json::Error::io)
+        .context(WriteOutput {})
+}
+
+fn write_value(writer: &mut impl Write, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Null => writer.write_all(&[0xf6]),
+        Value::Bool(false) => writer.write_all(&[0xf4]),
+        Value::Bool(true) => writer.write_all(&[0xf5]),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                write_head(writer, 0, n)
+            } else if let Some(n) = number.as_i64() {
+                // Negative integers are encoded as -1 - n.
+                write_head(writer, 1, !(n as u64))
+            } else {
+                let n = number.as_f64().unwrap_or(f64::NAN);
+                if f64::from(n as f32) == n {
+                    writer.write_all(&[0xfa])?;
+                    writer.write_all(&(n as f32).to_be_bytes())
+                } else {
+                    writer.write_all(&[0xfb])?;
+                    writer.write_all(&n.to_be_bytes())
+                }
+            }
+        }
+        Value::String(string) => write_str(writer, string),
+        Value::Array(items) => {
+            write_head(writer, 4, items.len() as u64)?;
+            items.iter().try_for_each(|item| write_value(writer, item))
+        }
+        Value::Object(obj) => {
+            write_head(writer, 5, obj.len() as u64)?;
+            obj.iter().try_for_each(|(key, value)| {
+                write_str(writer, key)?;
+                write_value(writer, value)
+            })
+        }
+    }
+}
+
+fn write_str(writer: &mut impl Write, string: &str) -> io::Result<()> {
+    write_head(writer, 3, string.len() as u64)?;
+    writer.write_all(string.as_bytes())
+}
+
+/// Write the initial byte of an item of `major` type with the argument `n`, and `n` itself
+/// when it does not fit in that byte.
+fn write_head(writer: &mut impl Write, major: u8, n: u64) -> io::Result<()> {
+    let major = major << 5;
+    if n < 24 {
+        writer.write_all(&[major | n as u8])
+    } else if let Ok(n) = u8::try_from(n) {
+        writer.write_all(&[major | 24, n])
+    } else if let Ok(n) = u16::try_from(n) {
+        writer.write_all(&[major | 25])?;
+        writer.write_all(&n.to_be_bytes())
+    } else if let Ok(n) = u32::try_from(n) {
+        writer.write_all(&[major | 26])?;
+        writer.write_all(&n.to_be_bytes())
+    } else {
+        writer.write_all(&[major | 27])?;
+        writer.write_all(&n.to_be_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_cbor_vec;
+    use serde_json::json;
+
+    #[test]
+    fn values_are_encoded_compactly() {
+        // The examples of RFC 8949, appendix A.
+        let cases = [
+            (json!(0), vec![0x00]),
+            (json!(24), vec![0x18, 0x18]),
+            (json!(1000), vec![0x19, 0x03, 0xe8]),
+            (
+                json!(1000000000000u64),
+                vec![0x1b, 0, 0, 0, 0xe8, 0xd4, 0xa5, 0x10, 0],
+            ),
+            (json!(-1000), vec![0x39, 0x03, 0xe7]),
+            (json!(1.5), vec![0xfa, 0x3f, 0xc0, 0, 0]),
+            (
+                json!(1.1),
+                vec![0xfb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a],
+            ),
+            (json!(null), vec![0xf6]),
+            (json!("IETF"), vec![0x64, 0x49, 0x45, 0x54, 0x46]),
+            (json!([1, [2, 3]]), vec![0x82, 0x01, 0x82, 0x02, 0x03]),
+            (
+                json!({"a": 1, "b": [2, 3]}),
+                vec![0xa2, 0x61, 0x61, 0x01, 0x61, 0x62, 0x82, 0x02, 0x03],
+            ),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(to_cbor_vec(&value).unwrap(), bytes, "{}", value);
+        }
+    }
+}
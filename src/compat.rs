@@ -0,0 +1,355 @@
+//! Compatibility classification of the changes between two schema versions.
+//!
+//! Terminology follows schema registries:
+//!
+//! * **readers**: consumers that switch to the new schema but still receive data written against
+//!   the old one. A change is breaking for readers when the new schema rejects data the old one
+//!   accepted (e.g. a new required field, a narrower type).
+//! * **writers**: producers that switch to the new schema while consumers still use the old one.
+//!   A change is breaking for writers when the new schema accepts data the old one rejects
+//!   (e.g. a removed `required` entry, a wider type).
+
+use serde_json::Value;
+
+use crate::diff::{diff, Change, ChangeKind};
+
+/// Whether a change is safe for one side of the exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    Compatible,
+    Breaking,
+}
+
+use Compatibility::{Breaking, Compatible};
+
+/// A change between the two schemas together with its classification.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassifiedChange {
+    pub change: Change,
+    pub readers: Compatibility,
+    pub writers: Compatibility,
+}
+
+/// All changes between two schema versions, classified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompatibilityReport {
+    pub changes: Vec<ClassifiedChange>,
+}
+
+impl CompatibilityReport {
+    /// Data written against the old schema is still accepted by the new one.
+    pub fn is_compatible_for_readers(&self) -> bool {
+        self.changes.iter().all(|c| c.readers == Compatible)
+    }
+
+    /// Data written against the new schema is still accepted by the old one.
+    pub fn is_compatible_for_writers(&self) -> bool {
+        self.changes.iter().all(|c| c.writers == Compatible)
+    }
+
+    /// Changes that break readers or writers.
+    pub fn breaking(&self) -> impl Iterator<Item = &ClassifiedChange> {
+        self.changes
+            .iter()
+            .filter(|c| c.readers == Breaking || c.writers == Breaking)
+    }
+}
+
+/// Keywords that do not affect which instances are valid.
+const ANNOTATIONS: &[&str] = &[
+    "title",
+    "description",
+    "default",
+    "examples",
+    "$comment",
+    "deprecated",
+    "readOnly",
+    "writeOnly",
+    "definitions",
+    "$defs",
+    "$id",
+    "$schema",
+];
+
+/// Numeric keywords where a larger value accepts more instances.
+const UPPER_BOUNDS: &[&str] = &[
+    "maximum",
+    "exclusiveMaximum",
+    "maxLength",
+    "maxItems",
+    "maxProperties",
+    "maxContains",
+];
+
+/// Numeric keywords where a smaller value accepts more instances.
+const LOWER_BOUNDS: &[&str] = &[
+    "minimum",
+    "exclusiveMinimum",
+    "minLength",
+    "minItems",
+    "minProperties",
+    "minContains",
+];
+
+/// Compare two dereferenced schemas and classify every change for readers and writers.
+///
+/// ```
+/// use polywrap_jsonref::compat::check_compatibility;
+/// use serde_json::json;
+///
+/// let old = json!({"properties": {"name": {"type": ["string", "null"]}}});
+/// let new = json!({"properties": {"name": {"type": "string"}}, "required": ["name"]});
+///
+/// let report = check_compatibility(&old, &new);
+/// assert!(!report.is_compatible_for_readers());
+/// assert!(report.is_compatible_for_writers());
+/// ```
+pub fn check_compatibility(old: &Value, new: &Value) -> CompatibilityReport {
+    let changes = diff(old, new)
+        .into_iter()
+        .map(|change| {
+            let (readers, writers) = classify(&change, old, new);
+            ClassifiedChange {
+                change,
+                readers,
+                writers,
+            }
+        })
+        .collect();
+    CompatibilityReport { changes }
+}
+
+/// Classify one change as `(readers, writers)`.
+fn classify(change: &Change, old: &Value, new: &Value) -> (Compatibility, Compatibility) {
+    let keyword = change.name();
+    let parent = parent_pointer(&change.pointer);
+    match &change.kind {
+        ChangeKind::RequiredAdded { .. } => (Breaking, Compatible),
+        ChangeKind::RequiredRemoved { .. } => (Compatible, Breaking),
+        ChangeKind::PropertyRemoved { schema } => {
+            // Old data may still carry the property, which a closed new schema rejects; an open
+            // new schema instead lets new data carry values the old subschema rejected.
+            if is_closed(new, parent_pointer(parent)) {
+                (Breaking, Compatible)
+            } else if is_trivially_true(schema) {
+                (Compatible, Compatible)
+            } else {
+                (Compatible, Breaking)
+            }
+        }
+        ChangeKind::PropertyAdded { schema } => {
+            // New data may carry the property, which a closed old schema rejects; an open old
+            // schema instead let old data carry values the new subschema rejects.
+            if is_closed(old, parent_pointer(parent)) {
+                (Compatible, Breaking)
+            } else if is_trivially_true(schema) {
+                (Compatible, Compatible)
+            } else {
+                (Breaking, Compatible)
+            }
+        }
+        _ if ANNOTATIONS.contains(&keyword.as_str()) || in_definitions(&change.pointer) => {
+            (Compatible, Compatible)
+        }
+        // A keyword the old schema did not have can only restrict, and removing one can only
+        // relax, with the exception of `additionalProperties: true` and similar no-ops.
+        ChangeKind::Added { value } => {
+            if is_trivially_true(value) {
+                (Compatible, Compatible)
+            } else {
+                (Breaking, Compatible)
+            }
+        }
+        ChangeKind::Removed { value } => {
+            if is_trivially_true(value) {
+                (Compatible, Compatible)
+            } else {
+                (Compatible, Breaking)
+            }
+        }
+        ChangeKind::Changed { old, new } => classify_changed(&keyword, old, new),
+    }
+}
+
+fn classify_changed(keyword: &str, old: &Value, new: &Value) -> (Compatibility, Compatibility) {
+    let narrowed = match keyword {
+        "type" => {
+            let (old_types, new_types) = (types(old), types(new));
+            Some((
+                subset(&new_types, &old_types),
+                subset(&old_types, &new_types),
+            ))
+        }
+        "enum" => {
+            let old_values = old.as_array().cloned().unwrap_or_default();
+            let new_values = new.as_array().cloned().unwrap_or_default();
+            Some((
+                subset(&new_values, &old_values),
+                subset(&old_values, &new_values),
+            ))
+        }
+        _ if UPPER_BOUNDS.contains(&keyword) => compare_numbers(new, old),
+        _ if LOWER_BOUNDS.contains(&keyword) => compare_numbers(old, new),
+        "additionalProperties" | "unevaluatedProperties" | "additionalItems" => {
+            if is_trivially_true(old) || new == &Value::Bool(false) {
+                Some((true, false))
+            } else if is_trivially_true(new) {
+                Some((false, true))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    // `(new accepts less, new accepts more)`; unknown changes are assumed to break both sides.
+    match narrowed {
+        Some((true, true)) => (Compatible, Compatible),
+        Some((true, false)) => (Breaking, Compatible),
+        Some((false, true)) => (Compatible, Breaking),
+        _ => (Breaking, Breaking),
+    }
+}
+
+/// Whether the schema at `pointer` rejects properties it does not declare.
+fn is_closed(schema: &Value, pointer: &str) -> bool {
+    schema
+        .pointer(pointer)
+        .and_then(|s| s.get("additionalProperties"))
+        == Some(&Value::Bool(false))
+}
+
+fn in_definitions(pointer: &str) -> bool {
+    pointer.starts_with("/definitions/") || pointer.starts_with("/$defs/")
+}
+
+fn is_trivially_true(value: &Value) -> bool {
+    value == &Value::Bool(true) || value.as_object().is_some_and(|obj| obj.is_empty())
+}
+
+fn parent_pointer(pointer: &str) -> &str {
+    match pointer.rfind('/') {
+        Some(index) => &pointer[..index],
+        None => "",
+    }
+}
+
+/// The set of types a `type` keyword allows, with `integer` implied by `number`.
+fn types(value: &Value) -> Vec<Value> {
+    let mut types: Vec<Value> = match value {
+        Value::Array(types) => types.clone(),
+        other => vec![other.clone()],
+    };
+    if types.contains(&Value::from("number")) && !types.contains(&Value::from("integer")) {
+        types.push(Value::from("integer"));
+    }
+    types
+}
+
+/// Whether every item of `a` is in `b`.
+fn subset(a: &[Value], b: &[Value]) -> bool {
+    a.iter().all(|item| b.contains(item))
+}
+
+/// `Some((tighter, looser))` for a bound where `smaller <= larger` means tighter.
+fn compare_numbers(smaller: &Value, larger: &Value) -> Option<(bool, bool)> {
+    let (smaller, larger) = (smaller.as_f64()?, larger.as_f64()?);
+    Some((smaller <= larger, smaller >= larger))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_compatibility, Compatibility::*};
+    use serde_json::json;
+
+    /// The classified changes, sorted as their order depends on `preserve_order`.
+    fn classes(old: serde_json::Value, new: serde_json::Value) -> Vec<(String, String, String)> {
+        let mut classes: Vec<_> = check_compatibility(&old, &new)
+            .changes
+            .iter()
+            .map(|c| {
+                (
+                    c.change.pointer.clone(),
+                    format!("{:?}", c.readers),
+                    format!("{:?}", c.writers),
+                )
+            })
+            .collect();
+        classes.sort();
+        classes
+    }
+
+    fn entry(
+        pointer: &str,
+        readers: super::Compatibility,
+        writers: super::Compatibility,
+    ) -> (String, String, String) {
+        (
+            pointer.to_owned(),
+            format!("{:?}", readers),
+            format!("{:?}", writers),
+        )
+    }
+
+    #[test]
+    fn classify_common_changes() {
+        let old = json!({
+            "additionalProperties": false,
+            "required": ["id", "legacy"],
+            "properties": {
+                "id": {"type": "number", "description": "old"},
+                "legacy": {"type": "string"},
+                "name": {"type": "string", "maxLength": 10},
+                "kind": {"enum": ["a", "b"]}
+            }
+        });
+        let new = json!({
+            "additionalProperties": false,
+            "required": ["id", "name"],
+            "properties": {
+                "id": {"type": "integer", "description": "new"},
+                "name": {"type": "string", "maxLength": 20},
+                "kind": {"enum": ["a", "b", "c"]}
+            }
+        });
+
+        assert_eq!(
+            classes(old, new),
+            vec![
+                entry("/properties/id/description", Compatible, Compatible),
+                entry("/properties/id/type", Breaking, Compatible),
+                entry("/properties/kind/enum", Compatible, Breaking),
+                entry("/properties/legacy", Breaking, Compatible),
+                entry("/properties/name/maxLength", Compatible, Breaking),
+                entry("/required", Breaking, Compatible),
+                entry("/required", Compatible, Breaking),
+            ]
+        );
+    }
+
+    #[test]
+    fn properties_on_open_and_closed_schemas() {
+        let old = json!({"properties": {"a": {}}});
+        let new = json!({"properties": {"a": {}, "b": {"type": "string"}}});
+        assert_eq!(
+            classes(old.clone(), new.clone()),
+            vec![entry("/properties/b", Breaking, Compatible)]
+        );
+        assert_eq!(
+            classes(new, old.clone()),
+            vec![entry("/properties/b", Compatible, Breaking)]
+        );
+
+        let anything = json!({"properties": {"a": {}, "b": {}}});
+        let report = check_compatibility(&old, &anything);
+        assert!(report.is_compatible_for_readers());
+        assert!(report.is_compatible_for_writers());
+        assert_eq!(report.breaking().count(), 0);
+        assert_eq!(check_compatibility(&anything, &old).breaking().count(), 0);
+
+        let closed_old = json!({"properties": {"a": {}}, "additionalProperties": false});
+        let closed_new = json!({"properties": {"a": {}, "b": {}}, "additionalProperties": false});
+        let report = check_compatibility(&closed_old, &closed_new);
+        assert!(report.is_compatible_for_readers());
+        assert!(!report.is_compatible_for_writers());
+    }
+}
@@ -0,0 +1,191 @@
+//! Running the referencing cases of the official
+//! [JSON Schema Test Suite](https://github.com/json-schema-org/JSON-Schema-Test-Suite), to
+//! check how dereferencing behaves for the drafts a project depends on.
+//!
+//! Point [`run`] at a checkout of the suite. Its `remotes` directory is added to the cache
+//! under `http://localhost:1234/`, where the cases expect it, so no server is needed. Each
+//! case whose schema dereferences without error, leaving no `$ref` in the output, passes.
+//! The test instances of the cases are not validated, as this crate does not validate, so a
+//! pass says the refs were all followed, not that they were followed to the right place.
+//!
+//! ```no_run
+//! use polywrap_jsonref::{conformance, Draft, JsonRef};
+//! use std::path::Path;
+//!
+//! let mut jsonref = JsonRef::new();
+//! jsonref.set_offline(true);
+//! let suite = Path::new("JSON-Schema-Test-Suite");
+//! for report in conformance::run(suite, &jsonref, &[Draft::Draft7, Draft::Draft202012]).unwrap() {
+//!     println!("{}: {} passed, {} failed", report.draft, report.passed(), report.failed());
+//! }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::ResultExt;
+use std::fs;
+use std::path::Path;
+
+use crate::index::schema_files;
+use crate::{assert_fully_resolved, Draft, JsonRef, Result, SchemaFromFile, SchemaNotJsonSerde};
+
+/// Files of each draft's directory holding referencing cases, those a draft lacks skipped.
+pub const REFERENCING_FILES: &[&str] = &[
+    "ref.json",
+    "refRemote.json",
+    "anchor.json",
+    "id.json",
+    "dynamicRef.json",
+    "recursiveRef.json",
+];
+
+/// Where the suite's cases expect the files of its `remotes` directory.
+pub const REMOTES_URL: &str = "http://localhost:1234/";
+
+/// How one case of the suite went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CaseOutcome {
+    /// The file of the case, e.g. `refRemote.json`.
+    pub file: String,
+    pub description: String,
+    /// Why the case failed: the error of the deref, or the refs left in its output.
+    pub error: Option<String>,
+}
+
+impl CaseOutcome {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The outcomes of the cases of one draft, in the order of [`REFERENCING_FILES`] and of the
+/// cases within each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DraftReport {
+    pub draft: Draft,
+    pub cases: Vec<CaseOutcome>,
+}
+
+impl DraftReport {
+    pub fn passed(&self) -> usize {
+        self.cases.iter().filter(|case| case.passed()).count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.cases.len() - self.passed()
+    }
+}
+
+/// A case as the suite writes it; its tests are not needed.
+#[derive(Deserialize)]
+struct Case {
+    description: String,
+    schema: Value,
+}
+
+/// Run the referencing cases of each of `drafts` of the suite checked out at `suite`, with
+/// a clone of `jsonref` and whatever it was configured with, reading the cases as their
+/// draft where they do not say otherwise. A draft the suite has no directory for gets an
+/// empty report.
+///
+/// Fails when a file of the suite can not be read or is not valid JSON.
+pub fn run(suite: &Path, jsonref: &JsonRef, drafts: &[Draft]) -> Result<Vec<DraftReport>> {
+    let mut jsonref = jsonref.clone();
+    add_remotes(&suite.join("remotes"), &mut jsonref)?;
+    let mut reports = vec![];
+    for draft in drafts {
+        let mut jsonref = jsonref.clone();
+        jsonref.set_default_draft(*draft);
+        let dir = suite.join("tests").join(directory(*draft));
+        let mut cases = vec![];
+        for file in REFERENCING_FILES {
+            let path = dir.join(file);
+            if !path.is_file() {
+                continue;
+            }
+            for case in read::<Vec<Case>>(&path)? {
+                let mut schema = case.schema;
+                let error = match jsonref.deref_value(&mut schema) {
+                    Ok(()) => assert_fully_resolved(&schema)
+                        .err()
+                        .map(|left| format!("refs left at {}", left.join(", "))),
+                    Err(error) => Some(error.to_string()),
+                };
+                cases.push(CaseOutcome {
+                    file: (*file).to_owned(),
+                    description: case.description,
+                    error,
+                });
+            }
+        }
+        reports.push(DraftReport {
+            draft: *draft,
+            cases,
+        });
+    }
+    Ok(reports)
+}
+
+/// The directory of the suite's `tests` holding the cases of `draft`.
+fn directory(draft: Draft) -> &'static str {
+    match draft {
+        Draft::Draft4 => "draft4",
+        Draft::Draft6 => "draft6",
+        Draft::Draft7 => "draft7",
+        Draft::Draft201909 => "draft2019-09",
+        Draft::Draft202012 => "draft2020-12",
+    }
+}
+
+/// Add each file under `remotes` to the cache of `jsonref`, at its path under
+/// [`REMOTES_URL`].
+fn add_remotes(remotes: &Path, jsonref: &mut JsonRef) -> Result<()> {
+    if !remotes.is_dir() {
+        return Ok(());
+    }
+    let mut files = vec![];
+    schema_files(remotes, &mut files)?;
+    for file in files {
+        let relative = file.strip_prefix(remotes).unwrap_or(&file);
+        let path: Vec<_> = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect();
+        let url = format!("{}{}", REMOTES_URL, path.join("/"));
+        jsonref.add_schema(&url, read(&file)?)?;
+    }
+    Ok(())
+}
+
+fn read<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let text = fs::read_to_string(path).context(SchemaFromFile {
+        filename: path.to_string_lossy(),
+    })?;
+    serde_json::from_str(&text).context(SchemaNotJsonSerde {
+        url: path.to_string_lossy(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run;
+    use crate::{Draft, JsonRef};
+    use std::path::Path;
+
+    #[test]
+    fn cases_are_reported_per_draft() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_offline(true);
+        let suite = Path::new("fixtures/conformance");
+        let drafts = [Draft::Draft4, Draft::Draft7, Draft::Draft202012];
+        let reports = run(suite, &jsonref, &drafts).unwrap();
+
+        assert!(reports[0].cases.is_empty());
+        let draft7 = &reports[1];
+        assert_eq!((draft7.passed(), draft7.failed()), (2, 1));
+        let failed = draft7.cases.iter().find(|case| !case.passed()).unwrap();
+        assert_eq!(failed.description, "ref to a definition that is not there");
+        assert_eq!(draft7.cases[2].file, "refRemote.json");
+        assert_eq!((reports[2].passed(), reports[2].failed()), (1, 0));
+    }
+}
@@ -0,0 +1,84 @@
+//! Documents embedded in `data:` URLs (RFC 2397).
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use url::{Position, Url};
+
+use crate::{Error, Result};
+
+/// The bytes embedded in the `data:` URL `url`, and a path telling `parse_document`
+/// how to parse them: YAML media types get a `.yaml` path, anything else is read as JSON.
+pub(crate) fn decode(url: &Url) -> Result<(Vec<u8>, &'static str)> {
+    let invalid = |reason: &str| Error::InvalidDataUrl {
+        reference: url.to_string(),
+        reason: reason.to_owned(),
+    };
+    // Everything after `data:` up to the fragment, which is a JSON pointer into the document.
+    let (metadata, payload) = url[Position::BeforePath..Position::AfterQuery]
+        .split_once(',')
+        .ok_or_else(|| invalid("no `,` before the data"))?;
+    let mut parameters = metadata.split(';');
+    let media_type = parameters.next().unwrap_or("").trim().to_ascii_lowercase();
+    let base64 = parameters.any(|parameter| parameter.eq_ignore_ascii_case("base64"));
+
+    let payload: Vec<u8> = percent_decode_str(payload).collect();
+    let bytes = if base64 {
+        let payload: Vec<u8> = payload
+            .into_iter()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .collect();
+        STANDARD
+            .decode(payload)
+            .map_err(|error| invalid(&error.to_string()))?
+    } else {
+        payload
+    };
+    let path = match media_type.as_str() {
+        "application/yaml" | "application/x-yaml" | "text/yaml" | "text/x-yaml" => "data.yaml",
+        _ => "",
+    };
+    Ok((bytes, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode;
+    use crate::{Error, JsonRef};
+    use serde_json::json;
+    use url::Url;
+
+    #[test]
+    fn decodes_base64_and_percent_encoded_data() {
+        let url = Url::parse("data:application/json;base64,eyJ0eXBlIjogInN0cmluZyJ9").unwrap();
+        assert_eq!(
+            decode(&url).unwrap(),
+            (br#"{"type": "string"}"#.to_vec(), "")
+        );
+        let url = Url::parse("data:application/json,%7B%22type%22:%22string%22%7D").unwrap();
+        assert_eq!(decode(&url).unwrap().0, br#"{"type":"string"}"#.to_vec());
+        let url = Url::parse("data:text/yaml,type:%20string").unwrap();
+        assert_eq!(decode(&url).unwrap().1, "data.yaml");
+
+        let url = Url::parse("data:application/json;base64").unwrap();
+        assert!(matches!(decode(&url), Err(Error::InvalidDataUrl { .. })));
+        let url = Url::parse("data:application/json;base64,!!").unwrap();
+        assert!(matches!(decode(&url), Err(Error::InvalidDataUrl { .. })));
+    }
+
+    #[test]
+    fn data_refs_are_inlined() {
+        // {"definitions": {"name": {"type": "string"}}}
+        let data = "data:application/json;base64,\
+                    eyJkZWZpbml0aW9ucyI6IHsibmFtZSI6IHsidHlwZSI6ICJzdHJpbmcifX19";
+        let mut schema = json!({"properties": {
+            "name": {"$ref": format!("{}#/definitions/name", data)},
+            "inline": {"$ref": "data:application/json,%7B%22type%22:%22integer%22%7D"}
+        }});
+        JsonRef::new().deref_value(&mut schema).unwrap();
+        assert_eq!(
+            schema,
+            json!({"properties": {"name": {"type": "string"}, "inline": {"type": "integer"}}})
+        );
+    }
+}
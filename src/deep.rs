@@ -0,0 +1,131 @@
+//! Copying and dropping values without recursing, as `serde_json` does, so that documents
+//! nested deeper than the stack allows can still be walked.
+
+use serde_json::{map, Value};
+use std::mem;
+use std::slice;
+use std::sync::Arc;
+
+/// A copy of `value`.
+pub(crate) fn clone(value: &Value) -> Value {
+    /// A container being copied: what is copied so far, and the members left.
+    enum Partial<'a> {
+        Array(Vec<Value>, slice::Iter<'a, Value>),
+        Object(Vec<(String, Value)>, map::Iter<'a>, Option<&'a String>),
+    }
+
+    let mut stack: Vec<Partial> = vec![];
+    let mut next = value;
+    loop {
+        let mut copied = match next {
+            Value::Array(items) => {
+                stack.push(Partial::Array(
+                    Vec::with_capacity(items.len()),
+                    items.iter(),
+                ));
+                None
+            }
+            Value::Object(members) => {
+                stack.push(Partial::Object(
+                    Vec::with_capacity(members.len()),
+                    members.iter(),
+                    None,
+                ));
+                None
+            }
+            scalar => Some(scalar.clone()),
+        };
+        // Hand the copy to its container, until one has members left to copy.
+        loop {
+            let container = match stack.last_mut() {
+                Some(container) => container,
+                None => return copied.unwrap_or_default(),
+            };
+            match container {
+                Partial::Array(items, rest) => {
+                    items.extend(copied.take());
+                    if let Some(item) = rest.next() {
+                        next = item;
+                        break;
+                    }
+                }
+                Partial::Object(members, rest, key) => {
+                    if let (Some(copied), Some(key)) = (copied.take(), key.take()) {
+                        members.push((key.clone(), copied));
+                    }
+                    if let Some((name, member)) = rest.next() {
+                        *key = Some(name);
+                        next = member;
+                        break;
+                    }
+                }
+            }
+            copied = match stack.pop() {
+                Some(Partial::Array(items, _)) => Some(Value::Array(items)),
+                // The keys come in order, so the map is built at once rather than key by key.
+                Some(Partial::Object(members, _, _)) => {
+                    Some(Value::Object(members.into_iter().collect()))
+                }
+                None => None,
+            };
+        }
+    }
+}
+
+/// Drop `value`, emptying its containers before dropping them.
+pub(crate) fn drop(value: Value) {
+    let mut stack = vec![value];
+    while let Some(value) = stack.pop() {
+        match value {
+            Value::Array(items) => stack.extend(items),
+            Value::Object(members) => stack.extend(members.into_iter().map(|(_, member)| member)),
+            _ => {}
+        }
+    }
+}
+
+/// A shared document, dropped with [`drop`] when the last share of it goes.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedDocument(pub(crate) Arc<Value>);
+
+impl Drop for SharedDocument {
+    fn drop(&mut self) {
+        let document = mem::replace(&mut self.0, Arc::new(Value::Null));
+        if let Ok(document) = Arc::try_unwrap(document) {
+            drop(document);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+
+    /// `{"a": [{"a": [... "leaf" ...]}]}`, `depth` objects deep.
+    fn nested(depth: usize) -> Value {
+        let mut value = json!("leaf");
+        for _ in 0..depth {
+            // Not with `json!`, which serializes the values it is given, recursively.
+            let mut object = Map::new();
+            object.insert("a".to_owned(), Value::Array(vec![value]));
+            value = Value::Object(object);
+        }
+        value
+    }
+
+    #[test]
+    fn deep_values_are_copied_and_dropped() {
+        let value = nested(100_000);
+        let copy = super::clone(&value);
+        let mut at = &copy;
+        for _ in 0..100_000 {
+            at = &at["a"][0];
+        }
+        assert_eq!(*at, "leaf");
+        super::drop(copy);
+        super::drop(value);
+
+        let small = json!({"a": [1, {"b": null}], "c": "d"});
+        assert_eq!(super::clone(&small), small);
+    }
+}
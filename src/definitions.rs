@@ -0,0 +1,399 @@
+//! What becomes of the `definitions` and `$defs` of the documents an inlining deref walks.
+
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::bundle::definition_name;
+use crate::{Error, JsonRef, Result, Scope, DEFINITIONS_KEYWORDS};
+
+/// Where the definitions of the documents walked while inlining end up, set with
+/// [`JsonRef::set_definitions_mode`]. It is the same whichever of `deref_value`,
+/// `deref_file`, `deref_url` and the others is used.
+///
+/// Schemas moved into the root's definitions by [`RecursionStrategy::Definitions`] or an
+/// `x-jsonref` bundle directive are always kept, as refs point to them, and
+/// [`ResolutionMode::Bundle`] always keeps definitions in place, for the same reason.
+///
+/// [`RecursionStrategy::Definitions`]: crate::RecursionStrategy::Definitions
+/// [`ResolutionMode::Bundle`]: crate::ResolutionMode::Bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DefinitionsMode {
+    /// Move the definitions of the root and of every schema inlined into the root's, under
+    /// the keyword they were found under. What becomes of two different ones of the same
+    /// name is up to the [`DefinitionCollisions`] policy.
+    #[default]
+    Accumulate,
+    /// Leave definitions where they are, dereferenced like any other subschema, so the
+    /// root keeps its own and inlined schemas bring theirs along.
+    KeepInPlace,
+    /// Leave definitions out of the output, which has every ref into them inlined already.
+    Drop,
+}
+
+/// What is done when definitions of the same name, from different documents or schemas,
+/// differ, as they are accumulated by [`DefinitionsMode::Accumulate`]; set with
+/// [`JsonRef::set_definition_collisions`]. Equal ones are kept once, whatever the policy.
+///
+/// Of two that differ, the one of the root document ranks first, then the one whose
+/// document URL and pointer sort first, so the output does not depend on the order the
+/// documents are loaded in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DefinitionCollisions {
+    /// Keep the one that ranks first, and leave the others out.
+    #[default]
+    KeepFirst,
+    /// Fail the deref with [`Error::DefinitionCollision`].
+    Fail,
+    /// Keep the one that ranks first under the name, and each other under the name prefixed
+    /// with its document's file name, e.g. `other_json_Address`. The local refs from that
+    /// document left in the output, in its definitions or kept in place, are rewritten to
+    /// point there.
+    Namespace,
+}
+
+/// Definitions collected under another name, by the document and pointer of the schema
+/// they were found in, keyword and name.
+pub(crate) type Renames = IndexMap<(String, String, &'static str, String), String>;
+
+impl JsonRef {
+    /// Choose where the definitions of the documents walked end up when inlining.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{DefinitionsMode, JsonRef};
+    /// use serde_json::json;
+    ///
+    /// let mut input = json!({
+    ///     "properties": {"id": {"$ref": "#/definitions/id"}},
+    ///     "definitions": {"id": {"type": "integer"}}
+    /// });
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_definitions_mode(DefinitionsMode::Drop);
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(input, json!({"properties": {"id": {"type": "integer"}}}));
+    /// ```
+    pub fn set_definitions_mode(&mut self, mode: DefinitionsMode) {
+        self.definitions_mode = mode;
+    }
+
+    /// Choose what is done when definitions of the same name collected from different
+    /// places differ.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{DefinitionCollisions, JsonRef};
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref
+    ///     .add_schema(
+    ///         "https://example.com/other.json",
+    ///         json!({"definitions": {"Address": {"type": "string"}}}),
+    ///     )
+    ///     .unwrap();
+    /// jsonref.set_definition_collisions(DefinitionCollisions::Namespace);
+    /// let mut input = json!({
+    ///     "properties": {"home": {"$ref": "https://example.com/other.json"}},
+    ///     "definitions": {"Address": {"type": "object"}}
+    /// });
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(
+    ///     input["definitions"],
+    ///     json!({"Address": {"type": "object"}, "other_json_Address": {"type": "string"}})
+    /// );
+    /// ```
+    pub fn set_definition_collisions(&mut self, collisions: DefinitionCollisions) {
+        self.definition_collisions = collisions;
+    }
+
+    /// Collect `definition`, found as `name` under `keyword` in the schema at `scope`, into
+    /// `collected`, what the root has under `keyword` so far.
+    pub(crate) fn collect_definition(
+        &mut self,
+        collected: &mut Map<String, Value>,
+        keyword: &'static str,
+        name: String,
+        definition: Value,
+        scope: &Scope,
+    ) -> Result<()> {
+        let rank = self.hoist_rank(scope);
+        let taken = match self.hoisted.get(&(keyword, name.clone())) {
+            Some(taken) => taken.clone(),
+            None => {
+                self.hoisted.insert((keyword, name.clone()), rank);
+                collected.insert(name, definition);
+                return Ok(());
+            }
+        };
+        let renamed = (rank.1.clone(), rank.2.clone(), keyword, name.clone());
+        if taken == rank || self.renamed_definitions.contains_key(&renamed) {
+            // The same schema reached again.
+            return Ok(());
+        }
+        if collected.get(&name) == Some(&definition) {
+            if rank < taken {
+                self.hoisted.insert((keyword, name), rank);
+            }
+            return Ok(());
+        }
+        match self.definition_collisions {
+            DefinitionCollisions::KeepFirst => {
+                if rank < taken {
+                    self.hoisted.insert((keyword, name.clone()), rank);
+                    collected.insert(name, definition);
+                }
+            }
+            DefinitionCollisions::Fail => {
+                let (first, second) = if rank < taken {
+                    (rank, taken)
+                } else {
+                    (taken, rank)
+                };
+                return Err(Error::DefinitionCollision {
+                    keyword: keyword.to_owned(),
+                    name,
+                    first: format!("{}#{}", first.1, first.2),
+                    second: format!("{}#{}", second.1, second.2),
+                });
+            }
+            DefinitionCollisions::Namespace => {
+                let (moved, moved_rank) = if rank < taken {
+                    self.hoisted.insert((keyword, name.clone()), rank);
+                    let displaced = collected.insert(name.clone(), definition);
+                    (displaced.unwrap_or_default(), taken)
+                } else {
+                    (definition, rank)
+                };
+                let new_name = namespaced(collected, &moved_rank.1, &name);
+                self.renamed_definitions.insert(
+                    (moved_rank.1.clone(), moved_rank.2.clone(), keyword, name),
+                    new_name.clone(),
+                );
+                self.hoisted.insert((keyword, new_name.clone()), moved_rank);
+                collected.insert(new_name, moved);
+            }
+        }
+        Ok(())
+    }
+
+    /// Point the local refs of the output, `value` and the `definitions` going into its root,
+    /// to the new names of the definitions of their document that were renamed.
+    pub(crate) fn rename_definition_refs(&self, value: &mut Value, definitions: &mut Value) {
+        if self.renamed_definitions.is_empty() {
+            return;
+        }
+        for (pointer, document, reference) in &self.kept_local_refs {
+            // Bundled targets are kept under their definition's pointer in the output.
+            let holder = match value.pointer_mut(pointer) {
+                Some(holder) => Some(holder),
+                None => definitions.pointer_mut(pointer),
+            };
+            let Some(Value::Object(obj)) = holder else {
+                continue;
+            };
+            match obj.get_mut("$ref") {
+                Some(Value::String(kept)) if kept == reference => {
+                    if let Some(renamed) = self.renamed_ref(document, reference) {
+                        *kept = renamed;
+                    }
+                }
+                _ => {}
+            }
+        }
+        let Value::Object(collected) = definitions else {
+            return;
+        };
+        for (keyword, defs) in collected.iter_mut() {
+            let (Some(keyword), Value::Object(defs)) = (
+                DEFINITIONS_KEYWORDS.iter().find(|known| **known == keyword),
+                defs,
+            ) else {
+                continue;
+            };
+            for (name, definition) in defs.iter_mut() {
+                let Some((_, document, _)) = self.hoisted.get(&(*keyword, name.clone())) else {
+                    continue;
+                };
+                let mut stack = vec![definition];
+                while let Some(value) = stack.pop() {
+                    match value {
+                        Value::Object(obj) => {
+                            if let Some(Value::String(reference)) = obj.get_mut("$ref") {
+                                if let Some(renamed) = self.renamed_ref(document, reference) {
+                                    *reference = renamed;
+                                }
+                            }
+                            stack.extend(obj.values_mut());
+                        }
+                        Value::Array(items) => stack.extend(items.iter_mut()),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    /// `reference`, a ref in `document`, pointing to the new name of the definition it
+    /// points to, if that was renamed.
+    fn renamed_ref(&self, document: &str, reference: &str) -> Option<String> {
+        let mut segments = reference.strip_prefix("#/")?.splitn(3, '/');
+        let keyword = segments.next()?;
+        let name = segments.next()?.replace("~1", "/").replace("~0", "~");
+        let (_, renamed) = self
+            .renamed_definitions
+            .iter()
+            .find(|((doc, _, kw, old), _)| doc == document && *kw == keyword && *old == name)?;
+        let renamed = renamed.replace('~', "~0").replace('/', "~1");
+        Some(match segments.next() {
+            Some(rest) => format!("#/{}/{}/{}", keyword, renamed, rest),
+            None => format!("#/{}/{}", keyword, renamed),
+        })
+    }
+}
+
+/// `name` prefixed with the file name of `document`, made unique among `collected`.
+fn namespaced(collected: &Map<String, Value>, document: &str, name: &str) -> String {
+    let prefix =
+        Url::parse(document).map_or_else(|_| "schema".to_owned(), |url| definition_name(&url));
+    let candidate = format!("{}_{}", prefix, name);
+    if !collected.contains_key(&candidate) {
+        return candidate;
+    }
+    (2..)
+        .map(|i| format!("{}_{}", candidate, i))
+        .find(|numbered| !collected.contains_key(numbered))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        DefinitionCollisions, DefinitionsMode, Error, JsonRef, RecursionStrategy, SiblingStrategy,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn definitions_follow_the_mode_for_every_entry_point() {
+        let deref_file = |mode| {
+            let mut jsonref = JsonRef::new();
+            jsonref.set_definitions_mode(mode);
+            jsonref
+                .deref_file("fixtures/definitions/base.json")
+                .unwrap()
+        };
+        let accumulated = deref_file(DefinitionsMode::Accumulate);
+        assert_eq!(
+            accumulated["definitions"],
+            json!({"def1": {"title": "def1"}, "def2": {"title": "def2"}})
+        );
+        assert!(accumulated["properties"]["prop3"]
+            .get("definitions")
+            .is_none());
+
+        let kept = deref_file(DefinitionsMode::KeepInPlace);
+        assert_eq!(kept["definitions"], json!({"def1": {"title": "def1"}}));
+        assert_eq!(
+            kept["properties"]["prop3"]["definitions"],
+            json!({"def2": {"title": "def2"}})
+        );
+        assert_eq!(
+            kept["properties"]["prop3"]["properties"]["prop3"],
+            json!({"title": "def2"})
+        );
+
+        let dropped = deref_file(DefinitionsMode::Drop);
+        assert!(dropped.get("definitions").is_none());
+        assert!(dropped["properties"]["prop3"].get("definitions").is_none());
+        assert_eq!(
+            dropped["properties"]["prop3"]["properties"]["prop3"],
+            json!({"title": "def2"})
+        );
+
+        // Values are handled as files are, and definitions refs point into are kept.
+        let mut jsonref = JsonRef::new();
+        jsonref.set_definitions_mode(DefinitionsMode::Drop);
+        jsonref.set_recursion_strategy(RecursionStrategy::Definitions);
+        let mut tree = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "items": {"$ref": "#/definitions/node"},
+            "definitions": {"node": {"items": {"$ref": "#/definitions/node"}}, "unused": {}}
+        });
+        jsonref.deref_value(&mut tree).unwrap();
+        assert_eq!(
+            tree["definitions"],
+            json!({"anon_json_node": {"items": {"$ref": "#/definitions/anon_json_node"}}})
+        );
+    }
+
+    #[test]
+    fn colliding_definitions_follow_the_policy() {
+        let deref = |collisions, siblings| {
+            let mut jsonref = JsonRef::new();
+            jsonref.set_sibling_strategy(siblings);
+            for (name, title) in [("a", "street"), ("b", "postal")] {
+                jsonref
+                    .add_schema(
+                        &format!("https://example.com/{}.json", name),
+                        json!({
+                            "properties": {
+                                "home": {"$ref": "#/definitions/Address"},
+                                "kept": {"$ref": "#/definitions/Home", "x-jsonref": "keep"}
+                            },
+                            "definitions": {
+                                "Address": {"title": title},
+                                "Home": {"title": name, "items": {"$ref": "#/definitions/Address"}},
+                                "Id": {"type": "integer"}
+                            }
+                        }),
+                    )
+                    .unwrap();
+            }
+            jsonref.set_definition_collisions(collisions);
+            let mut input = json!({"properties": {
+                "b": {"$ref": "https://example.com/b.json", "description": "b"},
+                "a": {"$ref": "https://example.com/a.json"}
+            }});
+            jsonref.deref_value(&mut input).map(|()| input)
+        };
+
+        let kept = deref(DefinitionCollisions::KeepFirst, SiblingStrategy::Discard).unwrap();
+        assert_eq!(kept["definitions"]["Address"], json!({"title": "street"}));
+        assert_eq!(kept["definitions"].as_object().unwrap().len(), 3);
+
+        let error = deref(DefinitionCollisions::Fail, SiblingStrategy::Discard).unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            Error::DefinitionCollision { name, first, second, .. } if name == "Address"
+                && first == "https://example.com/a.json#"
+                && second == "https://example.com/b.json#"
+        ));
+
+        let namespaced = deref(DefinitionCollisions::Namespace, SiblingStrategy::Discard).unwrap();
+        assert_eq!(
+            namespaced["definitions"],
+            json!({
+                "Address": {"title": "street"},
+                "Home": {"title": "a", "items": {"$ref": "#/definitions/Address"}},
+                "Id": {"type": "integer"},
+                "b_json_Address": {"title": "postal"},
+                "b_json_Home": {"title": "b", "items": {"$ref": "#/definitions/b_json_Address"}}
+            })
+        );
+        assert_eq!(
+            namespaced["properties"]["b"]["properties"]["home"],
+            json!({"title": "postal"})
+        );
+        // Refs left in place outside the definitions follow them too.
+        let kept =
+            |name: &str| namespaced["properties"][name]["properties"]["kept"]["$ref"].clone();
+        assert_eq!(kept("a"), "#/definitions/Home");
+        assert_eq!(kept("b"), "#/definitions/b_json_Home");
+
+        // Also when the target is wrapped in an allOf with the siblings of its ref.
+        let wrapped = deref(DefinitionCollisions::Namespace, SiblingStrategy::AllOf).unwrap();
+        assert_eq!(
+            wrapped["properties"]["b"]["allOf"][0]["properties"]["kept"]["$ref"],
+            "#/definitions/b_json_Home"
+        );
+    }
+}
@@ -0,0 +1,307 @@
+//! Semantic diff between two (dereferenced) schemas.
+
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::explain::push_pointer;
+
+/// Keywords whose value is a map of names to subschemas.
+const SCHEMA_MAPS: &[&str] = &[
+    "properties",
+    "patternProperties",
+    "definitions",
+    "$defs",
+    "dependentSchemas",
+];
+
+/// Keywords whose value is a single subschema.
+const SCHEMAS: &[&str] = &[
+    "additionalProperties",
+    "additionalItems",
+    "unevaluatedProperties",
+    "unevaluatedItems",
+    "items",
+    "contains",
+    "propertyNames",
+    "not",
+    "if",
+    "then",
+    "else",
+    "contentSchema",
+];
+
+/// Keywords whose value is a list of subschemas.
+const SCHEMA_LISTS: &[&str] = &["allOf", "anyOf", "oneOf", "prefixItems", "items"];
+
+/// What changed at a location.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    /// A property was added to `properties`.
+    PropertyAdded { schema: Value },
+    /// A property was removed from `properties`.
+    PropertyRemoved { schema: Value },
+    /// A name was added to `required`.
+    RequiredAdded { name: String },
+    /// A name was removed from `required`.
+    RequiredRemoved { name: String },
+    /// A keyword or subschema that only exists in the new schema.
+    Added { value: Value },
+    /// A keyword or subschema that only exists in the old schema.
+    Removed { value: Value },
+    /// A keyword that has a different value in the new schema.
+    Changed { old: Value, new: Value },
+}
+
+/// A single difference, located by the JSON pointer of the keyword or property it affects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub pointer: String,
+    pub kind: ChangeKind,
+}
+
+impl Change {
+    /// The last segment of the pointer, e.g. the keyword or property name.
+    pub fn name(&self) -> String {
+        self.pointer
+            .rsplit('/')
+            .next()
+            .unwrap_or("")
+            .replace("~1", "/")
+            .replace("~0", "~")
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ChangeKind::PropertyAdded { .. } => {
+                write!(f, "+ {}: property `{}` added", self.pointer, self.name())
+            }
+            ChangeKind::PropertyRemoved { .. } => {
+                write!(f, "- {}: property `{}` removed", self.pointer, self.name())
+            }
+            ChangeKind::RequiredAdded { name } => {
+                write!(f, "+ {}: `{}` is now required", self.pointer, name)
+            }
+            ChangeKind::RequiredRemoved { name } => {
+                write!(f, "- {}: `{}` is no longer required", self.pointer, name)
+            }
+            ChangeKind::Added { value } => write!(f, "+ {}: {}", self.pointer, value),
+            ChangeKind::Removed { value } => write!(f, "- {}: {}", self.pointer, value),
+            ChangeKind::Changed { old, new } => {
+                write!(f, "~ {}: {} -> {}", self.pointer, old, new)
+            }
+        }
+    }
+}
+
+/// Compare two schemas keyword by keyword, descending into subschemas.
+///
+/// Both schemas are expected to be dereferenced already; `$ref`s are compared as plain strings.
+///
+/// ```
+/// use polywrap_jsonref::diff::{diff, ChangeKind};
+/// use serde_json::json;
+///
+/// let old = json!({"properties": {"name": {"type": "string"}, "age": {"type": "integer"}}});
+/// let new = json!({"properties": {"name": {"type": "string", "maxLength": 10}},
+///                  "required": ["name"]});
+///
+/// let changes = diff(&old, &new);
+/// assert_eq!(changes.len(), 3);
+/// let removed = changes.iter().find(|c| c.pointer == "/properties/age").unwrap();
+/// assert!(matches!(removed.kind, ChangeKind::PropertyRemoved { .. }));
+/// ```
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = vec![];
+    diff_schema(old, new, "", &mut changes);
+    changes
+}
+
+fn diff_schema(old: &Value, new: &Value, pointer: &str, changes: &mut Vec<Change>) {
+    let (old_obj, new_obj) = match (old.as_object(), new.as_object()) {
+        (Some(old_obj), Some(new_obj)) => (old_obj, new_obj),
+        _ => {
+            if old != new {
+                changes.push(Change {
+                    pointer: pointer.to_owned(),
+                    kind: ChangeKind::Changed {
+                        old: old.clone(),
+                        new: new.clone(),
+                    },
+                });
+            }
+            return;
+        }
+    };
+
+    for keyword in keywords(old_obj, new_obj) {
+        let keyword_pointer = push_pointer(pointer, keyword);
+        match (old_obj.get(keyword), new_obj.get(keyword)) {
+            (Some(old_value), None) => changes.push(Change {
+                pointer: keyword_pointer,
+                kind: ChangeKind::Removed {
+                    value: old_value.clone(),
+                },
+            }),
+            (None, Some(new_value)) => changes.push(Change {
+                pointer: keyword_pointer,
+                kind: ChangeKind::Added {
+                    value: new_value.clone(),
+                },
+            }),
+            (Some(old_value), Some(new_value)) => {
+                diff_keyword(keyword, old_value, new_value, &keyword_pointer, changes)
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+fn diff_keyword(keyword: &str, old: &Value, new: &Value, pointer: &str, changes: &mut Vec<Change>) {
+    if old == new {
+        return;
+    }
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) if SCHEMA_MAPS.contains(&keyword) => {
+            for name in keywords(old_map, new_map) {
+                let entry_pointer = push_pointer(pointer, name);
+                let is_property = keyword == "properties";
+                match (old_map.get(name), new_map.get(name)) {
+                    (Some(schema), None) => changes.push(Change {
+                        pointer: entry_pointer,
+                        kind: if is_property {
+                            ChangeKind::PropertyRemoved {
+                                schema: schema.clone(),
+                            }
+                        } else {
+                            ChangeKind::Removed {
+                                value: schema.clone(),
+                            }
+                        },
+                    }),
+                    (None, Some(schema)) => changes.push(Change {
+                        pointer: entry_pointer,
+                        kind: if is_property {
+                            ChangeKind::PropertyAdded {
+                                schema: schema.clone(),
+                            }
+                        } else {
+                            ChangeKind::Added {
+                                value: schema.clone(),
+                            }
+                        },
+                    }),
+                    (Some(old_schema), Some(new_schema)) => {
+                        diff_schema(old_schema, new_schema, &entry_pointer, changes)
+                    }
+                    (None, None) => {}
+                }
+            }
+        }
+        (Value::Object(_), Value::Object(_)) if SCHEMAS.contains(&keyword) => {
+            diff_schema(old, new, pointer, changes)
+        }
+        (Value::Array(old_list), Value::Array(new_list))
+            if SCHEMA_LISTS.contains(&keyword) && old_list.len() == new_list.len() =>
+        {
+            for (i, (old_schema, new_schema)) in old_list.iter().zip(new_list).enumerate() {
+                diff_schema(
+                    old_schema,
+                    new_schema,
+                    &push_pointer(pointer, &i.to_string()),
+                    changes,
+                )
+            }
+        }
+        (Value::Array(old_list), Value::Array(new_list)) if keyword == "required" => {
+            for name in old_list.iter().filter(|name| !new_list.contains(name)) {
+                changes.push(Change {
+                    pointer: pointer.to_owned(),
+                    kind: ChangeKind::RequiredRemoved {
+                        name: name.as_str().unwrap_or_default().to_owned(),
+                    },
+                })
+            }
+            for name in new_list.iter().filter(|name| !old_list.contains(name)) {
+                changes.push(Change {
+                    pointer: pointer.to_owned(),
+                    kind: ChangeKind::RequiredAdded {
+                        name: name.as_str().unwrap_or_default().to_owned(),
+                    },
+                })
+            }
+        }
+        _ => changes.push(Change {
+            pointer: pointer.to_owned(),
+            kind: ChangeKind::Changed {
+                old: old.clone(),
+                new: new.clone(),
+            },
+        }),
+    }
+}
+
+/// Keys of both objects, old keys first, each key once.
+fn keywords<'a>(old: &'a Map<String, Value>, new: &'a Map<String, Value>) -> Vec<&'a str> {
+    let mut keys: Vec<&str> = old.keys().map(String::as_str).collect();
+    keys.extend(
+        new.keys()
+            .map(String::as_str)
+            .filter(|key| !old.contains_key(*key)),
+    );
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff, ChangeKind};
+    use serde_json::json;
+
+    #[test]
+    fn diff_nested_constraints() {
+        let old = json!({
+            "required": ["a", "b"],
+            "properties": {
+                "a": {"type": "string"},
+                "b": {"items": {"type": "integer", "minimum": 0}},
+                "c": {"allOf": [{"title": "c"}]}
+            }
+        });
+        let new = json!({
+            "required": ["a", "c"],
+            "properties": {
+                "a": {"type": ["string", "null"]},
+                "b": {"items": {"type": "integer"}},
+                "c": {"allOf": [{"title": "renamed"}]}
+            }
+        });
+
+        // Keywords are compared in the order of the keys, which `preserve_order` changes.
+        let mut changes: Vec<String> = diff(&old, &new).iter().map(|c| c.to_string()).collect();
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                "+ /required: `c` is now required",
+                "- /properties/b/items/minimum: 0",
+                "- /required: `b` is no longer required",
+                "~ /properties/a/type: \"string\" -> [\"string\",\"null\"]",
+                "~ /properties/c/allOf/0/title: \"c\" -> \"renamed\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_identical_is_empty() {
+        let schema = json!({"properties": {"a": {"type": "string"}}});
+        assert!(diff(&schema, &schema).is_empty());
+
+        let changes = diff(&schema, &json!({"properties": {}}));
+        assert_eq!(changes[0].name(), "a");
+        assert!(matches!(
+            changes[0].kind,
+            ChangeKind::PropertyRemoved { .. }
+        ));
+    }
+}
@@ -0,0 +1,33 @@
+//! `x-jsonref` directives, letting a schema choose how its refs are resolved.
+
+use serde_json::Value;
+
+/// Keyword next to a `$ref` that overrides the resolution mode for that ref only.
+pub(crate) const DIRECTIVE_KEYWORD: &str = "x-jsonref";
+
+/// Keyword that, when `true`, leaves the object holding it and everything below it as
+/// written, e.g. for example schemas embedded in documentation.
+pub(crate) const STOP_KEYWORD: &str = "x-jsonref-stop";
+
+/// How a `$ref` with an `x-jsonref` sibling is resolved, whatever the instance is set to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Directive {
+    /// `"inline"`: replace the ref with its target, as in `ResolutionMode::Inline`.
+    Inline,
+    /// `"bundle"`: copy the target into the root's definitions, as in `ResolutionMode::Bundle`.
+    Bundle,
+    /// `"keep"`: leave the `$ref` and the directive as written.
+    Keep,
+}
+
+impl Directive {
+    /// The directive `value` names, or `None` if it is not one.
+    pub(crate) fn from_value(value: &Value) -> Option<Directive> {
+        match value.as_str()? {
+            "inline" => Some(Directive::Inline),
+            "bundle" => Some(Directive::Bundle),
+            "keep" => Some(Directive::Keep),
+            _ => None,
+        }
+    }
+}
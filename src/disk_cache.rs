@@ -0,0 +1,168 @@
+//! Fetched documents kept on disk between runs, revalidated with `ETag` and `Last-Modified`.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::JsonRef;
+
+/// How the disk tier is used, set with [`JsonRef::set_disk_cache_policy`].
+///
+/// By default every copy on disk is revalidated with the server before it is used, which
+/// saves downloading it but not the round trip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct DiskPolicy {
+    /// How long after it was stored or last revalidated a copy is used without asking the
+    /// server. With it, documents served without an `ETag` or `Last-Modified` are kept too,
+    /// until then.
+    pub fresh_for: Option<Duration>,
+    /// Whether a copy that is no longer fresh is used anyway when the server can not be
+    /// reached or answers with a server error (5xx or `429`, once retries are used up),
+    /// rather than failing the deref. A [`Warning::StaleCopy`](crate::Warning::StaleCopy)
+    /// says when one was. With it, documents served without an `ETag` or `Last-Modified`
+    /// are kept too, for use then.
+    #[serde(default)]
+    pub stale_if_error: bool,
+}
+
+/// What is known about a cached response, kept next to its body.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    url: String,
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+    /// When the body was stored or last revalidated, in seconds since the Unix epoch.
+    #[serde(default)]
+    stored_at: Option<u64>,
+}
+
+impl Entry {
+    /// Whether the body may be used without asking the server, if it is current for
+    /// `fresh_for`.
+    pub(crate) fn is_fresh(&self, fresh_for: Option<Duration>) -> bool {
+        match (fresh_for, self.stored_at) {
+            (Some(fresh_for), Some(stored_at)) => {
+                now_secs().saturating_sub(stored_at) < fresh_for.as_secs()
+            }
+            _ => false,
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// A directory of fetched documents, one body and one [`Entry`] file per URL.
+#[derive(Debug, Clone)]
+pub(crate) struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    /// The entry and body cached for `url`, if both can be read and are for `url`.
+    pub(crate) fn lookup(&self, url: &str) -> Option<(Entry, Vec<u8>)> {
+        let (body, meta) = self.paths(url);
+        let entry: Entry = serde_json::from_slice(&fs::read(meta).ok()?).ok()?;
+        if entry.url != url {
+            return None;
+        }
+        Some((entry, fs::read(body).ok()?))
+    }
+
+    /// Cache `body` as the response for `url`, even without validators if `keep`. A cache
+    /// that can not be written is skipped: the document is simply fetched in full next time.
+    pub(crate) fn store(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+        body: &[u8],
+        keep: bool,
+    ) {
+        if etag.is_none() && last_modified.is_none() && !keep {
+            // Nothing to revalidate with.
+            return;
+        }
+        let entry = Entry {
+            url: url.to_owned(),
+            etag: etag.map(str::to_owned),
+            last_modified: last_modified.map(str::to_owned),
+            stored_at: Some(now_secs()),
+        };
+        let (body_path, meta_path) = self.paths(url);
+        let _ = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(body_path, body))
+            .and_then(|_| fs::write(meta_path, serde_json::to_vec(&entry)?));
+    }
+
+    /// Record that the server said the body cached for `url` is still current.
+    pub(crate) fn renew(&self, url: &str, mut entry: Entry) {
+        entry.stored_at = Some(now_secs());
+        if let Ok(meta) = serde_json::to_vec(&entry) {
+            let _ = fs::write(self.paths(url).1, meta);
+        }
+    }
+
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let name = format!("{:016x}", fnv1a(url.as_bytes()));
+        (
+            self.dir.join(format!("{}.body", name)),
+            self.dir.join(format!("{}.json", name)),
+        )
+    }
+}
+
+/// 64 bit FNV-1a, which unlike `DefaultHasher` gives the same file names on every build.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+impl JsonRef {
+    /// Keep fetched documents in the directory `dir`, created if missing, and revalidate them
+    /// on later runs with `If-None-Match` and `If-Modified-Since` instead of downloading
+    /// them again. Documents served without an `ETag` or `Last-Modified` are not kept.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_cache_dir(std::env::temp_dir().join("jsonref-schemas"));
+    /// ```
+    pub fn set_cache_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.disk_cache = Some(DiskCache { dir: dir.into() });
+    }
+
+    /// Choose how the directory of [`JsonRef::set_cache_dir`] is used.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{DiskPolicy, JsonRef};
+    /// use std::time::Duration;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_cache_dir(std::env::temp_dir().join("jsonref-schemas"));
+    /// // Ask the servers at most once an hour, and keep going while they are down.
+    /// jsonref.set_disk_cache_policy(DiskPolicy {
+    ///     fresh_for: Some(Duration::from_secs(3600)),
+    ///     stale_if_error: true,
+    /// });
+    /// ```
+    pub fn set_disk_cache_policy(&mut self, policy: DiskPolicy) {
+        self.disk_policy = policy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fnv1a;
+
+    #[test]
+    fn file_names_are_stable() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
@@ -0,0 +1,83 @@
+//! JSON Schema drafts and the parts of dereferencing that differ between them.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A JSON Schema draft, usually detected from a document's `$schema`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Draft {
+    Draft4,
+    Draft6,
+    Draft7,
+    Draft201909,
+    Draft202012,
+}
+
+impl Draft {
+    /// The draft a `$schema` URI refers to, if it is one of the known meta-schemas.
+    ///
+    /// ```
+    /// use polywrap_jsonref::Draft;
+    ///
+    /// assert_eq!(
+    ///     Draft::from_schema_uri("http://json-schema.org/draft-04/schema#"),
+    ///     Some(Draft::Draft4)
+    /// );
+    /// assert_eq!(
+    ///     Draft::from_schema_uri("https://json-schema.org/draft/2020-12/schema"),
+    ///     Some(Draft::Draft202012)
+    /// );
+    /// assert_eq!(Draft::from_schema_uri("https://example.com/custom"), None);
+    /// ```
+    pub fn from_schema_uri(uri: &str) -> Option<Draft> {
+        let uri = uri.trim_end_matches('#');
+        let path = uri
+            .strip_prefix("http://json-schema.org/")
+            .or_else(|| uri.strip_prefix("https://json-schema.org/"))?;
+        match path {
+            "draft-04/schema" => Some(Draft::Draft4),
+            "draft-06/schema" => Some(Draft::Draft6),
+            "draft-07/schema" => Some(Draft::Draft7),
+            "draft/2019-09/schema" => Some(Draft::Draft201909),
+            "draft/2020-12/schema" => Some(Draft::Draft202012),
+            _ => None,
+        }
+    }
+
+    /// Keyword that changes the base URI of a schema: `id` in draft-04, `$id` afterwards.
+    pub fn id_keyword(self) -> &'static str {
+        match self {
+            Draft::Draft4 => "id",
+            _ => "$id",
+        }
+    }
+
+    /// Whether keywords next to a `$ref` apply (2019-09 onwards) or are ignored.
+    pub fn siblings_apply(self) -> bool {
+        self >= Draft::Draft201909
+    }
+
+    /// Keywords holding reusable subschemas.
+    ///
+    /// 2019-09 introduced `$defs`, but `definitions` is still reserved for compatibility.
+    pub fn definitions_keywords(self) -> &'static [&'static str] {
+        if self >= Draft::Draft201909 {
+            &["definitions", "$defs"]
+        } else {
+            &["definitions"]
+        }
+    }
+}
+
+impl fmt::Display for Draft {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Draft::Draft4 => "draft-04",
+            Draft::Draft6 => "draft-06",
+            Draft::Draft7 => "draft-07",
+            Draft::Draft201909 => "2019-09",
+            Draft::Draft202012 => "2020-12",
+        };
+        f.write_str(name)
+    }
+}
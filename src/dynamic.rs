@@ -0,0 +1,260 @@
+//! `$dynamicRef` and `$recursiveRef`, resolved against the `$dynamicAnchor`s and
+//! `$recursiveAnchor`s of the documents a deref went through.
+
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::recursion::RefChain;
+use crate::{shift_remove, Draft, JsonRef, Scope};
+
+pub(crate) const DYNAMIC_REF_KEYWORD: &str = "$dynamicRef";
+pub(crate) const RECURSIVE_REF_KEYWORD: &str = "$recursiveRef";
+
+impl JsonRef {
+    /// Whether `$dynamicRef`s, and the `$recursiveRef`s of 2019-09, are resolved, as `$ref`s
+    /// are, recursions included. On by default; when off they are left as written, and walked
+    /// no further.
+    ///
+    /// A `$dynamicRef` to `#name` resolves to the outermost document of the refs being
+    /// expanded that declares `name` with a `$dynamicAnchor`, so that a generic schema like
+    /// a tree of nodes can be extended by the schema referring to it. This only happens when
+    /// the subschema it resolves to on its own declares the same `$dynamicAnchor`; otherwise,
+    /// or when no document further out declares it, it resolves like a `$ref`, e.g. to an
+    /// `$anchor`. Each document, not each schema resource within it, counts as one scope.
+    /// A `$dynamicRef` written in a draft before 2020-12 means nothing and is left alone.
+    ///
+    /// A `$recursiveRef`, always to `#`, resolves in the same way, to the outermost document
+    /// whose root has `"$recursiveAnchor": true`, when the document it is in has one too;
+    /// otherwise to the root of the document it is in. It means nothing outside 2019-09.
+    ///
+    /// The output of a resolved `$dynamicRef` is what it resolved to at that point; when
+    /// bundled, or moved into definitions as a recursion, it becomes a plain `$ref` there.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.add_schema("https://example.com/list.json", json!({
+    ///     "$dynamicAnchor": "item",
+    ///     "type": "array",
+    ///     "items": {"$dynamicRef": "#item"}
+    /// })).unwrap();
+    /// let mut names = json!({
+    ///     "$id": "https://example.com/names.json",
+    ///     "$ref": "list.json",
+    ///     "$defs": {"name": {"$dynamicAnchor": "item", "type": "string"}}
+    /// });
+    /// jsonref.deref_value(&mut names).unwrap();
+    /// assert_eq!(names["items"], json!({"$dynamicAnchor": "item", "type": "string"}));
+    /// ```
+    pub fn set_expand_dynamic_refs(&mut self, expand: bool) {
+        self.expand_dynamic_refs = expand;
+    }
+
+    /// Take the `$dynamicRef` or `$recursiveRef` out of the schema `obj`, at `scope`, with
+    /// its keyword, if it is to be resolved.
+    pub(crate) fn take_dynamic_ref(
+        &self,
+        obj: &mut Map<String, Value>,
+        scope: &Scope,
+    ) -> Option<(&'static str, Value)> {
+        if !self.expand_dynamic_refs {
+            return None;
+        }
+        let keyword = match scope.draft {
+            None if obj.contains_key(DYNAMIC_REF_KEYWORD) => DYNAMIC_REF_KEYWORD,
+            None => RECURSIVE_REF_KEYWORD,
+            Some(Draft::Draft202012) => DYNAMIC_REF_KEYWORD,
+            Some(Draft::Draft201909) => RECURSIVE_REF_KEYWORD,
+            Some(_) => return None,
+        };
+        if obj.get(keyword)?.is_string() {
+            Some((keyword, shift_remove(obj, keyword)?))
+        } else {
+            None
+        }
+    }
+
+    /// Where the `$dynamicRef` to `ref_url`, already joined with its base, leads while the
+    /// refs `used_refs` are expanded: see [`JsonRef::set_expand_dynamic_refs`].
+    pub(crate) fn dynamic_target(&mut self, ref_url: Url, used_refs: &RefChain) -> Url {
+        let name = match ref_url.fragment() {
+            Some(name) if !name.is_empty() && !name.starts_with('/') => name.to_owned(),
+            _ => return ref_url,
+        };
+        let mut lexical = ref_url.clone();
+        lexical.set_fragment(None);
+        match self.document(&lexical) {
+            Ok(document) if self.declares_dynamic_anchor(&document, &name) => {}
+            _ => return ref_url,
+        }
+        for mut url in self.dynamic_scope(used_refs) {
+            let declares = match self.document(&url) {
+                Ok(document) => self.declares_dynamic_anchor(&document, &name),
+                Err(_) => false,
+            };
+            if declares {
+                url.set_fragment(Some(&name));
+                return url;
+            }
+        }
+        ref_url
+    }
+
+    /// Where the `$recursiveRef` to `ref_url`, already joined with its base, leads while the
+    /// refs `used_refs` are expanded: see [`JsonRef::set_expand_dynamic_refs`].
+    pub(crate) fn recursive_target(&mut self, ref_url: Url, used_refs: &RefChain) -> Url {
+        let mut lexical = ref_url.clone();
+        lexical.set_fragment(None);
+        if !self.has_recursive_anchor(&lexical) {
+            return ref_url;
+        }
+        for mut url in self.dynamic_scope(used_refs) {
+            if self.has_recursive_anchor(&url) {
+                url.set_fragment(Some(""));
+                return url;
+            }
+        }
+        ref_url
+    }
+
+    /// Whether the root of the document at `url` has `"$recursiveAnchor": true`.
+    fn has_recursive_anchor(&mut self, url: &Url) -> bool {
+        self.document(url)
+            .is_ok_and(|document| document.get("$recursiveAnchor") == Some(&Value::Bool(true)))
+    }
+
+    /// The URLs of the documents the refs `used_refs` were expanded from and to, outermost
+    /// first, starting with the root.
+    fn dynamic_scope(&self, used_refs: &RefChain) -> Vec<Url> {
+        std::iter::once(self.root_document.as_str())
+            .chain(used_refs.as_slice().iter().map(|used| {
+                used.split_once('#')
+                    .map_or(used.as_str(), |(document, _)| document)
+            }))
+            .filter_map(|document| Url::parse(document).ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{JsonRef, RecursionStrategy};
+    use serde_json::json;
+
+    fn trees() -> JsonRef {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_recursion_strategy(RecursionStrategy::KeepRef);
+        jsonref
+            .add_schema(
+                "https://example.com/tree.json",
+                json!({
+                    "$dynamicAnchor": "node",
+                    "type": "object",
+                    "properties": {
+                        "children": {"type": "array", "items": {"$dynamicRef": "#node"}},
+                        "label": {"$dynamicRef": "#label"}
+                    },
+                    "$defs": {"label": {"$anchor": "label", "type": "string"}}
+                }),
+            )
+            .unwrap();
+        jsonref
+    }
+
+    #[test]
+    fn dynamic_refs_resolve_to_the_outermost_anchor() {
+        let mut jsonref = trees();
+        let mut strict = json!({
+            "$id": "https://example.com/strict-tree.json",
+            "$dynamicAnchor": "node",
+            "$ref": "tree.json",
+            "unevaluatedProperties": false
+        });
+        jsonref.deref_value(&mut strict).unwrap();
+        // The children are strict trees too, the recursion into which is kept.
+        assert_eq!(
+            strict["properties"]["children"]["items"],
+            json!({
+                "$id": "https://example.com/strict-tree.json",
+                "$dynamicAnchor": "node",
+                "$ref": "tree.json",
+                "unevaluatedProperties": false
+            })
+        );
+        // `#label` is a plain `$anchor`, resolved like a `$ref`.
+        assert_eq!(strict["properties"]["label"]["type"], "string");
+
+        // Referred to on its own, the tree's own anchor is the outermost.
+        let mut tree = json!({"$ref": "https://example.com/tree.json"});
+        jsonref.deref_value(&mut tree).unwrap();
+        let children = &tree["properties"]["children"]["items"];
+        assert_eq!(children["type"], "object");
+        assert_eq!(
+            children["properties"]["children"]["items"],
+            json!({"$dynamicRef": "#node"})
+        );
+    }
+
+    #[test]
+    fn dynamic_refs_can_be_left_alone() {
+        let mut jsonref = trees();
+        jsonref.set_expand_dynamic_refs(false);
+        let mut tree = json!({"$ref": "https://example.com/tree.json"});
+        jsonref.deref_value(&mut tree).unwrap();
+        assert_eq!(
+            tree["properties"]["label"],
+            json!({"$dynamicRef": "#label"})
+        );
+        assert!(jsonref.warnings().is_empty());
+    }
+
+    #[test]
+    fn recursive_refs_resolve_to_the_outermost_recursive_anchor() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/tree.json",
+                json!({
+                    "$schema": "https://json-schema.org/draft/2019-09/schema",
+                    "$recursiveAnchor": true,
+                    "type": "object",
+                    "properties": {
+                        "children": {"type": "array", "items": {"$recursiveRef": "#"}}
+                    }
+                }),
+            )
+            .unwrap();
+        let mut strict = json!({
+            "$schema": "https://json-schema.org/draft/2019-09/schema",
+            "$id": "https://example.com/strict-tree.json",
+            "$recursiveAnchor": true,
+            "$ref": "tree.json",
+            "unevaluatedProperties": false
+        });
+        jsonref.deref_value(&mut strict).unwrap();
+        // The children are strict trees, whose `$ref` back to the tree is a recursion,
+        // truncated by the default strategy. From 2019-09 the keywords next to a `$ref`
+        // apply too, so the tree is one of the `allOf` of the strict tree.
+        assert_eq!(
+            strict["allOf"][0]["properties"]["children"]["items"],
+            json!({
+                "$schema": "https://json-schema.org/draft/2019-09/schema",
+                "$id": "https://example.com/strict-tree.json",
+                "$recursiveAnchor": true,
+                "unevaluatedProperties": false
+            })
+        );
+
+        // Without an anchor of its own, the root does not take over the recursion.
+        let mut loose = json!({
+            "$schema": "https://json-schema.org/draft/2019-09/schema",
+            "$ref": "https://example.com/tree.json"
+        });
+        jsonref.deref_value(&mut loose).unwrap();
+        let children = &loose["allOf"][0]["properties"]["children"]["items"];
+        assert_eq!(children["type"], "object");
+        assert!(children.get("unevaluatedProperties").is_none());
+    }
+}
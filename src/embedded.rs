@@ -0,0 +1,162 @@
+//! Schema resources embedded in a document under `$id`s of their own, e.g. the entries of a
+//! bundle, which refs can reach by those `$id`s without loading anything.
+
+use serde_json::Value;
+use std::sync::Arc;
+use url::Url;
+
+use crate::{canonical, deep, Draft, JsonRef, DATA_KEYWORDS};
+
+impl JsonRef {
+    /// The schema resources embedded in `document`, loaded from `url` (cache key `key`), each
+    /// by the cache key of its `$id`, as documents of their own. Resources within resources
+    /// are listed too; the subschemas copied keep them.
+    pub(crate) fn embedded_resources(
+        &self,
+        url: &Url,
+        key: &str,
+        document: &Value,
+    ) -> Vec<(String, Arc<Value>)> {
+        let draft = self.document_draft(document, None);
+        collect_resources(document, url, draft)
+            .into_iter()
+            .filter_map(|(mut url, resource)| {
+                self.query.apply(&mut url);
+                let resource_key = canonical::cache_key(&url);
+                (resource_key != key).then(|| (resource_key, Arc::new(deep::clone(resource))))
+            })
+            .collect()
+    }
+
+    /// Cache the resources embedded in `document`, cached under `key` for `url`, by their
+    /// `$id`s, but for those that name another document already, which are warned about.
+    pub(crate) fn cache_resources(&mut self, url: &Url, key: &str, document: &Value) {
+        for (resource_key, resource) in self.embedded_resources(url, key, document) {
+            if !self.check_id_conflict(url, &resource_key, &resource) {
+                self.schema_cache.put(resource_key, resource);
+            }
+        }
+    }
+
+    /// Drop what is cached under the `$id`s of the resources embedded in `document`, cached
+    /// under `key` for `url`, where it is those resources.
+    pub(crate) fn forget_resources(&mut self, url: &Url, key: &str, document: &Value) {
+        for (resource_key, resource) in self.embedded_resources(url, key, document) {
+            if self.schema_cache.get(&resource_key) == Some(resource) {
+                self.schema_cache.remove(&resource_key);
+            }
+        }
+    }
+}
+
+/// The subschemas of `document`, read as `draft`, that declare an `$id` of their own (`id`
+/// in draft-04), with the URL it resolves to against `base`, in document order. The `$id` of
+/// the document itself only changes the base of what is within it.
+///
+/// Walked with a stack rather than recursively, as documents may be of any depth.
+fn collect_resources<'a>(
+    document: &'a Value,
+    base: &Url,
+    draft: Option<Draft>,
+) -> Vec<(Url, &'a Value)> {
+    let mut found = vec![];
+    let mut stack = vec![(document, Arc::new(base.clone()), draft)];
+    while let Some((value, base, draft)) = stack.pop() {
+        let obj = match value {
+            Value::Object(obj) => obj,
+            Value::Array(items) => {
+                let items = items.iter().rev();
+                stack.extend(items.map(|item| (item, base.clone(), draft)));
+                continue;
+            }
+            _ => continue,
+        };
+        let draft = obj
+            .get("$schema")
+            .and_then(Value::as_str)
+            .and_then(Draft::from_schema_uri)
+            .or(draft);
+        let id_keyword = draft.map_or("$id", Draft::id_keyword);
+        let mut base = base;
+        if let Some(id) = obj.get(id_keyword).and_then(Value::as_str) {
+            match base.join(id) {
+                Ok(mut url) if !id.starts_with('#') => {
+                    url.set_fragment(None);
+                    if !std::ptr::eq(value, document) {
+                        found.push((url.clone(), value));
+                    }
+                    base = Arc::new(url);
+                }
+                _ => {}
+            }
+        }
+        let children = obj
+            .iter()
+            .rev()
+            .filter(|(key, _)| !DATA_KEYWORDS.contains(&key.as_str()));
+        stack.extend(children.map(|(_, child)| (child, base.clone(), draft)));
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::JsonRef;
+    use serde_json::json;
+    use url::Url;
+
+    #[test]
+    fn refs_reach_embedded_resources_by_their_id() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolver(Box::new(|url: &Url| -> Result<_, _> {
+            Err(format!("{} was loaded", url).into())
+        }));
+        let mut input = json!({
+            "$id": "https://example.com/root.json",
+            "properties": {
+                "inner": {"$ref": "https://example.com/inner"},
+                "street": {"$ref": "address.json#/properties/street"},
+                "nested": {"$ref": "https://example.com/schemas/nested.json#/title"}
+            },
+            "$defs": {
+                "inner": {"$id": "https://example.com/inner", "type": "integer"},
+                "address": {
+                    "$id": "address.json",
+                    "properties": {"street": {"$ref": "#/$defs/street"}},
+                    "$defs": {
+                        "street": {"type": "string"},
+                        "nested": {"$id": "schemas/nested.json", "title": "nested"}
+                    }
+                }
+            },
+            "examples": [{"$id": "https://example.com/data", "type": "null"}]
+        });
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input["properties"]["inner"]["type"], "integer");
+        assert_eq!(input["properties"]["street"], json!({"type": "string"}));
+        assert_eq!(input["properties"]["nested"], "nested");
+        let step = &jsonref.explain("/properties/street").steps[0];
+        assert_eq!(step.document, "https://example.com/address.json");
+
+        // Resources of the root are kept out of the cache, as the root is.
+        let mut data = json!({"$ref": "https://example.com/inner"});
+        assert!(jsonref.deref_value(&mut data).is_err());
+    }
+
+    #[test]
+    fn resources_of_loaded_documents_are_cached() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/bundle.json",
+                json!({"$defs": {
+                    "pet": {"$id": "https://example.com/pet.json", "title": "pet"}
+                }}),
+            )
+            .unwrap();
+        let mut input = json!({"items": {"$ref": "https://example.com/pet.json"}});
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input["items"]["title"], "pet");
+        assert!(jsonref.loaded_documents().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Errors as structured data, for services that return resolution errors from their APIs.
+
+use serde::{Serialize, Serializer};
+use serde_json::{json, Map, Value};
+
+use crate::Error;
+
+impl Error {
+    /// A stable, snake_case name for the kind of error, the `kind` of its serialized form.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::SchemaFromFile { .. } => "schema_from_file",
+            Error::HostNotAllowed { .. } => "host_not_allowed",
+            #[cfg(feature = "network")]
+            Error::InvalidProxy { .. } => "invalid_proxy",
+            #[cfg(feature = "network")]
+            Error::SchemaFromUrl { .. } => "schema_from_url",
+            Error::HttpStatus { .. } => "http_status",
+            Error::UrlParseError { .. } => "url_parse_error",
+            Error::SchemaNotJson { .. } | Error::SchemaNotJsonSerde { .. } => "schema_not_json",
+            Error::JsonPointerNotFound { .. } => "json_pointer_not_found",
+            Error::JSONRefError { .. } => "json_ref_error",
+            Error::ResolverFailed { .. } => "resolver_failed",
+            Error::RemoteFetchDisabled { .. } => "remote_fetch_disabled",
+            Error::WriteOutput { .. } => "write_output",
+            Error::RefFailed { .. } => "ref_failed",
+            Error::DocumentBudgetExceeded { .. } => "document_budget_exceeded",
+            Error::UnsupportedScheme { .. } => "unsupported_scheme",
+            Error::InvalidDataUrl { .. } => "invalid_data_url",
+            Error::InvalidDirective { .. } => "invalid_directive",
+            Error::InvalidSnapshot { .. } => "invalid_snapshot",
+            Error::WriteRepro { .. } => "write_repro",
+            Error::InvalidRepro { .. } => "invalid_repro",
+            Error::TooManyFetches { .. } => "too_many_fetches",
+            Error::TenantQuotaExceeded { .. } => "tenant_quota_exceeded",
+            Error::DocumentTooLarge { .. } => "document_too_large",
+            Error::ExpansionTooLarge { .. } => "expansion_too_large",
+            Error::DeadlineExceeded { .. } => "deadline_exceeded",
+            Error::RecursiveRef { .. } => "recursive_ref",
+            Error::DefinitionCollision { .. } => "definition_collision",
+            #[cfg(feature = "signing")]
+            Error::InvalidSigningKey { .. } => "invalid_signing_key",
+            #[cfg(feature = "signing")]
+            Error::InvalidSignature { .. } => "invalid_signature",
+            #[cfg(feature = "yaml")]
+            Error::SchemaNotYaml { .. } => "schema_not_yaml",
+            #[cfg(feature = "yaml")]
+            Error::YamlOutput { .. } => "yaml_output",
+        }
+    }
+
+    /// The fields of the variant, by their names in the enum. Errors from other crates are
+    /// only kept as their text, in `cause`; a nested [`Error`] is serialized in full as
+    /// `source`. Durations are given in milliseconds, with `_ms` added to the name.
+    fn fields(&self) -> Map<String, Value> {
+        let fields = match self {
+            Error::SchemaFromFile { filename, source } => {
+                json!({"filename": filename, "cause": source.to_string()})
+            }
+            Error::HostNotAllowed { url, host } => json!({"url": url, "host": host}),
+            #[cfg(feature = "network")]
+            Error::InvalidProxy { proxy, source } => {
+                json!({"proxy": proxy, "cause": source.to_string()})
+            }
+            #[cfg(feature = "network")]
+            Error::SchemaFromUrl { url, source } => {
+                json!({"url": url, "cause": source.to_string()})
+            }
+            Error::HttpStatus {
+                url,
+                status,
+                reason,
+                body,
+            } => json!({"url": url, "status": status, "reason": reason, "body": body}),
+            Error::UrlParseError { url, source } => {
+                json!({"url": url, "cause": source.to_string()})
+            }
+            Error::SchemaNotJson { url, source } => {
+                json!({"url": url, "cause": source.to_string()})
+            }
+            Error::SchemaNotJsonSerde { url, source } => {
+                json!({"url": url, "cause": source.to_string()})
+            }
+            Error::JsonPointerNotFound { pointer } => json!({ "pointer": pointer }),
+            Error::JSONRefError { source } => json!({"cause": source.to_string()}),
+            Error::ResolverFailed { url, source } => {
+                json!({"url": url, "cause": source.to_string()})
+            }
+            Error::RemoteFetchDisabled { url } => json!({ "url": url }),
+            Error::WriteOutput { source } => json!({"cause": source.to_string()}),
+            Error::RefFailed {
+                reference,
+                document,
+                pointer,
+                chain,
+                source,
+            } => json!({
+                "reference": reference,
+                "document": document,
+                "pointer": pointer,
+                "chain": chain,
+                "source": source.to_json(),
+            }),
+            Error::DocumentBudgetExceeded { document, limit } => {
+                json!({"document": document, "limit": limit})
+            }
+            Error::UnsupportedScheme { scheme, reference } => {
+                json!({"scheme": scheme, "reference": reference})
+            }
+            Error::InvalidDataUrl { reference, reason } => {
+                json!({"reference": reference, "reason": reason})
+            }
+            Error::InvalidDirective { value, pointer } => {
+                json!({"value": value, "pointer": pointer})
+            }
+            Error::InvalidSnapshot { reason } => json!({ "reason": reason }),
+            Error::WriteRepro { path, source } => {
+                json!({"path": path, "cause": source.to_string()})
+            }
+            Error::InvalidRepro { reason } => json!({ "reason": reason }),
+            Error::TooManyFetches { url, limit } => json!({"url": url, "limit": limit}),
+            Error::TenantQuotaExceeded {
+                tenant,
+                url,
+                quota,
+                limit,
+            } => json!({"tenant": tenant, "url": url, "quota": quota, "limit": limit}),
+            Error::DocumentTooLarge { url, limit } => json!({"url": url, "limit": limit}),
+            Error::ExpansionTooLarge { limit } => json!({ "limit": limit }),
+            Error::DeadlineExceeded { limit } => {
+                json!({"limit_ms": u64::try_from(limit.as_millis()).unwrap_or(u64::MAX)})
+            }
+            Error::RecursiveRef { reference, pointer } => {
+                json!({"reference": reference, "pointer": pointer})
+            }
+            Error::DefinitionCollision {
+                keyword,
+                name,
+                first,
+                second,
+            } => json!({"keyword": keyword, "name": name, "first": first, "second": second}),
+            #[cfg(feature = "signing")]
+            Error::InvalidSigningKey { reason } | Error::InvalidSignature { reason } => {
+                json!({ "reason": reason })
+            }
+            #[cfg(feature = "yaml")]
+            Error::SchemaNotYaml { url, source } => {
+                json!({"url": url, "cause": source.to_string()})
+            }
+            #[cfg(feature = "yaml")]
+            Error::YamlOutput { source } => json!({"cause": source.to_string()}),
+        };
+        match fields {
+            Value::Object(fields) => fields,
+            _ => unreachable!("every variant's fields are an object"),
+        }
+    }
+
+    /// The error as a JSON object: its `kind`, its `message` (the `Display` text) and the
+    /// fields of the variant.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut schema = json!({"items": {"$ref": "#/definitions/missing"}});
+    /// let error = JsonRef::new().deref_value(&mut schema).unwrap_err();
+    /// let error = error.to_json();
+    /// assert_eq!(error["kind"], "ref_failed");
+    /// assert_eq!(error["pointer"], "/items");
+    /// assert_eq!(error["source"]["kind"], "json_pointer_not_found");
+    /// ```
+    pub fn to_json(&self) -> Value {
+        let mut object = Map::new();
+        object.insert("kind".to_owned(), Value::from(self.kind()));
+        object.insert("message".to_owned(), Value::from(self.to_string()));
+        object.extend(self.fields());
+        Value::Object(object)
+    }
+}
+
+/// Serializes as [`Error::to_json`].
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Error;
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn errors_serialize_with_their_fields() {
+        let error = Error::HttpStatus {
+            url: "https://example.com/a.json".to_owned(),
+            status: 404,
+            reason: "Not Found".to_owned(),
+            body: String::new(),
+        };
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            json!({
+                "kind": "http_status",
+                "message": "fetching https://example.com/a.json failed with HTTP 404 Not Found",
+                "url": "https://example.com/a.json",
+                "status": 404,
+                "reason": "Not Found",
+                "body": "",
+            })
+        );
+        let error = Error::DeadlineExceeded {
+            limit: Duration::from_secs(2),
+        };
+        assert_eq!(error.to_json()["limit_ms"], 2000);
+        let error = Error::UrlParseError {
+            url: "::".to_owned(),
+            source: url::ParseError::RelativeUrlWithoutBase,
+        };
+        assert_eq!(error.to_json()["cause"], "relative URL without a base");
+    }
+}
@@ -0,0 +1,287 @@
+//! Trace of how the value at an output pointer was produced.
+
+use std::fmt;
+
+use crate::JsonRef;
+
+/// Something the dereferencer did while replacing a `$ref`, beyond plain inlining.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    /// The ref was already being resolved further up, so recursion stopped here and only the
+    /// sibling keywords of the `$ref` were kept.
+    CycleTruncated,
+    /// The ref was already being resolved further up, so it was left in place.
+    CycleKept,
+    /// The ref was already being resolved further up, so it was replaced with a stub.
+    CycleStubbed,
+    /// The ref is recursive, so its target was moved into the root's definitions and the
+    /// `$ref` rewritten to point there.
+    CycleDefinition { local_ref: String },
+    /// The sibling keywords of the `$ref` were stored under the reference key.
+    SiblingsStored { key: String },
+    /// The sibling keywords of the `$ref` were discarded.
+    SiblingsDiscarded,
+    /// The target and the sibling keywords of the `$ref` were combined in an `allOf`.
+    SiblingsAllOf,
+    /// The sibling keywords of the `$ref` were merged over its target.
+    SiblingsMerged,
+    /// The `$ref` was rewritten to point within the root document instead of being inlined.
+    Bundled { local_ref: String },
+    /// The target could not be resolved, so the `$ref` was left in place.
+    Unresolved { error: String },
+    /// An `"x-jsonref": "keep"` next to the `$ref` left it in place.
+    KeptByDirective,
+    /// The [`RefSelection`](crate::RefSelection) does not take in refs like this one, so it
+    /// was left in place.
+    KeptBySelection,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::CycleTruncated => write!(f, "cycle truncated"),
+            Rule::CycleKept => write!(f, "cycle kept as $ref"),
+            Rule::CycleStubbed => write!(f, "cycle replaced with stub"),
+            Rule::CycleDefinition { local_ref } => write!(f, "cycle moved to `{}`", local_ref),
+            Rule::SiblingsStored { key } => write!(f, "siblings stored under `{}`", key),
+            Rule::SiblingsDiscarded => write!(f, "siblings discarded"),
+            Rule::SiblingsAllOf => write!(f, "siblings combined with allOf"),
+            Rule::SiblingsMerged => write!(f, "siblings merged over target"),
+            Rule::Bundled { local_ref } => write!(f, "bundled as `{}`", local_ref),
+            Rule::Unresolved { error } => write!(f, "left unresolved: {}", error),
+            Rule::KeptByDirective => write!(f, "kept by x-jsonref"),
+            Rule::KeptBySelection => write!(f, "kept by ref selection"),
+        }
+    }
+}
+
+/// One `$ref` that was followed on the way to an output value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolutionStep {
+    /// JSON pointer in the output where the `$ref` was replaced.
+    pub pointer: String,
+    /// The `$ref` exactly as written in the schema.
+    pub reference: String,
+    /// Base URL the `$ref` was resolved against.
+    pub base: String,
+    /// Absolute URL of the ref, including its fragment.
+    pub url: String,
+    /// URL of the document the ref points into.
+    pub document: String,
+    /// Fragment of the ref within `document`, if any.
+    pub fragment: Option<String>,
+    /// Rules applied when replacing the ref.
+    pub rules: Vec<Rule>,
+}
+
+impl fmt::Display for ResolutionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{}: `{}` -> {}", pointer, self.reference, self.document)?;
+        if let Some(fragment) = &self.fragment {
+            write!(f, " at `{}`", fragment)?;
+        }
+        for rule in &self.rules {
+            write!(f, " [{}]", rule)?;
+        }
+        Ok(())
+    }
+}
+
+/// The chain of refs that produced the value at an output pointer, outermost first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub pointer: String,
+    pub steps: Vec<ResolutionStep>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.steps.is_empty() {
+            return write!(f, "{} was not produced by any $ref", self.pointer);
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}. {}", i + 1, step)?;
+        }
+        Ok(())
+    }
+}
+
+impl JsonRef {
+    /// Explain the value at `pointer` in the output of the last deref.
+    ///
+    /// Returns every `$ref` that was followed on the way to that value, outermost first.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut input = json!(
+    ///     {"properties": {"prop1": {"title": "name"},
+    ///                     "prop2": {"$ref": "#/properties/prop1"}}
+    ///     }
+    /// );
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.deref_value(&mut input).unwrap();
+    ///
+    /// let explanation = jsonref.explain("/properties/prop2/title");
+    /// assert_eq!(explanation.steps.len(), 1);
+    /// assert_eq!(explanation.steps[0].reference, "#/properties/prop1");
+    /// ```
+    pub fn explain(&self, pointer: &str) -> Explanation {
+        let mut steps: Vec<ResolutionStep> = self
+            .trace
+            .iter()
+            .filter(|step| pointer_starts_with(pointer, &step.pointer))
+            .cloned()
+            .collect();
+        // Steps are recorded when their ref is reached, before its target is walked. A shorter
+        // pointer is always further out, and at the same pointer the ref recorded first led to
+        // the other, so the stable sort keeps it first.
+        steps.sort_by_key(|step| step.pointer.len());
+        Explanation {
+            pointer: pointer.to_owned(),
+            steps,
+        }
+    }
+
+    /// Every `$ref` the last deref followed, in the order the walk reached them.
+    pub fn trace(&self) -> &[ResolutionStep] {
+        &self.trace
+    }
+}
+
+/// Whether `prefix` is `pointer` or one of its ancestors, comparing whole segments.
+pub(crate) fn pointer_starts_with(pointer: &str, prefix: &str) -> bool {
+    match pointer.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Append an object key to a JSON pointer, escaping it as in RFC 6901.
+pub(crate) fn push_pointer(pointer: &str, key: &str) -> String {
+    let mut pushed = String::with_capacity(pointer.len() + 1 + key.len());
+    pushed.push_str(pointer);
+    pushed.push('/');
+    if key.contains(['~', '/']) {
+        pushed.push_str(&key.replace('~', "~0").replace('/', "~1"));
+    } else {
+        pushed.push_str(key);
+    }
+    pushed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pointer_starts_with, push_pointer, Rule};
+    use crate::{JsonRef, ResolutionMode, SiblingStrategy};
+    use serde_json::json;
+
+    #[test]
+    fn pointer_prefixes_match_whole_segments() {
+        assert!(pointer_starts_with("/properties/a", ""));
+        assert!(pointer_starts_with("/properties/a", "/properties/a"));
+        assert!(pointer_starts_with("/properties/a/title", "/properties/a"));
+        assert!(!pointer_starts_with("/properties/ab", "/properties/a"));
+        assert_eq!(push_pointer("/paths", "/pets"), "/paths/~1pets");
+    }
+
+    #[test]
+    fn explain_nested_chain() {
+        let mut input = json!(
+            {"properties": {"prop1": {"$ref": "#/definitions/b"},
+                            "prop2": {"$ref": "#"}},
+             "definitions": {"b": {"properties": {"inner": {"$ref": "#/definitions/c"}}},
+                             "c": {"title": "c", "description": "c"}}}
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_reference_key("__reference__");
+        jsonref.deref_value(&mut input).unwrap();
+
+        let explanation = jsonref.explain("/properties/prop1/properties/inner/title");
+        let refs: Vec<&str> = explanation
+            .steps
+            .iter()
+            .map(|step| step.reference.as_str())
+            .collect();
+        assert_eq!(refs, vec!["#/definitions/b", "#/definitions/c"]);
+        assert_eq!(
+            explanation.steps[1].pointer,
+            "/properties/prop1/properties/inner"
+        );
+        assert_eq!(
+            explanation.steps[1].rules,
+            vec![Rule::SiblingsStored {
+                key: "__reference__".to_string()
+            }]
+        );
+
+        let recursion = jsonref.explain("/properties/prop2/properties/prop2");
+        assert_eq!(recursion.steps.len(), 2);
+        assert_eq!(recursion.steps[1].rules, vec![Rule::CycleTruncated]);
+
+        assert!(jsonref.explain("/title").steps.is_empty());
+    }
+
+    #[test]
+    fn explain_inside_targets_moved_in_the_output() {
+        let mut input = json!({
+            "properties": {"p": {"$ref": "#/$defs/a", "title": "p"}},
+            "$defs": {
+                "a": {"properties": {"x": {"$ref": "#/$defs/b"}}},
+                "b": {"type": "string"}
+            }
+        });
+        let mut jsonref = JsonRef::new();
+        jsonref.set_sibling_strategy(SiblingStrategy::AllOf);
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(
+            input["properties"]["p"]["allOf"][0]["properties"]["x"],
+            json!({"type": "string"})
+        );
+        let explanation = jsonref.explain("/properties/p/allOf/0/properties/x");
+        let steps: Vec<(&str, &str)> = explanation
+            .steps
+            .iter()
+            .map(|step| (step.pointer.as_str(), step.reference.as_str()))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                ("/properties/p", "#/$defs/a"),
+                ("/properties/p/allOf/0/properties/x", "#/$defs/b")
+            ]
+        );
+
+        // A bundled target is walked where it is stored.
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/a.json",
+                json!({"properties": {"x": {"$ref": "b.json"}}}),
+            )
+            .unwrap();
+        jsonref
+            .add_schema("https://example.com/b.json", json!({"type": "string"}))
+            .unwrap();
+        jsonref.set_resolution_mode(ResolutionMode::Bundle);
+        let mut input = json!({"properties": {"p": {"$ref": "https://example.com/a.json"}}});
+        jsonref.deref_value(&mut input).unwrap();
+        let pointer = "/$defs/a_json/properties/x";
+        assert_eq!(
+            input.pointer(pointer),
+            Some(&json!({"$ref": "#/$defs/b_json"}))
+        );
+        assert_eq!(jsonref.explain(pointer).steps[0].pointer, pointer);
+    }
+}
@@ -0,0 +1,228 @@
+//! Rewriting dereferenced schemas into the simpler forms fake data generators handle well.
+
+use serde_json::{json, Map, Value};
+
+use crate::shift_remove;
+
+/// Annotation next to a `oneOf` or `anyOf` giving the index of the alternative to keep.
+pub const CHOOSE_KEYWORD: &str = "x-faker-choose";
+
+/// Rewrite the dereferenced `schema` so that fake data generators produce valid data for it:
+///
+/// - `allOf` subschemas are merged into the schema that holds them;
+/// - a `oneOf` or `anyOf` is replaced by one alternative merged in: the one at the index
+///   given by an `x-faker-choose` next to it, or the first;
+/// - `enum` and `const` values that are local `$ref`s, which dereferencing leaves alone as
+///   they are data, are replaced by what they point to: the `const` or `enum` values of a
+///   schema, or the value itself;
+/// - a string with a well known `format` and neither `pattern` nor `examples` gets an example
+///   value of that format.
+///
+/// Merging keeps the `properties` of both schemas and the `required` names of either; for
+/// any other keyword in both, the merged-in subschema wins. The output describes a subset of
+/// the data the input does, which is all a generator needs.
+///
+/// ```
+/// use polywrap_jsonref::faker::normalize;
+/// use serde_json::json;
+///
+/// let mut schema = json!({
+///     "allOf": [{"properties": {"id": {"type": "integer"}}, "required": ["id"]}],
+///     "properties": {
+///         "contact": {"oneOf": [{"format": "email", "type": "string"}, {"type": "null"}]},
+///         "status": {"enum": [{"$ref": "#/$defs/open"}, "closed"]}
+///     },
+///     "$defs": {"open": {"const": "open"}}
+/// });
+/// normalize(&mut schema);
+/// assert_eq!(schema["required"], json!(["id"]));
+/// assert_eq!(schema["properties"]["contact"]["examples"], json!(["user@example.com"]));
+/// assert_eq!(schema["properties"]["status"]["enum"], json!(["open", "closed"]));
+/// ```
+pub fn normalize(schema: &mut Value) {
+    let root = schema.clone();
+    normalize_at(schema, &root);
+}
+
+fn normalize_at(schema: &mut Value, root: &Value) {
+    let obj = match schema {
+        Value::Object(obj) => obj,
+        Value::Array(items) => {
+            for item in items {
+                normalize_at(item, root);
+            }
+            return;
+        }
+        _ => return,
+    };
+
+    for keyword in ["enum", "const"] {
+        if let Some(values) = obj.get_mut(keyword) {
+            materialize(keyword, values, root);
+        }
+    }
+
+    if let Some(Value::Array(all)) = shift_remove(obj, "allOf") {
+        for subschema in all {
+            if let Value::Object(subschema) = subschema {
+                merge(obj, subschema);
+            }
+        }
+    }
+    let choice = shift_remove(obj, CHOOSE_KEYWORD)
+        .and_then(|choice| choice.as_u64())
+        .unwrap_or(0);
+    for keyword in ["oneOf", "anyOf"] {
+        if let Some(Value::Array(mut alternatives)) = shift_remove(obj, keyword) {
+            let index = (choice as usize).min(alternatives.len().saturating_sub(1));
+            if let Some(Value::Object(chosen)) = alternatives.get_mut(index).map(Value::take) {
+                merge(obj, chosen);
+            }
+        }
+    }
+    // What was merged in may hold more of the same.
+    if ["allOf", "oneOf", "anyOf"]
+        .iter()
+        .any(|keyword| obj.contains_key(*keyword))
+    {
+        return normalize_at(schema, root);
+    }
+
+    let is_string = obj.get("type").is_none_or(|types| types == "string");
+    if is_string && !obj.contains_key("pattern") && !obj.contains_key("examples") {
+        let example = obj
+            .get("format")
+            .and_then(Value::as_str)
+            .and_then(format_example);
+        if let Some(example) = example {
+            obj.insert("examples".to_owned(), json!([example]));
+        }
+    }
+
+    for value in obj.values_mut() {
+        normalize_at(value, root);
+    }
+}
+
+/// Replace the local `$ref`s among the values of the `enum` or `const` `values` by the values
+/// they point to in `root`.
+fn materialize(keyword: &str, values: &mut Value, root: &Value) {
+    if keyword == "const" {
+        if let Some(Some(target)) =
+            local_target(values, root).map(|target| target_values(target).into_iter().next())
+        {
+            *values = target;
+        }
+        return;
+    }
+    if let Value::Array(items) = values {
+        let mut materialized = Vec::with_capacity(items.len());
+        for item in items.drain(..) {
+            match local_target(&item, root) {
+                Some(target) => materialized.extend(target_values(target)),
+                None => materialized.push(item),
+            }
+        }
+        *items = materialized;
+    }
+}
+
+/// What `value` points to in `root`, if it is an object with only a local `$ref`.
+fn local_target<'a>(value: &Value, root: &'a Value) -> Option<&'a Value> {
+    let obj = value.as_object().filter(|obj| obj.len() == 1)?;
+    let pointer = obj.get("$ref")?.as_str()?.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+/// The values a `$ref` target stands for: those of its `const` or `enum` if it is a schema
+/// with one, else the target itself.
+fn target_values(target: &Value) -> Vec<Value> {
+    if let Some(value) = target.get("const") {
+        return vec![value.clone()];
+    }
+    match target.get("enum") {
+        Some(Value::Array(values)) => values.clone(),
+        _ => vec![target.clone()],
+    }
+}
+
+/// Merge the keywords of `from` into `into`.
+pub(crate) fn merge(into: &mut Map<String, Value>, from: Map<String, Value>) {
+    for (keyword, value) in from {
+        match (keyword.as_str(), into.get_mut(&keyword), value) {
+            ("properties", Some(Value::Object(properties)), Value::Object(more)) => {
+                properties.extend(more);
+            }
+            ("required", Some(Value::Array(required)), Value::Array(more)) => {
+                for name in more {
+                    if !required.contains(&name) {
+                        required.push(name);
+                    }
+                }
+            }
+            (_, _, value) => {
+                into.insert(keyword, value);
+            }
+        }
+    }
+}
+
+/// An example of a string in the `format` of that name, for the formats of the JSON Schema
+/// specification.
+fn format_example(format: &str) -> Option<&'static str> {
+    Some(match format {
+        "date-time" => "2024-01-01T12:00:00Z",
+        "date" => "2024-01-01",
+        "time" => "12:00:00Z",
+        "duration" => "P1D",
+        "email" | "idn-email" => "user@example.com",
+        "hostname" | "idn-hostname" => "example.com",
+        "ipv4" => "192.0.2.1",
+        "ipv6" => "2001:db8::1",
+        "uri" | "iri" => "https://example.com/",
+        "uri-reference" | "iri-reference" => "/path",
+        "uri-template" => "https://example.com/{id}",
+        "uuid" => "123e4567-e89b-12d3-a456-426614174000",
+        "json-pointer" => "/a/b",
+        "relative-json-pointer" => "0/a",
+        "regex" => "^[a-z]+$",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+    use serde_json::json;
+
+    #[test]
+    fn alternatives_are_chosen_and_merged() {
+        let mut schema = json!({
+            "type": "object",
+            "properties": {"kind": {"type": "string"}},
+            "required": ["kind"],
+            "x-faker-choose": 1,
+            "oneOf": [
+                {"properties": {"a": {"type": "integer"}}},
+                {"properties": {"b": {"allOf": [{"type": "string"}, {"format": "uuid"}]}},
+                 "required": ["b", "kind"]}
+            ]
+        });
+        normalize(&mut schema);
+        assert_eq!(
+            schema,
+            json!({
+                "type": "object",
+                "properties": {
+                    "kind": {"type": "string"},
+                    "b": {
+                        "type": "string",
+                        "format": "uuid",
+                        "examples": ["123e4567-e89b-12d3-a456-426614174000"]
+                    }
+                },
+                "required": ["kind", "b"]
+            })
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Conversions between file paths and `file://` URLs, whose spellings differ by platform: on
+//! Windows the path of `file:///C:/schemas/a.json` is `/C:/schemas/a.json`, which no file API
+//! opens, and canonical paths start with `\\?\`, which no URL does.
+
+use snafu::ResultExt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+use crate::{JSONRefError, Result};
+
+/// The `file://` URL of the file at `path`, made absolute with [`fs::canonicalize`].
+pub(crate) fn from_path(path: impl AsRef<Path>) -> Result<Url> {
+    let path = fs::canonicalize(path).context(JSONRefError {})?;
+    Url::from_file_path(&path).map_err(|()| not_absolute(&path))
+}
+
+/// The `file://` URL of `name` in the directory `dir`, which need not exist.
+pub(crate) fn in_directory(dir: &Path, name: &str) -> Result<Url> {
+    let dir = Url::from_directory_path(dir).map_err(|()| not_absolute(dir))?;
+    // `name` is a file name, which only fails to join when it is not a relative URL.
+    Ok(dir.join(name).unwrap_or(dir))
+}
+
+/// The file `url` names, for the file APIs of the platform. URLs that name no local file,
+/// e.g. those with a host on Unix, are read as a path of this platform as they are.
+pub(crate) fn to_path(url: &Url) -> PathBuf {
+    url.to_file_path()
+        .unwrap_or_else(|()| PathBuf::from(url.path()))
+}
+
+fn not_absolute(path: &Path) -> crate::Error {
+    crate::Error::JSONRefError {
+        source: io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not an absolute path", path.display()),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_path, in_directory, to_path};
+    use crate::JsonRef;
+    use std::{env, fs};
+
+    #[test]
+    fn relative_file_refs_resolve_in_directories_with_spaces() {
+        let dir = env::temp_dir().join(format!("jsonref file url {}", std::process::id()));
+        fs::create_dir_all(dir.join("common defs")).unwrap();
+        fs::write(
+            dir.join("root.json"),
+            r#"{"properties": {"id": {"$ref": "common%20defs/id.json"}}}"#,
+        )
+        .unwrap();
+        fs::write(dir.join("common defs/id.json"), r#"{"type": "integer"}"#).unwrap();
+
+        let root = from_path(dir.join("root.json")).unwrap();
+        assert!(root.as_str().contains("/jsonref%20file%20url%20"));
+        assert_eq!(
+            to_path(&root),
+            fs::canonicalize(dir.join("root.json")).unwrap()
+        );
+        let anon = in_directory(&dir, "anon.json").unwrap();
+        assert_eq!(to_path(&anon), dir.join("anon.json"));
+
+        let mut jsonref = JsonRef::new();
+        let value = jsonref
+            .deref_file(dir.join("root.json").to_str().unwrap())
+            .unwrap();
+        assert_eq!(value["properties"]["id"]["type"], "integer");
+        assert_eq!(jsonref.loaded_documents()[0], root.as_str());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_paths_map_to_drive_letter_urls() {
+        use std::path::Path;
+        use url::Url;
+
+        let url = Url::from_file_path(Path::new(r"\\?\C:\schemas\a b.json")).unwrap();
+        assert_eq!(url.as_str(), "file:///C:/schemas/a%20b.json");
+        assert_eq!(to_path(&url), Path::new(r"C:\schemas\a b.json"));
+        let relative = url.join("../defs/id.json").unwrap();
+        assert_eq!(to_path(&relative), Path::new(r"C:\defs\id.json"));
+        let anon = in_directory(Path::new(r"C:\work"), "anon.json").unwrap();
+        assert_eq!(anon.as_str(), "file:///C:/work/anon.json");
+
+        let share = Url::parse("file://server/share/a.json").unwrap();
+        assert_eq!(to_path(&share), Path::new(r"\\server\share\a.json"));
+    }
+}
@@ -0,0 +1,152 @@
+//! The documentation of a ref target, for editor hovers and docs tooltips.
+
+use serde_json::Value;
+use snafu::ResultExt;
+use url::Url;
+
+use crate::{Error, JsonRef, RefSite, Result, UrlParseError};
+
+/// How many `$ref`-only schemas are followed to find the documented one.
+const MAX_HOPS: usize = 16;
+
+/// The documentation keywords of a ref target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Hover {
+    /// URL of the schema the documentation was taken from, after following any `$ref`s that
+    /// the target consisted of.
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// `type` as written: a string or an array of strings.
+    pub type_: Option<Value>,
+    /// The first of `examples`, or else `example`.
+    pub example: Option<Value>,
+}
+
+impl JsonRef {
+    /// The title, description, type and example of the schema `site` points to.
+    ///
+    /// Only the documents on the way to the target are loaded; nothing is dereferenced. A
+    /// target that is just another `$ref` is followed.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{ref_sites, JsonRef};
+    /// use serde_json::json;
+    /// use url::Url;
+    ///
+    /// let schema = json!({"properties": {"name": {"$ref": "#/$defs/name"}},
+    ///                     "$defs": {"name": {"title": "Name", "type": "string",
+    ///                                        "examples": ["Rex"]}}});
+    /// let base = Url::parse("https://example.com/pet.json").unwrap();
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.add_schema(base.as_str(), schema.clone()).unwrap();
+    ///
+    /// let site = &ref_sites(&schema, &base)[0];
+    /// let hover = jsonref.hover(site).unwrap();
+    /// assert_eq!(hover.title.as_deref(), Some("Name"));
+    /// assert_eq!(hover.example, Some(json!("Rex")));
+    /// ```
+    pub fn hover(&mut self, site: &RefSite) -> Result<Hover> {
+        let mut url_string = site
+            .resolved_url
+            .clone()
+            .ok_or(Error::JsonPointerNotFound {
+                pointer: format!("ref `{}` could not be resolved", site.raw_ref),
+            })?;
+        for _ in 0..MAX_HOPS {
+            let url = Url::parse(&url_string).context(UrlParseError {
+                url: url_string.clone(),
+            })?;
+            let mut document_url = url.clone();
+            document_url.set_fragment(None);
+            self.query.apply(&mut document_url);
+            let document = self.document(&document_url)?;
+            let pointer =
+                self.fragment_pointer(&url_string, &document, url.fragment().unwrap_or(""))?;
+            let schema = document.pointer(&pointer).unwrap_or(&Value::Null);
+
+            let next = match schema.get("$ref").and_then(Value::as_str) {
+                Some(next) if !has_documentation(schema) => next,
+                _ => return Ok(Hover::from_schema(url_string, schema)),
+            };
+            url_string = url
+                .join(next)
+                .context(UrlParseError {
+                    url: next.to_owned(),
+                })?
+                .to_string();
+        }
+        Ok(Hover {
+            url: url_string,
+            ..Hover::default()
+        })
+    }
+}
+
+fn has_documentation(schema: &Value) -> bool {
+    ["title", "description", "type", "examples", "example"]
+        .iter()
+        .any(|keyword| schema.get(keyword).is_some())
+}
+
+impl Hover {
+    fn from_schema(url: String, schema: &Value) -> Hover {
+        let string = |keyword| {
+            schema
+                .get(keyword)
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+        };
+        let example = schema
+            .get("examples")
+            .and_then(Value::as_array)
+            .and_then(|examples| examples.first())
+            .or_else(|| schema.get("example"))
+            .cloned();
+        Hover {
+            url,
+            title: string("title"),
+            description: string("description"),
+            type_: schema.get("type").cloned(),
+            example,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ref_sites, JsonRef};
+    use serde_json::json;
+    use std::fs;
+    use url::Url;
+
+    #[test]
+    fn hover_follows_ref_only_targets() {
+        let path = fs::canonicalize("fixtures/nested_relative/base.json").unwrap();
+        let base = Url::from_file_path(path).unwrap();
+        let schema: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(base.to_file_path().unwrap()).unwrap())
+                .unwrap();
+        let sites = ref_sites(&schema, &base);
+        let prop5 = sites
+            .iter()
+            .find(|site| site.pointer == "/properties/prop5")
+            .unwrap();
+
+        let mut jsonref = JsonRef::new();
+        let hover = jsonref.hover(prop5).unwrap();
+        assert!(hover.url.ends_with("other.json#/properties/prop1"));
+        assert_eq!(
+            hover.title.as_deref(),
+            Some("sub property title in other.json")
+        );
+        assert_eq!(hover.type_, None);
+
+        let mut looping = JsonRef::new();
+        let schema = json!({"$defs": {"a": {"$ref": "#/$defs/a"}}, "items": {"$ref": "#/$defs/a"}});
+        let base = Url::parse("https://example.com/loop.json").unwrap();
+        looping.add_schema(base.as_str(), schema.clone()).unwrap();
+        let hover = looping.hover(&ref_sites(&schema, &base)[1]).unwrap();
+        assert_eq!(hover.title, None);
+    }
+}
@@ -0,0 +1,257 @@
+//! Index of the canonical URIs defined by the schemas in a directory tree.
+
+use serde::Serialize;
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+use crate::explain::push_pointer;
+use crate::{file_url, parse_document, Draft, Result, SchemaFromFile};
+
+/// What a URI in a [`UriIndex`] names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IndexKind {
+    /// A schema resource: a whole file (under both its `file://` URL and its `$id`), or a
+    /// subschema with its own `$id`.
+    Document,
+    /// A plain-name fragment from `$anchor`, or from an `$id` like `#foo` before 2019-09.
+    Anchor,
+    /// An entry of `definitions` or `$defs`.
+    Definition,
+}
+
+/// Where the schema a URI names lives.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IndexEntry {
+    pub file: PathBuf,
+    /// JSON pointer of the schema within `file`.
+    pub pointer: String,
+    pub kind: IndexKind,
+}
+
+/// Every canonical URI defined in a set of schema files, mapped to where it lives.
+///
+/// Serializes as a JSON object keyed by URI, for tools such as language servers or doc sites.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct UriIndex {
+    pub entries: BTreeMap<String, IndexEntry>,
+}
+
+impl UriIndex {
+    /// Index every `.json` file (and `.yaml`/`.yml` file with the `yaml` feature) under `dir`.
+    ///
+    /// Files are read in path order and the first file to define a URI wins. Directories
+    /// starting with `.` are skipped.
+    ///
+    /// ```
+    /// use polywrap_jsonref::UriIndex;
+    ///
+    /// let index = UriIndex::from_directory("fixtures/index").unwrap();
+    /// let entry = &index.entries["https://example.com/pet.json#/$defs/name"];
+    /// assert_eq!(entry.pointer, "/$defs/name");
+    /// ```
+    pub fn from_directory(dir: impl AsRef<Path>) -> Result<UriIndex> {
+        let mut files = vec![];
+        schema_files(dir.as_ref(), &mut files)?;
+        files.sort();
+        let mut index = UriIndex::default();
+        for file in files {
+            index.add_file(&file)?;
+        }
+        Ok(index)
+    }
+
+    /// Add the URIs defined in the schema at `path`.
+    pub fn add_file(&mut self, path: &Path) -> Result<()> {
+        let path_string = path.to_string_lossy().into_owned();
+        let file = fs::File::open(path).context(SchemaFromFile {
+            filename: path_string.clone(),
+        })?;
+        let value = parse_document(&path_string, &path_string, file)?;
+        let base = file_url::from_path(path)?;
+        self.insert(base.clone(), path, "", IndexKind::Document);
+
+        let resource = Resource {
+            base,
+            pointer: String::new(),
+            draft: None,
+        };
+        self.add_value(path, &value, &resource, "");
+        Ok(())
+    }
+
+    fn insert(&mut self, uri: Url, file: &Path, pointer: &str, kind: IndexKind) {
+        self.entries
+            .entry(uri.to_string())
+            .or_insert_with(|| IndexEntry {
+                file: file.to_path_buf(),
+                pointer: pointer.to_owned(),
+                kind,
+            });
+    }
+
+    fn add_value(&mut self, file: &Path, value: &Value, resource: &Resource, pointer: &str) {
+        let obj = match value {
+            Value::Object(obj) => obj,
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    let pointer = push_pointer(pointer, &i.to_string());
+                    self.add_value(file, item, resource, &pointer);
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        let mut resource = resource.clone();
+        resource.draft = obj
+            .get("$schema")
+            .and_then(Value::as_str)
+            .and_then(Draft::from_schema_uri)
+            .or(resource.draft);
+        let id_keyword = resource.draft.map_or("$id", Draft::id_keyword);
+        if let Some(id) = obj.get(id_keyword).and_then(Value::as_str) {
+            match resource.base.join(id) {
+                Ok(url) if id.starts_with('#') => {
+                    self.insert(url, file, pointer, IndexKind::Anchor)
+                }
+                Ok(url) => {
+                    resource.base = url;
+                    resource.base.set_fragment(None);
+                    resource.pointer = pointer.to_owned();
+                }
+                Err(_) => {}
+            }
+        }
+        if resource.pointer == pointer {
+            self.insert(resource.base.clone(), file, pointer, IndexKind::Document);
+        }
+        if let Some(anchor) = obj.get("$anchor").and_then(Value::as_str) {
+            let mut url = resource.base.clone();
+            url.set_fragment(Some(anchor));
+            self.insert(url, file, pointer, IndexKind::Anchor);
+        }
+
+        let keywords = resource
+            .draft
+            .map_or(crate::DEFINITIONS_KEYWORDS, Draft::definitions_keywords);
+        for (key, child) in obj {
+            let child_pointer = push_pointer(pointer, key);
+            if keywords.contains(&key.as_str()) {
+                if let Some(definitions) = child.as_object() {
+                    for name in definitions.keys() {
+                        let definition_pointer = push_pointer(&child_pointer, name);
+                        let mut url = resource.base.clone();
+                        url.set_fragment(Some(&definition_pointer[resource.pointer.len()..]));
+                        self.insert(url, file, &definition_pointer, IndexKind::Definition);
+                    }
+                }
+            }
+            self.add_value(file, child, &resource, &child_pointer);
+        }
+    }
+}
+
+/// The schema resource a value belongs to.
+#[derive(Debug, Clone)]
+struct Resource {
+    base: Url,
+    /// JSON pointer of the resource root within its file.
+    pointer: String,
+    draft: Option<Draft>,
+}
+
+pub(crate) fn schema_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let filename = dir.to_string_lossy().into_owned();
+    let entries = fs::read_dir(dir).context(SchemaFromFile {
+        filename: filename.clone(),
+    })?;
+    for entry in entries {
+        let path = entry
+            .context(SchemaFromFile {
+                filename: filename.clone(),
+            })?
+            .path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            if !name.starts_with('.') {
+                schema_files(&path, files)?;
+            }
+        } else if is_schema_file(&name) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_schema_file(name: &str) -> bool {
+    #[cfg(feature = "yaml")]
+    if crate::yaml::is_yaml_path(name) {
+        return true;
+    }
+    name.ends_with(".json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IndexKind, UriIndex};
+
+    #[test]
+    fn index_fixture() {
+        let index = UriIndex::from_directory("fixtures/index").unwrap();
+        let get = |uri: &str| {
+            let entry = &index.entries[uri];
+            (
+                entry.file.file_name().unwrap().to_str().unwrap().to_owned(),
+                entry.pointer.as_str(),
+                entry.kind,
+            )
+        };
+
+        assert_eq!(
+            get("https://example.com/pet.json"),
+            ("pet.json".to_owned(), "", IndexKind::Document)
+        );
+        assert_eq!(
+            get("https://example.com/pet.json#/$defs/name"),
+            ("pet.json".to_owned(), "/$defs/name", IndexKind::Definition)
+        );
+        assert_eq!(
+            get("https://example.com/pet.json#tag"),
+            ("pet.json".to_owned(), "/properties/tag", IndexKind::Anchor)
+        );
+        assert_eq!(
+            get("https://example.com/owner.json"),
+            (
+                "pet.json".to_owned(),
+                "/properties/owner",
+                IndexKind::Document
+            )
+        );
+        assert_eq!(
+            get("https://example.com/owner.json#/definitions/id"),
+            (
+                "pet.json".to_owned(),
+                "/properties/owner/definitions/id",
+                IndexKind::Definition
+            )
+        );
+        assert_eq!(
+            get("http://example.com/legacy.json#old"),
+            (
+                "legacy.json".to_owned(),
+                "/definitions/old",
+                IndexKind::Anchor
+            )
+        );
+        assert!(index
+            .entries
+            .keys()
+            .any(|uri| uri.starts_with("file://") && uri.ends_with("/index/plain.json")));
+    }
+}
@@ -0,0 +1,183 @@
+//! Canonical JSON ([RFC 8785](https://www.rfc-editor.org/rfc/rfc8785), JCS) output, so that
+//! a dereferenced schema serializes to the same bytes whatever produced it.
+
+use serde_json::{Map, Number, Value};
+
+/// Serialize a (dereferenced) schema as canonical JSON, to hash or sign it.
+///
+/// Object keys are sorted by their UTF-16 code units, nothing is indented, strings are
+/// escaped as little as JSON allows, and numbers are written as ECMAScript writes doubles,
+/// e.g. `1e+30` and `0.002`. As RFC 8785 expects numbers to be doubles, integers beyond
+/// ±2⁵³ are rounded to the nearest one.
+///
+/// ```
+/// use polywrap_jsonref::to_canonical_string;
+/// use serde_json::json;
+///
+/// let schema = json!({"type": "number", "maximum": 1.50, "exclusiveMinimum": 1e-7});
+/// assert_eq!(
+///     to_canonical_string(&schema),
+///     r#"{"exclusiveMinimum":1e-7,"maximum":1.5,"type":"number"}"#
+/// );
+/// ```
+pub fn to_canonical_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => write_number(out, n),
+        Value::String(s) => write_string(out, s),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value(out, item);
+            }
+            out.push(']');
+        }
+        Value::Object(obj) => write_object(out, obj),
+    }
+}
+
+fn write_object(out: &mut String, obj: &Map<String, Value>) {
+    let mut members: Vec<_> = obj.iter().collect();
+    members.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+    out.push('{');
+    for (i, (key, value)) in members.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write_string(out, key);
+        out.push(':');
+        write_value(out, value);
+    }
+    out.push('}');
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Write `n` as ECMAScript's `Number.prototype.toString` writes the double nearest to it.
+fn write_number(out: &mut String, n: &Number) {
+    let n = n.as_f64().unwrap_or_default();
+    if n == 0.0 {
+        // Negative zero too.
+        out.push('0');
+        return;
+    }
+    if n < 0.0 {
+        out.push('-');
+    }
+    // The shortest digits that read back as `n`, and the exponent of the first of them.
+    let scientific = format!("{:e}", n.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    let k = digits.len() as i32;
+    // Where the decimal point goes, counted in digits.
+    let point = exponent + 1;
+    if k <= point && point <= 21 {
+        out.push_str(&digits);
+        out.extend(std::iter::repeat_n('0', (point - k) as usize));
+    } else if 0 < point && point <= 21 {
+        out.push_str(&digits[..point as usize]);
+        out.push('.');
+        out.push_str(&digits[point as usize..]);
+    } else if -6 < point && point <= 0 {
+        out.push_str("0.");
+        out.extend(std::iter::repeat_n('0', -point as usize));
+        out.push_str(&digits);
+    } else {
+        out.push_str(&digits[..1]);
+        if k > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        out.push('e');
+        out.push(if point > 0 { '+' } else { '-' });
+        out.push_str(&(point - 1).abs().to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_canonical_string;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn output_follows_rfc_8785() {
+        // The examples of RFC 8785, sections 3.2.2.3 and 3.2.3.
+        let mut numbers: Vec<Value> = [
+            "333333333.33333329",
+            "1E30",
+            "4.50",
+            "2e-3",
+            "0.000001",
+            "1e-7",
+            "1e21",
+            "1e20",
+            "-0.0",
+        ]
+        .iter()
+        .map(|n| json!(n.parse::<f64>().unwrap()))
+        .collect();
+        numbers.extend([json!(9007199254740993u64), json!(-5)]);
+        let numbers = Value::Array(numbers);
+        assert_eq!(
+            to_canonical_string(&numbers),
+            "[333333333.3333333,1e+30,4.5,0.002,0.000001,1e-7,1e+21,100000000000000000000,0,\
+             9007199254740992,-5]"
+        );
+        let sorted = json!({
+            "\u{20ac}": "Euro Sign",
+            "\r": "Carriage Return",
+            "\u{fb33}": "Hebrew Letter Dalet With Dagesh",
+            "1": "One",
+            "\u{1f600}": "Emoji: Grinning Face",
+            "\u{80}": "Control\u{7f}",
+            "\u{f6}": "Latin Small Letter O With Diaeresis"
+        });
+        let keys: Vec<_> = to_canonical_string(&sorted)
+            .split(',')
+            .map(|member| member.split(':').next().unwrap().to_owned())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "{\"\\r\"",
+                "\"1\"",
+                "\"\u{80}\"",
+                "\"\u{f6}\"",
+                "\"\u{20ac}\"",
+                "\"\u{1f600}\"",
+                "\"\u{fb33}\""
+            ]
+        );
+        assert_eq!(
+            to_canonical_string(&json!({"a": "\u{1}\"\n/"})),
+            r#"{"a":"\u0001\"\n/"}"#
+        );
+    }
+}
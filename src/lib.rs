@@ -12,7 +12,7 @@
 //! Example:
 //! ```
 //! use serde_json::json;
-//! use jsonref::JsonRef;
+//! use polywrap_jsonref::JsonRef;
 //!
 //! let mut simple_example = json!(
 //!           {"properties": {"prop1": {"title": "name"},
@@ -34,19 +34,157 @@
 //! ```
 //!
 //! **Note**:  If the JSONSchema has recursive `$ref` only the first recursion will happen.
-//! This is to stop an infinate loop.
-
+//! This is to stop an infinate loop. Use [`JsonRef::set_recursion_strategy`] to change this.
+//!
+//! **Ordering**: the same input gives the same output, but for the `fetched_at` times of
+//! [`JsonRef::set_record_origins`]. Lists (loaded documents, the trace, warnings, cache
+//! entries, ...) are in the order their items were first reached or added. Object keys,
+//! including the names of hoisted and bundled definitions, are sorted, as `serde_json` keeps
+//! them by default. The `preserve_order` feature turns on `serde_json`'s feature of the same
+//! name, for every `serde_json::Value` of the build, and keeps them in first-seen order
+//! instead: as the documents have them, with a ref's target in its place, and the names of
+//! hoisted and bundled definitions after the root's own in the order their refs were reached.
+//!
+//! **Caching**: the document a ref leads to is read through three tiers, each filling the
+//! one above it. **Memory**, the [`SchemaCache`], is looked in first; a [`MemoryCache`] or
+//! [`SharedCache`] keeps as many documents for as long as its [`MemoryPolicy`] allows.
+//! Then, for http(s) documents, **disk**, if [`JsonRef::set_cache_dir`] is set: a copy there
+//! is used as long as its [`DiskPolicy`] says it is fresh, else revalidated with the server,
+//! and, if the policy allows, used stale while the server is down.
+//! Last comes the **network**, unless [`JsonRef::set_offline`] forbids it.
+
+use indexmap::IndexMap;
 use serde_json::json;
-use serde_json::Value;
+use serde_json::{map, Map, Value};
 use snafu::{ResultExt, Snafu};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::env;
+use std::fmt;
 use std::fs;
-use std::io;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
 use std::mem;
-use std::path::PathBuf;
+use std::sync::{Arc, Weak};
+use std::time::{Instant, SystemTime};
 use url::Url;
 
+use anchor::AnchorIndex;
+use bundle::Bundle;
+use cache::CacheHandle;
+use deep::SharedDocument;
+use definitions::Renames;
+use directive::{Directive, DIRECTIVE_KEYWORD, STOP_KEYWORD};
+#[cfg(feature = "network")]
+use disk_cache::DiskCache;
+use dynamic::{DYNAMIC_REF_KEYWORD, RECURSIVE_REF_KEYWORD};
+use origins::ORIGINS_KEYWORD;
+use paths::PathFilter;
+#[cfg(feature = "network")]
+use prefetch::Prefetched;
+use recursion::RefChain;
+use resources::Usage;
+use vocabulary::VOCABULARY_KEYWORD;
+
+mod anchor;
+mod builder;
+mod bundle;
+mod cache;
+mod canonical;
+#[cfg(feature = "cbor")]
+mod cbor;
+pub mod compat;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+mod data;
+mod deep;
+mod definitions;
+pub mod diff;
+mod directive;
+#[cfg(feature = "network")]
+mod disk_cache;
+mod draft;
+mod dynamic;
+mod embedded;
+mod error_json;
+mod explain;
+pub mod faker;
+mod file_url;
+mod hover;
+#[cfg(feature = "network")]
+mod http;
+mod index;
+mod jcs;
+mod limits;
+mod missing;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+mod origins;
+mod paths;
+#[cfg(feature = "network")]
+mod prefetch;
+pub mod profile;
+mod query;
+mod recursion;
+mod refresh;
+mod refs;
+mod repro;
+mod resolver;
+mod resources;
+mod selection;
+mod siblings;
+#[cfg(feature = "signing")]
+pub mod signing;
+mod snapshot;
+mod span;
+pub mod subset;
+mod suggest;
+#[cfg(feature = "network")]
+mod tenant;
+mod timebox;
+mod vocabulary;
+mod warning;
+mod watch;
+mod workspace;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+pub use builder::JsonRefBuilder;
+pub use bundle::ResolutionMode;
+pub use cache::{MemoryCache, MemoryPolicy, SchemaCache, SharedCache};
+#[cfg(feature = "cbor")]
+pub use cbor::{to_cbor_vec, to_cbor_writer};
+pub use definitions::{DefinitionCollisions, DefinitionsMode};
+#[cfg(feature = "network")]
+pub use disk_cache::DiskPolicy;
+pub use draft::Draft;
+pub use explain::{Explanation, ResolutionStep, Rule};
+pub use hover::Hover;
+#[cfg(feature = "network")]
+pub use http::{AuthHeader, HttpOptions};
+pub use index::{IndexEntry, IndexKind, UriIndex};
+pub use jcs::to_canonical_string;
+pub use limits::ConcurrencyLimits;
+pub use missing::OnMissing;
+#[cfg(feature = "msgpack")]
+pub use msgpack::{to_msgpack_vec, to_msgpack_writer};
+pub use query::QueryStrategy;
+pub use recursion::RecursionStrategy;
+pub use refresh::Refreshed;
+pub use refs::{assert_fully_resolved, ref_sites, RefKind, RefSite};
+pub use repro::Repro;
+pub use resolver::{BoxError, RefResolver};
+pub use resources::ResourceLimits;
+pub use selection::RefSelection;
+pub use siblings::SiblingStrategy;
+#[cfg(feature = "network")]
+pub use tenant::{TenantQuota, TenantQuotas, TenantUsage};
+pub use warning::{Unsupported, Warning};
+pub use watch::Watcher;
+pub use workspace::{Location, Workspace};
+#[cfg(feature = "yaml")]
+pub use yaml::to_yaml_string;
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Could not open schema from {}: {}", filename, source))]
@@ -54,8 +192,38 @@ pub enum Error {
         filename: String,
         source: std::io::Error,
     },
+    /// The host of `url` is not in the allowed hosts, or is blocked.
+    #[snafu(display("fetching {} is not allowed: host `{}` is not allowed", url, host))]
+    HostNotAllowed { url: String, host: String },
+    #[snafu(display("invalid proxy {}: {}", proxy, source))]
+    #[cfg(feature = "network")]
+    InvalidProxy {
+        proxy: String,
+        #[snafu(source(from(ureq::Error, Box::new)))]
+        source: Box<ureq::Error>,
+    },
     #[snafu(display("Could not open schema from url {}: {}", url, source))]
-    SchemaFromUrl { url: String, source: ureq::Error },
+    #[cfg(feature = "network")]
+    SchemaFromUrl {
+        url: String,
+        #[snafu(source(from(ureq::Error, Box::new)))]
+        source: Box<ureq::Error>,
+    },
+    /// The server answered with a status outside 2xx; `body` is the start of the response
+    /// body, which often says what went wrong.
+    #[snafu(display(
+        "fetching {} failed with HTTP {} {}{}",
+        url,
+        status,
+        reason,
+        if body.is_empty() { String::new() } else { format!(": {}", body) }
+    ))]
+    HttpStatus {
+        url: String,
+        status: u16,
+        reason: String,
+        body: String,
+    },
     #[snafu(display("Parse error for url {}: {}", url, source))]
     UrlParseError {
         url: String,
@@ -72,6 +240,127 @@ pub enum Error {
     JsonPointerNotFound { pointer: String },
     #[snafu(display("{}", "Json Ref Error"))]
     JSONRefError { source: std::io::Error },
+    #[snafu(display("resolver failed to load {}: {}", url, source))]
+    ResolverFailed { url: String, source: BoxError },
+    /// Fetching `url` would need the network, which is off in offline mode and in builds
+    /// without the `network` feature.
+    #[snafu(display("not fetching {}: remote fetches are disabled", url))]
+    RemoteFetchDisabled { url: String },
+    #[snafu(display("could not write schema: {}", source))]
+    WriteOutput { source: serde_json::Error },
+    /// `reference` at `pointer` in `document` could not be resolved. `chain` lists the refs
+    /// followed to reach `document`, outermost first.
+    #[snafu(display(
+        "could not resolve `{}` at {} in {}{}: {}",
+        reference,
+        if pointer.is_empty() { "/" } else { pointer },
+        document,
+        if chain.is_empty() {
+            String::new()
+        } else {
+            format!(" (via {})", chain.join(" → "))
+        },
+        source
+    ))]
+    RefFailed {
+        reference: String,
+        document: String,
+        pointer: String,
+        chain: Vec<String>,
+        source: Box<Error>,
+    },
+    #[snafu(display(
+        "more than {} refs resolved from document {}, giving up",
+        limit,
+        document
+    ))]
+    DocumentBudgetExceeded { document: String, limit: usize },
+    /// `reference` uses a scheme that is not `file`, `http`, `https` or `data`, and no
+    /// resolver supplied it.
+    #[snafu(display("can not load {}: `{}` URLs are not supported", reference, scheme))]
+    UnsupportedScheme { scheme: String, reference: String },
+    #[snafu(display("invalid data URL {}: {}", reference, reason))]
+    InvalidDataUrl { reference: String, reason: String },
+    /// An `x-jsonref` next to a `$ref` is not `"inline"`, `"bundle"` or `"keep"`.
+    #[snafu(display(
+        "unknown x-jsonref directive {} at {}, expected \"inline\", \"bundle\" or \"keep\"",
+        value,
+        pointer
+    ))]
+    InvalidDirective { value: String, pointer: String },
+    #[snafu(display("could not read snapshot: {}", reason))]
+    InvalidSnapshot { reason: String },
+    #[snafu(display("could not write reproduction file {}: {}", path, source))]
+    WriteRepro {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("could not read reproduction: {}", reason))]
+    InvalidRepro { reason: String },
+    #[snafu(display("not fetching {}: more than {} remote fetches needed", url, limit))]
+    TooManyFetches { url: String, limit: usize },
+    #[snafu(display(
+        "not fetching {}: tenant `{}` is over its quota of {} {}",
+        url,
+        tenant,
+        limit,
+        quota
+    ))]
+    TenantQuotaExceeded {
+        tenant: String,
+        url: String,
+        /// `fetches` or `bytes`.
+        quota: String,
+        limit: u64,
+    },
+    #[snafu(display("{} is larger than the limit of {} bytes", url, limit))]
+    DocumentTooLarge { url: String, limit: u64 },
+    #[snafu(display("dereferencing copies more than {} values into the schema", limit))]
+    ExpansionTooLarge { limit: usize },
+    #[snafu(display("dereferencing took longer than {:?}", limit))]
+    DeadlineExceeded { limit: std::time::Duration },
+    #[snafu(display("recursive ref `{}` at {}", reference, pointer))]
+    RecursiveRef { reference: String, pointer: String },
+    #[snafu(display(
+        "`{}/{}` is defined differently at {} and {}",
+        keyword,
+        name,
+        first,
+        second
+    ))]
+    DefinitionCollision {
+        keyword: String,
+        name: String,
+        /// Where the definition that ranks first was found, as a URL with a pointer fragment.
+        first: String,
+        second: String,
+    },
+    #[cfg(feature = "signing")]
+    #[snafu(display("invalid ed25519 key: {}", reason))]
+    InvalidSigningKey { reason: String },
+    #[cfg(feature = "signing")]
+    #[snafu(display("invalid signature: {}", reason))]
+    InvalidSignature { reason: String },
+    #[cfg(feature = "yaml")]
+    #[snafu(display("schema from {} not valid YAML: {}", url, source))]
+    SchemaNotYaml {
+        url: String,
+        source: serde_yaml::Error,
+    },
+    #[cfg(feature = "yaml")]
+    #[snafu(display("could not write schema as YAML: {}", source))]
+    YamlOutput { source: serde_yaml::Error },
+}
+
+impl Error {
+    /// The error underneath any [`Error::RefFailed`] locations, e.g. the
+    /// [`Error::JsonPointerNotFound`] that made a ref fail.
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::RefFailed { source, .. } => source.root_cause(),
+            error => error,
+        }
+    }
 }
 
 /// Trait used to remove Json Value's element
@@ -84,7 +373,7 @@ impl Remove for serde_json::Value {
     /// # Examples: Remove an element in a table
     /// ```
     /// use serde_json::Value;
-    /// use json_value_remove::Remove;
+    /// use polywrap_jsonref::Remove;
     ///
     /// let mut array1: Value = serde_json::from_str(r#"{"my_table":["a","b","c"]}"#).unwrap();
     /// assert_eq!(Some(Value::String("a".to_string())), array1.remove("/my_table/0").unwrap());
@@ -93,7 +382,7 @@ impl Remove for serde_json::Value {
     /// # Examples: Remove a field from an object
     /// ```
     /// use serde_json::Value;
-    /// use json_value_remove::Remove;
+    /// use polywrap_jsonref::Remove;
     ///
     /// let mut object1: Value = serde_json::from_str(r#"{"field1.0":{"field1.1":"value1.1","field1.2":"value1.2"},"field2.0":"value2.0"}"#).unwrap();
     /// assert_eq!(Some(Value::String("value1.2".to_string())), object1.remove("/field1.0/field1.2").unwrap());
@@ -106,6 +395,14 @@ impl Remove for serde_json::Value {
     }
 }
 
+/// Remove `key` from `map`, leaving the other keys in their order when `preserve_order` is on.
+pub(crate) fn shift_remove(map: &mut Map<String, Value>, key: &str) -> Option<Value> {
+    #[cfg(feature = "preserve_order")]
+    return map.shift_remove(key);
+    #[cfg(not(feature = "preserve_order"))]
+    map.remove(key)
+}
+
 fn remove(json_value: &mut Value, fields: Vec<&str>) -> io::Result<Option<Value>> {
     if fields.is_empty() {
         return Ok(None);
@@ -126,12 +423,7 @@ fn remove(json_value: &mut Value, fields: Vec<&str>) -> io::Result<Option<Value>
                     Err(e) => {
                         return Err(io::Error::new(
                             io::ErrorKind::InvalidInput,
-                            format!(
-                                "{}. Can't find the field '{}' in {}.",
-                                e,
-                                field,
-                                json_value
-                            ),
+                            format!("{}. Can't find the field '{}' in {}.", e, field, json_value),
                         ))
                     }
                 };
@@ -141,15 +433,13 @@ fn remove(json_value: &mut Value, fields: Vec<&str>) -> io::Result<Option<Value>
                         io::ErrorKind::InvalidInput,
                         format!(
                             "removal index (is {}) should be < len (is {}) from {}",
-                            index,
-                            len,
-                            json_value
+                            index, len, json_value
                         ),
                     ));
                 }
                 Ok(Some(vec.remove(index)))
             }
-            Value::Object(map) => Ok(map.remove(field)),
+            Value::Object(map) => Ok(shift_remove(map, field)),
             _ => Ok(None),
         },
         false => match json_value.pointer_mut(format!("/{}", field).as_str()) {
@@ -165,23 +455,290 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 ///
 /// Instantiate with
 /// ```
-/// use jsonref::JsonRef;
+/// use polywrap_jsonref::JsonRef;
 /// let jsonref = JsonRef::new();
 /// ```
 ///
 /// Configuration is done through the `set_` methods on the struct.
-#[derive(Debug)]
 pub struct JsonRef {
-    schema_cache: HashMap<String, Value>,
+    schema_cache: CacheHandle,
+    /// Documents parsed from fetched bytes, keyed by [`content_key`], so byte-identical
+    /// documents at different URLs share one `Value`.
+    by_content: HashMap<u64, Weak<Value>>,
+    anchors: AnchorIndex,
     reference_key: Option<String>,
+    sibling_strategy: SiblingStrategy,
+    definitions_mode: DefinitionsMode,
+    definition_collisions: DefinitionCollisions,
+    limits: ConcurrencyLimits,
+    trace: Vec<ResolutionStep>,
+    loaded: Vec<String>,
+    /// When each document this instance loaded was loaded, by cache key.
+    loaded_at: HashMap<String, SystemTime>,
+    warnings: Vec<Warning>,
+    mode: ResolutionMode,
+    recursion: RecursionStrategy,
+    max_inline_depth: Option<usize>,
+    expand_dynamic_refs: bool,
+    on_missing: OnMissing,
+    selection: RefSelection,
+    /// Errors of the refs that failed, while [`JsonRef::deref_value_collect_errors`] runs.
+    collected: Option<Vec<Error>>,
+    /// When [`JsonRef::deref_value_until`] stops resolving refs, while it runs.
+    time_box: Option<Instant>,
+    /// The refs left unresolved once past `time_box`.
+    unresolved: Vec<RefSite>,
+    document_ref_budget: Option<usize>,
+    resource_limits: ResourceLimits,
+    usage: Usage,
+    /// Refs resolved so far from each document during the current deref.
+    document_refs: HashMap<String, usize>,
+    query: QueryStrategy,
+    /// URL of the document the current deref started from.
+    root_document: String,
+    /// That document by its cache keys, from its URL and its `$id`, kept out of the cache,
+    /// which may be shared with other instances, so that refs within it never see the root
+    /// of another deref.
+    root: Option<(Vec<String>, SharedDocument)>,
+    /// The schema resources embedded in that document, by the cache keys of their `$id`s,
+    /// kept out of the cache like it.
+    root_resources: HashMap<String, Arc<Value>>,
+    /// Where each definition collected during the current deref was taken from, by keyword
+    /// and name, as ranked by [`JsonRef::hoist_rank`].
+    hoisted: IndexMap<(&'static str, String), (bool, String, String)>,
+    /// The definitions collected under another name during the current deref, as
+    /// [`DefinitionCollisions::Namespace`] renames them.
+    renamed_definitions: Renames,
+    /// The local refs left in place during the current deref when definitions may be
+    /// renamed, by pointer in the output, with the document each is in and the ref.
+    kept_local_refs: Vec<(String, String, String)>,
+    bundle: Bundle,
+    resolver: Option<Arc<dyn RefResolver>>,
+    schemes: IndexMap<String, Arc<dyn RefResolver>>,
+    offline: bool,
+    fetch_vocabularies: bool,
+    record_origins: bool,
+    only_under: Vec<String>,
+    skip_under: Vec<String>,
+    /// Pointer of the subtree [`JsonRef::deref_pointer`] is dereferencing.
+    subtree: Option<String>,
+    #[cfg(feature = "network")]
+    http: HttpOptions,
+    #[cfg(feature = "network")]
+    agent: Option<ureq::Agent>,
+    /// The agent built from `http` on the first fetch, kept so that later fetches reuse its
+    /// pooled connections.
+    #[cfg(feature = "network")]
+    pooled_agent: Option<ureq::Agent>,
+    #[cfg(feature = "network")]
+    fetch_retries: usize,
+    #[cfg(feature = "network")]
+    disk_cache: Option<DiskCache>,
+    #[cfg(feature = "network")]
+    disk_policy: DiskPolicy,
+    /// Whether [`JsonRef::refresh`] is running, so fresh copies on disk are revalidated too.
+    #[cfg(feature = "network")]
+    revalidating: bool,
+    #[cfg(feature = "network")]
+    parallel_fetching: bool,
+    /// Documents fetched ahead during the current deref, until the walk reaches them.
+    #[cfg(feature = "network")]
+    prefetched: Prefetched,
+    #[cfg(feature = "network")]
+    tenant_quotas: TenantQuotas,
+    #[cfg(feature = "network")]
+    tenant: Option<String>,
+    draft: Option<Draft>,
+    default_draft: Option<Draft>,
+    /// URL that [`JsonRef::deref_value`] takes its value to be from, instead of an
+    /// `anon.json` in the working directory.
+    base_url: Option<Url>,
+}
+
+/// A clone has the same configuration and shares the cache, so cloning one configured
+/// instance for each request reuses the documents loaded for earlier ones.
+impl Clone for JsonRef {
+    fn clone(&self) -> Self {
+        JsonRef {
+            schema_cache: self.schema_cache.clone(),
+            reference_key: self.reference_key.clone(),
+            sibling_strategy: self.sibling_strategy,
+            definitions_mode: self.definitions_mode,
+            definition_collisions: self.definition_collisions,
+            limits: self.limits.clone(),
+            mode: self.mode,
+            recursion: self.recursion.clone(),
+            max_inline_depth: self.max_inline_depth,
+            expand_dynamic_refs: self.expand_dynamic_refs,
+            on_missing: self.on_missing,
+            selection: self.selection,
+            document_ref_budget: self.document_ref_budget,
+            resource_limits: self.resource_limits,
+            query: self.query,
+            resolver: self.resolver.clone(),
+            schemes: self.schemes.clone(),
+            offline: self.offline,
+            fetch_vocabularies: self.fetch_vocabularies,
+            record_origins: self.record_origins,
+            only_under: self.only_under.clone(),
+            skip_under: self.skip_under.clone(),
+            #[cfg(feature = "network")]
+            http: self.http.clone(),
+            #[cfg(feature = "network")]
+            agent: self.agent.clone(),
+            #[cfg(feature = "network")]
+            pooled_agent: self.pooled_agent.clone(),
+            #[cfg(feature = "network")]
+            fetch_retries: self.fetch_retries,
+            #[cfg(feature = "network")]
+            disk_cache: self.disk_cache.clone(),
+            #[cfg(feature = "network")]
+            disk_policy: self.disk_policy,
+            #[cfg(feature = "network")]
+            parallel_fetching: self.parallel_fetching,
+            #[cfg(feature = "network")]
+            tenant_quotas: self.tenant_quotas.clone(),
+            #[cfg(feature = "network")]
+            tenant: self.tenant.clone(),
+            draft: self.draft,
+            default_draft: self.default_draft,
+            base_url: self.base_url.clone(),
+            // Everything else is the state of one deref.
+            ..JsonRef::new()
+        }
+    }
+}
+
+impl fmt::Debug for JsonRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("JsonRef");
+        debug
+            .field(
+                "schema_cache",
+                &self
+                    .schema_cache
+                    .entries()
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>(),
+            )
+            .field("reference_key", &self.reference_key)
+            .field("sibling_strategy", &self.sibling_strategy)
+            .field("definitions_mode", &self.definitions_mode)
+            .field("definition_collisions", &self.definition_collisions)
+            .field("limits", &self.limits)
+            .field("trace", &self.trace)
+            .field("loaded", &self.loaded)
+            .field("warnings", &self.warnings)
+            .field("mode", &self.mode)
+            .field("recursion", &self.recursion)
+            .field("max_inline_depth", &self.max_inline_depth)
+            .field("expand_dynamic_refs", &self.expand_dynamic_refs)
+            .field("on_missing", &self.on_missing)
+            .field("selection", &self.selection)
+            .field("resource_limits", &self.resource_limits)
+            .field("query", &self.query)
+            .field("resolver", &self.resolver.is_some())
+            .field("schemes", &self.schemes.keys().collect::<Vec<_>>())
+            .field("offline", &self.offline)
+            .field("fetch_vocabularies", &self.fetch_vocabularies)
+            .field("record_origins", &self.record_origins)
+            .field("only_under", &self.only_under)
+            .field("skip_under", &self.skip_under);
+        #[cfg(feature = "network")]
+        debug
+            .field("http", &self.http)
+            .field("agent", &self.agent.is_some())
+            .field("fetch_retries", &self.fetch_retries)
+            .field("disk_cache", &self.disk_cache)
+            .field("disk_policy", &self.disk_policy)
+            .field("parallel_fetching", &self.parallel_fetching)
+            .field("tenant_quotas", &self.tenant_quotas)
+            .field("tenant", &self.tenant);
+        debug
+            .field("draft", &self.draft)
+            .field("default_draft", &self.default_draft)
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+impl Default for JsonRef {
+    fn default() -> Self {
+        JsonRef::new()
+    }
 }
 
 impl JsonRef {
     /// Create a new instance of JsonRef.
     pub fn new() -> JsonRef {
         JsonRef {
-            schema_cache: HashMap::new(),
+            schema_cache: CacheHandle::new(MemoryCache::new()),
+            by_content: HashMap::new(),
+            anchors: AnchorIndex::new(),
             reference_key: None,
+            sibling_strategy: SiblingStrategy::default(),
+            definitions_mode: DefinitionsMode::default(),
+            definition_collisions: DefinitionCollisions::default(),
+            limits: ConcurrencyLimits::default(),
+            trace: Vec::new(),
+            loaded: Vec::new(),
+            loaded_at: HashMap::new(),
+            warnings: Vec::new(),
+            mode: ResolutionMode::default(),
+            recursion: RecursionStrategy::default(),
+            max_inline_depth: None,
+            expand_dynamic_refs: true,
+            on_missing: OnMissing::default(),
+            selection: RefSelection::default(),
+            collected: None,
+            time_box: None,
+            unresolved: Vec::new(),
+            document_ref_budget: None,
+            resource_limits: ResourceLimits::default(),
+            usage: Usage::default(),
+            document_refs: HashMap::new(),
+            query: QueryStrategy::default(),
+            root_document: String::new(),
+            root: None,
+            root_resources: HashMap::new(),
+            hoisted: IndexMap::new(),
+            renamed_definitions: Renames::new(),
+            kept_local_refs: Vec::new(),
+            bundle: Bundle::default(),
+            resolver: None,
+            schemes: IndexMap::new(),
+            offline: false,
+            fetch_vocabularies: false,
+            record_origins: false,
+            only_under: Vec::new(),
+            skip_under: Vec::new(),
+            subtree: None,
+            #[cfg(feature = "network")]
+            http: HttpOptions::default(),
+            #[cfg(feature = "network")]
+            agent: None,
+            #[cfg(feature = "network")]
+            pooled_agent: None,
+            #[cfg(feature = "network")]
+            fetch_retries: 3,
+            #[cfg(feature = "network")]
+            disk_cache: None,
+            #[cfg(feature = "network")]
+            disk_policy: DiskPolicy::default(),
+            #[cfg(feature = "network")]
+            revalidating: false,
+            #[cfg(feature = "network")]
+            parallel_fetching: true,
+            #[cfg(feature = "network")]
+            prefetched: Prefetched::new(),
+            #[cfg(feature = "network")]
+            tenant_quotas: TenantQuotas::default(),
+            #[cfg(feature = "network")]
+            tenant: None,
+            draft: None,
+            default_draft: None,
+            base_url: None,
         }
     }
 
@@ -190,7 +747,7 @@ impl JsonRef {
     /// This example uses `__reference__` as the key.
     ///
     /// ```
-    /// # use jsonref::JsonRef;
+    /// # use polywrap_jsonref::JsonRef;
     /// # let jsonref = JsonRef::new();
     /// use serde_json::json;
     ///
@@ -214,36 +771,308 @@ impl JsonRef {
     ///                                                                                          
     /// assert_eq!(input, expected)
     /// ```
-
     pub fn set_reference_key(&mut self, reference_key: &str) {
         self.reference_key = Some(reference_key.to_owned());
     }
 
+    /// Resolve relative refs in values given to [`JsonRef::deref_value`] against `base_url`,
+    /// as if the value had been loaded from there.
+    pub fn set_base_url(&mut self, base_url: Url) {
+        self.base_url = Some(base_url);
+    }
+
     /// deref a serde_json value directly. Uses the current working directory for any relative
-    /// refs.
+    /// refs, unless a base URL is set with [`JsonRef::set_base_url`]. If it fails, `value` is
+    /// left as it was.
     pub fn deref_value(&mut self, value: &mut Value) -> Result<()> {
-        let anon_file_url = format!(
-            "file://{}/anon.json",
-            env::current_dir()
-                .context(JSONRefError {})?
-                .to_string_lossy()
-        );
-        self.schema_cache
-            .insert(anon_file_url.clone(), value.clone());
+        let anon_file_url = match &self.base_url {
+            Some(base_url) => base_url.to_string(),
+            None => {
+                let dir = env::current_dir().context(JSONRefError {})?;
+                file_url::in_directory(&dir, "anon.json")?.to_string()
+            }
+        };
+
+        let limits = self.limits.clone();
+        let _permit = limits.resolution_permit();
+        self.loaded.clear();
+        self.warnings.clear();
+        #[cfg(feature = "network")]
+        self.prefetched.clear();
+        self.usage = Usage::default();
+        self.deref_root(value, anon_file_url)
+    }
 
-        let mut definitions = json!({});
+    /// Deref `value` like [`JsonRef::deref_value`], but carry on past refs that fail and
+    /// return all their errors, each an [`Error::RefFailed`] saying where the ref is.
+    ///
+    /// Failed refs are left in place. An empty list means the whole document resolved.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{Error, JsonRef};
+    /// use serde_json::json;
+    ///
+    /// let mut input = json!({"properties": {"a": {"$ref": "#/definitions/missing"},
+    ///                                       "b": {"$ref": "#/definitions/gone"}}});
+    /// let errors = JsonRef::new().deref_value_collect_errors(&mut input);
+    /// let pointers: Vec<_> = errors
+    ///     .iter()
+    ///     .map(|error| match error {
+    ///         Error::RefFailed { pointer, .. } => pointer.as_str(),
+    ///         _ => unreachable!(),
+    ///     })
+    ///     .collect();
+    /// assert_eq!(pointers, ["/properties/a", "/properties/b"]);
+    /// ```
+    pub fn deref_value_collect_errors(&mut self, value: &mut Value) -> Vec<Error> {
+        self.collected = Some(vec![]);
+        let result = self.deref_value(value);
+        let mut errors = self.collected.take().unwrap_or_default();
+        if let Err(error) = result {
+            errors.push(error);
+        }
+        errors
+    }
+
+    /// Limit how many fetches, file reads and dereference operations run at the same time.
+    ///
+    /// The limits are shared with every other `JsonRef` given a clone of the same
+    /// [`ConcurrencyLimits`], which lets a server keep all its dereferencing within one budget.
+    pub fn set_concurrency_limits(&mut self, limits: ConcurrencyLimits) {
+        self.limits = limits;
+    }
+
+    /// Load documents whose URL uses `scheme`, e.g. `urn` or `s3`, with `handler`.
+    ///
+    /// The handler is asked after the resolver set with [`JsonRef::set_resolver`]. Returning
+    /// `Ok(None)` falls back to the built in loading, so registering `https` lets a handler
+    /// serve some hosts and leave the rest to be fetched; for other schemes it fails with
+    /// [`Error::UnsupportedScheme`].
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.register_scheme(
+    ///     "urn",
+    ///     Box::new(|url: &url::Url| match url.path() {
+    ///         "example:pet" => Ok(Some(json!({"$defs": {"name": {"type": "string"}}}))),
+    ///         _ => Ok(None),
+    ///     }),
+    /// );
+    ///
+    /// let mut input = json!({"items": {"$ref": "urn:example:pet#/$defs/name"}});
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(input, json!({"items": {"type": "string"}}));
+    /// ```
+    pub fn register_scheme(&mut self, scheme: &str, handler: Box<dyn RefResolver>) {
+        self.schemes
+            .insert(scheme.to_ascii_lowercase(), Arc::from(handler));
+    }
+
+    /// Limit how many refs written in any one document are resolved during a deref, failing
+    /// with [`Error::DocumentBudgetExceeded`] once a document goes over.
+    ///
+    /// Refs are counted against the document they appear in, so one pathological schema
+    /// runs out of budget without using up that of the others. Refs left in place, such as
+    /// those kept by a directive or failing before their target is looked up, do not count.
+    /// `None`, the default, means no limit.
+    pub fn set_document_ref_budget(&mut self, budget: Option<usize>) {
+        self.document_ref_budget = budget;
+    }
+
+    /// Set a [`RefResolver`] that is asked for external documents before they are fetched.
+    pub fn set_resolver(&mut self, resolver: Box<dyn RefResolver>) {
+        self.resolver = Some(Arc::from(resolver));
+    }
+
+    /// Forbid network access.
+    ///
+    /// In offline mode any `$ref` that would need an HTTP fetch fails with
+    /// [`Error::RemoteFetchDisabled`]. Schemas registered with [`JsonRef::add_schema`] or supplied
+    /// by a resolver are still used.
+    pub fn set_offline(&mut self, offline: bool) {
+        self.offline = offline;
+    }
+
+    /// Treat every document as written in `draft`, ignoring their `$schema`.
+    ///
+    /// By default the draft is taken from each document's `$schema`, so a draft-04 schema
+    /// referencing a 2020-12 one gets the right semantics for both. The draft decides:
+    ///
+    /// * whether `id` (draft-04) or `$id` changes the base URI,
+    /// * whether keywords next to a `$ref` are ignored (up to draft-07) or apply (2019-09
+    ///   onwards, where the target and its siblings are combined in an `allOf`), unless a
+    ///   [`SiblingStrategy`] says otherwise,
+    /// * whether only `definitions` or also `$defs` is collected at the root.
+    ///
+    /// Documents without a known draft keep `$id`, legacy sibling handling and collect both
+    /// `definitions` and `$defs`.
+    pub fn set_draft(&mut self, draft: Draft) {
+        self.draft = Some(draft);
+    }
+
+    /// Set the draft for documents that do not declare a known `$schema`.
+    pub fn set_default_draft(&mut self, draft: Draft) {
+        self.default_draft = Some(draft);
+    }
+
+    /// Choose whether refs are inlined (the default) or bundled into the root document.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{JsonRef, ResolutionMode};
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_resolution_mode(ResolutionMode::Bundle);
+    /// jsonref
+    ///     .add_schema("https://example.com/address.json", json!({"title": "address"}))
+    ///     .unwrap();
+    ///
+    /// let mut input = json!({"properties": {"home": {"$ref": "https://example.com/address.json"},
+    ///                                       "work": {"$ref": "https://example.com/address.json"}}});
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(
+    ///     input,
+    ///     json!({"properties": {"home": {"$ref": "#/$defs/address_json"},
+    ///                           "work": {"$ref": "#/$defs/address_json"}},
+    ///            "$defs": {"address_json": {"title": "address"}}})
+    /// );
+    /// ```
+    pub fn set_resolution_mode(&mut self, mode: ResolutionMode) {
+        self.mode = mode;
+    }
+
+    /// Choose what happens when a `$ref` is reached again inside its own expansion.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{JsonRef, RecursionStrategy};
+    /// use serde_json::json;
+    ///
+    /// let mut input = json!({"properties": {"child": {"$ref": "#"}}});
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_recursion_strategy(RecursionStrategy::KeepRef);
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(
+    ///     input,
+    ///     json!({"properties": {"child": {"properties": {"child": {"$ref": "#"}}}}})
+    /// );
+    /// ```
+    pub fn set_recursion_strategy(&mut self, strategy: RecursionStrategy) {
+        self.recursion = strategy;
+    }
+
+    /// Inline refs only `depth` levels deep: a ref reached within the expansion of `depth`
+    /// others is bundled instead, as with [`ResolutionMode::Bundle`], so the output stays
+    /// complete however deep the schemas go. Refs into the root document are kept, pointing
+    /// within it; the targets of the others are copied into its definitions.
+    ///
+    /// `depth` 0 inlines nothing; `None`, the default, inlines every ref. An
+    /// `"x-jsonref": "inline"` next to a ref inlines it at any depth.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref
+    ///     .add_schema(
+    ///         "https://example.com/address.json",
+    ///         json!({"properties": {"country": {"$ref": "country.json"}}}),
+    ///     )
+    ///     .unwrap();
+    /// jsonref
+    ///     .add_schema("https://example.com/country.json", json!({"title": "country"}))
+    ///     .unwrap();
+    /// jsonref.set_max_inline_depth(Some(1));
+    ///
+    /// let mut input = json!({"properties": {"home": {"$ref": "https://example.com/address.json"}}});
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(
+    ///     input,
+    ///     json!({"properties": {"home": {"properties": {
+    ///                "country": {"$ref": "#/$defs/country_json"}
+    ///            }}},
+    ///            "$defs": {"country_json": {"title": "country"}}})
+    /// );
+    /// ```
+    pub fn set_max_inline_depth(&mut self, depth: Option<usize>) {
+        self.max_inline_depth = depth;
+    }
+
+    /// Choose what happens to a `$ref` whose document can not be loaded or whose pointer is
+    /// missing, instead of failing the whole dereference.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{JsonRef, OnMissing};
+    /// use serde_json::json;
+    ///
+    /// let mut input = json!({"properties": {"broken": {"$ref": "#/definitions/missing"},
+    ///                                       "fine": {"$ref": "#/definitions/name"}},
+    ///                        "definitions": {"name": {"type": "string"}}});
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_on_missing(OnMissing::Keep);
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(input["properties"]["broken"], json!({"$ref": "#/definitions/missing"}));
+    /// assert_eq!(input["properties"]["fine"], json!({"type": "string"}));
+    /// ```
+    pub fn set_on_missing(&mut self, on_missing: OnMissing) {
+        self.on_missing = on_missing;
+    }
 
-        self.deref(value, anon_file_url, &vec![], &mut definitions)?;
+    /// Choose whether query strings in ref URLs are kept, stripped or canonicalized.
+    ///
+    /// The strategy applies to every URL a document is cached under, including the URLs of
+    /// [`JsonRef::add_schema`] and [`JsonRef::deref_url`].
+    pub fn set_query_strategy(&mut self, strategy: QueryStrategy) {
+        self.query = strategy;
+    }
 
-        let val = value.as_object_mut().unwrap();
-        val.insert("definitions".to_string(), definitions);
+    /// Register an in-memory schema under `uri`.
+    ///
+    /// Any `$ref` to `uri` (or to a fragment within it) is resolved from this schema instead of
+    /// being loaded from disk or the network.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref
+    ///     .add_schema("https://example.com/address.json", json!({"title": "address"}))
+    ///     .unwrap();
+    ///
+    /// let mut input = json!({"properties": {"home": {"$ref": "https://example.com/address.json"}}});
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(input, json!({"properties": {"home": {"title": "address"}}}));
+    /// ```
+    pub fn add_schema(&mut self, uri: &str, schema: Value) -> Result<()> {
+        let mut url = Url::parse(uri).context(UrlParseError {
+            url: uri.to_owned(),
+        })?;
+        url.set_fragment(None);
+        self.query.apply(&mut url);
+        let key = canonical::cache_key(&url);
+        self.cache_resources(&url, &key, &schema);
+        self.schema_cache.put(key, Arc::new(schema));
         Ok(())
     }
 
+    /// Drop the cached document for `uri`, and its `$id` alias, so it is loaded again when
+    /// next referenced.
+    pub(crate) fn forget(&mut self, uri: &Url) {
+        let key = canonical::cache_key(uri);
+        if let Some(document) = self.schema_cache.get(&key) {
+            self.forget_alias(uri, &key, &document);
+        }
+        self.schema_cache.remove(&key);
+    }
+
     /// deref from a URL:
     ///
     /// ```
-    /// # use jsonref::JsonRef;
+    /// # use polywrap_jsonref::JsonRef;
     /// # let jsonref = JsonRef::new();
     /// # use serde_json::Value;
     /// # use std::fs;
@@ -255,30 +1084,25 @@ impl JsonRef {
     /// # assert_eq!(input_url, file_expected)
     /// ```
     pub fn deref_url(&mut self, url: &str) -> Result<Value> {
-        let mut value: Value = ureq::get(url)
-            .call()
-            .context(SchemaFromUrl {
-                url: url.to_owned(),
-            })?
-            .into_json()
-            .context(SchemaNotJson {
-                url: url.to_owned(),
-            })?;
-
-        self.schema_cache.insert(url.to_string(), value.clone());
-        let mut definitions = json!({});
-        self.deref(&mut value, url.to_string(), &vec![], &mut definitions)?;
-        
-        let val = value.as_object_mut().unwrap();
-        val.insert("definitions".to_string(), definitions);
-
-        Ok(value)
+        let limits = self.limits.clone();
+        let _permit = limits.resolution_permit();
+        let mut parsed_url = Url::parse(url).context(UrlParseError {
+            url: url.to_owned(),
+        })?;
+        self.query.apply(&mut parsed_url);
+        self.loaded.clear();
+        self.warnings.clear();
+        #[cfg(feature = "network")]
+        self.prefetched.clear();
+        self.usage = Usage::default();
+        let value = Value::clone(&*self.document(&parsed_url)?);
+        self.deref_document(value, parsed_url.to_string())
     }
 
     /// deref from a File:
     ///
     /// ```
-    /// # use jsonref::JsonRef;
+    /// # use polywrap_jsonref::JsonRef;
     /// # let jsonref = JsonRef::new();
     /// # use serde_json::Value;
     /// # use std::fs;
@@ -293,197 +1117,1227 @@ impl JsonRef {
     /// # assert_eq!(file_example, file_expected)
     /// ```
     pub fn deref_file(&mut self, file_path: &str) -> Result<Value> {
-        let file = fs::File::open(file_path).context(SchemaFromFile {
-            filename: file_path.to_owned(),
-        })?;
-        let mut value: Value = serde_json::from_reader(file).context(SchemaNotJsonSerde {
-            url: file_path.to_owned(),
-        })?;
-        let path = PathBuf::from(file_path);
-        let absolute_path = fs::canonicalize(path).context(JSONRefError {})?;
-        let url = format!("file://{}", absolute_path.to_string_lossy());
-
-        self.schema_cache.insert(url.clone(), value.clone());
-        let mut definitions = json!({});
-        self.deref(&mut value, url, &vec![], &mut definitions)?;
-
-        let val = value.as_object_mut().unwrap();
-        val.insert("definitions".to_string(), definitions);
+        self.deref_path(file_path, |url, path, bytes| {
+            parse_document(url, path, bytes)
+        })
+    }
 
-        Ok(value)
+    /// Deref the file at `file_path` and serialize the result straight into `writer`, pretty
+    /// printed if `pretty` is set, instead of building the JSON text in memory.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// let mut output = Vec::new();
+    /// JsonRef::new()
+    ///     .deref_file_to_writer("fixtures/nested_relative/base.json", &mut output, false)
+    ///     .unwrap();
+    /// let value: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    /// assert_eq!(value["title"], "title from file");
+    /// ```
+    pub fn deref_file_to_writer(
+        &mut self,
+        file_path: &str,
+        writer: impl io::Write,
+        pretty: bool,
+    ) -> Result<()> {
+        let value = self.deref_file(file_path)?;
+        write_json(writer, &value, pretty)
     }
 
-    fn deref(
+    /// Deref `value` like [`JsonRef::deref_value`] and serialize the result into `writer`.
+    pub fn deref_value_to_writer(
         &mut self,
         value: &mut Value,
-        id: String,
-        used_refs: &Vec<String>,
-        definitions: &mut Value,
+        writer: impl io::Write,
+        pretty: bool,
     ) -> Result<()> {
-        let mut new_id = id;
-        if let Some(id_value) = value.get("$id") {
-            if let Some(id_string) = id_value.as_str() {
-                new_id = id_string.to_string()
-            }
+        self.deref_value(value)?;
+        write_json(writer, value, pretty)
+    }
+
+    /// Read the file at `file_path` with `parse` and deref it.
+    fn deref_path(
+        &mut self,
+        file_path: &str,
+        parse: fn(&str, &str, &[u8]) -> Result<Value>,
+    ) -> Result<Value> {
+        let limits = self.limits.clone();
+        let _permit = limits.resolution_permit();
+        self.loaded.clear();
+        self.warnings.clear();
+        #[cfg(feature = "network")]
+        self.prefetched.clear();
+        self.usage = Usage::default();
+        let mut bytes = Vec::new();
+        {
+            let _file = limits.file_permit();
+            let limit = self.read_limit();
+            fs::File::open(file_path)
+                .and_then(|file| file.take(limit).read_to_end(&mut bytes))
+                .context(SchemaFromFile {
+                    filename: file_path.to_owned(),
+                })?;
         }
+        self.check_document_size(file_path, bytes.len())?;
+        let value = parse(file_path, file_path, &bytes)?;
+        let url = file_url::from_path(file_path)?.to_string();
+        self.loaded.push(url.clone());
+        self.deref_document(value, url)
+    }
 
-        if let Some(obj) = value.as_object_mut() {
-            if let Some(defs) = obj.get_mut("definitions") {
-                let mut defs_clone = defs.clone();
-                obj.remove("definitions").unwrap();
+    /// Deref a document that was loaded from `url`.
+    fn deref_document(&mut self, mut value: Value, url: String) -> Result<Value> {
+        self.deref_root(&mut value, url)?;
+        Ok(value)
+    }
 
-                if let Some(def_obj) = defs_clone.as_object_mut() {
-                    let accumulated_defs = definitions.as_object_mut().unwrap();
-                    for (key, val) in def_obj.iter_mut() {
-                        accumulated_defs.insert(key.to_string(), val.clone());
-                    }
+    /// Deref the root document `value`, which was loaded from `url`.
+    fn deref_root(&mut self, value: &mut Value, url: String) -> Result<()> {
+        let document = Arc::new(deep::clone(value));
+        let (keys, resources) = match Url::parse(&url) {
+            Ok(parsed) => {
+                let key = canonical::cache_key(&parsed);
+                let id_key = self.id_key(&parsed, &key, &document);
+                // Refs within the root go to it whatever is cached under its `$id`.
+                if let Some(id_key) = &id_key {
+                    self.check_id_conflict(&parsed, id_key, &document);
                 }
+                let resources = self.embedded_resources(&parsed, &key, &document);
+                let keys = std::iter::once(key).chain(id_key).collect();
+                (keys, resources.into_iter().collect())
             }
-
-            if let Some(ref_value) = obj.remove("$ref") {
-                if let Some(ref_string) = ref_value.as_str() {
-                    let id_url = Url::parse(&new_id).context(UrlParseError {
-                        url: new_id.clone(),
-                    })?;
-                    let ref_url = id_url.join(ref_string).context(UrlParseError {
-                        url: ref_string.to_owned(),
-                    })?;
-
-                    let mut ref_url_no_fragment = ref_url.clone();
-                    ref_url_no_fragment.set_fragment(None);
-                    let ref_no_fragment = ref_url_no_fragment.to_string();
-
-                    let mut schema = match self.schema_cache.get(&ref_no_fragment) {
-                        Some(cached_schema) => cached_schema.clone(),
-                        None => {
-                            if ref_no_fragment.starts_with("http") {
-                                ureq::get(&ref_no_fragment)
-                                    .call()
-                                    .context(SchemaFromUrl {
-                                        url: ref_no_fragment.clone(),
-                                    })?
-                                    .into_json()
-                                    .context(SchemaNotJson {
-                                        url: ref_no_fragment.clone(),
-                                    })?
-                            } else if ref_no_fragment.starts_with("file") {
-                                let file = fs::File::open(ref_url_no_fragment.path()).context(
-                                    SchemaFromFile {
-                                        filename: ref_no_fragment.clone(),
-                                    },
-                                )?;
-                                serde_json::from_reader(file).context(SchemaNotJsonSerde {
-                                    url: ref_no_fragment.clone(),
-                                })?
-                            } else {
-                                panic!("need url to be a file or a http based url")
-                            }
-                        }
-                    };
-
-                    if !self.schema_cache.contains_key(&ref_no_fragment) {
-                        self.schema_cache
-                            .insert(ref_no_fragment.clone(), schema.clone());
-                    }
-
-                    let ref_url_string = ref_url.to_string();
-                    if let Some(ref_fragment) = ref_url.fragment() {
-                        schema = schema.pointer(ref_fragment).ok_or(
-                            Error::JsonPointerNotFound {pointer: format!("ref `{}` can not be resolved as pointer `{}` can not be found in the schema", ref_string, ref_fragment)}
-                            )?.clone();
-                    }
-                    if used_refs.contains(&ref_url_string) {
-                        return Ok(());
-                    }
-
-                    let mut new_used_refs = used_refs.clone();
-                    new_used_refs.push(ref_url_string);
-
-                    self.deref(&mut schema, ref_no_fragment, &new_used_refs, definitions)?;
-                    let old_value = mem::replace(value, schema);
-
-                    if let Some(reference_key) = &self.reference_key {
-                        if let Some(new_obj) = value.as_object_mut() {
-                            new_obj.insert(reference_key.clone(), old_value);
-                        }
-                    }
-                }
+            Err(_) => (vec![url.clone()], HashMap::new()),
+        };
+        self.root = Some((keys, SharedDocument(document)));
+        self.root_resources = resources;
+        #[cfg(feature = "network")]
+        if let Ok(base) = Url::parse(&url) {
+            self.prefetch_refs(value, &base, true);
+        }
+        let mut definitions = json!({});
+        self.trace.clear();
+        self.document_refs.clear();
+        self.hoisted.clear();
+        self.renamed_definitions.clear();
+        self.kept_local_refs.clear();
+        self.root_document = url.clone();
+        let scope = self.root_scope(&url, value)?;
+        // Set up even when inlining, for refs bundled by an `x-jsonref` directive.
+        let keyword = scope.draft.map_or("$defs", |draft| {
+            draft.definitions_keywords().last().unwrap()
+        });
+        self.bundle = Bundle::new(keyword, value);
+        let walked = self.deref(
+            mem::take(value),
+            scope,
+            &mut RefChain::default(),
+            &mut definitions,
+        );
+        *value = match walked {
+            Ok(walked) => walked,
+            Err(error) => {
+                *value = deep::clone(&self.root.as_ref().unwrap().1 .0);
+                return Err(error);
+            }
+        };
+
+        let bundle = mem::take(&mut self.bundle);
+        if !bundle.schemas.is_empty() {
+            let keyword = bundle.keyword;
+            let collected = definitions
+                .as_object_mut()
+                .unwrap()
+                .entry(keyword)
+                .or_insert_with(|| json!({}));
+            if let Value::Object(collected) = collected {
+                collected.extend(bundle.into_schemas());
             }
         }
-
-        if let Some(obj) = value.as_object_mut() {
-            for obj_value in obj.values_mut() {
-                self.deref(obj_value, new_id.clone(), used_refs, definitions)?
+        self.rename_definition_refs(value, &mut definitions);
+        insert_definitions(value, definitions);
+        if self.record_origins {
+            let origins = self.origins();
+            if let Value::Object(root) = value {
+                root.insert(ORIGINS_KEYWORD.to_owned(), origins);
             }
         }
         Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::JsonRef;
-    use serde_json::{json, Value};
-    use std::fs;
 
-    #[test]
-    fn json_no_refs() {
-        let no_ref_example = json!({"properties": {"prop1": {"title": "proptitle"}}});
-
-        let mut jsonref = JsonRef::new();
-
-        let mut input = no_ref_example.clone();
-
-        jsonref.deref_value(&mut input).unwrap();
-
-        assert_eq!(input, no_ref_example)
+    /// URLs of the documents the last deref loaded, in the order they were loaded.
+    ///
+    /// Documents already in the cache, whether from an earlier deref or
+    /// [`JsonRef::add_schema`], are not listed.
+    pub fn loaded_documents(&self) -> &[String] {
+        &self.loaded
     }
 
-    #[test]
-    fn json_with_recursion() {
-        let mut simple_refs_example = json!(
-            {"properties": {"prop1": {"$ref": "#"}}}
-        );
+    /// The document at `url` (without fragment), from the cache or loaded and cached.
+    fn document(&mut self, url: &Url) -> Result<Arc<Value>> {
+        let key = canonical::cache_key(url);
+        if let Some(cached) = self.cached(&key) {
+            return Ok(cached);
+        }
+        let document = self.load_document(url)?;
+        self.loaded.push(url.to_string());
+        self.cache_document(url, key, document.clone());
+        #[cfg(feature = "network")]
+        self.prefetch_refs(&document, url, false);
+        Ok(document)
+    }
 
-        let simple_refs_expected = json!(
-            {"properties": {"prop1": {"properties": {"prop1": {}}}}
+    /// Cache `document`, just loaded from `url`, under `key` and its `$id`, and the resources
+    /// embedded in it under theirs.
+    pub(crate) fn cache_document(&mut self, url: &Url, key: String, document: Arc<Value>) {
+        self.loaded_at.insert(key.clone(), SystemTime::now());
+        self.cache_resources(url, &key, &document);
+        if let Some(id_key) = self.id_key(url, &key, &document) {
+            if !self.check_id_conflict(url, &id_key, &document) {
+                self.schema_cache.put(id_key, document.clone());
             }
-        );
+        }
+        self.schema_cache.put(key, document);
+    }
 
-        let mut jsonref = JsonRef::new();
-        jsonref.deref_value(&mut simple_refs_example).unwrap();
-        jsonref.set_reference_key("__reference__");
+    /// Drop what is cached under the `$id` of `document`, cached under `key` for `url`, if it
+    /// is that document, and under those of the resources embedded in it.
+    pub(crate) fn forget_alias(&mut self, url: &Url, key: &str, document: &Arc<Value>) {
+        self.forget_resources(url, key, document);
+        if let Some(id_key) = self.id_key(url, key, document) {
+            let aliased = self.schema_cache.get(&id_key);
+            if aliased.is_some_and(|aliased| Arc::ptr_eq(&aliased, document)) {
+                self.schema_cache.remove(&id_key);
+            }
+        }
+    }
 
-        println!(
-            "{}",
-            serde_json::to_string_pretty(&simple_refs_example).unwrap()
-        );
+    /// The cache key of the URL the top-level `$id` of `document`, loaded from `url` (cache
+    /// key `key`), declares, if it declares one other than `url`.
+    fn id_key(&self, url: &Url, key: &str, document: &Value) -> Option<String> {
+        let id_keyword = self
+            .document_draft(document, None)
+            .map_or("$id", Draft::id_keyword);
+        let id = document.get(id_keyword)?.as_str()?;
+        if id.starts_with('#') {
+            return None;
+        }
+        let mut id_url = url.join(id).ok()?;
+        id_url.set_fragment(None);
+        self.query.apply(&mut id_url);
+        let id_key = canonical::cache_key(&id_url);
+        (id_key != key).then_some(id_key)
+    }
 
-        assert_eq!(simple_refs_example, simple_refs_expected)
+    /// Whether a document other than `document`, loaded from `url`, is already known by
+    /// `id_key`, its `$id`, warning about it if so.
+    fn check_id_conflict(&mut self, url: &Url, id_key: &str, document: &Arc<Value>) -> bool {
+        let conflict = match self.schema_cache.get(id_key) {
+            Some(existing) => !Arc::ptr_eq(&existing, document) && existing != *document,
+            None => false,
+        };
+        if conflict {
+            self.warnings.push(Warning::IdConflict {
+                id: id_key.to_owned(),
+                url: url.to_string(),
+            });
+        }
+        conflict
     }
 
-    #[test]
-    fn simple_from_url() {
-        let mut simple_refs_example = json!(
-            {"properties": {"prop1": {"title": "name"},
-                            "prop2": {"$ref": "https://gist.githubusercontent.com/kindly/35a631d33792413ed8e34548abaa9d61/raw/b43dc7a76cc2a04fde2a2087f0eb389099b952fb/test.json", "title": "old_title"}}
+    /// The document under the cache key `key`: the root of the current deref or a resource
+    /// embedded in it, or one from the cache.
+    fn cached(&mut self, key: &str) -> Option<Arc<Value>> {
+        match &self.root {
+            Some((root_keys, root)) if root_keys.iter().any(|root_key| root_key == key) => {
+                Some(root.0.clone())
             }
-        );
+            _ => match self.root_resources.get(key) {
+                Some(resource) => Some(resource.clone()),
+                None => self.schema_cache.get(key),
+            },
+        }
+    }
 
-        let simple_refs_expected = json!(
-            {"properties": {"prop1": {"title": "name"},
-                            "prop2": {"title": "title from url", "__reference__": {"title": "old_title"}}}
+    /// Load the document at `url`, asking the resolver before fetching it ourselves.
+    fn load_document(&mut self, url: &Url) -> Result<Arc<Value>> {
+        let url_string = url.to_string();
+        if let Some(resolver) = &self.resolver {
+            let resolved = resolver.resolve(url).context(ResolverFailed {
+                url: url_string.clone(),
+            })?;
+            if let Some(value) = resolved {
+                return Ok(Arc::new(value));
             }
-        );
-
-        let mut jsonref = JsonRef::new();
-        jsonref.set_reference_key("__reference__");
-        jsonref.deref_value(&mut simple_refs_example).unwrap();
+        }
+        if let Some(handler) = self.schemes.get(url.scheme()) {
+            let resolved = handler.resolve(url).context(ResolverFailed {
+                url: url_string.clone(),
+            })?;
+            if let Some(value) = resolved {
+                return Ok(Arc::new(value));
+            }
+        }
 
-        assert_eq!(simple_refs_example, simple_refs_expected)
+        match url.scheme() {
+            #[cfg(feature = "network")]
+            "http" | "https" if !self.offline => self.fetch(url),
+            "http" | "https" => Err(Error::RemoteFetchDisabled { url: url_string }),
+            "file" => {
+                let mut bytes = Vec::new();
+                {
+                    let _file = self.limits.file_permit();
+                    let limit = self.read_limit();
+                    fs::File::open(file_url::to_path(url))
+                        .and_then(|file| file.take(limit).read_to_end(&mut bytes))
+                        .context(SchemaFromFile {
+                            filename: url_string.clone(),
+                        })?;
+                }
+                self.parse_shared(&url_string, url.path(), &bytes)
+            }
+            "data" => {
+                let (bytes, path) = data::decode(url)?;
+                self.parse_shared(&url_string, path, &bytes)
+            }
+            scheme => Err(Error::UnsupportedScheme {
+                scheme: scheme.to_owned(),
+                reference: url_string,
+            }),
+        }
     }
 
+    /// Parse `bytes` loaded from `url`, reusing the `Value` of a document still in use that was
+    /// parsed from the same bytes.
+    fn parse_shared(&mut self, url: &str, path: &str, bytes: &[u8]) -> Result<Arc<Value>> {
+        self.check_document_size(url, bytes.len())?;
+        let key = content_key(path, bytes);
+        if let Some(shared) = self.by_content.get(&key).and_then(Weak::upgrade) {
+            return Ok(shared);
+        }
+        let value = Arc::new(parse_document(url, path, bytes)?);
+        self.by_content
+            .retain(|_, document| document.strong_count() > 0);
+        self.by_content.insert(key, Arc::downgrade(&value));
+        Ok(value)
+    }
+
+    /// The scope a document at `base` is walked in.
+    fn root_scope(&self, base: &str, document: &Value) -> Result<Scope> {
+        Ok(Scope {
+            document: Arc::from(base),
+            document_pointer: String::new(),
+            base: Arc::new(Url::parse(base).context(UrlParseError { url: base })?),
+            pointer: String::new(),
+            draft: self.document_draft(document, None),
+            names: false,
+        })
+    }
+
+    /// How a definition found at `scope` ranks against others of the same name: the root
+    /// document's win, then those of the document URL and pointer that sort first.
+    ///
+    /// Ranking by where a definition came from, not by when it was reached, keeps the output
+    /// the same whatever order the documents are loaded in.
+    fn hoist_rank(&self, scope: &Scope) -> (bool, String, String) {
+        (
+            *scope.document != *self.root_document,
+            scope.document.to_string(),
+            scope.document_pointer.clone(),
+        )
+    }
+
+    /// The draft `document` is written in, falling back to `enclosing` when it declares none.
+    fn document_draft(&self, document: &Value, enclosing: Option<Draft>) -> Option<Draft> {
+        if self.draft.is_some() {
+            return self.draft;
+        }
+        document
+            .get("$schema")
+            .and_then(Value::as_str)
+            .and_then(Draft::from_schema_uri)
+            .or(enclosing)
+            .or(self.default_draft)
+    }
+
+    /// Dereference `value`, walked from `scope`.
+    ///
+    /// The walk keeps the objects it is inside of on a stack of its own rather than
+    /// recursing, so documents of any depth can be dereferenced. Each object is taken out of
+    /// its parent while its members are walked, and put back once they all are.
+    fn deref(
+        &mut self,
+        value: Value,
+        scope: Scope,
+        used_refs: &mut RefChain,
+        definitions: &mut Value,
+    ) -> Result<Value> {
+        let mut stack = vec![];
+        let mut next = Next::Walk(value, scope);
+        loop {
+            next = match next {
+                Next::Walk(value, scope) => {
+                    self.walk(value, scope, &mut stack, used_refs, definitions)?
+                }
+                Next::Done(value) => match stack.pop() {
+                    Some(frame) => self.resume(frame, value, &mut stack, used_refs)?,
+                    None => return Ok(value),
+                },
+            };
+        }
+    }
+
+    /// Start on `value`: resolve it if it is a `$ref`, else go on to its members.
+    fn walk(
+        &mut self,
+        value: Value,
+        mut scope: Scope,
+        stack: &mut Vec<Frame>,
+        used_refs: &mut RefChain,
+        definitions: &mut Value,
+    ) -> Result<Next> {
+        if value.get(STOP_KEYWORD) == Some(&Value::Bool(true)) {
+            return Ok(Next::Done(value));
+        }
+        if scope.names {
+            // A property named `$ref` is not a ref, nor one named `definitions` definitions.
+            return match value {
+                Value::Object(obj) => self.walk_members(obj, scope, stack, used_refs),
+                other => Ok(Next::Done(other)),
+            };
+        }
+        scope.draft = self.document_draft(&value, scope.draft);
+        let id_keyword = scope.draft.map_or("$id", Draft::id_keyword);
+        if let Some(id_string) = value.get(id_keyword).and_then(Value::as_str) {
+            if !id_string.starts_with('#') {
+                let base = scope.base.join(id_string).context(UrlParseError {
+                    url: id_string.to_owned(),
+                })?;
+                scope.base = Arc::new(base);
+            }
+        }
+        match self.path_filter(&scope.pointer) {
+            PathFilter::Walk => {}
+            PathFilter::Towards => {
+                return match value {
+                    Value::Object(obj) => self.walk_members(obj, scope, stack, used_refs),
+                    Value::Array(items) => Ok(self.walk_items(items, scope, stack)),
+                    other => Ok(Next::Done(other)),
+                };
+            }
+            PathFilter::Skip => return Ok(Next::Done(value)),
+        }
+
+        let mut obj = match value {
+            Value::Object(obj) => obj,
+            // Subschemas of `allOf`, `anyOf`, `oneOf`, tuple `items` and `prefixItems`.
+            Value::Array(items) => return Ok(self.walk_items(items, scope, stack)),
+            other => return Ok(Next::Done(other)),
+        };
+        self.check_keywords(&obj, &scope);
+        let keywords = match (self.mode, self.definitions_mode) {
+            // Bundled schemas keep their definitions, so local refs into them still work.
+            (ResolutionMode::Bundle, _) | (_, DefinitionsMode::KeepInPlace) => &[],
+            (ResolutionMode::Inline, _) => scope
+                .draft
+                .map_or(DEFINITIONS_KEYWORDS, Draft::definitions_keywords),
+        };
+        for keyword in keywords {
+            if let Some(Value::Object(def_obj)) = shift_remove(&mut obj, keyword) {
+                if self.definitions_mode == DefinitionsMode::Drop {
+                    continue;
+                }
+                let accumulated_defs = definitions
+                    .as_object_mut()
+                    .unwrap()
+                    .entry(*keyword)
+                    .or_insert_with(|| json!({}))
+                    .as_object_mut()
+                    .unwrap();
+                for (key, val) in def_obj {
+                    self.collect_definition(accumulated_defs, keyword, key, val, &scope)?;
+                }
+            }
+        }
+
+        let (ref_keyword, ref_value) = match shift_remove(&mut obj, "$ref") {
+            Some(ref_value) => ("$ref", ref_value),
+            None => match self.take_dynamic_ref(&mut obj, &scope) {
+                Some(dynamic_ref) => dynamic_ref,
+                None => return self.walk_members(obj, scope, stack, used_refs),
+            },
+        };
+        let ref_string = match ref_value.as_str() {
+            Some(ref_string) => ref_string,
+            None => return self.walk_members(obj, scope, stack, used_refs),
+        };
+        // The object is left as it is, with its `$ref`, when resolving it fails.
+        macro_rules! fail {
+            ($error:expr) => {{
+                let error = $error;
+                obj.insert(ref_keyword.to_owned(), ref_value.clone());
+                self.collect(&scope, used_refs, ref_string, error)?;
+                return Ok(Next::Done(Value::Object(obj)));
+            }};
+        }
+
+        self.check_deadline()?;
+        let directive = match obj.get(DIRECTIVE_KEYWORD) {
+            Some(directive) => match Directive::from_value(directive) {
+                Some(directive) => Some(directive),
+                None => fail!(Error::InvalidDirective {
+                    value: directive.to_string(),
+                    pointer: scope.pointer.clone(),
+                }),
+            },
+            None => None,
+        };
+        let mode = match directive {
+            Some(Directive::Inline) => ResolutionMode::Inline,
+            Some(Directive::Bundle) => ResolutionMode::Bundle,
+            None if self
+                .max_inline_depth
+                .is_some_and(|max| used_refs.as_slice().len() >= max) =>
+            {
+                ResolutionMode::Bundle
+            }
+            Some(Directive::Keep) | None => self.mode,
+        };
+        if directive.is_some_and(|directive| directive != Directive::Keep) {
+            shift_remove(&mut obj, DIRECTIVE_KEYWORD);
+        }
+        let mut ref_url = match scope.base.join(ref_string) {
+            Ok(ref_url) => ref_url,
+            Err(source) => fail!(Error::UrlParseError {
+                url: ref_string.to_owned(),
+                source,
+            }),
+        };
+        self.query.apply(&mut ref_url);
+        match ref_keyword {
+            DYNAMIC_REF_KEYWORD => ref_url = self.dynamic_target(ref_url, used_refs),
+            RECURSIVE_REF_KEYWORD => ref_url = self.recursive_target(ref_url, used_refs),
+            _ => {}
+        }
+
+        let mut ref_url_no_fragment = ref_url.clone();
+        ref_url_no_fragment.set_fragment(None);
+        let ref_no_fragment = ref_url_no_fragment.to_string();
+
+        let ref_url_string = ref_url.to_string();
+        let kept = if directive == Some(Directive::Keep) {
+            Some(Rule::KeptByDirective)
+        } else if !self.selects(&ref_url_no_fragment) {
+            Some(Rule::KeptBySelection)
+        } else if self.out_of_time() {
+            let site = refs::site(&scope.pointer, ref_string, &scope.base);
+            self.unresolved.push(site);
+            Some(Rule::Unresolved {
+                error: "deadline reached".to_owned(),
+            })
+        } else {
+            None
+        };
+        if let Some(rule) = kept {
+            // A ref into the root from a schema copied out of another document has to be
+            // local to point there from the output.
+            let local = rule == Rule::KeptBySelection
+                && self.selection == RefSelection::ExternalOnly
+                && *scope.document != *self.root_document;
+            let kept_ref = match ref_url.fragment() {
+                Some(fragment) if local => Value::String(format!("#{}", fragment)),
+                None if local => Value::String("#".to_owned()),
+                _ => ref_value.clone(),
+            };
+            if let Value::String(kept) = &kept_ref {
+                if self.definition_collisions == DefinitionCollisions::Namespace
+                    && ref_keyword == "$ref"
+                    && kept.starts_with("#/")
+                {
+                    let document = if local {
+                        &self.root_document
+                    } else {
+                        &*scope.document
+                    };
+                    self.kept_local_refs.push((
+                        scope.pointer.clone(),
+                        document.to_owned(),
+                        kept.clone(),
+                    ));
+                }
+            }
+            obj.insert(ref_keyword.to_owned(), kept_ref);
+            self.trace.push(ResolutionStep {
+                pointer: scope.pointer.clone(),
+                reference: ref_string.to_owned(),
+                base: scope.base.to_string(),
+                url: ref_url_string,
+                document: ref_no_fragment,
+                fragment: ref_url.fragment().map(|fragment| fragment.to_owned()),
+                rules: vec![rule],
+            });
+            // Only the ref is left alone, its siblings are still walked.
+            return self.walk_members(obj, scope, stack, used_refs);
+        }
+        if let Some(limit) = self.document_ref_budget {
+            let count = self
+                .document_refs
+                .entry(scope.document.to_string())
+                .or_default();
+            *count += 1;
+            if *count > limit {
+                return Err(Error::DocumentBudgetExceeded {
+                    document: scope.document.to_string(),
+                    limit,
+                });
+            }
+        }
+        let target = self.document(&ref_url_no_fragment).and_then(|document| {
+            let pointer = match ref_url.fragment() {
+                Some(ref_fragment) => self.fragment_pointer(ref_string, &document, ref_fragment)?,
+                None => String::new(),
+            };
+            let schema = deep::clone(document.pointer(&pointer).unwrap_or(&Value::Null));
+            Ok((self.document_draft(&document, None), pointer, schema))
+        });
+        let (target_draft, target_pointer, schema) = match target {
+            Ok(target) => {
+                self.count_expansion(&target.2)?;
+                target
+            }
+            Err(error) => {
+                if self.on_missing == OnMissing::Warn {
+                    self.trace.push(ResolutionStep {
+                        pointer: scope.pointer.clone(),
+                        reference: ref_string.to_owned(),
+                        base: scope.base.to_string(),
+                        url: ref_url_string,
+                        document: ref_no_fragment,
+                        fragment: ref_url.fragment().map(|fragment| fragment.to_owned()),
+                        rules: vec![Rule::Unresolved {
+                            error: error.to_string(),
+                        }],
+                    });
+                }
+                if self.on_missing == OnMissing::Error || self.collected.is_some() {
+                    fail!(error);
+                }
+                obj.insert(ref_keyword.to_owned(), ref_value.clone());
+                return Ok(Next::Done(Value::Object(obj)));
+            }
+        };
+
+        let target_scope = Scope {
+            document: Arc::from(ref_no_fragment.as_str()),
+            document_pointer: target_pointer,
+            base: Arc::new(ref_url_no_fragment),
+            pointer: scope.pointer.clone(),
+            draft: target_draft,
+            names: false,
+        };
+
+        let step = self.trace.len();
+        self.trace.push(ResolutionStep {
+            pointer: scope.pointer.clone(),
+            reference: ref_string.to_owned(),
+            base: scope.base.to_string(),
+            url: ref_url_string.clone(),
+            document: ref_no_fragment.clone(),
+            fragment: ref_url.fragment().map(|fragment| fragment.to_owned()),
+            rules: vec![],
+        });
+
+        if mode == ResolutionMode::Bundle {
+            let (local_ref, new) = if self.is_root(&target_scope.base) {
+                (format!("#{}", ref_url.fragment().unwrap_or("")), false)
+            } else {
+                self.bundle.local_ref(&ref_url)
+            };
+            obj.insert("$ref".to_owned(), Value::String(local_ref.clone()));
+            // The target is walked where it is stored in the output.
+            let pointer = local_ref[1..].to_owned();
+            self.trace[step].rules.push(Rule::Bundled { local_ref });
+            if new {
+                stack.push(Frame::Bundled {
+                    ref_url,
+                    holder: obj,
+                    scope,
+                });
+                return Ok(Next::Walk(
+                    schema,
+                    Scope {
+                        pointer,
+                        ..target_scope
+                    },
+                ));
+            }
+            return self.walk_members(obj, scope, stack, used_refs);
+        }
+
+        let depth = used_refs.depth(&ref_url_string);
+        if depth > 0 {
+            match &self.recursion {
+                RecursionStrategy::Error => fail!(Error::RecursiveRef {
+                    reference: ref_string.to_owned(),
+                    pointer: scope.pointer.clone(),
+                }),
+                RecursionStrategy::KeepRef => {
+                    obj.insert(ref_keyword.to_owned(), ref_value.clone());
+                    self.trace[step].rules.push(Rule::CycleKept);
+                    return Ok(Next::Done(Value::Object(obj)));
+                }
+                RecursionStrategy::Stub(stub) => {
+                    let stub = stub.clone();
+                    self.trace[step].rules.push(Rule::CycleStubbed);
+                    return Ok(Next::Done(stub));
+                }
+                RecursionStrategy::Depth(max) if depth >= *max => {
+                    self.trace[step].rules.push(Rule::CycleTruncated);
+                    return Ok(Next::Done(Value::Object(obj)));
+                }
+                RecursionStrategy::Definitions => {
+                    // The expansion further up stores the target once the cycle is named.
+                    let (local_ref, _) = self.bundle.local_ref(&ref_url);
+                    obj.insert("$ref".to_owned(), Value::String(local_ref.clone()));
+                    self.trace[step]
+                        .rules
+                        .push(Rule::CycleDefinition { local_ref });
+                    return Ok(Next::Done(Value::Object(obj)));
+                }
+                RecursionStrategy::Depth(_) => {}
+            }
+        }
+
+        let expansion = Expansion {
+            ref_url,
+            step,
+            holder: obj,
+            scope,
+            marks: self.marks(),
+        };
+        // A recursive schema is moved into the definitions the first time it is expanded,
+        // later refs to it only point there.
+        if self.recursion == RecursionStrategy::Definitions
+            && self.bundle.has_schema(&expansion.ref_url)
+        {
+            return Ok(self.expanded(expansion, schema, stack));
+        }
+        used_refs.push(ref_url_string);
+        stack.push(Frame::Expanding(expansion));
+        Ok(Next::Walk(schema, target_scope))
+    }
+
+    /// Go on to the next member of `obj` not walked yet, or be done with it.
+    ///
+    /// Every member but the data of `const`, `default`, `enum` and `examples` is walked, so
+    /// subschemas under any object-valued applicator (`patternProperties`,
+    /// `dependentSchemas`/`dependencies`, `if`/`then`/`else`, `not`, `contentSchema`,
+    /// `unevaluatedProperties`, ...) are reached whatever the draft.
+    fn walk_members(
+        &mut self,
+        obj: Map<String, Value>,
+        scope: Scope,
+        stack: &mut Vec<Frame>,
+        used_refs: &RefChain,
+    ) -> Result<Next> {
+        let members = Members {
+            walked: Vec::with_capacity(obj.len()),
+            left: obj.into_iter(),
+            key: String::new(),
+            scope,
+        };
+        self.next_member(members, stack, used_refs)
+    }
+
+    fn next_member(
+        &mut self,
+        mut members: Members,
+        stack: &mut Vec<Frame>,
+        used_refs: &RefChain,
+    ) -> Result<Next> {
+        for (key, member) in members.left.by_ref() {
+            // Nothing in a string, number, boolean or null is dereferenced.
+            if !member.is_object() && !member.is_array()
+                || !members.scope.names && DATA_KEYWORDS.contains(&key.as_str())
+            {
+                members.walked.push((key, member));
+                continue;
+            }
+            if key == VOCABULARY_KEYWORD && vocabulary::is_declaration(&member) {
+                if self.fetch_vocabularies && self.loads_documents() {
+                    let scope = members.scope.child(&key);
+                    self.load_vocabularies(&member, &scope, used_refs)?;
+                }
+                members.walked.push((key, member));
+                continue;
+            }
+            let scope = members.scope.child(&key);
+            members.key = key;
+            stack.push(Frame::Members(members));
+            return Ok(Next::Walk(member, scope));
+        }
+        Ok(Next::Done(Value::Object(
+            members.walked.into_iter().collect(),
+        )))
+    }
+
+    /// Go on to the first item of `items`, or be done with it.
+    fn walk_items(&mut self, items: Vec<Value>, scope: Scope, stack: &mut Vec<Frame>) -> Next {
+        let items = Items {
+            walked: Vec::with_capacity(items.len()),
+            left: items.into_iter(),
+            scope,
+        };
+        self.next_item(items, stack)
+    }
+
+    /// Go on to the next item of an array not walked yet, or be done with it.
+    fn next_item(&mut self, mut items: Items, stack: &mut Vec<Frame>) -> Next {
+        for item in items.left.by_ref() {
+            if !item.is_object() && !item.is_array() {
+                items.walked.push(item);
+                continue;
+            }
+            let scope = items.scope.child(&items.walked.len().to_string());
+            stack.push(Frame::Items(items));
+            return Next::Walk(item, scope);
+        }
+        Next::Done(Value::Array(items.walked))
+    }
+
+    /// Carry on with `frame` now that the walk of `value` below it is done.
+    fn resume(
+        &mut self,
+        frame: Frame,
+        value: Value,
+        stack: &mut Vec<Frame>,
+        used_refs: &mut RefChain,
+    ) -> Result<Next> {
+        match frame {
+            Frame::Members(mut members) => {
+                let key = mem::take(&mut members.key);
+                members.walked.push((key, value));
+                self.next_member(members, stack, used_refs)
+            }
+            Frame::Items(mut items) => {
+                items.walked.push(value);
+                Ok(self.next_item(items, stack))
+            }
+            Frame::Bundled {
+                ref_url,
+                holder,
+                scope,
+            } => {
+                self.bundle.add(&ref_url, value);
+                self.walk_members(holder, scope, stack, used_refs)
+            }
+            Frame::Expanding(expansion) => {
+                used_refs.pop();
+                Ok(self.expanded(expansion, value, stack))
+            }
+            Frame::SiblingsAllOf { target, step } => {
+                self.trace[step].rules.push(Rule::SiblingsAllOf);
+                Ok(Next::Done(json!({ "allOf": [target, value] })))
+            }
+            Frame::SiblingsMerged { mut target, step } => {
+                self.trace[step].rules.push(Rule::SiblingsMerged);
+                match value {
+                    Value::Object(siblings) => target.extend(siblings),
+                    value => return Ok(Next::Done(json!({ "allOf": [target, value] }))),
+                }
+                Ok(Next::Done(Value::Object(target)))
+            }
+            Frame::SiblingsStored {
+                mut target,
+                key,
+                step,
+            } => {
+                target[&key] = value;
+                self.trace[step].rules.push(Rule::SiblingsStored { key });
+                Ok(Next::Done(target))
+            }
+        }
+    }
+
+    /// Replace the object holding a `$ref` by `schema`, its dereferenced target, dealing
+    /// with the siblings of the `$ref`.
+    fn expanded(
+        &mut self,
+        expansion: Expansion,
+        mut schema: Value,
+        stack: &mut Vec<Frame>,
+    ) -> Next {
+        let Expansion {
+            ref_url,
+            step,
+            holder,
+            scope,
+            marks,
+        } = expansion;
+        let moved = self.recursion == RecursionStrategy::Definitions;
+        if let Some(local_ref) = self.bundle.named_ref(&ref_url).filter(|_| moved) {
+            if !self.bundle.has_schema(&ref_url) {
+                self.bundle.add(&ref_url, schema);
+                self.relocate(marks, &scope.pointer, &local_ref[1..]);
+            }
+            schema = json!({ "$ref": local_ref });
+            self.trace[step]
+                .rules
+                .push(Rule::CycleDefinition { local_ref });
+        }
+
+        let strategy = self.sibling_strategy.resolve(scope.draft);
+        if !holder.is_empty() && strategy != SiblingStrategy::Discard {
+            let (frame, scope) = match schema {
+                Value::Object(target) if strategy == SiblingStrategy::Merge => {
+                    (Frame::SiblingsMerged { target, step }, scope)
+                }
+                target => {
+                    let all_of = explain::push_pointer(&scope.pointer, "allOf");
+                    let pointer = explain::push_pointer(&all_of, "0");
+                    self.relocate(marks, &scope.pointer, &pointer);
+                    let pointer = explain::push_pointer(&all_of, "1");
+                    (
+                        Frame::SiblingsAllOf { target, step },
+                        Scope { pointer, ..scope },
+                    )
+                }
+            };
+            stack.push(frame);
+            return Next::Walk(Value::Object(holder), scope);
+        }
+        if let Some(reference_key) = self.reference_key.clone() {
+            if schema.is_object() {
+                let siblings_scope = scope.child(&reference_key);
+                stack.push(Frame::SiblingsStored {
+                    target: schema,
+                    key: reference_key,
+                    step,
+                });
+                return Next::Walk(Value::Object(holder), siblings_scope);
+            }
+        } else if !holder.is_empty() {
+            self.trace[step].rules.push(Rule::SiblingsDiscarded);
+        }
+        // The target was dereferenced already, walking it again would expand refs kept by
+        // the recursion strategy.
+        Next::Done(schema)
+    }
+}
+
+/// What the walk does next.
+enum Next {
+    /// Walk this value, at this scope.
+    Walk(Value, Scope),
+    /// Hand this value, walked, to the frame on top of the stack.
+    Done(Value),
+}
+
+/// Something the walk is in the middle of, waiting for a value below it to be walked.
+enum Frame {
+    /// Walking the members of an object.
+    Members(Members),
+    /// Walking the items of an array.
+    Items(Items),
+    /// Walking the target of a `$ref` being bundled, to store it, then the members of the
+    /// object holding the ref, at `scope`.
+    Bundled {
+        ref_url: Url,
+        holder: Map<String, Value>,
+        scope: Scope,
+    },
+    /// Walking the target of a `$ref` being inlined.
+    Expanding(Expansion),
+    /// Walking the siblings of an inlined `$ref`, to go in an `allOf` with its `target`.
+    SiblingsAllOf { target: Value, step: usize },
+    /// Walking the siblings of an inlined `$ref`, to be merged over its `target`.
+    SiblingsMerged {
+        target: Map<String, Value>,
+        step: usize,
+    },
+    /// Walking the siblings of an inlined `$ref`, to go under `key` in its `target`.
+    SiblingsStored {
+        target: Value,
+        key: String,
+        step: usize,
+    },
+}
+
+/// An object whose members are being walked.
+struct Members {
+    /// The members walked, not counting the one being walked, in the order of `left`, so
+    /// that the object is built from them at once rather than key by key.
+    walked: Vec<(String, Value)>,
+    left: map::IntoIter,
+    /// Name of the member being walked.
+    key: String,
+    scope: Scope,
+}
+
+/// An array whose items are being walked.
+struct Items {
+    /// The items walked, not counting the one being walked.
+    walked: Vec<Value>,
+    left: std::vec::IntoIter<Value>,
+    scope: Scope,
+}
+
+/// A `$ref` being inlined: the object holding it, without the ref, at `scope`, and the index
+/// of its step in the trace.
+struct Expansion {
+    ref_url: Url,
+    step: usize,
+    holder: Map<String, Value>,
+    scope: Scope,
+    /// What had been recorded when the target started to be walked.
+    marks: Marks,
+}
+
+/// How many steps, unresolved refs and kept local refs, recorded by their pointers in the
+/// output, there were when the walk of a value began.
+#[derive(Debug, Clone, Copy)]
+struct Marks {
+    trace: usize,
+    unresolved: usize,
+    kept_local_refs: usize,
+}
+
+impl JsonRef {
+    fn marks(&self) -> Marks {
+        Marks {
+            trace: self.trace.len(),
+            unresolved: self.unresolved.len(),
+            kept_local_refs: self.kept_local_refs.len(),
+        }
+    }
+
+    /// Move what was recorded since `marks` at or under the pointer `from` to under `to`, as
+    /// the value walked at `from` ends up there in the output.
+    fn relocate(&mut self, marks: Marks, from: &str, to: &str) {
+        let relocate = |pointer: &mut String| {
+            if explain::pointer_starts_with(pointer, from) {
+                pointer.replace_range(..from.len(), to);
+            }
+        };
+        for step in &mut self.trace[marks.trace..] {
+            relocate(&mut step.pointer);
+        }
+        for site in &mut self.unresolved[marks.unresolved..] {
+            relocate(&mut site.pointer);
+        }
+        for (pointer, _, _) in &mut self.kept_local_refs[marks.kept_local_refs..] {
+            relocate(pointer);
+        }
+    }
+
+    /// Note `error` from the `$ref` `reference` in `scope` if errors are being collected, or
+    /// fail with it, in either case saying where the ref is.
+    fn collect(
+        &mut self,
+        scope: &Scope,
+        used_refs: &RefChain,
+        reference: &str,
+        error: Error,
+    ) -> Result<()> {
+        let error = Error::RefFailed {
+            reference: reference.to_owned(),
+            document: scope.document.to_string(),
+            pointer: scope.document_pointer.clone(),
+            chain: used_refs.as_slice().to_vec(),
+            source: Box::new(error),
+        };
+        match &mut self.collected {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+}
+
+/// Where a value sits during the walk.
+///
+/// The URLs are shared, as they are the same for most of a document, while the pointers are
+/// built anew for every value.
+#[derive(Debug, Clone)]
+struct Scope {
+    /// URL of the document the value was loaded from.
+    document: Arc<str>,
+    /// JSON pointer of the value within `document`.
+    document_pointer: String,
+    /// Base URL that relative refs are resolved against.
+    base: Arc<Url>,
+    /// JSON pointer of the value in the output.
+    pointer: String,
+    /// Draft of the enclosing schema resource, if known.
+    draft: Option<Draft>,
+    /// Whether the value maps names to subschemas, as under `properties`, rather than
+    /// keywords to their values.
+    names: bool,
+}
+
+impl Scope {
+    fn child(&self, key: &str) -> Scope {
+        Scope {
+            document: Arc::clone(&self.document),
+            document_pointer: explain::push_pointer(&self.document_pointer, key),
+            base: Arc::clone(&self.base),
+            pointer: explain::push_pointer(&self.pointer, key),
+            draft: self.draft,
+            names: !self.names && NAMED_SUBSCHEMAS_KEYWORDS.contains(&key),
+        }
+    }
+}
+
+/// Parse the document at `url` from `reader`.
+///
+/// With the `yaml` feature, documents whose `path` ends in `.yaml` or `.yml` are parsed as YAML.
+#[cfg_attr(not(feature = "yaml"), allow(unused_variables))]
+fn parse_document(url: &str, path: &str, reader: impl io::Read) -> Result<Value> {
+    #[cfg(feature = "yaml")]
+    if yaml::is_yaml_path(path) {
+        return yaml::from_reader(url, reader);
+    }
+    serde_json::from_reader(reader).context(SchemaNotJsonSerde {
+        url: url.to_owned(),
+    })
+}
+
+/// Serialize `value` as JSON into `writer`.
+fn write_json(writer: impl io::Write, value: &Value, pretty: bool) -> Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    if pretty {
+        serde_json::to_writer_pretty(&mut writer, value).context(WriteOutput {})?;
+    } else {
+        serde_json::to_writer(&mut writer, value).context(WriteOutput {})?;
+    }
+    io::Write::flush(&mut writer)
+        .map_err(serde_json::Error::io)
+        .context(WriteOutput {})
+}
+
+/// Identity of a document's content: a hash of its bytes and of how `path` has it parsed.
+#[cfg_attr(not(feature = "yaml"), allow(unused_variables))]
+fn content_key(path: &str, bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    #[cfg(feature = "yaml")]
+    yaml::is_yaml_path(path).hash(&mut hasher);
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Keywords holding reusable subschemas, which are collected at the root of the output.
+///
+/// `definitions` is the draft-07 and earlier spelling, `$defs` the 2019-09 onwards one.
+const DEFINITIONS_KEYWORDS: &[&str] = &["definitions", "$defs"];
+/// Keywords whose value maps names to subschemas.
+const NAMED_SUBSCHEMAS_KEYWORDS: &[&str] = &[
+    "$defs",
+    "definitions",
+    "dependencies",
+    "dependentSchemas",
+    "patternProperties",
+    "properties",
+];
+/// Keywords whose value is instance data, where a `$ref` member is data too.
+const DATA_KEYWORDS: &[&str] = &["const", "default", "enum", "examples"];
+
+/// Add the accumulated definitions to the root of the output, if any were found.
+///
+/// `definitions` maps each of [`DEFINITIONS_KEYWORDS`] to what was collected under it. They
+/// go next to those the root still has, which were not walked or are kept when bundling,
+/// and which win over collected ones of the same name.
+fn insert_definitions(value: &mut Value, definitions: Value) {
+    let Value::Object(collected) = definitions else {
+        return;
+    };
+    for (keyword, defs) in collected {
+        let (Some(obj), Value::Object(defs)) = (value.as_object_mut(), defs) else {
+            continue;
+        };
+        if defs.is_empty() {
+            continue;
+        }
+        match obj.get_mut(&keyword) {
+            Some(Value::Object(kept)) => {
+                for (name, definition) in defs {
+                    kept.entry(name).or_insert(definition);
+                }
+            }
+            _ => {
+                obj.insert(keyword, Value::Object(defs));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_url, Error, JsonRef, OnMissing, RecursionStrategy, ResolutionMode, Rule};
+    use serde_json::{json, Map, Value};
+    use std::fs;
+    use std::sync::Arc;
+
     #[test]
+    fn json_no_refs() {
+        let no_ref_example = json!({"properties": {"prop1": {"title": "proptitle"}}});
+
+        let mut jsonref = JsonRef::new();
+
+        let mut input = no_ref_example.clone();
+
+        jsonref.deref_value(&mut input).unwrap();
+
+        assert_eq!(input, no_ref_example)
+    }
+
+    #[test]
+    fn json_with_recursion() {
+        let mut simple_refs_example = json!(
+            {"properties": {"prop1": {"$ref": "#"}}}
+        );
+
+        let simple_refs_expected = json!(
+            {"properties": {"prop1": {"properties": {"prop1": {}}}}
+            }
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut simple_refs_example).unwrap();
+        jsonref.set_reference_key("__reference__");
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&simple_refs_example).unwrap()
+        );
+
+        assert_eq!(simple_refs_example, simple_refs_expected)
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
+    fn simple_from_url() {
+        let mut simple_refs_example = json!(
+            {"properties": {"prop1": {"title": "name"},
+                            "prop2": {"$ref": "https://gist.githubusercontent.com/kindly/35a631d33792413ed8e34548abaa9d61/raw/b43dc7a76cc2a04fde2a2087f0eb389099b952fb/test.json", "title": "old_title"}}
+            }
+        );
+
+        let simple_refs_expected = json!(
+            {"properties": {"prop1": {"title": "name"},
+                            "prop2": {"title": "title from url", "__reference__": {"title": "old_title"}}}
+            }
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_reference_key("__reference__");
+        jsonref.deref_value(&mut simple_refs_example).unwrap();
+
+        assert_eq!(simple_refs_example, simple_refs_expected)
+    }
+
+    #[test]
+    #[cfg(feature = "network")]
     fn nested_with_ref_from_url() {
         let mut simple_refs_example = json!(
             {"properties": {"prop1": {"title": "name"},
@@ -508,6 +2362,104 @@ mod tests {
         assert_eq!(simple_refs_example, simple_refs_expected)
     }
 
+    #[test]
+    fn resolver_supplies_external_refs() {
+        let mut input = json!(
+            {"properties": {"prop1": {"$ref": "https://example.com/schema.json#/properties/a"},
+                            "prop2": {"$ref": "fixtures/nested_relative/other.json#/properties/prop1"}}
+            }
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolver(Box::new(|url: &url::Url| {
+            if url.scheme() == "file" {
+                return Ok(None);
+            }
+            assert_eq!(url.as_str(), "https://example.com/schema.json");
+            Ok(Some(
+                json!({"properties": {"a": {"title": "from resolver"}}}),
+            ))
+        }));
+        jsonref.deref_value(&mut input).unwrap();
+
+        assert_eq!(
+            input,
+            json!({"properties": {"prop1": {"title": "from resolver"},
+                                  "prop2": {"title": "sub property title in other.json"}}})
+        );
+
+        let mut failing = JsonRef::new();
+        failing.set_resolver(Box::new(|_: &url::Url| Err("store unavailable".into())));
+        let mut input = json!({"$ref": "https://example.com/schema.json"});
+        let err = failing.deref_value(&mut input).unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "resolver failed to load https://example.com/schema.json: store unavailable"
+        );
+    }
+
+    #[test]
+    fn add_schema_avoids_loading() {
+        let mut input = json!(
+            {"properties": {"prop1": {"$ref": "https://example.com/schemas/base.json#/properties/a"},
+                            "prop2": {"$ref": "https://example.com/schemas/other.json"}}
+            }
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/schemas/base.json#",
+                json!({"properties": {"a": {"$ref": "other.json"}}}),
+            )
+            .unwrap();
+        jsonref
+            .add_schema(
+                "https://example.com/schemas/other.json",
+                json!({"title": "other"}),
+            )
+            .unwrap();
+        jsonref.deref_value(&mut input).unwrap();
+
+        assert_eq!(
+            input,
+            json!({"properties": {"prop1": {"title": "other"}, "prop2": {"title": "other"}}})
+        );
+        assert!(jsonref.add_schema("not a url", json!({})).is_err());
+    }
+
+    #[test]
+    fn offline_mode_refuses_http() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_offline(true);
+        jsonref
+            .add_schema(
+                "https://example.com/cached.json",
+                json!({"title": "cached"}),
+            )
+            .unwrap();
+
+        let mut cached = json!({"$ref": "https://example.com/cached.json"});
+        jsonref.deref_value(&mut cached).unwrap();
+        assert_eq!(cached, json!({"title": "cached"}));
+
+        let mut remote = json!({"properties": {"a": {"$ref": "https://example.com/remote.json"}}});
+        match jsonref
+            .deref_value(&mut remote)
+            .as_ref()
+            .map_err(Error::root_cause)
+        {
+            Err(Error::RemoteFetchDisabled { url }) => {
+                assert_eq!(url, "https://example.com/remote.json")
+            }
+            other => panic!("expected RemoteFetchDisabled, got {:?}", other),
+        }
+        assert!(matches!(
+            jsonref.deref_url("https://example.com/remote.json"),
+            Err(Error::RemoteFetchDisabled { .. })
+        ));
+    }
+
     #[test]
     fn nested_ref_from_local_file() {
         let mut jsonref = JsonRef::new();
@@ -524,6 +2476,583 @@ mod tests {
         assert_eq!(file_example, file_expected)
     }
 
+    #[test]
+    fn dollar_defs_resolve_and_accumulate() {
+        let mut input = json!(
+            {"$defs": {"Address": {"title": "address", "$ref": "#/definitions/Street"}},
+             "definitions": {"Street": {"title": "street"}},
+             "properties": {"home": {"$ref": "#/$defs/Address"},
+                            "work": {"$defs": {"Office": {"title": "office"}},
+                                     "$ref": "#/properties/work/$defs/Office"}}}
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut input).unwrap();
+
+        assert_eq!(
+            input,
+            json!({"$defs": {"Address": {"title": "address", "$ref": "#/definitions/Street"},
+                             "Office": {"title": "office"}},
+                   "definitions": {"Street": {"title": "street"}},
+                   "properties": {"home": {"title": "street"},
+                                  "work": {"title": "office"}}})
+        );
+    }
+
+    #[test]
+    fn draft_04_id_changes_base() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/v1/other.json",
+                json!({"title": "v1 other"}),
+            )
+            .unwrap();
+        let mut input = json!(
+            {"$schema": "http://json-schema.org/draft-04/schema#",
+             "id": "https://example.com/v1/root.json",
+             "$id": "https://example.com/v2/root.json",
+             "properties": {"prop1": {"$ref": "other.json"}}}
+        );
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input["properties"]["prop1"], json!({"title": "v1 other"}));
+    }
+
+    #[test]
+    fn draft_2019_09_siblings_apply() {
+        let mut input = json!(
+            {"$schema": "https://json-schema.org/draft/2019-09/schema",
+             "$defs": {"name": {"type": "string"}},
+             "properties": {"prop1": {"$ref": "#/$defs/name", "maxLength": 5}}}
+        );
+        let mut jsonref = JsonRef::new();
+        jsonref.set_reference_key("__reference__");
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(
+            input["properties"]["prop1"],
+            json!({"allOf": [{"type": "string"}, {"maxLength": 5}]})
+        );
+        assert_eq!(
+            jsonref.explain("/properties/prop1").steps[0].rules,
+            vec![Rule::SiblingsAllOf]
+        );
+    }
+
+    #[test]
+    fn draft_07_ignores_dollar_defs() {
+        let mut input = json!(
+            {"$schema": "http://json-schema.org/draft-07/schema#",
+             "definitions": {"a": {"title": "a"}},
+             "properties": {"prop1": {"$ref": "#/definitions/a", "title": "ignored"},
+                            "prop2": {"$defs": {"b": {"title": "b"}}}}}
+        );
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input["properties"]["prop1"], json!({"title": "a"}));
+        assert_eq!(
+            input["properties"]["prop2"],
+            json!({"$defs": {"b": {"title": "b"}}})
+        );
+        assert!(input.get("$defs").is_none());
+    }
+
+    #[test]
+    fn recursion_strategies() {
+        let input = json!({"properties": {"child": {"$ref": "#", "title": "child"}}});
+        let deref = |strategy| {
+            let mut jsonref = JsonRef::new();
+            jsonref.set_recursion_strategy(strategy);
+            let mut value = input.clone();
+            jsonref.deref_value(&mut value).map(|_| value)
+        };
+
+        assert!(matches!(
+            deref(RecursionStrategy::Error)
+                .as_ref()
+                .map_err(Error::root_cause),
+            Err(Error::RecursiveRef { .. })
+        ));
+        assert_eq!(
+            deref(RecursionStrategy::Depth(2)).unwrap(),
+            json!({"properties": {"child": {"properties": {"child": {"properties": {"child": {"title": "child"}}}}}}})
+        );
+        assert_eq!(
+            deref(RecursionStrategy::Stub(json!({"type": "object"}))).unwrap(),
+            json!({"properties": {"child": {"properties": {"child": {"type": "object"}}}}})
+        );
+
+        let mut tree = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "properties": {"root": {"$ref": "#/definitions/Node"}},
+            "definitions": {"Node": {"properties": {"children": {"items": {"$ref": "#/definitions/Node"}},
+                                                    "label": {"$ref": "#/definitions/Label"}}},
+                            "Label": {"type": "string"}}
+        });
+        let mut jsonref = JsonRef::new();
+        jsonref.set_recursion_strategy(RecursionStrategy::Definitions);
+        jsonref.deref_value(&mut tree).unwrap();
+        assert_eq!(
+            tree["properties"]["root"],
+            json!({"$ref": "#/definitions/anon_json_Node"})
+        );
+        assert_eq!(
+            tree["definitions"]["anon_json_Node"],
+            json!({"properties": {"children": {"items": {"$ref": "#/definitions/anon_json_Node"}},
+                                  "label": {"type": "string"}}})
+        );
+        assert!(tree["definitions"]["Node"].is_object());
+    }
+
+    #[test]
+    fn on_missing_warn_records_error() {
+        let mut input =
+            json!({"properties": {"gone": {"$ref": "fixtures/missing.json", "title": "gone"}}});
+        let mut jsonref = JsonRef::new();
+        assert!(matches!(
+            jsonref
+                .deref_value(&mut input.clone())
+                .as_ref()
+                .map_err(Error::root_cause),
+            Err(Error::SchemaFromFile { .. })
+        ));
+
+        jsonref.set_on_missing(OnMissing::Warn);
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(
+            input,
+            json!({"properties": {"gone": {"$ref": "fixtures/missing.json", "title": "gone"}}})
+        );
+        let steps = jsonref.explain("/properties/gone").steps;
+        assert!(
+            matches!(&steps[0].rules[..], [Rule::Unresolved { error }] if error.contains("missing.json"))
+        );
+    }
+
+    #[test]
+    fn errors_say_where_the_ref_is() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/a.json",
+                json!({"properties": {"b": {"$ref": "b.json#/definitions/b"}}}),
+            )
+            .unwrap();
+        jsonref
+            .add_schema(
+                "https://example.com/b.json",
+                json!({"definitions": {"b": {"items": {"$ref": "#/definitions/missing"}}}}),
+            )
+            .unwrap();
+        let mut input = json!({"items": {"$ref": "https://example.com/a.json"}});
+        let error = jsonref.deref_value(&mut input).unwrap_err();
+        match &error {
+            Error::RefFailed {
+                reference,
+                document,
+                pointer,
+                chain,
+                ..
+            } => {
+                assert_eq!(reference, "#/definitions/missing");
+                assert_eq!(document, "https://example.com/b.json");
+                assert_eq!(pointer, "/definitions/b/items");
+                assert_eq!(
+                    chain,
+                    &[
+                        "https://example.com/a.json",
+                        "https://example.com/b.json#/definitions/b"
+                    ]
+                );
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert!(matches!(
+            error.root_cause(),
+            Error::JsonPointerNotFound { .. }
+        ));
+        assert!(error.to_string().starts_with(
+            "could not resolve `#/definitions/missing` at /definitions/b/items in \
+             https://example.com/b.json (via https://example.com/a.json → \
+             https://example.com/b.json#/definitions/b): "
+        ));
+    }
+
+    #[test]
+    fn unsupported_scheme_is_an_error() {
+        let mut input = json!({"items": {"$ref": "ftp://example.com/schema.json"}});
+        let error = JsonRef::new().deref_value(&mut input).unwrap_err();
+        match error.root_cause() {
+            Error::UnsupportedScheme { scheme, reference } => {
+                assert_eq!(scheme, "ftp");
+                assert_eq!(reference, "ftp://example.com/schema.json");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn document_ref_budget() {
+        let mut jsonref = JsonRef::new();
+        // base.json has four refs and other.json two, more than four in all.
+        jsonref.set_document_ref_budget(Some(4));
+        let value = jsonref
+            .deref_file("fixtures/nested_relative/base.json")
+            .unwrap();
+        assert_eq!(
+            value["properties"]["prop5"],
+            json!({"title": "sub property title in other.json"})
+        );
+
+        jsonref.set_document_ref_budget(Some(3));
+        match jsonref.deref_file("fixtures/nested_relative/base.json") {
+            Err(Error::DocumentBudgetExceeded { document, limit }) => {
+                assert!(document.ends_with("base.json"));
+                assert_eq!(limit, 3);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        // Only the ref that is resolved counts.
+        jsonref.set_document_ref_budget(Some(1));
+        let mut input = json!({
+            "properties": {
+                "kept": {"$ref": "#/$defs/a", "x-jsonref": "keep"},
+                "resolved": {"$ref": "#/$defs/a"}
+            },
+            "$defs": {"a": {"title": "a"}}
+        });
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input["properties"]["resolved"], json!({"title": "a"}));
+    }
+
+    #[test]
+    fn identical_documents_are_shared() {
+        let mut jsonref = JsonRef::new();
+        let value = jsonref.deref_file("fixtures/mirror/base.json").unwrap();
+        assert_eq!(value["properties"]["home"], value["properties"]["work"]);
+
+        let cached = |name: &str| {
+            let url = file_url::from_path(format!("fixtures/mirror/{}", name)).unwrap();
+            jsonref.schema_cache.get(url.as_str()).unwrap()
+        };
+        assert!(Arc::ptr_eq(
+            &cached("address.json"),
+            &cached("address_copy.json")
+        ));
+    }
+
+    #[test]
+    fn hoisting_does_not_depend_on_load_order() {
+        let hoisted = |first: &str, second: &str, root_defs: bool| {
+            let mut jsonref = JsonRef::new();
+            for name in ["a", "z"] {
+                jsonref
+                    .add_schema(
+                        &format!("https://example.com/{}.json", name),
+                        json!({"definitions": {"Shared": {"title": name}}}),
+                    )
+                    .unwrap();
+            }
+            let mut schema = json!({"properties": {"first": {"$ref": first},
+                                                   "second": {"$ref": second}}});
+            if root_defs {
+                schema["definitions"] = json!({"Shared": {"title": "root"}});
+            }
+            jsonref.deref_value(&mut schema).unwrap();
+            schema["definitions"]["Shared"]["title"].clone()
+        };
+        let (a, z) = ("https://example.com/a.json", "https://example.com/z.json");
+        assert_eq!(hoisted(a, z, false), "a");
+        assert_eq!(hoisted(z, a, false), "a");
+        assert_eq!(hoisted(z, a, true), "root");
+    }
+
+    #[test]
+    fn directives_override_the_mode() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/pet.json",
+                json!({"definitions": {"Name": {"type": "string"}}}),
+            )
+            .unwrap();
+        let pet = "https://example.com/pet.json#/definitions/Name";
+        let mut schema = json!({"properties": {
+            "inlined": {"$ref": pet},
+            "bundled": {"$ref": pet, "x-jsonref": "bundle"},
+            "kept": {"$ref": pet, "x-jsonref": "keep", "items": {"$ref": pet}}
+        }});
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(
+            schema,
+            json!({
+                "properties": {
+                    "inlined": {"type": "string"},
+                    "bundled": {"$ref": "#/$defs/pet_json_Name"},
+                    "kept": {"$ref": pet, "x-jsonref": "keep", "items": {"type": "string"}}
+                },
+                "$defs": {"pet_json_Name": {"type": "string"}}
+            })
+        );
+        assert!(jsonref
+            .explain("/properties/kept")
+            .steps
+            .iter()
+            .any(|step| step.rules == [Rule::KeptByDirective]));
+
+        jsonref.set_resolution_mode(ResolutionMode::Bundle);
+        let mut schema = json!({"items": {"$ref": pet, "x-jsonref": "inline"}});
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(schema, json!({"items": {"type": "string"}}));
+
+        let mut schema = json!({"items": {"$ref": pet, "x-jsonref": "skip"}});
+        let error = jsonref.deref_value(&mut schema).unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            Error::InvalidDirective { pointer, .. } if pointer == "/items"
+        ));
+    }
+
+    #[test]
+    fn stop_directive_leaves_subtree_alone() {
+        let mut schema = json!({
+            "properties": {
+                "name": {"$ref": "#/definitions/name"},
+                "example": {"$ref": "#/examples/0"}
+            },
+            "examples": [{"x-jsonref-stop": true, "items": {"$ref": "#/definitions/name"}}],
+            "definitions": {"name": {"type": "string"}}
+        });
+        JsonRef::new().deref_value(&mut schema).unwrap();
+        assert_eq!(schema["properties"]["name"], json!({"type": "string"}));
+        assert_eq!(
+            schema["properties"]["example"],
+            json!({"x-jsonref-stop": true, "items": {"$ref": "#/definitions/name"}})
+        );
+
+        let mut schema = json!({"x-jsonref-stop": false, "items": {"$ref": "#/$defs/a"},
+                                "$defs": {"a": {"title": "a"}}});
+        JsonRef::new().deref_value(&mut schema).unwrap();
+        assert_eq!(schema["items"], json!({"title": "a"}));
+    }
+
+    #[test]
+    fn refs_under_object_applicators() {
+        for (input, expected) in [
+            ("base.json", "expected.json"),
+            ("legacy.json", "legacy_expected.json"),
+            ("unevaluated.json", "unevaluated_expected.json"),
+        ] {
+            let mut jsonref = JsonRef::new();
+            let value = jsonref
+                .deref_file(&format!("fixtures/applicators/{}", input))
+                .unwrap();
+            let file = fs::File::open(format!("fixtures/applicators/{}", expected)).unwrap();
+            let expected: Value = serde_json::from_reader(file).unwrap();
+            assert_eq!(value, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn data_is_not_dereferenced() {
+        let mut schema = json!({
+            "properties": {
+                "$ref": {"$ref": "#/$defs/a"},
+                "default": {"$ref": "#/$defs/a", "default": {"$ref": "#/$defs/a"}},
+                "definitions": {"properties": {"a": {"$ref": "#/$defs/a"}}}
+            },
+            "enum": [{"$ref": "#/$defs/a"}],
+            "const": {"$ref": "#/$defs/a"},
+            "examples": [{"$ref": "#/$defs/a"}],
+            "$defs": {"a": {"title": "a"}}
+        });
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolution_mode(ResolutionMode::Bundle);
+        let expected = json!({
+            "properties": {
+                "$ref": {"$ref": "#/$defs/a"},
+                "default": {"$ref": "#/$defs/a", "default": {"$ref": "#/$defs/a"}},
+                "definitions": {"properties": {"a": {"$ref": "#/$defs/a"}}}
+            },
+            "enum": [{"$ref": "#/$defs/a"}],
+            "const": {"$ref": "#/$defs/a"},
+            "examples": [{"$ref": "#/$defs/a"}],
+            "$defs": {"a": {"title": "a"}}
+        });
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(schema, expected);
+        let pointers: Vec<&str> = jsonref
+            .trace()
+            .iter()
+            .map(|step| step.pointer.as_str())
+            .collect();
+        assert_eq!(
+            pointers,
+            [
+                "/properties/$ref",
+                "/properties/default",
+                "/properties/definitions/properties/a"
+            ]
+        );
+
+        let mut schema = json!({
+            "properties": {"default": {"$ref": "#/$defs/a"}},
+            "enum": [{"$ref": "#/$defs/a"}],
+            "$defs": {"a": {"title": "a"}}
+        });
+        JsonRef::new().deref_value(&mut schema).unwrap();
+        assert_eq!(schema["properties"]["default"], json!({"title": "a"}));
+        assert_eq!(schema["enum"], json!([{"$ref": "#/$defs/a"}]));
+    }
+
+    #[test]
+    fn refs_in_arrays() {
+        let mut schema = json!({
+            "allOf": [{"$ref": "#/$defs/a"}, {"required": ["a"]}],
+            "anyOf": [{"$ref": "#/$defs/a"}, {"$ref": "#/$defs/b"}],
+            "oneOf": [[{"$ref": "#/$defs/b"}]],
+            "items": [{"$ref": "#/$defs/a"}, {"type": "null"}],
+            "$defs": {"a": {"title": "a"}, "b": {"title": "b"}}
+        });
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(
+            schema,
+            json!({
+                "allOf": [{"title": "a"}, {"required": ["a"]}],
+                "anyOf": [{"title": "a"}, {"title": "b"}],
+                "oneOf": [[{"title": "b"}]],
+                "items": [{"title": "a"}, {"type": "null"}],
+                "$defs": {"a": {"title": "a"}, "b": {"title": "b"}}
+            })
+        );
+        let mut pointers: Vec<&str> = jsonref
+            .trace()
+            .iter()
+            .map(|step| step.pointer.as_str())
+            .collect();
+        pointers.sort_unstable();
+        assert_eq!(
+            pointers,
+            ["/allOf/0", "/anyOf/0", "/anyOf/1", "/items/0", "/oneOf/0/0"]
+        );
+    }
+
+    #[test]
+    fn deeply_nested_schema() {
+        // Built by hand, as `json!` serializes the values it is given, recursively.
+        let object = |members: Vec<(&str, Value)>| {
+            let members = members
+                .into_iter()
+                .map(|(key, value)| (key.to_owned(), value));
+            Value::Object(members.collect())
+        };
+        let depth = 2_000;
+        let mut schema = json!({"$ref": "#/definitions/id"});
+        for _ in 0..depth {
+            let properties = object(vec![
+                ("child", schema),
+                ("id", json!({"$ref": "#/definitions/id"})),
+            ]);
+            schema = object(vec![("properties", properties)]);
+        }
+        if let Value::Object(root) = &mut schema {
+            root.insert("definitions".to_owned(), json!({"id": {"type": "integer"}}));
+        }
+
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut schema).unwrap();
+        let mut at = &schema;
+        for _ in 0..depth {
+            assert_eq!(at["properties"]["id"], json!({"type": "integer"}));
+            at = &at["properties"]["child"];
+        }
+        assert_eq!(*at, json!({"type": "integer"}));
+        crate::deep::drop(schema);
+    }
+
+    #[test]
+    fn wide_schema() {
+        let properties: Map<String, Value> = (0..50_000)
+            .map(|i| {
+                let property = match i % 3 {
+                    0 => json!({"$ref": "#/definitions/id"}),
+                    1 => json!(true),
+                    _ => json!({"items": [{"$ref": "#/definitions/id"}, 1]}),
+                };
+                (format!("p{}", i), property)
+            })
+            .collect();
+        let mut schema =
+            json!({"definitions": {"id": {"type": "integer"}}, "properties": properties});
+
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut schema).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        assert_eq!(properties.len(), 50_000);
+        assert_eq!(properties["p0"], json!({"type": "integer"}));
+        assert_eq!(properties["p1"], json!(true));
+        assert_eq!(properties["p2"], json!({"items": [{"type": "integer"}, 1]}));
+        assert_eq!(properties["p49998"], json!({"type": "integer"}));
+    }
+
+    #[test]
+    fn inline_depth() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/order.json",
+                json!({
+                    "properties": {
+                        "customer": {"$ref": "customer.json"},
+                        "id": {"$ref": "root.json#/$defs/id"}
+                    }
+                }),
+            )
+            .unwrap();
+        jsonref
+            .add_schema(
+                "https://example.com/customer.json",
+                json!({
+                    "properties": {
+                        "address": {"$ref": "address.json"},
+                        "pinned": {"$ref": "address.json", "x-jsonref": "inline"}
+                    }
+                }),
+            )
+            .unwrap();
+        jsonref
+            .add_schema(
+                "https://example.com/address.json",
+                json!({"properties": {"id": {"$ref": "root.json#/$defs/id"}}}),
+            )
+            .unwrap();
+        jsonref.set_max_inline_depth(Some(2));
+        let mut input = json!({
+            "$id": "https://example.com/root.json",
+            "items": {"$ref": "order.json"},
+            "$defs": {"id": {"type": "integer"}}
+        });
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(
+            input,
+            json!({
+                "$id": "https://example.com/root.json",
+                "items": {"properties": {
+                    "customer": {"properties": {
+                        "address": {"$ref": "#/$defs/address_json"},
+                        "pinned": {"properties": {"id": {"$ref": "#/$defs/id"}}}
+                    }},
+                    "id": {"type": "integer"}
+                }},
+                "$defs": {
+                    "id": {"type": "integer"},
+                    "address_json": {"properties": {"id": {"$ref": "#/$defs/id"}}}
+                }
+            })
+        );
+    }
+
     #[test]
     fn test_defs() {
         let mut jsonref = JsonRef::new();
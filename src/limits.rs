@@ -0,0 +1,160 @@
+//! Concurrency limits shared between dereferencing operations.
+
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Independent limits for the different kinds of work a `JsonRef` does.
+///
+/// Cloning a `ConcurrencyLimits` shares the underlying permits, so handing the same value to
+/// several `JsonRef` instances gives them one combined budget:
+///
+/// ```
+/// use polywrap_jsonref::{ConcurrencyLimits, JsonRef};
+///
+/// let limits = ConcurrencyLimits::new(4, 8, 2);
+///
+/// let mut first = JsonRef::new();
+/// first.set_concurrency_limits(limits.clone());
+/// let mut second = JsonRef::new();
+/// second.set_concurrency_limits(limits);
+/// ```
+#[derive(Clone)]
+pub struct ConcurrencyLimits {
+    http_fetches: Arc<Semaphore>,
+    file_reads: Arc<Semaphore>,
+    resolution_tasks: Arc<Semaphore>,
+}
+
+impl ConcurrencyLimits {
+    /// Create limits for concurrent HTTP fetches, concurrent file reads and concurrent
+    /// resolution (CPU-bound) tasks. A limit of `0` is treated as `1`.
+    pub fn new(http_fetches: usize, file_reads: usize, resolution_tasks: usize) -> Self {
+        ConcurrencyLimits {
+            http_fetches: Arc::new(Semaphore::new(http_fetches)),
+            file_reads: Arc::new(Semaphore::new(file_reads)),
+            resolution_tasks: Arc::new(Semaphore::new(resolution_tasks)),
+        }
+    }
+
+    /// Maximum number of HTTP fetches in flight at once.
+    pub fn http_fetches(&self) -> usize {
+        self.http_fetches.permits
+    }
+
+    /// Maximum number of files being read at once.
+    pub fn file_reads(&self) -> usize {
+        self.file_reads.permits
+    }
+
+    /// Maximum number of dereference operations running at once.
+    pub fn resolution_tasks(&self) -> usize {
+        self.resolution_tasks.permits
+    }
+
+    #[cfg(feature = "network")]
+    pub(crate) fn http_permit(&self) -> Permit<'_> {
+        self.http_fetches.acquire()
+    }
+
+    pub(crate) fn file_permit(&self) -> Permit<'_> {
+        self.file_reads.acquire()
+    }
+
+    pub(crate) fn resolution_permit(&self) -> Permit<'_> {
+        self.resolution_tasks.acquire()
+    }
+}
+
+impl Default for ConcurrencyLimits {
+    fn default() -> Self {
+        let cpus = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        ConcurrencyLimits::new(8, 8, cpus)
+    }
+}
+
+impl fmt::Debug for ConcurrencyLimits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimits")
+            .field("http_fetches", &self.http_fetches())
+            .field("file_reads", &self.file_reads())
+            .field("resolution_tasks", &self.resolution_tasks())
+            .finish()
+    }
+}
+
+/// Minimal counting semaphore, blocking until a permit is free.
+#[derive(Debug)]
+pub(crate) struct Semaphore {
+    permits: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    pub(crate) fn new(permits: usize) -> Self {
+        let permits = permits.max(1);
+        Semaphore {
+            permits,
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        Permit { semaphore: self }
+    }
+
+    #[cfg(test)]
+    fn try_acquire(&self) -> Option<Permit<'_>> {
+        let mut available = self.available.lock().unwrap();
+        if *available == 0 {
+            return None;
+        }
+        *available -= 1;
+        Some(Permit { semaphore: self })
+    }
+}
+
+/// Held while a limited operation runs; releases its permit when dropped.
+pub(crate) struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConcurrencyLimits, Semaphore};
+
+    #[test]
+    fn permits_are_released_on_drop() {
+        let semaphore = Semaphore::new(2);
+        let first = semaphore.try_acquire().unwrap();
+        let _second = semaphore.try_acquire().unwrap();
+        assert!(semaphore.try_acquire().is_none());
+        drop(first);
+        assert!(semaphore.try_acquire().is_some());
+    }
+
+    #[test]
+    fn clones_share_permits() {
+        let limits = ConcurrencyLimits::new(3, 1, 0);
+        let shared = limits.clone();
+        let _permit = limits.file_permit();
+        assert!(shared.file_reads.try_acquire().is_none());
+        assert_eq!(shared.http_fetches(), 3);
+        assert_eq!(shared.resolution_tasks(), 1);
+    }
+}
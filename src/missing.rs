@@ -0,0 +1,22 @@
+//! What to do with a `$ref` whose target can not be loaded or found.
+
+use serde::{Deserialize, Serialize};
+
+/// How unresolvable `$ref`s are handled, set with [`JsonRef::set_on_missing`].
+///
+/// A ref is unresolvable when its document can not be read, fetched or parsed, or when its
+/// fragment points at nothing. Errors within a target that did resolve are reported at the
+/// refs they come from.
+///
+/// [`JsonRef::set_on_missing`]: crate::JsonRef::set_on_missing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum OnMissing {
+    /// Fail the whole dereference with the error.
+    #[default]
+    Error,
+    /// Leave the `$ref` in place.
+    Keep,
+    /// Leave the `$ref` in place and record the error in the trace as
+    /// [`Rule::Unresolved`](crate::Rule::Unresolved).
+    Warn,
+}
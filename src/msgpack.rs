@@ -0,0 +1,181 @@
+//! MessagePack output, behind the `msgpack` feature.
+
+use serde_json::Value;
+use snafu::ResultExt;
+use std::io::{self, Write};
+
+use crate::{Result, WriteOutput};
+
+/// Serialize a (dereferenced) schema as [MessagePack](https://msgpack.org), for consumers
+/// where JSON is too bulky.
+///
+/// Lengths and integers take the fewest bytes they fit in, and floats are written as single
+/// precision when that loses nothing. Object keys keep the order of the `Value`. Fails when
+/// a string, array or object is longer than MessagePack allows, 2³² - 1.
+///
+/// ```
+/// use polywrap_jsonref::to_msgpack_vec;
+/// use serde_json::json;
+///
+/// assert_eq!(to_msgpack_vec(&json!({"type": "string"})).unwrap().len(), 13);
+/// ```
+pub fn to_msgpack_vec(value: &Value) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    to_msgpack_writer(value, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Serialize a (dereferenced) schema as MessagePack into `writer`, as [`to_msgpack_vec`].
+pub fn to_msgpack_writer(value: &Value, writer: impl Write) -> Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    write_value(&mut writer, value)
+        .and_then(|()| writer.flush())
+        .map_err(serde_json::Error::io)
+        .context(WriteOutput {})
+}
+
+fn write_value(writer: &mut impl Write, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Null => writer.write_all(&[0xc0]),
+        Value::Bool(false) => writer.write_all(&[0xc2]),
+        Value::Bool(true) => writer.write_all(&[0xc3]),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                write_uint(writer, n)
+            } else if let Some(n) = number.as_i64() {
+                write_int(writer, n)
+            } else {
+                let n = number.as_f64().unwrap_or(f64::NAN);
+                if f64::from(n as f32) == n {
+                    writer.write_all(&[0xca])?;
+                    writer.write_all(&(n as f32).to_be_bytes())
+                } else {
+                    writer.write_all(&[0xcb])?;
+                    writer.write_all(&n.to_be_bytes())
+                }
+            }
+        }
+        Value::String(string) => write_str(writer, string),
+        Value::Array(items) => {
+            write_container(writer, length(items.len())?, 0x90, 0xdc)?;
+            items.iter().try_for_each(|item| write_value(writer, item))
+        }
+        Value::Object(obj) => {
+            write_container(writer, length(obj.len())?, 0x80, 0xde)?;
+            obj.iter().try_for_each(|(key, value)| {
+                write_str(writer, key)?;
+                write_value(writer, value)
+            })
+        }
+    }
+}
+
+fn write_str(writer: &mut impl Write, string: &str) -> io::Result<()> {
+    let len = length(string.len())?;
+    match len {
+        0..=31 => writer.write_all(&[0xa0 | len as u8])?,
+        32..=0xff => writer.write_all(&[0xd9, len as u8])?,
+        0x100..=0xffff => {
+            writer.write_all(&[0xda])?;
+            writer.write_all(&(len as u16).to_be_bytes())?
+        }
+        _ => {
+            writer.write_all(&[0xdb])?;
+            writer.write_all(&len.to_be_bytes())?
+        }
+    }
+    writer.write_all(string.as_bytes())
+}
+
+fn length(len: usize) -> io::Result<u32> {
+    u32::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} items do not fit in MessagePack", len),
+        )
+    })
+}
+
+fn write_uint(writer: &mut impl Write, n: u64) -> io::Result<()> {
+    if n < 0x80 {
+        writer.write_all(&[n as u8])
+    } else if let Ok(n) = u8::try_from(n) {
+        writer.write_all(&[0xcc, n])
+    } else if let Ok(n) = u16::try_from(n) {
+        writer.write_all(&[0xcd])?;
+        writer.write_all(&n.to_be_bytes())
+    } else if let Ok(n) = u32::try_from(n) {
+        writer.write_all(&[0xce])?;
+        writer.write_all(&n.to_be_bytes())
+    } else {
+        writer.write_all(&[0xcf])?;
+        writer.write_all(&n.to_be_bytes())
+    }
+}
+
+/// Write the negative integer `n`.
+fn write_int(writer: &mut impl Write, n: i64) -> io::Result<()> {
+    if n >= -32 {
+        writer.write_all(&(n as i8).to_be_bytes())
+    } else if let Ok(n) = i8::try_from(n) {
+        writer.write_all(&[0xd0])?;
+        writer.write_all(&n.to_be_bytes())
+    } else if let Ok(n) = i16::try_from(n) {
+        writer.write_all(&[0xd1])?;
+        writer.write_all(&n.to_be_bytes())
+    } else if let Ok(n) = i32::try_from(n) {
+        writer.write_all(&[0xd2])?;
+        writer.write_all(&n.to_be_bytes())
+    } else {
+        writer.write_all(&[0xd3])?;
+        writer.write_all(&n.to_be_bytes())
+    }
+}
+
+/// Write the header of an array or map of `len` items: `fix` with the length when it is
+/// below 16, else the 16 or 32 bit form of `marker`, which is followed by the 32 bit one.
+fn write_container(writer: &mut impl Write, len: u32, fix: u8, marker: u8) -> io::Result<()> {
+    if len < 16 {
+        writer.write_all(&[fix | len as u8])
+    } else if let Ok(len) = u16::try_from(len) {
+        writer.write_all(&[marker])?;
+        writer.write_all(&len.to_be_bytes())
+    } else {
+        writer.write_all(&[marker + 1])?;
+        writer.write_all(&len.to_be_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_msgpack_vec;
+    use serde_json::json;
+
+    #[test]
+    fn values_are_encoded_compactly() {
+        let long = "a".repeat(40);
+        let cases = [
+            (json!(0), vec![0x00]),
+            (json!(200), vec![0xcc, 0xc8]),
+            (json!(70000), vec![0xce, 0x00, 0x01, 0x11, 0x70]),
+            (json!(-1), vec![0xff]),
+            (json!(-200), vec![0xd1, 0xff, 0x38]),
+            (json!(1.5), vec![0xca, 0x3f, 0xc0, 0, 0]),
+            (
+                json!(1.1),
+                vec![0xcb, 0x3f, 0xf1, 0x99, 0x99, 0x99, 0x99, 0x99, 0x9a],
+            ),
+            (json!(null), vec![0xc0]),
+            (json!(true), vec![0xc3]),
+            (json!("ab"), vec![0xa2, 0x61, 0x62]),
+            (json!([1, [2, 3]]), vec![0x92, 0x01, 0x92, 0x02, 0x03]),
+            (json!({"a": 1}), vec![0x81, 0xa1, 0x61, 0x01]),
+        ];
+        for (value, bytes) in cases {
+            assert_eq!(to_msgpack_vec(&value).unwrap(), bytes, "{}", value);
+        }
+        assert_eq!(to_msgpack_vec(&json!(long)).unwrap()[..2], [0xd9, 40]);
+        let many: Vec<_> = (0..20).collect();
+        assert_eq!(to_msgpack_vec(&json!(many)).unwrap()[..3], [0xdc, 0, 20]);
+    }
+}
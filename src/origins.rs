@@ -0,0 +1,247 @@
+//! `x-origins`, the list of documents a dereferenced schema was built from.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+use crate::{canonical, jcs, JsonRef, Rule};
+
+pub(crate) const ORIGINS_KEYWORD: &str = "x-origins";
+
+impl JsonRef {
+    /// Add an `x-origins` array to the root of each dereferenced schema, so that the output
+    /// can be archived and audited on its own. It lists every document other than the root
+    /// whose content was copied into the output, sorted by URL:
+    ///
+    /// - `url`: where the document was loaded from;
+    /// - `sha256`: the hex SHA-256 of the document as compact JSON with sorted keys;
+    /// - `fetched_at`: when this instance loaded it, in RFC 3339 UTC, or `null` for
+    ///   documents it did not load itself, e.g. from [`JsonRef::add_schema`].
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_record_origins(true);
+    /// jsonref.add_schema("https://example.com/pet.json", json!({"title": "pet"})).unwrap();
+    /// let mut schema = json!({"items": {"$ref": "https://example.com/pet.json"}});
+    /// jsonref.deref_value(&mut schema).unwrap();
+    /// assert_eq!(schema["x-origins"][0]["url"], "https://example.com/pet.json");
+    /// assert_eq!(schema["x-origins"][0]["fetched_at"], json!(null));
+    /// ```
+    pub fn set_record_origins(&mut self, record: bool) {
+        self.record_origins = record;
+    }
+
+    /// The `x-origins` of the deref that just ran.
+    pub(crate) fn origins(&mut self) -> Value {
+        let root_keys = self.root.as_ref().map(|(keys, _)| keys.clone());
+        let mut documents = BTreeMap::new();
+        for step in &self.trace {
+            let embedded = step.rules.iter().all(|rule| {
+                !matches!(
+                    rule,
+                    Rule::Unresolved { .. } | Rule::KeptByDirective | Rule::KeptBySelection
+                )
+            });
+            let url = match Url::parse(&step.document) {
+                Ok(url) if embedded => url,
+                _ => continue,
+            };
+            let key = canonical::cache_key(&url);
+            if !root_keys.iter().flatten().any(|root_key| *root_key == key) {
+                documents.entry(step.document.clone()).or_insert(key);
+            }
+        }
+        let origins = documents
+            .into_iter()
+            .filter_map(|(url, key)| {
+                let document = self.cached(&key)?;
+                let fetched_at = self.loaded_at.get(&key).map(|&time| rfc3339(time));
+                Some(json!({
+                    "url": url,
+                    "sha256": content_hash(&document),
+                    "fetched_at": fetched_at,
+                }))
+            })
+            .collect();
+        Value::Array(origins)
+    }
+}
+
+/// `time` as an RFC 3339 UTC timestamp, to the second.
+fn rfc3339(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, seconds) = (seconds / 86_400, seconds % 86_400);
+
+    // The civil date of a day count from the epoch, with years starting in March so leap
+    // days fall last.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (era * 400 + year_of_era, month + 3)
+    } else {
+        (era * 400 + year_of_era + 1, month - 9)
+    };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The hex SHA-256 of `document` as compact JSON with sorted keys, i.e. its JCS form, so
+/// the order of its keys does not change it.
+pub(crate) fn content_hash(document: &Value) -> String {
+    hex(&sha256(jcs::to_canonical_string(document).as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// SHA-256 of `bytes`, as specified in FIPS 180-4.
+fn sha256(bytes: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    // The message, a one bit, zeros up to 8 bytes short of a block, and the length in bits.
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(bytes.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 32];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hex, rfc3339, sha256};
+    use crate::JsonRef;
+    use serde_json::json;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn digests_and_timestamps() {
+        assert_eq!(
+            hex(&sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&sha256(&[b'a'; 100])),
+            "2816597888e4a0d3a36b82b83316ab32680eb8f00f8cd3b904d681246d285a0e"
+        );
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let leap_day = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(rfc3339(leap_day), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn origins_list_the_embedded_documents() {
+        let dir = std::env::temp_dir().join(format!("jsonref-origins-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let pet = dir.join("pet.json");
+        let document = json!({
+            "title": "pet",
+            "properties": {"id": {"$ref": "#/$defs/id"}},
+            "$defs": {"id": {"type": "integer"}}
+        });
+        std::fs::write(&pet, document.to_string()).unwrap();
+        let pet_url = url::Url::from_file_path(&pet).unwrap().to_string();
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_record_origins(true);
+        jsonref
+            .add_schema("https://example.com/kept.json", json!({}))
+            .unwrap();
+        let mut schema = json!({
+            "properties": {
+                "pet": {"$ref": pet_url},
+                "same": {"$ref": format!("{}#/properties/id", pet_url)},
+                "kept": {"$ref": "https://example.com/kept.json", "x-jsonref": "keep"},
+                "local": {"$ref": "#/properties/pet"}
+            }
+        });
+        jsonref.deref_value(&mut schema).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let origins = schema["x-origins"].as_array().unwrap();
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins[0]["url"], pet_url);
+        assert_eq!(origins[0]["sha256"].as_str().unwrap().len(), 64);
+        assert!(origins[0]["fetched_at"].as_str().unwrap().ends_with('Z'));
+    }
+}
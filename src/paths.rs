@@ -0,0 +1,264 @@
+//! Restricting dereferencing to parts of the root document.
+
+use serde_json::Value;
+use std::slice;
+
+use crate::explain::pointer_starts_with;
+use crate::{JsonPointerNotFound, JsonRef, Result};
+
+/// What the walk does with the value at a pointer of the root document, from doing the least
+/// to the most, so that of two filters the smaller holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PathFilter {
+    /// Leave it as it is.
+    Skip,
+    /// Go through it to the parts below it that are dereferenced, changing nothing else.
+    Towards,
+    /// Dereference it.
+    Walk,
+}
+
+impl JsonRef {
+    /// Only dereference what is under the JSON Pointer `prefix` of the root document, e.g.
+    /// `/components/schemas` of an OpenAPI document. Called more than once, what is under
+    /// any of the prefixes is dereferenced.
+    ///
+    /// Everything else is left as it is: its refs are not resolved, nor its definitions
+    /// hoisted or removed. Prefixes are matched by whole segments, so `/a` is not a prefix of
+    /// `/ab`. What a ref under a prefix points to is dereferenced wherever it is.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.only_under("/components/schemas");
+    /// let mut document = json!({
+    ///     "paths": {"/pets": {"$ref": "#/components/schemas/Pet"}},
+    ///     "components": {"schemas": {
+    ///         "Pet": {"properties": {"name": {"$ref": "#/components/schemas/Name"}}},
+    ///         "Name": {"type": "string"}
+    ///     }}
+    /// });
+    /// jsonref.deref_value(&mut document).unwrap();
+    /// assert_eq!(document["paths"]["/pets"], json!({"$ref": "#/components/schemas/Pet"}));
+    /// assert_eq!(
+    ///     document["components"]["schemas"]["Pet"]["properties"]["name"],
+    ///     json!({"type": "string"})
+    /// );
+    /// ```
+    pub fn only_under(&mut self, prefix: &str) {
+        self.only_under.push(prefix.to_owned());
+    }
+
+    /// Leave what is under the JSON Pointer `prefix` of the root document as it is, e.g.
+    /// `/examples`, even if it is also under a prefix given to [`JsonRef::only_under`].
+    /// Called more than once, what is under any of the prefixes is left alone.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.skip_under("/examples");
+    /// let mut document = json!({
+    ///     "properties": {"id": {"$ref": "#/$defs/id"}},
+    ///     "examples": {"raw": {"$ref": "#/$defs/id"}},
+    ///     "$defs": {"id": {"type": "integer"}}
+    /// });
+    /// jsonref.deref_value(&mut document).unwrap();
+    /// assert_eq!(document["properties"]["id"], json!({"type": "integer"}));
+    /// assert_eq!(document["examples"]["raw"], json!({"$ref": "#/$defs/id"}));
+    /// ```
+    pub fn skip_under(&mut self, prefix: &str) {
+        self.skip_under.push(prefix.to_owned());
+    }
+
+    /// Dereference only the subtree of `value` at the JSON Pointer `subtree`, e.g. one section
+    /// of a large form schema, leaving the rest of `value` as it is.
+    ///
+    /// Refs in the subtree are resolved as [`JsonRef::deref_value`] would: against the base
+    /// URI set by the `$id`s above it, and into the whole of `value`. Prefixes given to
+    /// [`JsonRef::only_under`] and [`JsonRef::skip_under`] still apply within it. Fails with
+    /// [`Error::JsonPointerNotFound`](crate::Error::JsonPointerNotFound) if `value` has
+    /// nothing at `subtree`.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut schema = json!({
+    ///     "properties": {
+    ///         "address": {"properties": {"street": {"$ref": "#/$defs/street"}}},
+    ///         "billing": {"$ref": "#/properties/address"}
+    ///     },
+    ///     "$defs": {"street": {"type": "string"}}
+    /// });
+    /// JsonRef::new().deref_pointer(&mut schema, "/properties/address").unwrap();
+    /// assert_eq!(schema["properties"]["address"]["properties"]["street"], json!({"type": "string"}));
+    /// assert_eq!(schema["properties"]["billing"], json!({"$ref": "#/properties/address"}));
+    /// ```
+    pub fn deref_pointer(&mut self, value: &mut Value, subtree: &str) -> Result<()> {
+        if value.pointer(subtree).is_none() {
+            return JsonPointerNotFound { pointer: subtree }.fail();
+        }
+        self.subtree = Some(subtree.to_owned());
+        let result = self.deref_value(value);
+        self.subtree = None;
+        result
+    }
+
+    /// What to do with the value at `pointer` of the root document, given the prefixes set
+    /// and the subtree being dereferenced, if any.
+    pub(crate) fn path_filter(&self, pointer: &str) -> PathFilter {
+        let filter = prefix_filter(pointer, &self.only_under, &self.skip_under);
+        match &self.subtree {
+            Some(subtree) => filter.min(prefix_filter(pointer, slice::from_ref(subtree), &[])),
+            None => filter,
+        }
+    }
+}
+
+/// What to do with the value at `pointer` when only what is under `only` is dereferenced, or
+/// everything if it is empty, but for what is under `skip`.
+fn prefix_filter(pointer: &str, only: &[String], skip: &[String]) -> PathFilter {
+    let under = |prefixes: &[String]| {
+        prefixes
+            .iter()
+            .any(|prefix| pointer_starts_with(pointer, prefix))
+    };
+    if under(skip) {
+        PathFilter::Skip
+    } else if only.is_empty() || under(only) {
+        PathFilter::Walk
+    } else if only
+        .iter()
+        .any(|prefix| pointer_starts_with(prefix, pointer))
+    {
+        PathFilter::Towards
+    } else {
+        PathFilter::Skip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PathFilter;
+    use crate::{Error, JsonRef};
+    use serde_json::json;
+
+    #[test]
+    fn prefixes_are_matched_by_segment() {
+        let mut jsonref = JsonRef::new();
+        assert_eq!(jsonref.path_filter("/anything"), PathFilter::Walk);
+        jsonref.only_under("/components/schemas");
+        jsonref.skip_under("/components/schemas/Legacy");
+        assert_eq!(jsonref.path_filter(""), PathFilter::Towards);
+        assert_eq!(jsonref.path_filter("/components"), PathFilter::Towards);
+        assert_eq!(
+            jsonref.path_filter("/components/schemas/Pet"),
+            PathFilter::Walk
+        );
+        assert_eq!(
+            jsonref.path_filter("/components/schemasX"),
+            PathFilter::Skip
+        );
+        assert_eq!(
+            jsonref.path_filter("/components/schemas/Legacy/a"),
+            PathFilter::Skip
+        );
+        assert_eq!(jsonref.path_filter("/paths"), PathFilter::Skip);
+
+        jsonref.subtree = Some("/components/schemas/Pet/properties".to_owned());
+        assert_eq!(jsonref.path_filter("/components"), PathFilter::Towards);
+        assert_eq!(
+            jsonref.path_filter("/components/schemas/Pet"),
+            PathFilter::Towards
+        );
+        assert_eq!(
+            jsonref.path_filter("/components/schemas/Pet/properties/a"),
+            PathFilter::Walk
+        );
+        assert_eq!(
+            jsonref.path_filter("/components/schemas/Cat"),
+            PathFilter::Skip
+        );
+    }
+
+    #[test]
+    fn subtrees_resolve_against_the_whole_document() {
+        let mut schema = json!({
+            "$id": "https://example.com/root.json",
+            "definitions": {"kept": {"type": "null"}},
+            "properties": {
+                "section": {
+                    "$id": "forms/",
+                    "properties": {"name": {"$ref": "name.json"}},
+                    "definitions": {"local": {"title": "local"}},
+                    "items": {"$ref": "../root.json#/definitions/kept"}
+                },
+                "other": {"$ref": "#/definitions/kept"}
+            }
+        });
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/forms/name.json",
+                json!({"type": "string"}),
+            )
+            .unwrap();
+        jsonref
+            .deref_pointer(&mut schema, "/properties/section")
+            .unwrap();
+        assert_eq!(
+            schema,
+            json!({
+                "$id": "https://example.com/root.json",
+                "definitions": {"kept": {"type": "null"}, "local": {"title": "local"}},
+                "properties": {
+                    "section": {
+                        "$id": "forms/",
+                        "properties": {"name": {"type": "string"}},
+                        "items": {"type": "null"}
+                    },
+                    "other": {"$ref": "#/definitions/kept"}
+                }
+            })
+        );
+
+        assert!(matches!(
+            jsonref.deref_pointer(&mut schema, "/properties/missing"),
+            Err(Error::JsonPointerNotFound { .. })
+        ));
+        // The subtree is for that one deref only.
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(schema["properties"]["other"], json!({"type": "null"}));
+    }
+
+    #[test]
+    fn skipped_parts_are_untouched() {
+        let mut jsonref = JsonRef::new();
+        jsonref.only_under("/components/schemas");
+        jsonref.skip_under("/components/schemas/Raw");
+        let mut document = json!({
+            "definitions": {"keep": {"type": "null"}},
+            "paths": {"/a": {"$ref": "#/definitions/keep"}},
+            "components": {"schemas": {
+                "Raw": {"$ref": "#/definitions/keep"},
+                "Tuple": {"items": [{"$ref": "#/definitions/keep"}]}
+            }}
+        });
+        jsonref.deref_value(&mut document).unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "definitions": {"keep": {"type": "null"}},
+                "paths": {"/a": {"$ref": "#/definitions/keep"}},
+                "components": {"schemas": {
+                    "Raw": {"$ref": "#/definitions/keep"},
+                    "Tuple": {"items": [{"type": "null"}]}
+                }}
+            })
+        );
+    }
+}
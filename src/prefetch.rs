@@ -0,0 +1,351 @@
+//! Fetching the remote documents a schema refers to all at once, rather than one after the
+//! other as the walk reaches their refs.
+
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use url::Url;
+
+use crate::http::Source;
+use crate::paths::PathFilter;
+use crate::resources::Usage;
+use crate::{canonical, ref_sites, JsonRef, RefKind, Result, UrlParseError};
+
+/// Documents fetched ahead of the walk, by URL, and what fetching each gave.
+pub(crate) type Prefetched = HashMap<String, Result<Fetched>>;
+
+/// A document fetched over http(s).
+pub(crate) struct Fetched {
+    pub(crate) document: Arc<Value>,
+    /// How many bytes it was.
+    pub(crate) len: usize,
+    /// Where its bytes came from.
+    pub(crate) source: Source,
+}
+
+/// What downloading a document gave: its bytes, and where they came from.
+type Downloaded = Result<(Vec<u8>, Source)>;
+
+impl JsonRef {
+    /// Whether the remote documents a document refers to are fetched concurrently, up to
+    /// [`ConcurrencyLimits::http_fetches`](crate::ConcurrencyLimits::http_fetches) at once.
+    /// On by default.
+    ///
+    /// When a document is loaded, every http(s) document it refers to that is not cached yet
+    /// is fetched, then those they refer to, and so on, before the walk goes on. This can
+    /// fetch documents the walk turns out not to need, e.g. behind an `"x-jsonref": "keep"`;
+    /// they are not kept for later derefs. Only the documents the walk uses count
+    /// against [`ResourceLimits::max_fetches`](crate::ResourceLimits::max_fetches) and the
+    /// [`TenantQuota`](crate::TenantQuota), and no more are fetched ahead than they have left.
+    ///
+    /// With a resolver set, or a scheme handler for http(s), documents are fetched one at a
+    /// time when reached, as the resolver may provide them.
+    pub fn set_parallel_fetching(&mut self, parallel: bool) {
+        self.parallel_fetching = parallel;
+    }
+
+    /// Fetch the documents `document`, loaded from `base`, refers to over http(s), and the
+    /// documents those refer to, a level at a time, for [`JsonRef::fetch`] to take. In the
+    /// `root` document, refs the walk leaves alone, as set by [`JsonRef::only_under`] and
+    /// [`JsonRef::skip_under`], are not followed.
+    ///
+    /// Failures are kept too, so the walk reports them where it reaches the ref.
+    pub(crate) fn prefetch_refs(&mut self, document: &Value, base: &Url, root: bool) {
+        if !self.fetches_concurrently() {
+            return;
+        }
+        let mut level = self.unfetched_refs(document, base, &[], root);
+        // A single document is fetched as fast when the walk reaches it.
+        while level.len() > 1 {
+            let fetched = match self.download_all(&level) {
+                Some(fetched) => fetched,
+                None => return,
+            };
+            let mut next = Vec::new();
+            for (url, fetched) in self.parse_fetched(fetched) {
+                if let Ok(fetched) = &fetched {
+                    let refs = self.unfetched_refs(&fetched.document, &url, &next, false);
+                    next.extend(refs);
+                }
+                self.prefetched.insert(url.to_string(), fetched);
+            }
+            level = next;
+        }
+    }
+
+    /// Fetch the documents at `urls` and cache them for the derefs that follow, so those do
+    /// no work for them but in memory. `urls` is typically the dependency list of an earlier
+    /// deref of the same schema, its [`JsonRef::loaded_documents`], for a request path that
+    /// cannot wait on the network.
+    ///
+    /// The http(s) documents are fetched concurrently, as with
+    /// [`JsonRef::set_parallel_fetching`], unless it is off or a resolver or scheme handler may
+    /// provide them; other documents are loaded one after the other. Documents cached already
+    /// are not loaded again, and the refs within those loaded are not followed. The
+    /// [`ResourceLimits`](crate::ResourceLimits) apply to the prefetch as a whole.
+    ///
+    /// Every document that can be loaded is cached; then the prefetch fails with the error of
+    /// the first of `urls` that could not be.
+    pub fn prefetch(&mut self, urls: &[&str]) -> Result<()> {
+        self.usage = Usage::default();
+        let mut pending: Vec<Url> = Vec::with_capacity(urls.len());
+        for uri in urls {
+            let mut url = Url::parse(uri).context(UrlParseError {
+                url: (*uri).to_owned(),
+            })?;
+            url.set_fragment(None);
+            self.query.apply(&mut url);
+            let key = canonical::cache_key(&url);
+            if !pending.contains(&url) && self.schema_cache.get(&key).is_none() {
+                pending.push(url);
+            }
+        }
+        if self.fetches_concurrently() {
+            let mut remote: Vec<Url> = pending
+                .iter()
+                .filter(|url| matches!(url.scheme(), "http" | "https"))
+                .cloned()
+                .collect();
+            if let Some(left) = self.fetch_allowance() {
+                remote.truncate(left);
+            }
+            if remote.len() > 1 {
+                if let Some(fetched) = self.download_all(&remote) {
+                    let parsed = self.parse_fetched(fetched);
+                    self.prefetched.extend(
+                        parsed
+                            .into_iter()
+                            .map(|(url, document)| (url.to_string(), document)),
+                    );
+                }
+            }
+        }
+        let mut failed = None;
+        for url in pending {
+            match self.load_document(&url) {
+                Ok(document) => {
+                    let key = canonical::cache_key(&url);
+                    self.cache_document(&url, key, document);
+                }
+                Err(error) => failed = failed.or(Some(error)),
+            }
+        }
+        self.prefetched.clear();
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Whether http(s) documents are fetched ahead, concurrently, rather than when reached.
+    fn fetches_concurrently(&self) -> bool {
+        self.parallel_fetching
+            && self.loads_documents()
+            && !self.offline
+            && self.resolver.is_none()
+            && !self.schemes.contains_key("http")
+            && !self.schemes.contains_key("https")
+    }
+
+    /// Parse what downloading each of `fetched` gave, keeping how many bytes it was.
+    fn parse_fetched(&mut self, fetched: Vec<(Url, Downloaded)>) -> Vec<(Url, Result<Fetched>)> {
+        fetched
+            .into_iter()
+            .map(|(url, downloaded)| {
+                let document = downloaded.and_then(|(bytes, source)| {
+                    let document = self.parse_shared(url.as_str(), url.path(), &bytes)?;
+                    Ok(Fetched {
+                        document,
+                        len: bytes.len(),
+                        source,
+                    })
+                });
+                (url, document)
+            })
+            .collect()
+    }
+
+    /// How many more documents may be fetched, within the fetch limit and the tenant's
+    /// quota, if either limits them.
+    fn fetch_allowance(&self) -> Option<usize> {
+        match (self.fetches_left(), self.tenant_fetches_left()) {
+            (Some(left), Some(tenant_left)) => Some(left.min(tenant_left)),
+            (left, tenant_left) => left.or(tenant_left),
+        }
+    }
+
+    /// The http(s) documents `document` at `base` refers to that are neither loaded nor
+    /// fetched already, nor in `pending`, within what is left of the fetch limit.
+    fn unfetched_refs(
+        &mut self,
+        document: &Value,
+        base: &Url,
+        pending: &[Url],
+        root: bool,
+    ) -> Vec<Url> {
+        let mut urls: Vec<Url> = Vec::new();
+        for site in ref_sites(document, base) {
+            if root && self.path_filter(&site.pointer) != PathFilter::Walk {
+                continue;
+            }
+            let mut url = match (site.kind, site.resolved_url) {
+                (RefKind::Remote, Some(url)) => match Url::parse(&url) {
+                    Ok(url) => url,
+                    Err(_) => continue,
+                },
+                _ => continue,
+            };
+            url.set_fragment(None);
+            self.query.apply(&mut url);
+            if urls.contains(&url)
+                || pending.contains(&url)
+                || self.prefetched.contains_key(url.as_str())
+                || self.cached(&canonical::cache_key(&url)).is_some()
+            {
+                continue;
+            }
+            urls.push(url);
+        }
+        if let Some(left) = self.fetch_allowance() {
+            let left = left.saturating_sub(pending.len() + self.prefetched.len());
+            urls.truncate(left);
+        }
+        urls
+    }
+
+    /// Download every one of `urls` on as many threads as fetches may run at once, or `None`
+    /// if no agent can be built, which the walk will then report.
+    fn download_all(&mut self, urls: &[Url]) -> Option<Vec<(Url, Downloaded)>> {
+        let downloader = self.downloader().ok()?;
+        let workers = downloader.limits.http_fetches().min(urls.len());
+        let next = AtomicUsize::new(0);
+        let fetched = Mutex::new(Vec::with_capacity(urls.len()));
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while let Some(url) = urls.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        let mut bytes = Vec::new();
+                        let result = downloader.download(url.as_str(), &mut bytes);
+                        let mut fetched = fetched.lock().unwrap();
+                        fetched.push((url.clone(), result.map(|source| (bytes, source))));
+                    }
+                });
+            }
+        });
+        Some(fetched.into_inner().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, JsonRef, ResourceLimits};
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Serve a schema referring to four others, each answered slowly, returning its URL and
+    /// the most requests that were in flight at once.
+    fn serve_slowly() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/schema.json", listener.local_addr().unwrap());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let most_in_flight = Arc::clone(&most);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (in_flight, most) = (Arc::clone(&in_flight), Arc::clone(&most));
+                let mut stream = BufReader::new(stream.unwrap());
+                thread::spawn(move || {
+                    let mut request_line = String::new();
+                    stream.read_line(&mut request_line).unwrap();
+                    let mut line = String::new();
+                    while stream.read_line(&mut line).is_ok() && line != "\r\n" {
+                        line.clear();
+                    }
+                    let path = request_line.split(' ').nth(1).unwrap_or("").to_owned();
+                    let body = if path == "/schema.json" {
+                        json!({"properties": {
+                            "a": {"$ref": "a.json"}, "b": {"$ref": "b.json"},
+                            "c": {"$ref": "c.json"}, "d": {"$ref": "d.json#/title"}
+                        }})
+                    } else {
+                        let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        thread::sleep(Duration::from_millis(200));
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        json!({ "title": path })
+                    }
+                    .to_string();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    stream.get_mut().write_all(response.as_bytes()).unwrap();
+                });
+            }
+        });
+        (url, most_in_flight)
+    }
+
+    #[test]
+    fn refs_are_fetched_concurrently() {
+        let (url, most_in_flight) = serve_slowly();
+        let mut jsonref = JsonRef::new();
+        let resolved = jsonref.deref_url(&url).unwrap();
+        assert_eq!(resolved["properties"]["c"], json!({"title": "/c.json"}));
+        assert_eq!(resolved["properties"]["d"], "/d.json");
+        assert_eq!(jsonref.loaded_documents().len(), 5);
+        assert!(most_in_flight.load(Ordering::SeqCst) > 1);
+
+        let (url, most_in_flight) = serve_slowly();
+        let mut jsonref = JsonRef::new();
+        jsonref.set_parallel_fetching(false);
+        jsonref.deref_url(&url).unwrap();
+        assert_eq!(most_in_flight.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn prefetched_documents_are_served_from_memory() {
+        let (url, most_in_flight) = serve_slowly();
+        let base = url.trim_end_matches("schema.json").to_owned();
+        let dependencies: Vec<String> = ["a.json", "b.json", "c.json", "d.json"]
+            .iter()
+            .map(|name| format!("{}{}", base, name))
+            .collect();
+        let mut jsonref = JsonRef::new();
+        let mut urls: Vec<&str> = dependencies.iter().map(String::as_str).collect();
+        urls.push(&url);
+        jsonref.prefetch(&urls).unwrap();
+        assert!(most_in_flight.load(Ordering::SeqCst) > 1);
+
+        jsonref.set_offline(true);
+        let mut schema = json!({"items": {"$ref": url}});
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(schema["items"]["properties"]["d"], "/d.json");
+        assert!(jsonref.loaded_documents().is_empty());
+
+        jsonref.set_offline(false);
+        let missing = "http://127.0.0.1:1/missing.json";
+        let error = jsonref.prefetch(&[missing, &url]).unwrap_err();
+        assert!(error.to_string().contains("missing.json"));
+    }
+
+    #[test]
+    fn fetches_ahead_stay_within_the_limit() {
+        let (url, _) = serve_slowly();
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resource_limits(ResourceLimits {
+            max_fetches: Some(3),
+            ..ResourceLimits::default()
+        });
+        match jsonref.deref_url(&url).unwrap_err().root_cause() {
+            Error::TooManyFetches { url, .. } => assert!(url.ends_with("/c.json")),
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,554 @@
+//! Rewriting dereferenced schemas into the shape particular kinds of tools expect.
+
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::explain::push_pointer;
+use crate::faker::merge;
+use crate::shift_remove;
+
+/// Keywords holding one subschema.
+const SUBSCHEMA_KEYWORDS: &[&str] = &[
+    "additionalItems",
+    "additionalProperties",
+    "contains",
+    "else",
+    "if",
+    "items",
+    "not",
+    "propertyNames",
+    "then",
+    "unevaluatedItems",
+    "unevaluatedProperties",
+];
+/// Keywords holding an array of subschemas.
+const SUBSCHEMA_ARRAY_KEYWORDS: &[&str] = &["anyOf", "items", "oneOf", "prefixItems"];
+/// Keywords holding an object of subschemas.
+const SUBSCHEMA_MAP_KEYWORDS: &[&str] = &[
+    "$defs",
+    "definitions",
+    "dependentSchemas",
+    "patternProperties",
+    "properties",
+];
+
+/// Rewrite the dereferenced `schema` for generating forms from it, as JSON Schema form
+/// libraries mostly expect:
+///
+/// - `allOf` subschemas are merged into the schema that holds them, as in
+///   [`faker::normalize`](crate::faker::normalize);
+/// - an object with `properties` gets a `ui:order` listing them: first those with a numeric
+///   `propertyOrder` or `x-order`, by it, then the `required` ones as listed there, then the
+///   rest by name;
+/// - each property gets a `ui:required` flag saying whether the object requires it, so a
+///   field can be rendered without looking at its parent;
+/// - a property without a `title` gets one made from its name, e.g. `Billing address` for
+///   `billingAddress` or `billing_address`.
+///
+/// ```
+/// use polywrap_jsonref::profile;
+/// use serde_json::json;
+///
+/// let mut schema = json!({
+///     "allOf": [{"properties": {"email": {"type": "string"}}, "required": ["email"]}],
+///     "properties": {
+///         "comment": {"type": "string", "title": "Anything else?"},
+///         "firstName": {"type": "string", "x-order": 0}
+///     }
+/// });
+/// profile::form(&mut schema);
+/// assert_eq!(schema["ui:order"], json!(["firstName", "email", "comment"]));
+/// assert_eq!(schema["properties"]["email"]["ui:required"], true);
+/// assert_eq!(schema["properties"]["firstName"]["title"], "First name");
+/// ```
+pub fn form(schema: &mut Value) {
+    let obj = match schema {
+        Value::Object(obj) => obj,
+        _ => return,
+    };
+    while let Some(Value::Array(all)) = shift_remove(obj, "allOf") {
+        for subschema in all {
+            if let Value::Object(subschema) = subschema {
+                merge(obj, subschema);
+            }
+        }
+    }
+
+    let required: Vec<String> = obj
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|required| {
+            required
+                .iter()
+                .filter_map(Value::as_str)
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(Value::Object(properties)) = obj.get_mut("properties") {
+        for (name, property) in properties.iter_mut() {
+            if let Value::Object(property) = property {
+                let is_required = required.contains(name);
+                property.insert("ui:required".to_owned(), Value::Bool(is_required));
+                if !property.contains_key("title") {
+                    property.insert("title".to_owned(), Value::String(title(name)));
+                }
+            }
+        }
+        let order = property_order(properties, &required);
+        obj.insert("ui:order".to_owned(), order.into());
+    }
+
+    for_each_subschema(obj, form);
+}
+
+/// The names of `properties` in the order of [`form`].
+fn property_order(properties: &Map<String, Value>, required: &[String]) -> Vec<String> {
+    let position = |name: &str| {
+        let property = &properties[name];
+        let order = ["propertyOrder", "x-order"]
+            .iter()
+            .find_map(|keyword| property.get(*keyword).and_then(Value::as_f64));
+        let required = required.iter().position(|required| *required == name);
+        match (order, required) {
+            (Some(order), _) => (0, order, 0),
+            (None, Some(index)) => (1, 0.0, index),
+            (None, None) => (2, 0.0, 0),
+        }
+    };
+    let mut names: Vec<&String> = properties.keys().collect();
+    // Names break ties themselves, as the keys are in document order with `preserve_order`.
+    names.sort_by(|a, b| {
+        let (x, y) = (position(a), position(b));
+        x.0.cmp(&y.0)
+            .then(x.1.total_cmp(&y.1))
+            .then(x.2.cmp(&y.2))
+            .then(a.cmp(b))
+    });
+    names.into_iter().cloned().collect()
+}
+
+/// A title for the property `name`: its words split at `_`, `-`, spaces and lower to upper
+/// case changes, lower cased but for the first letter.
+fn title(name: &str) -> String {
+    let mut title = String::with_capacity(name.len() + 4);
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if c == '_' || c == '-' || c == ' ' {
+            if !title.is_empty() && !title.ends_with(' ') {
+                title.push(' ');
+            }
+            previous = None;
+            continue;
+        }
+        if c.is_uppercase() && previous.is_some_and(char::is_lowercase) && !title.ends_with(' ') {
+            title.push(' ');
+        }
+        if title.is_empty() {
+            title.extend(c.to_uppercase());
+        } else {
+            title.extend(c.to_lowercase());
+        }
+        previous = Some(c);
+    }
+    title.trim_end().to_owned()
+}
+
+/// Call `f` on every subschema directly below `obj`.
+fn for_each_subschema(obj: &mut Map<String, Value>, f: fn(&mut Value)) {
+    for (keyword, value) in obj.iter_mut() {
+        let keyword = keyword.as_str();
+        match value {
+            Value::Array(subschemas) if SUBSCHEMA_ARRAY_KEYWORDS.contains(&keyword) => {
+                subschemas.iter_mut().for_each(f);
+            }
+            Value::Object(subschemas) if SUBSCHEMA_MAP_KEYWORDS.contains(&keyword) => {
+                subschemas.values_mut().for_each(f);
+            }
+            Value::Object(_) if SUBSCHEMA_KEYWORDS.contains(&keyword) => f(value),
+            _ => {}
+        }
+    }
+}
+
+/// Rewrite `schema`, dereferenced in [`ResolutionMode::Bundle`], for generating TypeScript
+/// types (or those of similar languages) from it, and name the types.
+///
+/// The definitions under `$defs` and `definitions` are kept as named types, and the refs to
+/// them stay. Refs to any other part of the document describe anonymous types, so they are
+/// replaced by what they point to, combined in an `allOf` with their siblings if they have
+/// any. Only a ref that is recursive is not: what it points to is moved into the definitions,
+/// named after the last segment of its pointer, like a named type.
+///
+/// Returns the type name for each definition, by its local ref: its name in PascalCase,
+/// starting with a letter, and numbered from 2 if another definition sorting first has it.
+///
+/// ```
+/// use polywrap_jsonref::{profile, JsonRef, ResolutionMode};
+/// use serde_json::json;
+///
+/// let mut schema = json!({
+///     "properties": {
+///         "owner": {"$ref": "#/$defs/pet_owner"},
+///         "nickname": {"$ref": "#/properties/name"},
+///         "name": {"type": "string"}
+///     },
+///     "$defs": {"pet_owner": {"properties": {"id": {"type": "integer"}}}}
+/// });
+/// let mut jsonref = JsonRef::new();
+/// jsonref.set_resolution_mode(ResolutionMode::Bundle);
+/// jsonref.deref_value(&mut schema).unwrap();
+///
+/// let names = profile::typescript(&mut schema);
+/// assert_eq!(schema["properties"]["owner"], json!({"$ref": "#/$defs/pet_owner"}));
+/// assert_eq!(schema["properties"]["nickname"], json!({"type": "string"}));
+/// assert_eq!(names["#/$defs/pet_owner"], "PetOwner");
+/// ```
+///
+/// [`ResolutionMode::Bundle`]: crate::ResolutionMode::Bundle
+pub fn typescript(schema: &mut Value) -> BTreeMap<String, String> {
+    let root = schema.clone();
+    let mut promoted = Promoted::default();
+    inline_anonymous(schema, &root, &mut vec![], &mut promoted);
+    if !promoted.definitions.is_empty() {
+        let keyword = ["$defs", "definitions"]
+            .into_iter()
+            .find(|keyword| schema.get(keyword).is_some())
+            .unwrap_or("$defs");
+        if let Value::Object(root) = schema {
+            if let Value::Object(definitions) = root
+                .entry(keyword)
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                definitions.extend(promoted.definitions);
+            }
+        }
+    }
+
+    name_definitions(schema, type_name).names
+}
+
+/// Suggested Rust type names for the definitions of a schema, from [`rust_type_names`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RustTypeNames {
+    /// The identifier for each definition, by its local ref, e.g. `#/$defs/pet_owner`.
+    pub names: BTreeMap<String, String>,
+    /// The identifiers more than one definition made, in order.
+    pub collisions: Vec<TypeNameCollision>,
+}
+
+/// Definitions whose names make the same identifier, listed by [`RustTypeNames`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TypeNameCollision {
+    pub identifier: String,
+    /// Local refs of the definitions, in key order: the first gets the identifier, the others
+    /// get it numbered from 2.
+    pub definitions: Vec<String>,
+}
+
+/// Suggest a Rust identifier for each definition under `$defs` and `definitions` in
+/// `schema`, e.g. one bundled in [`ResolutionMode::Bundle`], for generating `serde` structs.
+///
+/// Identifiers are made as type names are by [`typescript`], but only from ASCII letters and
+/// digits, and `Self` becomes `SelfType`. Definitions making the same identifier are
+/// reported as collisions.
+///
+/// ```
+/// use polywrap_jsonref::profile::rust_type_names;
+/// use serde_json::json;
+///
+/// let schema = json!({"$defs": {"pet-owner": {}, "pet_owner": {}, "café": {}}});
+/// let names = rust_type_names(&schema);
+/// assert_eq!(names.names["#/$defs/pet-owner"], "PetOwner");
+/// assert_eq!(names.names["#/$defs/pet_owner"], "PetOwner2");
+/// assert_eq!(names.names["#/$defs/café"], "Caf");
+/// assert_eq!(names.collisions[0].definitions, ["#/$defs/pet-owner", "#/$defs/pet_owner"]);
+/// ```
+///
+/// [`ResolutionMode::Bundle`]: crate::ResolutionMode::Bundle
+pub fn rust_type_names(schema: &Value) -> RustTypeNames {
+    name_definitions(schema, |name| {
+        let ascii: String = name
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '_' })
+            .collect();
+        match type_name(&ascii).as_str() {
+            "Self" => "SelfType".to_owned(),
+            identifier => identifier.to_owned(),
+        }
+    })
+}
+
+/// Name the definitions of `schema` with `to_name`, numbering the names made more than once.
+fn name_definitions(schema: &Value, to_name: impl Fn(&str) -> String) -> RustTypeNames {
+    let mut names = BTreeMap::new();
+    let mut taken = HashSet::new();
+    let mut made: Vec<(String, Vec<String>)> = vec![];
+    for keyword in ["$defs", "definitions"] {
+        let definitions = match schema.get(keyword).and_then(Value::as_object) {
+            Some(definitions) => definitions,
+            None => continue,
+        };
+        for name in definitions.keys() {
+            let local_ref = format!("#{}", push_pointer(&push_pointer("", keyword), name));
+            let type_name = to_name(name);
+            let mut unique = type_name.clone();
+            let mut number = 2;
+            while !taken.insert(unique.clone()) {
+                unique = format!("{}{}", type_name, number);
+                number += 1;
+            }
+            match made.iter_mut().find(|(made, _)| *made == type_name) {
+                Some((_, definitions)) => definitions.push(local_ref.clone()),
+                None => made.push((type_name, vec![local_ref.clone()])),
+            }
+            names.insert(local_ref, unique);
+        }
+    }
+    let collisions = made
+        .into_iter()
+        .filter(|(_, definitions)| definitions.len() > 1)
+        .map(|(identifier, definitions)| TypeNameCollision {
+            identifier,
+            definitions,
+        })
+        .collect();
+    RustTypeNames { names, collisions }
+}
+
+/// Anonymous schemas [`typescript`] moved into the definitions, as they are recursive.
+#[derive(Default)]
+struct Promoted {
+    /// Local ref to the definition for each ref promoted.
+    refs: HashMap<String, String>,
+    definitions: Map<String, Value>,
+}
+
+/// Replace the local refs below `value` to anything but a definition (or the root) by what
+/// they point to in `root`, `chain` being the refs replaced further up.
+fn inline_anonymous(
+    value: &mut Value,
+    root: &Value,
+    chain: &mut Vec<String>,
+    promoted: &mut Promoted,
+) {
+    let obj = match value {
+        Value::Object(obj) => obj,
+        Value::Array(items) => {
+            for item in items {
+                inline_anonymous(item, root, chain, promoted);
+            }
+            return;
+        }
+        _ => return,
+    };
+    let reference = match obj.get("$ref").and_then(Value::as_str) {
+        Some(reference) if is_anonymous(reference) => reference.to_owned(),
+        _ => {
+            for member in obj.values_mut() {
+                inline_anonymous(member, root, chain, promoted);
+            }
+            return;
+        }
+    };
+    let pointer = percent_decode_str(&reference[1..]).decode_utf8_lossy();
+    let mut target = match root.pointer(&pointer) {
+        Some(target) => target.clone(),
+        None => return,
+    };
+
+    if chain.contains(&reference) {
+        let local_ref = promoted_ref(&reference, &pointer, root, promoted);
+        obj.insert("$ref".to_owned(), Value::String(local_ref));
+        return;
+    }
+    chain.push(reference.clone());
+    inline_anonymous(&mut target, root, chain, promoted);
+    chain.pop();
+    if let Some(local_ref) = promoted.refs.get(&reference) {
+        // Recursive, so the target goes into the definitions, once.
+        let name = local_ref.rsplit('/').next().unwrap_or_default().to_owned();
+        promoted.definitions.entry(name).or_insert(target);
+        obj.insert("$ref".to_owned(), Value::String(local_ref.clone()));
+        return;
+    }
+
+    shift_remove(obj, "$ref");
+    for member in obj.values_mut() {
+        inline_anonymous(member, root, chain, promoted);
+    }
+    *value = if obj.is_empty() {
+        target
+    } else {
+        json!({ "allOf": [target, Value::Object(std::mem::take(obj))] })
+    };
+}
+
+/// Whether `reference` is a local ref to something other than a definition or the root.
+fn is_anonymous(reference: &str) -> bool {
+    let pointer = match reference.strip_prefix("#/") {
+        Some(pointer) => pointer,
+        None => return false,
+    };
+    match pointer.split_once('/') {
+        Some((keyword, name)) => !["$defs", "definitions"].contains(&keyword) || name.contains('/'),
+        None => true,
+    }
+}
+
+/// The local ref to the definition for the recursive `reference`, at `pointer`, naming it
+/// the first time.
+fn promoted_ref(reference: &str, pointer: &str, root: &Value, promoted: &mut Promoted) -> String {
+    if let Some(local_ref) = promoted.refs.get(reference) {
+        return local_ref.clone();
+    }
+    let keyword = ["$defs", "definitions"]
+        .into_iter()
+        .find(|keyword| root.get(keyword).is_some())
+        .unwrap_or("$defs");
+    let last = pointer.rsplit('/').next().unwrap_or_default();
+    let last = last.replace("~1", "/").replace("~0", "~");
+    let base = if last.is_empty() {
+        "schema".to_owned()
+    } else {
+        last
+    };
+    let mut name = base.clone();
+    let mut number = 2;
+    while root
+        .get(keyword)
+        .and_then(|definitions| definitions.get(&name))
+        .is_some()
+        || promoted
+            .refs
+            .values()
+            .any(|taken| taken.rsplit('/').next() == Some(&name))
+    {
+        name = format!("{}_{}", base, number);
+        number += 1;
+    }
+    let local_ref = format!("#{}", push_pointer(&push_pointer("", keyword), &name));
+    promoted
+        .refs
+        .insert(reference.to_owned(), local_ref.clone());
+    local_ref
+}
+
+/// `name` in PascalCase: its words, split at characters other than letters and digits and at
+/// lower to upper case changes, each capitalized, with a `T` in front if it would start with a
+/// digit or be empty.
+fn type_name(name: &str) -> String {
+    let mut type_name = String::with_capacity(name.len());
+    let mut word_start = true;
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if !c.is_alphanumeric() {
+            word_start = true;
+            previous = None;
+            continue;
+        }
+        if c.is_uppercase() && previous.is_some_and(char::is_lowercase) {
+            word_start = true;
+        }
+        if word_start {
+            type_name.extend(c.to_uppercase());
+        } else {
+            type_name.push(c);
+        }
+        word_start = false;
+        previous = Some(c);
+    }
+    if !type_name.starts_with(char::is_alphabetic) {
+        type_name.insert(0, 'T');
+    }
+    type_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{form, title, type_name, typescript};
+    use serde_json::json;
+
+    #[test]
+    fn titles_from_names() {
+        assert_eq!(title("billingAddress"), "Billing address");
+        assert_eq!(title("billing_address"), "Billing address");
+        assert_eq!(title("zip-code"), "Zip code");
+        assert_eq!(title("_id"), "Id");
+    }
+
+    #[test]
+    fn nested_objects_and_data_are_told_apart() {
+        let mut schema = json!({
+            "properties": {
+                "pets": {"type": "array", "items": {
+                    "properties": {"name": {"type": "string"}},
+                    "required": ["name"]
+                }},
+                "kind": {"default": {"allOf": []}, "enum": [{"properties": {}}]}
+            }
+        });
+        form(&mut schema);
+        assert_eq!(
+            schema,
+            json!({
+                "ui:order": ["kind", "pets"],
+                "properties": {
+                    "pets": {"type": "array", "title": "Pets", "ui:required": false, "items": {
+                        "ui:order": ["name"],
+                        "properties": {"name": {
+                            "type": "string", "title": "Name", "ui:required": true
+                        }},
+                        "required": ["name"]
+                    }},
+                    "kind": {
+                        "default": {"allOf": []},
+                        "enum": [{"properties": {}}],
+                        "title": "Kind",
+                        "ui:required": false
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn type_names() {
+        assert_eq!(type_name("pet_owner"), "PetOwner");
+        assert_eq!(type_name("other_json_Address"), "OtherJsonAddress");
+        assert_eq!(type_name("petOwner"), "PetOwner");
+        assert_eq!(type_name("2fa"), "T2fa");
+    }
+
+    #[test]
+    fn recursive_anonymous_schemas_become_definitions() {
+        let mut schema = json!({
+            "properties": {
+                "tree": {"properties": {"children": {
+                    "type": "array", "items": {"$ref": "#/properties/tree"}
+                }}},
+                "forest": {"items": {"$ref": "#/properties/tree"}, "minItems": 1}
+            },
+            "definitions": {"Tree": {"title": "taken"}, "tree": {"title": "taken too"}}
+        });
+        let names = typescript(&mut schema);
+        let tree = json!({"properties": {"children": {
+            "type": "array", "items": {"$ref": "#/definitions/tree_2"}
+        }}});
+        assert_eq!(schema["definitions"]["tree_2"], tree);
+        assert_eq!(
+            schema["properties"]["forest"]["items"],
+            json!({"$ref": "#/definitions/tree_2"})
+        );
+        assert_eq!(
+            names.into_iter().collect::<Vec<_>>(),
+            [
+                ("#/definitions/Tree".to_owned(), "Tree".to_owned()),
+                ("#/definitions/tree".to_owned(), "Tree2".to_owned()),
+                ("#/definitions/tree_2".to_owned(), "Tree22".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! How query strings in ref URLs affect which document a ref names.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// What the query string of a ref URL means for document identity, set with
+/// [`JsonRef::set_query_strategy`].
+///
+/// Documents are cached, bundled and checked for recursion by URL, so the strategy decides
+/// whether `schema.json?version=2` and `schema.json` are one document or two.
+///
+/// [`JsonRef::set_query_strategy`]: crate::JsonRef::set_query_strategy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum QueryStrategy {
+    /// The query is part of the URL as written: `?a=1&b=2` and `?b=2&a=1` are different
+    /// documents, and the query is sent when fetching.
+    #[default]
+    Keep,
+    /// The query is removed before the document is cached or fetched.
+    Strip,
+    /// The query parameters are sorted and re-encoded, so parameter order and percent-encoding
+    /// do not matter. An empty query is removed.
+    Canonicalize,
+}
+
+impl QueryStrategy {
+    /// Rewrite the query of `url` according to the strategy.
+    pub(crate) fn apply(self, url: &mut Url) {
+        // What looks like the query of a `data:` URL is part of the embedded document.
+        if url.cannot_be_a_base() {
+            return;
+        }
+        match self {
+            QueryStrategy::Keep => {}
+            QueryStrategy::Strip => url.set_query(None),
+            QueryStrategy::Canonicalize => {
+                let mut pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+                if pairs.is_empty() {
+                    url.set_query(None);
+                    return;
+                }
+                pairs.sort();
+                url.query_pairs_mut().clear().extend_pairs(pairs);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryStrategy;
+    use crate::JsonRef;
+    use serde_json::json;
+    use url::Url;
+
+    #[test]
+    fn query_strategies() {
+        let apply = |strategy: QueryStrategy, url: &str| {
+            let mut url = Url::parse(url).unwrap();
+            strategy.apply(&mut url);
+            url.to_string()
+        };
+        let url = "https://example.com/pet.json?v=2&a=b%20c#/title";
+        assert_eq!(apply(QueryStrategy::Keep, url), url);
+        assert_eq!(
+            apply(QueryStrategy::Strip, url),
+            "https://example.com/pet.json#/title"
+        );
+        assert_eq!(
+            apply(QueryStrategy::Canonicalize, url),
+            "https://example.com/pet.json?a=b+c&v=2#/title"
+        );
+        assert_eq!(
+            apply(QueryStrategy::Canonicalize, "https://example.com/pet.json?"),
+            "https://example.com/pet.json"
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_query_strategy(QueryStrategy::Canonicalize);
+        jsonref
+            .add_schema(
+                "https://example.com/pet.json?a=1&v=2",
+                json!({"title": "pet"}),
+            )
+            .unwrap();
+        let mut input = json!({"items": {"$ref": "https://example.com/pet.json?v=2&a=1"}});
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input, json!({"items": {"title": "pet"}}));
+    }
+}
@@ -0,0 +1,72 @@
+//! What to do when a `$ref` is reached again while it is still being resolved.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How recursive `$ref`s are handled, set with [`JsonRef::set_recursion_strategy`].
+///
+/// [`JsonRef::set_recursion_strategy`]: crate::JsonRef::set_recursion_strategy
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RecursionStrategy {
+    /// Fail with [`Error::RecursiveRef`](crate::Error::RecursiveRef).
+    Error,
+    /// Leave the recursive `$ref` in the output as written.
+    KeepRef,
+    /// Expand a ref up to this many times within itself, then drop it and keep only its
+    /// sibling keywords. `Depth(1)` is the default: the first recursion is expanded.
+    /// `Depth(0)` behaves like `Depth(1)`, as a ref is only known to be recursive once it is
+    /// reached again.
+    Depth(usize),
+    /// Replace the recursive `$ref` and its siblings with this value.
+    Stub(Value),
+    /// Move each recursive schema into the root's `definitions` (or `$defs` from 2019-09)
+    /// and point every `$ref` to it there, e.g. `{"$ref": "#/definitions/node_json"}`, so the
+    /// output stays complete. Names are made as in [`ResolutionMode::Bundle`].
+    ///
+    /// [`ResolutionMode::Bundle`]: crate::ResolutionMode::Bundle
+    Definitions,
+}
+
+impl Default for RecursionStrategy {
+    fn default() -> Self {
+        RecursionStrategy::Depth(1)
+    }
+}
+
+/// The `$ref`s being expanded at a point of the walk, outermost first, with how many times
+/// each is in there so that a recursion is found without going through all of them.
+#[derive(Debug, Default)]
+pub(crate) struct RefChain {
+    chain: Vec<String>,
+    depths: HashMap<String, usize>,
+}
+
+impl RefChain {
+    /// Enter the expansion of `reference`.
+    pub(crate) fn push(&mut self, reference: String) {
+        *self.depths.entry(reference.clone()).or_insert(0) += 1;
+        self.chain.push(reference);
+    }
+
+    /// Leave the innermost expansion.
+    pub(crate) fn pop(&mut self) {
+        if let Some(reference) = self.chain.pop() {
+            match self.depths.get_mut(&reference) {
+                Some(depth) if *depth > 1 => *depth -= 1,
+                _ => {
+                    self.depths.remove(&reference);
+                }
+            }
+        }
+    }
+
+    /// How many times `reference` is being expanded.
+    pub(crate) fn depth(&self, reference: &str) -> usize {
+        self.depths.get(reference).copied().unwrap_or(0)
+    }
+
+    pub(crate) fn as_slice(&self) -> &[String] {
+        &self.chain
+    }
+}
@@ -0,0 +1,140 @@
+//! Picking up changes to cached documents, for long-lived services.
+
+use snafu::ResultExt;
+use std::time::SystemTime;
+use url::Url;
+
+use crate::origins::content_hash;
+use crate::resources::Usage;
+use crate::{canonical, JsonRef, Result, UrlParseError};
+
+/// What [`JsonRef::refresh`] found for one document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refreshed {
+    /// URL of the document, without fragment.
+    pub url: String,
+    /// Whether what was loaded differs from the cached copy, or nothing was cached.
+    pub changed: bool,
+    /// The hex SHA-256 of what was loaded, as compact JSON with sorted keys, as in
+    /// [`JsonRef::set_record_origins`].
+    pub sha256: String,
+}
+
+impl JsonRef {
+    /// Load the documents at `urls` again, replacing the copies cached, and report which of
+    /// them changed.
+    ///
+    /// Documents are loaded as when a ref leads to them: from the resolver, a scheme handler,
+    /// the file or the server, which the disk cache, if set, asks whether its copy is still
+    /// current. Only documents are cached, not what refs into them resolved to, so the next
+    /// deref of any document referring to one that changed sees the change.
+    ///
+    /// Fails on the first document that cannot be loaded, whose cached copy is then kept;
+    /// those before it are refreshed. The [`ResourceLimits`](crate::ResourceLimits) apply to
+    /// the refresh as a whole.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    /// use std::fs;
+    ///
+    /// let path = std::env::temp_dir().join("jsonref-refresh-doc.json");
+    /// fs::write(&path, r#"{"type": "string"}"#).unwrap();
+    /// let url = url::Url::from_file_path(&path).unwrap().to_string();
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// let mut input = json!({"items": {"$ref": url}});
+    /// jsonref.deref_value(&mut input.clone()).unwrap();
+    ///
+    /// fs::write(&path, r#"{"type": "integer"}"#).unwrap();
+    /// assert!(jsonref.refresh(&[&url]).unwrap()[0].changed);
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(input["items"], json!({"type": "integer"}));
+    /// ```
+    pub fn refresh(&mut self, urls: &[&str]) -> Result<Vec<Refreshed>> {
+        self.usage = Usage::default();
+        let mut refreshed = Vec::with_capacity(urls.len());
+        for uri in urls {
+            let mut url = Url::parse(uri).context(UrlParseError {
+                url: (*uri).to_owned(),
+            })?;
+            url.set_fragment(None);
+            self.query.apply(&mut url);
+            let key = canonical::cache_key(&url);
+            let cached = self.schema_cache.get(&key);
+            #[cfg(feature = "network")]
+            {
+                self.revalidating = true;
+            }
+            let document = self.load_document(&url);
+            #[cfg(feature = "network")]
+            {
+                self.revalidating = false;
+            }
+            let document = document?;
+            let changed = cached.as_ref() != Some(&document);
+            let sha256 = content_hash(&document);
+            if changed {
+                if let Some(cached) = &cached {
+                    self.forget_alias(&url, &key, cached);
+                }
+                self.cache_document(&url, key, document);
+            } else {
+                self.loaded_at.insert(key, SystemTime::now());
+            }
+            refreshed.push(Refreshed {
+                url: url.to_string(),
+                changed,
+                sha256,
+            });
+        }
+        Ok(refreshed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::JsonRef;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use url::Url;
+
+    #[test]
+    fn refreshed_documents_replace_their_aliases() {
+        let upstream = Arc::new(Mutex::new(json!({
+            "$id": "https://example.com/v1/pet.json",
+            "title": "pet"
+        })));
+        let served = upstream.clone();
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolver(Box::new(move |_: &Url| -> Result<Option<Value>, _> {
+            Ok(Some(served.lock().unwrap().clone()))
+        }));
+        let pet = "https://example.com/pet.json";
+        let mut input = json!({
+            "properties": {
+                "pet": {"$ref": pet},
+                "v1": {"$ref": "https://example.com/v1/pet.json"}
+            }
+        });
+        jsonref.deref_value(&mut input.clone()).unwrap();
+
+        let unchanged = jsonref.refresh(&[&format!("{}#/title", pet)]).unwrap();
+        assert_eq!(unchanged[0].url, pet);
+        assert!(!unchanged[0].changed);
+
+        *upstream.lock().unwrap() = json!({
+            "$id": "https://example.com/v2/pet.json",
+            "title": "pet v2"
+        });
+        assert!(jsonref.refresh(&[pet]).unwrap()[0].changed);
+        assert!(jsonref.warnings().is_empty());
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input["properties"]["pet"]["title"], "pet v2");
+        // The old `$id` no longer leads to the document, so it is loaded on its own.
+        assert_eq!(
+            jsonref.loaded_documents(),
+            ["https://example.com/v1/pet.json"]
+        );
+    }
+}
@@ -0,0 +1,239 @@
+//! Finding the `$ref`s in a value without resolving them.
+
+use serde_json::Value;
+use std::rc::Rc;
+use url::Url;
+
+use crate::explain::push_pointer;
+use crate::{Draft, DATA_KEYWORDS, NAMED_SUBSCHEMAS_KEYWORDS};
+
+/// Where a `$ref` points, relative to the document it appears in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RefKind {
+    /// Into the document the ref appears in, e.g. `#/definitions/a`.
+    Internal,
+    /// Into a local file.
+    File,
+    /// Into a document fetched over http(s).
+    Remote,
+    /// Into a document with some other URL scheme.
+    Other,
+    /// The ref could not be joined to its base URL.
+    Invalid,
+}
+
+/// A `$ref` found in a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefSite {
+    /// JSON pointer of the object holding the `$ref`.
+    pub pointer: String,
+    /// The `$ref` exactly as written.
+    pub raw_ref: String,
+    /// Absolute URL of the ref, including its fragment, if it could be resolved.
+    pub resolved_url: Option<String>,
+    pub kind: RefKind,
+}
+
+/// Every string `$ref` in `value`, in document order, resolved against `base`.
+///
+/// Base URI changes from `$id` (or `id` in draft-04 schemas) are followed the same way as
+/// when dereferencing. Arrays are walked too, but not the data of `const`, `default`, `enum`
+/// and `examples`, and a property named `$ref` is not a ref.
+///
+/// ```
+/// use polywrap_jsonref::{ref_sites, RefKind};
+/// use serde_json::json;
+/// use url::Url;
+///
+/// let schema = json!({"properties": {"a": {"$ref": "#/definitions/a"},
+///                                    "b": {"$ref": "b.json"}}});
+/// let base = Url::parse("https://example.com/schemas/root.json").unwrap();
+///
+/// let sites = ref_sites(&schema, &base);
+/// assert_eq!(sites[0].kind, RefKind::Internal);
+/// assert_eq!(sites[1].pointer, "/properties/b");
+/// assert_eq!(
+///     sites[1].resolved_url.as_deref(),
+///     Some("https://example.com/schemas/b.json")
+/// );
+/// ```
+pub fn ref_sites(value: &Value, base: &Url) -> Vec<RefSite> {
+    let mut sites = vec![];
+    // Members are pushed last to first so they come off the stack in document order. Only
+    // objects and arrays can hold refs, so nothing else is pushed.
+    let mut stack = vec![(value, Rc::new(base.clone()), None, String::new(), false)];
+    while let Some((value, base, draft, pointer, names)) = stack.pop() {
+        match value {
+            Value::Object(obj) if names => {
+                for (key, child) in obj.iter().rev().filter(|(_, child)| is_container(child)) {
+                    let pointer = push_pointer(&pointer, key);
+                    stack.push((child, Rc::clone(&base), draft, pointer, false));
+                }
+            }
+            Value::Object(obj) => {
+                let draft = obj
+                    .get("$schema")
+                    .and_then(Value::as_str)
+                    .and_then(Draft::from_schema_uri)
+                    .or(draft);
+                let id_keyword = draft.map_or("$id", Draft::id_keyword);
+                let mut base = base;
+                if let Some(id) = obj.get(id_keyword).and_then(Value::as_str) {
+                    if !id.starts_with('#') {
+                        if let Ok(joined) = base.join(id) {
+                            base = Rc::new(joined);
+                        }
+                    }
+                }
+
+                if let Some(raw_ref) = obj.get("$ref").and_then(Value::as_str) {
+                    sites.push(site(&pointer, raw_ref, &base));
+                }
+                for (key, child) in obj.iter().rev().filter(|(_, child)| is_container(child)) {
+                    if DATA_KEYWORDS.contains(&key.as_str()) {
+                        continue;
+                    }
+                    let names = NAMED_SUBSCHEMAS_KEYWORDS.contains(&key.as_str());
+                    let pointer = push_pointer(&pointer, key);
+                    stack.push((child, Rc::clone(&base), draft, pointer, names));
+                }
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter().enumerate().rev() {
+                    if is_container(item) {
+                        let pointer = push_pointer(&pointer, &i.to_string());
+                        stack.push((item, Rc::clone(&base), draft, pointer, false));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    sites
+}
+
+fn is_container(value: &Value) -> bool {
+    value.is_object() || value.is_array()
+}
+
+/// Check that no `$ref` is left in `value`, for pipelines that need a fully flat schema.
+///
+/// Fails with the JSON pointers of the objects still holding a string `$ref`, in document
+/// order, whatever kept them: a recursion strategy, an `x-jsonref` directive or
+/// [`OnMissing`](crate::OnMissing).
+///
+/// ```
+/// use polywrap_jsonref::{assert_fully_resolved, JsonRef, RecursionStrategy};
+/// use serde_json::json;
+///
+/// let mut schema = json!({"properties": {"next": {"$ref": "#"}}});
+/// let mut jsonref = JsonRef::new();
+/// jsonref.set_recursion_strategy(RecursionStrategy::KeepRef);
+/// jsonref.deref_value(&mut schema).unwrap();
+/// assert_eq!(
+///     assert_fully_resolved(&schema),
+///     Err(vec!["/properties/next/properties/next".to_owned()])
+/// );
+/// ```
+pub fn assert_fully_resolved(value: &Value) -> Result<(), Vec<String>> {
+    // Only the pointers are returned, so any base will do.
+    let base = Url::parse("file:///").unwrap();
+    let remaining: Vec<String> = ref_sites(value, &base)
+        .into_iter()
+        .map(|site| site.pointer)
+        .collect();
+    if remaining.is_empty() {
+        Ok(())
+    } else {
+        Err(remaining)
+    }
+}
+
+pub(crate) fn site(pointer: &str, raw_ref: &str, base: &Url) -> RefSite {
+    let resolved = base.join(raw_ref).ok();
+    let kind = match &resolved {
+        None => RefKind::Invalid,
+        Some(url) => {
+            let mut document = url.clone();
+            document.set_fragment(None);
+            let mut base_document = base.clone();
+            base_document.set_fragment(None);
+            if document == base_document {
+                RefKind::Internal
+            } else {
+                match url.scheme() {
+                    "file" => RefKind::File,
+                    "http" | "https" => RefKind::Remote,
+                    _ => RefKind::Other,
+                }
+            }
+        }
+    };
+    RefSite {
+        pointer: pointer.to_owned(),
+        raw_ref: raw_ref.to_owned(),
+        resolved_url: resolved.map(|url| url.to_string()),
+        kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assert_fully_resolved, ref_sites, RefKind};
+    use serde_json::json;
+    use url::Url;
+
+    #[test]
+    fn follows_ids_and_arrays() {
+        let schema = json!(
+            {"$id": "https://example.com/root.json",
+             "allOf": [{"$ref": "file:///schemas/a.json"},
+                       {"$id": "nested/", "$ref": "b.json#/x"}],
+             "items": {"$ref": "#"},
+             "not": {"$ref": "urn:example:c"},
+             "ignored": {"$ref": 3}}
+        );
+        let base = Url::parse("file:///tmp/anon.json").unwrap();
+        let sites: Vec<_> = ref_sites(&schema, &base)
+            .into_iter()
+            .map(|site| (site.pointer, site.resolved_url.unwrap(), site.kind))
+            .collect();
+        assert_eq!(
+            sites,
+            vec![
+                (
+                    "/allOf/0".to_owned(),
+                    "file:///schemas/a.json".to_owned(),
+                    RefKind::File
+                ),
+                (
+                    "/allOf/1".to_owned(),
+                    "https://example.com/nested/b.json#/x".to_owned(),
+                    RefKind::Remote
+                ),
+                (
+                    "/items".to_owned(),
+                    "https://example.com/root.json#".to_owned(),
+                    RefKind::Internal
+                ),
+                (
+                    "/not".to_owned(),
+                    "urn:example:c".to_owned(),
+                    RefKind::Other
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn remaining_refs_are_reported() {
+        assert_eq!(
+            assert_fully_resolved(&json!({"properties": {"$ref": {}}})),
+            Ok(())
+        );
+        assert_eq!(
+            assert_fully_resolved(&json!({"items": [{"$ref": "a.json"}], "not": {"$ref": "#"}})),
+            Err(vec!["/items/0".to_owned(), "/not".to_owned()])
+        );
+    }
+}
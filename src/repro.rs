@@ -0,0 +1,332 @@
+//! Reproductions of failed dereferences, to attach to bug reports and to replay in tests.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use url::Url;
+
+use crate::{
+    canonical, DefinitionCollisions, DefinitionsMode, Draft, Error, JsonRef, OnMissing,
+    QueryStrategy, RecursionStrategy, ResolutionMode, ResourceLimits, Result, SchemaFromFile,
+    SchemaNotJsonSerde, SiblingStrategy, WriteOutput, WriteRepro,
+};
+
+/// Bumped whenever the layout of `repro.json` changes, so old reproductions are refused.
+const REPRO_VERSION: u32 = 1;
+
+/// Keywords whose string values are kept when redacting, as resolution depends on them.
+const KEPT_KEYWORDS: &[&str] = &[
+    "$ref",
+    "$id",
+    "id",
+    "$schema",
+    "$anchor",
+    "$dynamicRef",
+    "$dynamicAnchor",
+    "$recursiveRef",
+    "x-jsonref",
+];
+
+/// The options of a `JsonRef` that can be written down. Resolvers, HTTP settings and
+/// concurrency limits are not, and replays run offline.
+#[derive(Debug, Serialize, Deserialize)]
+struct Options {
+    mode: ResolutionMode,
+    recursion: RecursionStrategy,
+    on_missing: OnMissing,
+    query: QueryStrategy,
+    draft: Option<Draft>,
+    default_draft: Option<Draft>,
+    reference_key: Option<String>,
+    #[serde(default)]
+    sibling_strategy: SiblingStrategy,
+    #[serde(default)]
+    definitions_mode: DefinitionsMode,
+    #[serde(default)]
+    definition_collisions: DefinitionCollisions,
+    document_ref_budget: Option<usize>,
+    resource_limits: ResourceLimits,
+}
+
+/// The `repro.json` at the top of a reproduction directory.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    /// URL of the document the dereference started from.
+    root: String,
+    /// The innermost ref that failed, with the document and pointer it was found at.
+    trigger: Option<Value>,
+    error: Value,
+    options: Options,
+    redacted: bool,
+    /// Cache key of each document, and the file under `documents/` holding it.
+    documents: BTreeMap<String, String>,
+}
+
+/// A reproduction written by [`JsonRef::write_repro`], read back to replay it.
+///
+/// ```
+/// # use polywrap_jsonref::{JsonRef, Repro};
+/// use serde_json::json;
+///
+/// let mut jsonref = JsonRef::new();
+/// let mut schema = json!({"items": {"$ref": "#/definitions/missing"}});
+/// let error = jsonref.deref_value(&mut schema).unwrap_err();
+///
+/// let dir = std::env::temp_dir().join("jsonref-repro-doc");
+/// jsonref.write_repro(&error, &dir, false).unwrap();
+///
+/// let repro = Repro::load(&dir).unwrap();
+/// assert_eq!(repro.error["kind"], "ref_failed");
+/// assert!(repro.replay().is_err());
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Repro {
+    /// URL of the document the dereference started from.
+    pub root: String,
+    /// The error the dereference failed with, as [`Error::to_json`] gives it.
+    pub error: Value,
+    /// The documents involved, by URL.
+    pub documents: BTreeMap<String, Value>,
+    options: Options,
+}
+
+impl JsonRef {
+    /// Write a reproduction of `error`, returned by the last dereference, into the directory
+    /// `dir`: a `repro.json` naming the failing ref, the error and the options, and the
+    /// documents involved under `documents/`.
+    ///
+    /// Only the root document, the documents on the chain of refs that led to the failure and
+    /// the target's document are kept. With `redact`, every string in them is replaced by
+    /// `"redacted"` except the refs, ids and anchors resolution depends on; property names
+    /// are kept too, as pointers go through them.
+    pub fn write_repro(
+        &mut self,
+        error: &Error,
+        dir: impl AsRef<Path>,
+        redact: bool,
+    ) -> Result<()> {
+        let dir = dir.as_ref();
+        let mut involved = vec![self.root_document.clone()];
+        let mut trigger = None;
+        let mut cause = error;
+        while let Error::RefFailed {
+            reference,
+            document,
+            pointer,
+            chain,
+            source,
+        } = cause
+        {
+            involved.extend(chain.iter().cloned());
+            involved.push(document.clone());
+            if let Ok(target) = Url::parse(document).and_then(|base| base.join(reference)) {
+                involved.push(target.to_string());
+            }
+            trigger = Some(serde_json::json!({
+                "reference": reference,
+                "document": document,
+                "pointer": pointer,
+            }));
+            cause = source;
+        }
+
+        let mut documents = BTreeMap::new();
+        for url in involved {
+            let key = match Url::parse(&url) {
+                Ok(mut url) => {
+                    url.set_fragment(None);
+                    canonical::cache_key(&url)
+                }
+                Err(_) => url,
+            };
+            if documents.contains_key(&key) {
+                continue;
+            }
+            if let Some(document) = self.cached(&key) {
+                documents.insert(key, document);
+            }
+        }
+
+        let documents_dir = dir.join("documents");
+        fs::create_dir_all(&documents_dir).context(WriteRepro {
+            path: documents_dir.to_string_lossy(),
+        })?;
+        let mut files = BTreeMap::new();
+        for (index, (key, document)) in documents.into_iter().enumerate() {
+            let file = format!("documents/{}.json", index);
+            let document = if redact {
+                redacted(&document)
+            } else {
+                Value::clone(&document)
+            };
+            write_file(&dir.join(&file), &document)?;
+            files.insert(key, file);
+        }
+
+        let manifest = Manifest {
+            version: REPRO_VERSION,
+            root: self.root_document.clone(),
+            trigger,
+            error: error.to_json(),
+            options: Options {
+                mode: self.mode,
+                recursion: self.recursion.clone(),
+                on_missing: self.on_missing,
+                query: self.query,
+                draft: self.draft,
+                default_draft: self.default_draft,
+                reference_key: self.reference_key.clone(),
+                sibling_strategy: self.sibling_strategy,
+                definitions_mode: self.definitions_mode,
+                definition_collisions: self.definition_collisions,
+                document_ref_budget: self.document_ref_budget,
+                resource_limits: self.resource_limits,
+            },
+            redacted: redact,
+            documents: files,
+        };
+        write_file(&dir.join("repro.json"), &manifest)
+    }
+}
+
+impl Repro {
+    /// Read the reproduction written into `dir` by [`JsonRef::write_repro`].
+    pub fn load(dir: impl AsRef<Path>) -> Result<Repro> {
+        let dir = dir.as_ref();
+        let manifest: Manifest = read_file(&dir.join("repro.json"))?;
+        if manifest.version != REPRO_VERSION {
+            return Err(Error::InvalidRepro {
+                reason: format!(
+                    "version {} is not the supported version {}",
+                    manifest.version, REPRO_VERSION
+                ),
+            });
+        }
+        let mut documents = BTreeMap::new();
+        for (key, file) in manifest.documents {
+            documents.insert(key, read_file(&dir.join(file))?);
+        }
+        Ok(Repro {
+            root: manifest.root,
+            error: manifest.error,
+            documents,
+            options: manifest.options,
+        })
+    }
+
+    /// Dereference the root document again, offline, with the recorded options and only the
+    /// recorded documents.
+    pub fn replay(&self) -> Result<Value> {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_offline(true);
+        jsonref.mode = self.options.mode;
+        jsonref.recursion = self.options.recursion.clone();
+        jsonref.on_missing = self.options.on_missing;
+        jsonref.query = self.options.query;
+        jsonref.draft = self.options.draft;
+        jsonref.default_draft = self.options.default_draft;
+        jsonref.reference_key = self.options.reference_key.clone();
+        jsonref.sibling_strategy = self.options.sibling_strategy;
+        jsonref.definitions_mode = self.options.definitions_mode;
+        jsonref.definition_collisions = self.options.definition_collisions;
+        jsonref.document_ref_budget = self.options.document_ref_budget;
+        jsonref.resource_limits = self.options.resource_limits;
+        for (key, document) in &self.documents {
+            jsonref
+                .schema_cache
+                .put(key.clone(), Arc::new(document.clone()));
+        }
+        jsonref.deref_url(&self.root)
+    }
+}
+
+/// `value` with every string replaced, except those of [`KEPT_KEYWORDS`].
+fn redacted(value: &Value) -> Value {
+    match value {
+        Value::String(_) => Value::from("redacted"),
+        Value::Array(items) => items.iter().map(redacted).collect(),
+        Value::Object(members) => {
+            let members: Map<String, Value> = members
+                .iter()
+                .map(|(key, member)| {
+                    let member = if KEPT_KEYWORDS.contains(&key.as_str()) && member.is_string() {
+                        member.clone()
+                    } else {
+                        redacted(member)
+                    };
+                    (key.clone(), member)
+                })
+                .collect();
+            Value::Object(members)
+        }
+        other => other.clone(),
+    }
+}
+
+fn write_file(path: &Path, value: &impl Serialize) -> Result<()> {
+    let text = serde_json::to_string_pretty(value).context(WriteOutput {})?;
+    fs::write(path, text).context(WriteRepro {
+        path: path.to_string_lossy(),
+    })
+}
+
+fn read_file<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let text = fs::read_to_string(path).context(SchemaFromFile {
+        filename: path.to_string_lossy(),
+    })?;
+    serde_json::from_str(&text).context(SchemaNotJsonSerde {
+        url: path.to_string_lossy(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repro;
+    use crate::JsonRef;
+    use serde_json::json;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn repro_keeps_only_the_involved_documents() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .add_schema(
+                "https://example.com/a.json",
+                json!({"title": "secret", "items": {"$ref": "b.json#/definitions/b"}}),
+            )
+            .unwrap();
+        jsonref
+            .add_schema(
+                "https://example.com/b.json",
+                json!({"definitions": {"b": {"items": {"$ref": "#/definitions/missing"}}}}),
+            )
+            .unwrap();
+        jsonref
+            .add_schema("https://example.com/unrelated.json", json!({}))
+            .unwrap();
+        let error = jsonref.deref_url("https://example.com/a.json").unwrap_err();
+
+        let dir = env::temp_dir().join(format!("jsonref-repro-{}", std::process::id()));
+        jsonref.write_repro(&error, &dir, true).unwrap();
+        let repro = Repro::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            repro.documents.keys().collect::<Vec<_>>(),
+            ["https://example.com/a.json", "https://example.com/b.json"]
+        );
+        assert_eq!(
+            repro.documents["https://example.com/a.json"],
+            json!({"title": "redacted", "items": {"$ref": "b.json#/definitions/b"}})
+        );
+        let replayed = repro.replay().unwrap_err();
+        assert_eq!(replayed.to_json()["source"], error.to_json()["source"]);
+    }
+}
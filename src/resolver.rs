@@ -0,0 +1,44 @@
+//! Hook for loading external `$ref` documents yourself.
+
+use serde_json::Value;
+use url::Url;
+
+/// Error type returned by a [`RefResolver`].
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Supplies the documents behind external `$ref`s.
+///
+/// The resolver is asked first for every document that is not in the cache yet. Returning
+/// `Ok(None)` falls back to the built in loading of `http(s)` and `file` URLs.
+///
+/// Any `Fn(&Url) -> Result<Option<Value>, BoxError>` closure is a resolver:
+///
+/// ```
+/// # use polywrap_jsonref::JsonRef;
+/// use serde_json::json;
+///
+/// let mut jsonref = JsonRef::new();
+/// jsonref.set_resolver(Box::new(|url: &url::Url| {
+///     if url.host_str() == Some("artifacts.internal") {
+///         return Ok(Some(json!({"title": "from the artifact store"})));
+///     }
+///     Ok(None)
+/// }));
+///
+/// let mut input = json!({"properties": {"a": {"$ref": "https://artifacts.internal/a.json"}}});
+/// jsonref.deref_value(&mut input).unwrap();
+/// assert_eq!(input, json!({"properties": {"a": {"title": "from the artifact store"}}}));
+/// ```
+pub trait RefResolver: Send + Sync {
+    /// Return the document at `url` (without its fragment), or `None` to use the default loader.
+    fn resolve(&self, url: &Url) -> Result<Option<Value>, BoxError>;
+}
+
+impl<F> RefResolver for F
+where
+    F: Fn(&Url) -> Result<Option<Value>, BoxError> + Send + Sync,
+{
+    fn resolve(&self, url: &Url) -> Result<Option<Value>, BoxError> {
+        self(url)
+    }
+}
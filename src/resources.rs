@@ -0,0 +1,250 @@
+//! Bounds on the work a single dereference may do, against ref bombs and hung servers.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "network")]
+use crate::TenantUsage;
+use crate::{Error, JsonRef, Result};
+
+/// Limits on one dereference, set with [`JsonRef::set_resource_limits`]. `None` means no
+/// limit, which is the default for all of them.
+///
+/// ```
+/// # use polywrap_jsonref::{Error, JsonRef, ResourceLimits};
+/// use serde_json::json;
+///
+/// let mut jsonref = JsonRef::new();
+/// jsonref.set_resource_limits(ResourceLimits {
+///     max_expanded_values: Some(1_000),
+///     ..ResourceLimits::default()
+/// });
+/// // Every level refers to the one below twice, doubling the size of the output.
+/// let mut bomb = json!({"items": {"$ref": "#/$defs/l9"}, "$defs": {"l0": {"type": "string"}}});
+/// for level in 1..10 {
+///     let below = format!("#/$defs/l{}", level - 1);
+///     bomb["$defs"][format!("l{}", level)] =
+///         json!({"items": {"$ref": below}, "not": {"$ref": below}});
+/// }
+/// let error = jsonref.deref_value(&mut bomb).unwrap_err();
+/// assert!(matches!(error, Error::ExpansionTooLarge { .. }));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    /// Most documents fetched over http(s). Cached documents are not fetched again, so they
+    /// are not counted twice, nor are fresh copies in the disk cache; those revalidated with
+    /// the server are.
+    pub max_fetches: Option<usize>,
+    /// Most bytes read for any one loaded document, whether fetched, read from a file or
+    /// embedded in a `data:` URL.
+    pub max_document_bytes: Option<u64>,
+    /// Most JSON values (objects, arrays, strings, numbers, ...) copied from ref targets into
+    /// the output. This bounds how large the output can grow however deeply refs nest.
+    pub max_expanded_values: Option<usize>,
+    /// Longest a dereference may take, including fetches: each request is given only the
+    /// time left, so a server that stops sending part way through a body cannot hold it up.
+    pub deadline: Option<Duration>,
+}
+
+/// What the current dereference has used so far.
+#[derive(Debug)]
+pub(crate) struct Usage {
+    #[cfg(feature = "network")]
+    fetches: usize,
+    /// What was fetched for the tenant, if any.
+    #[cfg(feature = "network")]
+    pub(crate) tenant: TenantUsage,
+    values: usize,
+    started: Instant,
+}
+
+impl Default for Usage {
+    fn default() -> Self {
+        Usage {
+            #[cfg(feature = "network")]
+            fetches: 0,
+            #[cfg(feature = "network")]
+            tenant: TenantUsage::default(),
+            values: 0,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl JsonRef {
+    /// Bound the fetches, document sizes, output size and time of each dereference, failing
+    /// with an error naming the limit once one is exceeded.
+    pub fn set_resource_limits(&mut self, limits: ResourceLimits) {
+        self.resource_limits = limits;
+    }
+
+    /// Count a fetch of `url`, made over the network, failing if it was one too many.
+    #[cfg(feature = "network")]
+    pub(crate) fn count_fetch(&mut self, url: &str) -> Result<()> {
+        self.usage.fetches += 1;
+        match self.resource_limits.max_fetches {
+            Some(limit) if self.usage.fetches > limit => Err(Error::TooManyFetches {
+                url: url.to_owned(),
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// How many more fetches the current dereference may make, if it is limited.
+    #[cfg(feature = "network")]
+    pub(crate) fn fetches_left(&self) -> Option<usize> {
+        let limit = self.resource_limits.max_fetches?;
+        Some(limit.saturating_sub(self.usage.fetches))
+    }
+
+    /// How many bytes to read at most for a document: one past the limit, so that going over
+    /// it can be told apart from reaching it.
+    pub(crate) fn read_limit(&self) -> u64 {
+        self.resource_limits
+            .max_document_bytes
+            .map_or(u64::MAX, |limit| limit.saturating_add(1))
+    }
+
+    /// Fail if the document at `url` was `len` bytes, more than allowed.
+    pub(crate) fn check_document_size(&self, url: &str, len: usize) -> Result<()> {
+        match self.resource_limits.max_document_bytes {
+            Some(limit) if len as u64 > limit => Err(Error::DocumentTooLarge {
+                url: url.to_owned(),
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count the values of a ref target about to be copied into the output.
+    pub(crate) fn count_expansion(&mut self, schema: &Value) -> Result<()> {
+        let limit = match self.resource_limits.max_expanded_values {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        self.usage.values = self.usage.values.saturating_add(count_values(schema));
+        if self.usage.values > limit {
+            return Err(Error::ExpansionTooLarge { limit });
+        }
+        Ok(())
+    }
+
+    /// Fail if the current dereference has run past its deadline.
+    pub(crate) fn check_deadline(&self) -> Result<()> {
+        match self.resource_limits.deadline {
+            Some(limit) if self.usage.started.elapsed() >= limit => {
+                Err(Error::DeadlineExceeded { limit })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// When the current dereference has to be done by, if it has a deadline.
+    #[cfg(feature = "network")]
+    pub(crate) fn deadline(&self) -> Option<Deadline> {
+        let limit = self.resource_limits.deadline?;
+        Some(Deadline {
+            at: self.usage.started.checked_add(limit)?,
+            limit,
+        })
+    }
+}
+
+/// When the current dereference has to be done by, from [`ResourceLimits::deadline`].
+#[cfg(feature = "network")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    at: Instant,
+    limit: Duration,
+}
+
+#[cfg(feature = "network")]
+impl Deadline {
+    /// How long is left, or [`Error::DeadlineExceeded`] once nothing is.
+    pub(crate) fn left(&self) -> Result<Duration> {
+        match self.at.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(left),
+            _ => Err(Error::DeadlineExceeded { limit: self.limit }),
+        }
+    }
+
+    /// `error`, or [`Error::DeadlineExceeded`] if it came from running out of time.
+    pub(crate) fn explain(&self, error: Error) -> Error {
+        self.left().err().unwrap_or(error)
+    }
+}
+
+/// The number of values in `value`, counting itself.
+fn count_values(value: &Value) -> usize {
+    let mut count = 0;
+    let mut stack = vec![value];
+    while let Some(value) = stack.pop() {
+        count += 1;
+        match value {
+            Value::Array(items) => stack.extend(items),
+            Value::Object(members) => stack.extend(members.values()),
+            _ => {}
+        }
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, JsonRef, ResourceLimits};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn limits_stop_dereferencing() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resource_limits(ResourceLimits {
+            max_document_bytes: Some(32),
+            ..ResourceLimits::default()
+        });
+        let mut schema = json!({"$ref": "fixtures/nested_relative/other.json"});
+        let error = jsonref.deref_value(&mut schema).unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            Error::DocumentTooLarge { limit: 32, url } if url.ends_with("other.json")
+        ));
+        // The root document is held to the limit too.
+        let error = jsonref
+            .deref_file("fixtures/nested_relative/base.json")
+            .unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            Error::DocumentTooLarge { limit: 32, url } if url.ends_with("base.json")
+        ));
+        let mut schema = json!({"items": {
+            "$ref": "data:application/json,%7B%22title%22:%20%22short%22%7D"
+        }});
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(schema, json!({"items": {"title": "short"}}));
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resource_limits(ResourceLimits {
+            max_expanded_values: Some(3),
+            ..ResourceLimits::default()
+        });
+        let mut schema = json!({"properties": {"a": {"$ref": "#/$defs/a"},
+                                               "b": {"$ref": "#/$defs/a"}},
+                                "$defs": {"a": {"title": "a"}}});
+        jsonref.deref_value(&mut schema.clone()).unwrap_err();
+        schema["properties"].as_object_mut().unwrap().remove("b");
+        jsonref.deref_value(&mut schema).unwrap();
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resource_limits(ResourceLimits {
+            deadline: Some(Duration::ZERO),
+            ..ResourceLimits::default()
+        });
+        let mut schema = json!({"items": {"$ref": "#/$defs/a"}, "$defs": {"a": {}}});
+        assert!(matches!(
+            jsonref.deref_value(&mut schema),
+            Err(Error::DeadlineExceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,171 @@
+//! Choosing which `$ref`s a deref resolves.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{canonical, JsonRef};
+
+/// Which `$ref`s are resolved, set with [`JsonRef::set_ref_selection`]. The others are left
+/// as they are, recorded in the trace as [`Rule::KeptBySelection`](crate::Rule::KeptBySelection),
+/// and the keywords next to them are still walked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RefSelection {
+    /// Every ref.
+    #[default]
+    All,
+    /// Only refs into the root document, e.g. `#/definitions/a`. No document is loaded, read,
+    /// fetched or asked of a resolver, so dereferencing a value is a pure function of it.
+    InternalOnly,
+    /// Only refs into other documents, which are inlined, along with the refs within them,
+    /// into a self-contained document. Refs into the root document are left in place, so its
+    /// definitions are shared rather than copied; those found within inlined schemas are
+    /// rewritten to be local, e.g. `root.json#/definitions/a` to `#/definitions/a`.
+    ///
+    /// Unlike [`ResolutionMode::Bundle`](crate::ResolutionMode::Bundle), which copies each
+    /// schema of another document into the root's definitions, this inlines them where they
+    /// are referred to.
+    ExternalOnly,
+}
+
+impl JsonRef {
+    /// Choose which refs are resolved.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{JsonRef, RefSelection};
+    /// use serde_json::json;
+    ///
+    /// let mut input = json!({
+    ///     "properties": {
+    ///         "id": {"$ref": "#/definitions/id"},
+    ///         "owner": {"$ref": "https://example.com/user.json"}
+    ///     },
+    ///     "definitions": {"id": {"type": "integer"}}
+    /// });
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_ref_selection(RefSelection::InternalOnly);
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(input["properties"]["id"], json!({"type": "integer"}));
+    /// assert_eq!(input["properties"]["owner"], json!({"$ref": "https://example.com/user.json"}));
+    /// assert!(jsonref.loaded_documents().is_empty());
+    /// ```
+    pub fn set_ref_selection(&mut self, selection: RefSelection) {
+        self.selection = selection;
+    }
+
+    /// Whether refs into `document`, a URL without fragment, are resolved.
+    pub(crate) fn selects(&self, document: &Url) -> bool {
+        match self.selection {
+            RefSelection::All => true,
+            RefSelection::InternalOnly => self.in_root(document),
+            RefSelection::ExternalOnly => !self.in_root(document),
+        }
+    }
+
+    /// Whether documents other than the root may be loaded.
+    pub(crate) fn loads_documents(&self) -> bool {
+        self.selection != RefSelection::InternalOnly
+    }
+
+    /// Whether `document`, a URL without fragment, is the root of the current deref or a
+    /// resource embedded in it.
+    fn in_root(&self, document: &Url) -> bool {
+        self.is_root(document)
+            || self
+                .root_resources
+                .contains_key(&canonical::cache_key(document))
+    }
+
+    /// Whether `document`, a URL without fragment, is the root of the current deref, by its
+    /// URL or its `$id`.
+    pub(crate) fn is_root(&self, document: &Url) -> bool {
+        let key = canonical::cache_key(document);
+        self.root
+            .as_ref()
+            .is_some_and(|(root_keys, _)| root_keys.contains(&key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RefSelection;
+    use crate::{JsonRef, Rule};
+    use serde_json::json;
+    use url::Url;
+
+    #[test]
+    fn internal_only_does_no_io() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolver(Box::new(|url: &Url| -> Result<_, _> {
+            panic!("{} was loaded", url)
+        }));
+        jsonref.set_fetch_vocabularies(true);
+        jsonref.set_ref_selection(RefSelection::InternalOnly);
+        let mut input = json!({
+            "$id": "https://example.com/root.json",
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$vocabulary": {"https://example.com/vocab": true},
+            "properties": {
+                "self": {"$ref": "root.json#/$defs/name"},
+                "file": {"$ref": "file:///schemas/other.json", "title": {"$ref": "#/$defs/name"}},
+                "remote": {"$ref": "other.json#/$defs/a"}
+            },
+            "$defs": {"name": {"type": "string"}}
+        });
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(input["properties"]["self"], json!({"type": "string"}));
+        assert_eq!(
+            input["properties"]["file"],
+            json!({"$ref": "file:///schemas/other.json", "title": {"type": "string"}})
+        );
+        assert_eq!(
+            input["properties"]["remote"],
+            json!({"$ref": "other.json#/$defs/a"})
+        );
+        let kept = jsonref.explain("/properties/remote").steps;
+        assert_eq!(kept[0].rules, [Rule::KeptBySelection]);
+    }
+
+    #[test]
+    fn external_only_keeps_refs_into_the_root() {
+        let mut jsonref = JsonRef::new();
+        jsonref.set_ref_selection(RefSelection::ExternalOnly);
+        jsonref
+            .add_schema(
+                "https://example.com/address.json",
+                json!({
+                    "properties": {
+                        "street": {"$ref": "#/definitions/street"},
+                        "owner": {"$ref": "root.json#/definitions/name"}
+                    },
+                    "definitions": {"street": {"type": "string", "minLength": 1}}
+                }),
+            )
+            .unwrap();
+        let mut input = json!({
+            "$id": "https://example.com/root.json",
+            "properties": {
+                "name": {"$ref": "#/definitions/name"},
+                "home": {"$ref": "address.json"}
+            },
+            "definitions": {"name": {"type": "string"}}
+        });
+        jsonref.deref_value(&mut input).unwrap();
+        assert_eq!(
+            input,
+            json!({
+                "$id": "https://example.com/root.json",
+                "properties": {
+                    "name": {"$ref": "#/definitions/name"},
+                    "home": {"properties": {
+                        "street": {"type": "string", "minLength": 1},
+                        "owner": {"$ref": "#/definitions/name"}
+                    }}
+                },
+                "definitions": {
+                    "name": {"type": "string"},
+                    "street": {"type": "string", "minLength": 1}
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! What becomes of the keywords next to an inlined `$ref`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Draft, JsonRef};
+
+/// How the keywords next to an inlined `$ref` are combined with its target, set with
+/// [`JsonRef::set_sibling_strategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SiblingStrategy {
+    /// As the draft of the document the ref is in has it: [`SiblingStrategy::AllOf`] from
+    /// 2019-09 on, [`SiblingStrategy::Discard`] before and when the draft is not known.
+    #[default]
+    ByDraft,
+    /// Drop them, as up to draft-07, where keywords next to a `$ref` are ignored. With a
+    /// [reference key](JsonRef::set_reference_key) set, they are stored under it instead.
+    Discard,
+    /// Copy them into the target, replacing the keywords of the same name, so the siblings
+    /// win. A target that is not an object, i.e. a boolean schema, goes in an `allOf` with
+    /// them instead, as there is nothing to merge them into.
+    Merge,
+    /// Combine the target and them in an `allOf`, as from 2019-09 on, where keywords next to
+    /// a `$ref` apply along with it.
+    AllOf,
+}
+
+impl SiblingStrategy {
+    /// What is done with siblings in a document of `draft`: never [`SiblingStrategy::ByDraft`].
+    pub(crate) fn resolve(self, draft: Option<Draft>) -> SiblingStrategy {
+        match self {
+            SiblingStrategy::ByDraft if draft.is_some_and(Draft::siblings_apply) => {
+                SiblingStrategy::AllOf
+            }
+            SiblingStrategy::ByDraft => SiblingStrategy::Discard,
+            strategy => strategy,
+        }
+    }
+}
+
+impl JsonRef {
+    /// Choose what becomes of the keywords next to an inlined `$ref`, whatever the draft.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::{JsonRef, SiblingStrategy};
+    /// use serde_json::json;
+    ///
+    /// let mut input = json!({
+    ///     "definitions": {"name": {"type": "string", "maxLength": 10}},
+    ///     "properties": {"nick": {"$ref": "#/definitions/name", "maxLength": 5}}
+    /// });
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.set_sibling_strategy(SiblingStrategy::Merge);
+    /// jsonref.deref_value(&mut input).unwrap();
+    /// assert_eq!(input["properties"]["nick"], json!({"type": "string", "maxLength": 5}));
+    /// ```
+    pub fn set_sibling_strategy(&mut self, strategy: SiblingStrategy) {
+        self.sibling_strategy = strategy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{JsonRef, Rule, SiblingStrategy};
+    use serde_json::json;
+
+    #[test]
+    fn siblings_follow_the_strategy_whatever_the_draft() {
+        let input = json!({
+            "$schema": "https://json-schema.org/draft/2019-09/schema",
+            "$defs": {"name": {"type": "string"}, "any": true},
+            "properties": {
+                "nick": {"$ref": "#/$defs/name", "type": "number", "title": "nick"},
+                "any": {"$ref": "#/$defs/any", "title": "any"}
+            }
+        });
+        let deref = |strategy| {
+            let mut jsonref = JsonRef::new();
+            jsonref.set_sibling_strategy(strategy);
+            let mut output = input.clone();
+            jsonref.deref_value(&mut output).unwrap();
+            let rules = jsonref.explain("/properties/nick").steps[0].rules.clone();
+            (output["properties"].clone(), rules)
+        };
+
+        let (merged, rules) = deref(SiblingStrategy::Merge);
+        assert_eq!(merged["nick"], json!({"type": "number", "title": "nick"}));
+        assert_eq!(merged["any"], json!({"allOf": [true, {"title": "any"}]}));
+        assert_eq!(rules, vec![Rule::SiblingsMerged]);
+
+        let (discarded, rules) = deref(SiblingStrategy::Discard);
+        assert_eq!(discarded["nick"], json!({"type": "string"}));
+        assert_eq!(discarded["any"], json!(true));
+        assert_eq!(rules, vec![Rule::SiblingsDiscarded]);
+
+        let (by_draft, _) = deref(SiblingStrategy::ByDraft);
+        assert_eq!(by_draft, deref(SiblingStrategy::AllOf).0);
+        assert_eq!(
+            by_draft["nick"],
+            json!({"allOf": [{"type": "string"}, {"type": "number", "title": "nick"}]})
+        );
+    }
+}
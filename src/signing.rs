@@ -0,0 +1,118 @@
+//! Detached ed25519 signatures of dereferenced schemas, behind the `signing` feature, for
+//! schema artifacts handed between teams.
+//!
+//! What is signed is the [canonical JSON](crate::to_canonical_string) of the schema, so a
+//! signature holds whatever the schema is written as, pretty or not, and whatever order its
+//! keys are in. Keys are raw 32 byte ed25519 keys: the private key is the seed the key pair
+//! is made from. Signatures are base64, to be shipped next to the artifact, e.g. as
+//! `schema.json.sig`.
+//!
+//! ```
+//! use polywrap_jsonref::signing::{public_key, sign, verify};
+//! use serde_json::json;
+//!
+//! let seed = [7; 32];
+//! let schema = json!({"type": "object", "properties": {"id": {"type": "integer"}}});
+//! let signature = sign(&schema, &seed).unwrap();
+//!
+//! let public = public_key(&seed).unwrap();
+//! let received: serde_json::Value = serde_json::from_str(
+//!     r#"{"properties": {"id": {"type": "integer"}}, "type": "object"}"#,
+//! ).unwrap();
+//! assert!(verify(&received, &public, &signature).is_ok());
+//! assert!(verify(&json!({"type": "object"}), &public, &signature).is_err());
+//! ```
+
+use base64::Engine;
+use ring::signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519};
+use serde_json::Value;
+
+use crate::{to_canonical_string, Error, Result};
+
+/// Sign the canonical JSON of `schema` with the ed25519 key made from `seed`, and return the
+/// signature as base64.
+pub fn sign(schema: &Value, seed: &[u8]) -> Result<String> {
+    let key_pair = key_pair(seed)?;
+    let signature = key_pair.sign(to_canonical_string(schema).as_bytes());
+    Ok(base64::engine::general_purpose::STANDARD.encode(signature.as_ref()))
+}
+
+/// The public key of the ed25519 key made from `seed`, to give to those verifying.
+pub fn public_key(seed: &[u8]) -> Result<Vec<u8>> {
+    Ok(key_pair(seed)?.public_key().as_ref().to_vec())
+}
+
+/// Check that `signature`, base64 as [`sign`] returns it, is one of the canonical JSON of
+/// `schema` by the ed25519 key `public_key`.
+///
+/// Fails with [`Error::InvalidSignature`] when it is not, or is not a signature at all.
+pub fn verify(schema: &Value, public_key: &[u8], signature: &str) -> Result<()> {
+    if public_key.len() != 32 {
+        return Err(Error::InvalidSigningKey {
+            reason: format!("public keys are 32 bytes, not {}", public_key.len()),
+        });
+    }
+    let signature = base64::engine::general_purpose::STANDARD
+        .decode(signature.trim())
+        .map_err(|error| Error::InvalidSignature {
+            reason: format!("not base64: {}", error),
+        })?;
+    UnparsedPublicKey::new(&ED25519, public_key)
+        .verify(to_canonical_string(schema).as_bytes(), &signature)
+        .map_err(|_| Error::InvalidSignature {
+            reason: "it was not made from this schema with this key".to_owned(),
+        })
+}
+
+fn key_pair(seed: &[u8]) -> Result<Ed25519KeyPair> {
+    if seed.len() != 32 {
+        return Err(Error::InvalidSigningKey {
+            reason: format!("private keys are 32 byte seeds, not {} bytes", seed.len()),
+        });
+    }
+    Ed25519KeyPair::from_seed_unchecked(seed).map_err(|error| Error::InvalidSigningKey {
+        reason: error.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{public_key, sign, verify};
+    use crate::Error;
+    use serde_json::json;
+
+    #[test]
+    fn signatures_cover_the_canonical_schema() {
+        // The key of RFC 8032, section 7.1, test 1.
+        let seed: Vec<u8> = (0..32)
+            .map(|i| {
+                let hex = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+                u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap()
+            })
+            .collect();
+        let public = public_key(&seed).unwrap();
+        assert_eq!(public[..4], [0xd7, 0x5a, 0x98, 0x01]);
+
+        let schema = json!({"b": 1, "a": [1.0, "x"]});
+        let signature = sign(&schema, &seed).unwrap();
+        assert_eq!(
+            signature,
+            sign(&json!({"a": [1.0, "x"], "b": 1}), &seed).unwrap()
+        );
+        verify(&schema, &public, &signature).unwrap();
+
+        let tampered = json!({"b": 2, "a": [1.0, "x"]});
+        assert!(matches!(
+            verify(&tampered, &public, &signature),
+            Err(Error::InvalidSignature { .. })
+        ));
+        assert!(matches!(
+            verify(&schema, &public, "not base64!"),
+            Err(Error::InvalidSignature { .. })
+        ));
+        assert!(matches!(
+            sign(&schema, &seed[..16]),
+            Err(Error::InvalidSigningKey { .. })
+        ));
+    }
+}
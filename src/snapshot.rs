@@ -0,0 +1,130 @@
+//! Snapshots of the document cache, for starting a `JsonRef` without fetching anything.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+
+use crate::{Error, JsonRef, Result, WriteOutput};
+
+/// Bumped whenever the snapshot layout changes, so old snapshots are refused.
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    documents: BTreeMap<&'a str, &'a Value>,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    version: u32,
+    documents: BTreeMap<String, Value>,
+}
+
+impl JsonRef {
+    /// Write every cached document to `writer` as a JSON snapshot that
+    /// [`JsonRef::from_snapshot`] can start from.
+    ///
+    /// Only documents are kept: options and resolvers are not serializable and have to be set
+    /// again on the restored instance.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    ///
+    /// let mut jsonref = JsonRef::new();
+    /// jsonref.add_schema("https://example.com/pet.json", json!({"title": "pet"})).unwrap();
+    /// let mut snapshot = Vec::new();
+    /// jsonref.write_snapshot(&mut snapshot).unwrap();
+    ///
+    /// let mut restored = JsonRef::from_snapshot(snapshot.as_slice()).unwrap();
+    /// restored.set_offline(true);
+    /// let mut schema = json!({"items": {"$ref": "https://example.com/pet.json"}});
+    /// restored.deref_value(&mut schema).unwrap();
+    /// assert_eq!(schema, json!({"items": {"title": "pet"}}));
+    /// ```
+    pub fn write_snapshot(&self, writer: impl io::Write) -> Result<()> {
+        let entries = self.schema_cache.entries();
+        let snapshot = SnapshotRef {
+            version: SNAPSHOT_VERSION,
+            documents: entries
+                .iter()
+                .map(|(key, document)| (key.as_str(), &**document))
+                .collect(),
+        };
+        let mut writer = io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, &snapshot).context(WriteOutput {})?;
+        io::Write::flush(&mut writer)
+            .map_err(serde_json::Error::io)
+            .context(WriteOutput {})
+    }
+
+    /// A `JsonRef` with default options whose cache holds the documents of a snapshot written
+    /// by [`JsonRef::write_snapshot`].
+    pub fn from_snapshot(reader: impl io::Read) -> Result<JsonRef> {
+        let snapshot: Snapshot =
+            serde_json::from_reader(io::BufReader::new(reader)).map_err(|error| {
+                Error::InvalidSnapshot {
+                    reason: error.to_string(),
+                }
+            })?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::InvalidSnapshot {
+                reason: format!(
+                    "version {} is not the supported version {}",
+                    snapshot.version, SNAPSHOT_VERSION
+                ),
+            });
+        }
+        let jsonref = JsonRef::new();
+        for (key, document) in snapshot.documents {
+            jsonref.schema_cache.put(key, Arc::new(document));
+        }
+        Ok(jsonref)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, JsonRef};
+    use serde_json::json;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut jsonref = JsonRef::new();
+        jsonref
+            .deref_file("fixtures/nested_relative/base.json")
+            .unwrap();
+        let mut snapshot = Vec::new();
+        jsonref.write_snapshot(&mut snapshot).unwrap();
+
+        let mut restored = JsonRef::from_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(
+            restored.schema_cache.entries().len(),
+            jsonref.schema_cache.entries().len()
+        );
+        let url = restored
+            .schema_cache
+            .entries()
+            .into_iter()
+            .map(|(key, _)| key)
+            .find(|key| key.ends_with("/other.json"))
+            .unwrap();
+        let mut schema = json!({"items": {"$ref": format!("{}#/properties/prop1", url)}});
+        restored.deref_value(&mut schema).unwrap();
+        assert_eq!(
+            schema,
+            json!({"items": {"title": "sub property title in other.json"}})
+        );
+
+        let old = br#"{"version": 0, "documents": {}}"#;
+        assert!(matches!(
+            JsonRef::from_snapshot(&old[..]),
+            Err(Error::InvalidSnapshot { .. })
+        ));
+        assert!(JsonRef::from_snapshot(&b"{"[..]).is_err());
+    }
+}
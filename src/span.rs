@@ -0,0 +1,147 @@
+//! Byte spans of the values in a JSON text, keyed by JSON pointer.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::explain::push_pointer;
+
+/// Byte range of every value in a JSON text, keyed by its JSON pointer.
+///
+/// Returns `None` if the text is not valid JSON.
+pub(crate) fn value_spans(text: &str) -> Option<BTreeMap<String, Range<usize>>> {
+    let mut parser = Parser {
+        bytes: text.as_bytes(),
+        text,
+        pos: 0,
+        spans: BTreeMap::new(),
+    };
+    parser.value(String::new())?;
+    parser.whitespace();
+    if parser.pos != parser.bytes.len() {
+        return None;
+    }
+    Some(parser.spans)
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    text: &'a str,
+    pos: usize,
+    spans: BTreeMap<String, Range<usize>>,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> Option<()> {
+        self.whitespace();
+        if self.peek()? == byte {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn value(&mut self, pointer: String) -> Option<()> {
+        self.whitespace();
+        let start = self.pos;
+        match self.peek()? {
+            b'{' => self.object(&pointer)?,
+            b'[' => self.array(&pointer)?,
+            b'"' => {
+                self.string()?;
+            }
+            _ => self.scalar()?,
+        }
+        self.spans.insert(pointer, start..self.pos);
+        Some(())
+    }
+
+    fn object(&mut self, pointer: &str) -> Option<()> {
+        self.eat(b'{')?;
+        if self.eat(b'}').is_some() {
+            return Some(());
+        }
+        loop {
+            self.whitespace();
+            let key = self.string()?;
+            self.eat(b':')?;
+            self.value(push_pointer(pointer, &key))?;
+            if self.eat(b',').is_none() {
+                return self.eat(b'}');
+            }
+        }
+    }
+
+    fn array(&mut self, pointer: &str) -> Option<()> {
+        self.eat(b'[')?;
+        if self.eat(b']').is_some() {
+            return Some(());
+        }
+        for i in 0.. {
+            self.value(push_pointer(pointer, &i.to_string()))?;
+            if self.eat(b',').is_none() {
+                break;
+            }
+        }
+        self.eat(b']')
+    }
+
+    /// A string literal, unescaped.
+    fn string(&mut self) -> Option<String> {
+        let start = self.pos;
+        if self.peek()? != b'"' {
+            return None;
+        }
+        self.pos += 1;
+        loop {
+            match self.peek()? {
+                b'"' => break,
+                b'\\' => self.pos += 2,
+                _ => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+        serde_json::from_str(self.text.get(start..self.pos)?).ok()
+    }
+
+    /// A number, `true`, `false` or `null`.
+    fn scalar(&mut self) -> Option<()> {
+        let start = self.pos;
+        while matches!(
+            self.peek(),
+            Some(b'-' | b'+' | b'.' | b'0'..=b'9' | b'a'..=b'z' | b'E')
+        ) {
+            self.pos += 1;
+        }
+        serde_json::from_str::<serde_json::Value>(&self.text[start..self.pos])
+            .ok()
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::value_spans;
+
+    #[test]
+    fn spans_by_pointer() {
+        let text = r#"{"a": [1, {"b~/": "x\"y"}], "c": null}"#;
+        let spans = value_spans(text).unwrap();
+        assert_eq!(&text[spans[""].clone()], text);
+        assert_eq!(&text[spans["/a/0"].clone()], "1");
+        assert_eq!(&text[spans["/a/1/b~0~1"].clone()], r#""x\"y""#);
+        assert_eq!(&text[spans["/c"].clone()], "null");
+        assert!(value_spans(r#"{"a": }"#).is_none());
+        assert!(value_spans(r#"{"a": 1} x"#).is_none());
+    }
+}
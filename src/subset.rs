@@ -0,0 +1,263 @@
+//! Subsets of a (dereferenced) schema covering only some of the data paths it describes, to
+//! validate partial payloads with.
+
+use serde_json::{Map, Value};
+
+/// One step of a data path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// Into the property of that name.
+    Property(String),
+    /// Into every item of an array.
+    Items,
+}
+
+/// The steps of `path`: property names separated by `.`, each followed by a `[]` for every
+/// level of array below it, e.g. `items[].price` or `[].id` for an array at the top.
+fn steps(path: &str) -> Vec<Step> {
+    let mut steps = vec![];
+    for segment in path.split('.') {
+        let name = segment.trim_end_matches("[]");
+        if !name.is_empty() {
+            steps.push(Step::Property(name.to_owned()));
+        }
+        let arrays = (segment.len() - name.len()) / 2;
+        steps.extend((0..arrays).map(|_| Step::Items));
+    }
+    steps
+}
+
+/// The part of `schema` that constrains the data at `paths`, and nothing else.
+///
+/// `schema` is expected to be dereferenced already; a `$ref` is kept as it is. The schema at
+/// the end of a path is kept whole. On the way there only what locates it is kept: `type`,
+/// `properties` and `items` narrowed to the paths, and `required` narrowed to the properties
+/// kept. `allOf`, `anyOf` and `oneOf` subschemas are narrowed the same way, and a `oneOf`
+/// becomes an `anyOf`, as narrowed alternatives may no longer exclude each other. A property
+/// found through `additionalProperties` is added to `properties`. Paths the schema does not
+/// describe are left out.
+///
+/// ```
+/// use polywrap_jsonref::subset::subset;
+/// use serde_json::json;
+///
+/// let schema = json!({
+///     "type": "object",
+///     "required": ["address", "name"],
+///     "properties": {
+///         "name": {"type": "string"},
+///         "address": {"type": "object", "properties": {
+///             "city": {"type": "string", "minLength": 1},
+///             "street": {"type": "string"}
+///         }},
+///         "items": {"type": "array", "items": {"properties": {
+///             "price": {"type": "number", "minimum": 0},
+///             "sku": {"type": "string"}
+///         }}}
+///     }
+/// });
+/// assert_eq!(
+///     subset(&schema, &["address.city", "items[].price"]),
+///     json!({
+///         "type": "object",
+///         "required": ["address"],
+///         "properties": {
+///             "address": {"type": "object", "properties": {
+///                 "city": {"type": "string", "minLength": 1}
+///             }},
+///             "items": {"type": "array", "items": {"properties": {
+///                 "price": {"type": "number", "minimum": 0}
+///             }}}
+///         }
+///     })
+/// );
+/// ```
+pub fn subset(schema: &Value, paths: &[&str]) -> Value {
+    let paths: Vec<Vec<Step>> = paths.iter().map(|path| steps(path)).collect();
+    let paths: Vec<&[Step]> = paths.iter().map(Vec::as_slice).collect();
+    let mut narrowed = narrow(schema, &paths);
+    if let (Some(narrowed), Some(dialect)) = (narrowed.as_object_mut(), schema.get("$schema")) {
+        narrowed.insert("$schema".to_owned(), dialect.clone());
+    }
+    narrowed
+}
+
+/// `schema` narrowed to the data at `paths`, relative to it.
+fn narrow(schema: &Value, paths: &[&[Step]]) -> Value {
+    let obj = match schema.as_object() {
+        // `true`, `false` and `$ref`s are as small as they get.
+        Some(obj) if !obj.contains_key("$ref") => obj,
+        _ => return schema.clone(),
+    };
+    if paths.iter().any(|path| path.is_empty()) {
+        return schema.clone();
+    }
+
+    let mut narrowed = Map::new();
+    if let Some(types) = obj.get("type") {
+        narrowed.insert("type".to_owned(), types.clone());
+    }
+
+    let mut properties = Map::new();
+    for (name, rest) in group_properties(paths) {
+        let property = obj
+            .get("properties")
+            .and_then(|properties| properties.get(&name))
+            .or_else(|| {
+                obj.get("additionalProperties")
+                    .filter(|extra| extra.is_object())
+            });
+        if let Some(property) = property {
+            properties.insert(name, narrow(property, &rest));
+        }
+    }
+    if let Some(required) = obj.get("required").and_then(Value::as_array) {
+        let required: Vec<Value> = required
+            .iter()
+            .filter(|name| {
+                name.as_str()
+                    .is_some_and(|name| properties.contains_key(name))
+            })
+            .cloned()
+            .collect();
+        if !required.is_empty() {
+            narrowed.insert("required".to_owned(), Value::Array(required));
+        }
+    }
+    if !properties.is_empty() {
+        narrowed.insert("properties".to_owned(), Value::Object(properties));
+    }
+
+    let items: Vec<&[Step]> = paths
+        .iter()
+        .filter_map(|path| match path.split_first() {
+            Some((Step::Items, rest)) => Some(rest),
+            _ => None,
+        })
+        .collect();
+    if !items.is_empty() {
+        for keyword in ["prefixItems", "items", "additionalItems"] {
+            match obj.get(keyword) {
+                Some(Value::Array(tuple)) => {
+                    let tuple = tuple.iter().map(|item| narrow(item, &items)).collect();
+                    narrowed.insert(keyword.to_owned(), Value::Array(tuple));
+                }
+                Some(item) => {
+                    narrowed.insert(keyword.to_owned(), narrow(item, &items));
+                }
+                None => {}
+            }
+        }
+    }
+
+    for (keyword, narrowed_keyword) in [("allOf", "allOf"), ("anyOf", "anyOf"), ("oneOf", "anyOf")]
+    {
+        let subschemas = match obj.get(keyword).and_then(Value::as_array) {
+            Some(subschemas) => subschemas,
+            None => continue,
+        };
+        let subschemas: Vec<Value> = subschemas
+            .iter()
+            .map(|subschema| narrow(subschema, paths))
+            .collect();
+        // An empty subschema of an `allOf` allows anything, one of an `anyOf` everything.
+        let subschemas: Vec<Value> = if keyword == "allOf" {
+            subschemas
+                .into_iter()
+                .filter(|subschema| !is_empty(subschema))
+                .collect()
+        } else if subschemas.iter().any(is_empty) {
+            continue;
+        } else {
+            subschemas
+        };
+        if subschemas.is_empty() {
+            continue;
+        }
+        if narrowed.contains_key(narrowed_keyword) {
+            // Both an `anyOf` and a `oneOf`, which must each still hold.
+            let mut both = Map::new();
+            both.insert(narrowed_keyword.to_owned(), Value::Array(subschemas));
+            match narrowed.get_mut("allOf") {
+                Some(Value::Array(all)) => all.push(Value::Object(both)),
+                _ => {
+                    narrowed.insert("allOf".to_owned(), Value::Array(vec![Value::Object(both)]));
+                }
+            }
+        } else {
+            narrowed.insert(narrowed_keyword.to_owned(), Value::Array(subschemas));
+        }
+    }
+    Value::Object(narrowed)
+}
+
+/// The property names `paths` start with, in order, each with the rest of its paths.
+fn group_properties<'a>(paths: &[&'a [Step]]) -> Vec<(String, Vec<&'a [Step]>)> {
+    let mut groups: Vec<(String, Vec<&[Step]>)> = vec![];
+    for path in paths {
+        if let Some((Step::Property(name), rest)) = path.split_first() {
+            match groups.iter_mut().find(|(grouped, _)| grouped == name) {
+                Some((_, rests)) => rests.push(rest),
+                None => groups.push((name.clone(), vec![rest])),
+            }
+        }
+    }
+    groups
+}
+
+fn is_empty(schema: &Value) -> bool {
+    schema.as_object().is_some_and(Map::is_empty) || *schema == Value::Bool(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{steps, subset, Step};
+    use serde_json::json;
+
+    #[test]
+    fn paths_are_parsed() {
+        assert_eq!(
+            steps("matrix[][].cell"),
+            [
+                Step::Property("matrix".to_owned()),
+                Step::Items,
+                Step::Items,
+                Step::Property("cell".to_owned())
+            ]
+        );
+        assert_eq!(
+            steps("[].id"),
+            [Step::Items, Step::Property("id".to_owned())]
+        );
+    }
+
+    #[test]
+    fn applicators_are_narrowed() {
+        let schema = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "allOf": [
+                {"properties": {"id": {"type": "integer"}}, "required": ["id"]},
+                {"properties": {"note": {"type": "string"}}}
+            ],
+            "oneOf": [
+                {"properties": {"kind": {"const": "a"}}},
+                {"properties": {"kind": {"const": "b"}}}
+            ],
+            "properties": {"tags": {"additionalProperties": {"type": "string", "maxLength": 8}}}
+        });
+        assert_eq!(
+            subset(&schema, &["id", "kind", "tags.color"]),
+            json!({
+                "$schema": "https://json-schema.org/draft/2020-12/schema",
+                "properties": {"tags": {"properties": {
+                    "color": {"type": "string", "maxLength": 8}
+                }}},
+                "allOf": [{"properties": {"id": {"type": "integer"}}, "required": ["id"]}],
+                "anyOf": [
+                    {"properties": {"kind": {"const": "a"}}},
+                    {"properties": {"kind": {"const": "b"}}}
+                ]
+            })
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! "Did you mean" hints for JSON pointers that are not in their document.
+
+use serde_json::Value;
+
+use crate::explain::push_pointer;
+use crate::Error;
+
+/// Most suggestions named in one error.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// The error for `ref_string`, whose `fragment` is not a pointer into `document`, naming the
+/// pointers that `fragment` probably meant.
+pub(crate) fn pointer_not_found(ref_string: &str, document: &Value, fragment: &str) -> Error {
+    let mut pointer = format!(
+        "ref `{}` can not be resolved as pointer `{}` can not be found in the schema",
+        ref_string, fragment
+    );
+    let suggestions = nearest_pointers(document, fragment);
+    if !suggestions.is_empty() {
+        let suggestions: Vec<String> = suggestions
+            .iter()
+            .map(|suggestion| format!("`#{}`", suggestion))
+            .collect();
+        pointer.push_str(&format!("; did you mean {}?", suggestions.join(" or ")));
+    }
+    Error::JsonPointerNotFound { pointer }
+}
+
+/// Pointers into `document` that differ from `pointer` only by near misses in some segments:
+/// case, `definitions` for `$defs` and back, singular for plural, or a single typo.
+pub(crate) fn nearest_pointers(document: &Value, pointer: &str) -> Vec<String> {
+    let segments: Vec<String> = match pointer.strip_prefix('/') {
+        Some(rest) => rest
+            .split('/')
+            .map(|segment| segment.replace("~1", "/").replace("~0", "~"))
+            .collect(),
+        None => return Vec::new(),
+    };
+    let mut found = Vec::new();
+    search(document, &segments, String::new(), &mut found);
+    found
+}
+
+fn search(value: &Value, segments: &[String], pointer: String, found: &mut Vec<String>) {
+    if found.len() >= MAX_SUGGESTIONS {
+        return;
+    }
+    let (segment, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            found.push(pointer);
+            return;
+        }
+    };
+    match value {
+        Value::Object(members) => {
+            if let Some(member) = members.get(segment) {
+                search(member, rest, push_pointer(&pointer, segment), found);
+            }
+            for (key, member) in members {
+                if key != segment && is_near_miss(segment, key) {
+                    search(member, rest, push_pointer(&pointer, key), found);
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item) = segment.parse::<usize>().ok().and_then(|i| items.get(i)) {
+                search(item, rest, push_pointer(&pointer, segment), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether the pointer segment `wanted` was probably meant to be the key `key`.
+fn is_near_miss(wanted: &str, key: &str) -> bool {
+    let wanted = wanted.to_lowercase();
+    let key = key.to_lowercase();
+    if wanted == key {
+        return true;
+    }
+    let definitions = |name: &str| name == "definitions" || name == "$defs";
+    if definitions(&wanted) && definitions(&key) {
+        return true;
+    }
+    if singular(&wanted) == singular(&key) {
+        return true;
+    }
+    wanted.chars().count() >= 4 && edit_distance(&wanted, &key) == 1
+}
+
+fn singular(word: &str) -> String {
+    if let Some(stem) = word.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if let Some(stem) = word.strip_suffix("es").filter(|stem| {
+        ["s", "x", "z", "ch", "sh"]
+            .iter()
+            .any(|ending| stem.ends_with(ending))
+    }) {
+        stem.to_owned()
+    } else if let Some(stem) = word.strip_suffix('s').filter(|stem| !stem.ends_with('s')) {
+        stem.to_owned()
+    } else {
+        word.to_owned()
+    }
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(a != *b));
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::nearest_pointers;
+    use crate::JsonRef;
+    use serde_json::json;
+
+    #[test]
+    fn near_misses_are_suggested() {
+        let document = json!({
+            "$defs": {"Address": {"properties": {"street": {}}}, "category": {}},
+            "items": [{"title": "first"}]
+        });
+        assert_eq!(
+            nearest_pointers(&document, "/definitions/address/properties/street"),
+            vec!["/$defs/Address/properties/street"]
+        );
+        assert_eq!(
+            nearest_pointers(&document, "/$defs/categories"),
+            vec!["/$defs/category"]
+        );
+        assert_eq!(nearest_pointers(&document, "/itens/0"), vec!["/items/0"]);
+        assert!(nearest_pointers(&document, "/$defs/Person").is_empty());
+
+        let mut schema = json!({"items": {"$ref": "#/definitions/Address"},
+                                "$defs": {"Address": {}}});
+        let error = JsonRef::new().deref_value(&mut schema).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("; did you mean `#/$defs/Address`?"));
+    }
+}
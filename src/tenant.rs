@@ -0,0 +1,346 @@
+//! Fetch and byte quotas per tenant, for services dereferencing schemas on behalf of others.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::{Error, JsonRef, Result};
+
+/// Most a tenant may fetch over http(s), across every dereference tagged with it. `None`
+/// means no limit, which is the default for both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TenantQuota {
+    /// Most documents fetched.
+    pub max_fetches: Option<u64>,
+    /// Most bytes of documents fetched. The fetch that goes over it fails, after the bytes
+    /// it was are counted.
+    pub max_bytes: Option<u64>,
+}
+
+/// What a tenant has fetched over http(s). Only documents the walk uses that came over the
+/// network are counted, along with disk cache copies the server was asked about, as fetches
+/// of no bytes: not those found fresh in a cache, in memory or on disk, fetched ahead but not
+/// used, or that failed to fetch or parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct TenantUsage {
+    /// Documents fetched.
+    pub fetches: u64,
+    /// Bytes of the documents fetched.
+    pub bytes: u64,
+}
+
+/// The quota of each tenant and what each has used, set with [`JsonRef::set_tenant_quotas`].
+///
+/// Cloning a `TenantQuotas` shares the quotas and usage, so the `JsonRef`s of a service,
+/// on whichever threads, draw from one account per tenant:
+///
+/// ```
+/// use polywrap_jsonref::{JsonRef, TenantQuota, TenantQuotas};
+///
+/// let quotas = TenantQuotas::new(TenantQuota {
+///     max_fetches: Some(100),
+///     max_bytes: Some(10_000_000),
+/// });
+/// quotas.set_quota("trial", TenantQuota { max_fetches: Some(5), max_bytes: None });
+///
+/// let mut jsonref = JsonRef::new();
+/// jsonref.set_tenant_quotas(quotas.clone());
+/// jsonref.set_tenant(Some("trial"));
+/// // ... dereference for the tenant, then
+/// assert_eq!(quotas.usage("trial").fetches, 0);
+/// ```
+#[derive(Clone, Default)]
+pub struct TenantQuotas {
+    accounts: Arc<Mutex<Accounts>>,
+}
+
+#[derive(Default)]
+struct Accounts {
+    default: TenantQuota,
+    quotas: HashMap<String, TenantQuota>,
+    usage: HashMap<String, TenantUsage>,
+}
+
+impl TenantQuotas {
+    /// Quotas giving every tenant `default`, unless set otherwise with
+    /// [`TenantQuotas::set_quota`].
+    pub fn new(default: TenantQuota) -> Self {
+        let accounts = Accounts {
+            default,
+            ..Accounts::default()
+        };
+        TenantQuotas {
+            accounts: Arc::new(Mutex::new(accounts)),
+        }
+    }
+
+    /// Give `tenant` a quota of its own.
+    pub fn set_quota(&self, tenant: &str, quota: TenantQuota) {
+        self.lock().quotas.insert(tenant.to_owned(), quota);
+    }
+
+    /// The quota of `tenant`.
+    pub fn quota(&self, tenant: &str) -> TenantQuota {
+        self.lock().quota(tenant)
+    }
+
+    /// What `tenant` has used since it was last reset.
+    pub fn usage(&self, tenant: &str) -> TenantUsage {
+        self.lock().usage.get(tenant).copied().unwrap_or_default()
+    }
+
+    /// Forget what `tenant` has used, e.g. at the start of a billing period.
+    pub fn reset(&self, tenant: &str) {
+        self.lock().usage.remove(tenant);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Accounts> {
+        // The accounts stay consistent whatever panicked while holding them.
+        self.accounts
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl fmt::Debug for TenantQuotas {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let accounts = self.lock();
+        f.debug_struct("TenantQuotas")
+            .field("default", &accounts.default)
+            .field("quotas", &accounts.quotas)
+            .field("usage", &accounts.usage)
+            .finish()
+    }
+}
+
+impl Accounts {
+    fn quota(&self, tenant: &str) -> TenantQuota {
+        self.quotas.get(tenant).copied().unwrap_or(self.default)
+    }
+}
+
+impl JsonRef {
+    /// The quotas the tenant set with [`JsonRef::set_tenant`] fetches against. Without any,
+    /// tenants have no limits, but their usage is still counted.
+    pub fn set_tenant_quotas(&mut self, quotas: TenantQuotas) {
+        self.tenant_quotas = quotas;
+    }
+
+    /// Tag the dereferences that follow with `tenant`, so their http(s) fetches count (and
+    /// fail once over) its quota, or with no tenant, as by default, for no quota at all.
+    pub fn set_tenant(&mut self, tenant: Option<&str>) {
+        self.tenant = tenant.map(str::to_owned);
+    }
+
+    /// What the last dereference fetched for its tenant, or `None` if it had none.
+    pub fn tenant_usage(&self) -> Option<TenantUsage> {
+        self.tenant.as_ref().map(|_| self.usage.tenant)
+    }
+
+    /// Count fetching `url` against the tenant's fetch quota, failing if it has used up a
+    /// quota already. Checked and counted under one lock, so `JsonRef`s sharing the quotas
+    /// cannot go over it together; [`JsonRef::charge_tenant`] or
+    /// [`JsonRef::release_tenant_fetch`] must follow.
+    pub(crate) fn reserve_tenant_fetch(&self, url: &str) -> Result<()> {
+        let tenant = match &self.tenant {
+            Some(tenant) => tenant,
+            None => return Ok(()),
+        };
+        let mut accounts = self.tenant_quotas.lock();
+        let quota = accounts.quota(tenant);
+        let used = accounts.usage.entry(tenant.clone()).or_default();
+        match (quota.max_fetches, quota.max_bytes) {
+            (Some(limit), _) if used.fetches >= limit => {
+                Err(quota_exceeded(tenant, url, limit, "fetches"))
+            }
+            (_, Some(limit)) if used.bytes >= limit => {
+                Err(quota_exceeded(tenant, url, limit, "bytes"))
+            }
+            _ => {
+                used.fetches += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Give back the fetch [`JsonRef::reserve_tenant_fetch`] counted, as it failed or the
+    /// document did not come over the network.
+    pub(crate) fn release_tenant_fetch(&self) {
+        if let Some(tenant) = &self.tenant {
+            if let Some(used) = self.tenant_quotas.lock().usage.get_mut(tenant) {
+                used.fetches = used.fetches.saturating_sub(1);
+            }
+        }
+    }
+
+    /// How many more documents the tenant may fetch, if it is limited.
+    pub(crate) fn tenant_fetches_left(&self) -> Option<usize> {
+        let tenant = self.tenant.as_ref()?;
+        let accounts = self.tenant_quotas.lock();
+        let limit = accounts.quota(tenant).max_fetches?;
+        let used = accounts.usage.get(tenant).map_or(0, |usage| usage.fetches);
+        Some(usize::try_from(limit.saturating_sub(used)).unwrap_or(usize::MAX))
+    }
+
+    /// Charge the tenant for fetching `url`, `bytes` long, the fetch itself having been
+    /// counted by [`JsonRef::reserve_tenant_fetch`], failing if that takes it over its byte
+    /// quota.
+    pub(crate) fn charge_tenant(&mut self, url: &str, bytes: usize) -> Result<()> {
+        let tenant = match &self.tenant {
+            Some(tenant) => tenant,
+            None => return Ok(()),
+        };
+        let bytes = bytes as u64;
+        self.usage.tenant.fetches += 1;
+        self.usage.tenant.bytes += bytes;
+        let mut accounts = self.tenant_quotas.lock();
+        let quota = accounts.quota(tenant);
+        let used = accounts.usage.entry(tenant.clone()).or_default();
+        used.bytes = used.bytes.saturating_add(bytes);
+        match quota.max_bytes {
+            Some(limit) if used.bytes > limit => Err(quota_exceeded(tenant, url, limit, "bytes")),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn quota_exceeded(tenant: &str, url: &str, limit: u64, quota: &str) -> Error {
+    Error::TenantQuotaExceeded {
+        tenant: tenant.to_owned(),
+        url: url.to_owned(),
+        quota: quota.to_owned(),
+        limit,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DiskPolicy, Error, JsonRef, TenantQuota, TenantQuotas, TenantUsage};
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Serve `{"type": "string"}`, 18 bytes, to every request, returning the base URL.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                let body = r#"{"type": "string"}"#;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn fetches_count_against_the_quota_of_the_tenant() {
+        let base = serve();
+        let quotas = TenantQuotas::new(TenantQuota {
+            max_fetches: Some(2),
+            max_bytes: None,
+        });
+        quotas.set_quota(
+            "small",
+            TenantQuota {
+                max_fetches: None,
+                max_bytes: Some(20),
+            },
+        );
+        let schema = |names: &[&str]| {
+            let items: Vec<_> = names
+                .iter()
+                .map(|name| json!({"$ref": format!("{}{}.json", base, name)}))
+                .collect();
+            json!({ "items": items })
+        };
+        let mut jsonref = JsonRef::new();
+        jsonref.set_parallel_fetching(false);
+        jsonref.set_tenant_quotas(quotas.clone());
+
+        jsonref.set_tenant(Some("big"));
+        jsonref.deref_value(&mut schema(&["a", "b"])).unwrap();
+        let used = TenantUsage {
+            fetches: 2,
+            bytes: 36,
+        };
+        assert_eq!(jsonref.tenant_usage(), Some(used));
+        assert_eq!(quotas.usage("big"), used);
+        let error = jsonref.deref_value(&mut schema(&["c"])).unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            Error::TenantQuotaExceeded { tenant, quota, limit: 2, .. }
+                if tenant == "big" && quota == "fetches"
+        ));
+
+        jsonref.set_tenant(Some("small"));
+        let error = jsonref.deref_value(&mut schema(&["d", "e"])).unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            Error::TenantQuotaExceeded { limit: 20, .. }
+        ));
+        assert_eq!(quotas.usage("small").bytes, 36);
+
+        quotas.reset("big");
+        jsonref.set_tenant(Some("big"));
+        jsonref.deref_value(&mut schema(&["c"])).unwrap();
+        jsonref.set_tenant(None);
+        jsonref.deref_value(&mut schema(&["f", "g", "h"])).unwrap();
+        assert_eq!(jsonref.tenant_usage(), None);
+        assert_eq!(quotas.usage("big").fetches, 1);
+    }
+
+    #[test]
+    fn only_fetches_over_the_network_count_and_never_go_over() {
+        let base = serve();
+        let quotas = TenantQuotas::new(TenantQuota {
+            max_fetches: Some(2),
+            max_bytes: None,
+        });
+        let dir = std::env::temp_dir().join(format!("jsonref-tenant-{}", std::process::id()));
+        for _ in 0..2 {
+            let mut jsonref = JsonRef::new();
+            jsonref.set_cache_dir(&dir);
+            jsonref.set_disk_cache_policy(DiskPolicy {
+                fresh_for: Some(Duration::from_secs(3600)),
+                ..DiskPolicy::default()
+            });
+            jsonref.set_tenant_quotas(quotas.clone());
+            jsonref.set_tenant(Some("cached"));
+            let mut schema = json!({"$ref": format!("{}a.json", base)});
+            jsonref.deref_value(&mut schema).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(quotas.usage("cached").fetches, 1);
+
+        // `JsonRef`s on other threads draw from the same quota.
+        let fetched: Vec<_> = (0..8)
+            .map(|i| {
+                let (quotas, url) = (quotas.clone(), format!("{}{}.json", base, i));
+                thread::spawn(move || {
+                    let mut jsonref = JsonRef::new();
+                    jsonref.set_tenant_quotas(quotas);
+                    jsonref.set_tenant(Some("shared"));
+                    jsonref.deref_value(&mut json!({ "$ref": url })).is_ok()
+                })
+            })
+            .collect();
+        let fetched = fetched
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|&ok| ok)
+            .count();
+        assert_eq!(fetched, 2);
+        assert_eq!(quotas.usage("shared").fetches, 2);
+    }
+}
@@ -0,0 +1,99 @@
+//! Dereferencing as far as a deadline allows, for tools that show something at once.
+
+use serde_json::Value;
+use std::mem;
+use std::time::Instant;
+
+use crate::{JsonRef, RefSite, Result};
+
+impl JsonRef {
+    /// Deref `value` like [`JsonRef::deref_value`] until `deadline`, and return the refs left
+    /// unresolved when it was reached, by their pointers in the output. An empty list means
+    /// the whole document resolved in time.
+    ///
+    /// Once the deadline is reached no more refs are resolved, but the walk goes on to list
+    /// the rest, so `value` is always the whole document with as much dereferenced as there
+    /// was time for. The refs left keep their `$ref` as written, which within a copied target
+    /// is relative to the document it came from, so refine the output by dereferencing the
+    /// original again: documents loaded the first time are cached, so it goes further.
+    ///
+    /// Unlike [`ResourceLimits::deadline`](crate::ResourceLimits::deadline), the deadline is
+    /// not an error. A fetch already under way when it is reached is waited for.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// use serde_json::json;
+    /// use std::time::Instant;
+    ///
+    /// let mut input = json!({"items": {"$ref": "#/$defs/a"}, "$defs": {"a": {"title": "a"}}});
+    /// let unresolved = JsonRef::new().deref_value_until(&mut input, Instant::now()).unwrap();
+    /// assert_eq!(unresolved[0].pointer, "/items");
+    /// assert_eq!(input["items"], json!({"$ref": "#/$defs/a"}));
+    /// ```
+    pub fn deref_value_until(
+        &mut self,
+        value: &mut Value,
+        deadline: Instant,
+    ) -> Result<Vec<RefSite>> {
+        self.time_box = Some(deadline);
+        let result = self.deref_value(value);
+        self.time_box = None;
+        let unresolved = mem::take(&mut self.unresolved);
+        result.map(|_| unresolved)
+    }
+
+    /// Whether the deadline of [`JsonRef::deref_value_until`] has been reached.
+    pub(crate) fn out_of_time(&self) -> bool {
+        self.time_box
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{JsonRef, RefKind, Rule};
+    use serde_json::json;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn refs_past_the_deadline_are_listed() {
+        let mut input = json!({
+            "properties": {
+                "a": {"$ref": "#/$defs/a"},
+                "b": {"$ref": "#/$defs/b"},
+                "c": {"items": {"$ref": "other.json"}}
+            },
+            "$defs": {"a": {"title": "a"}, "b": {"title": "b"}}
+        });
+        let mut jsonref = JsonRef::new();
+        let mut partial = input.clone();
+        let unresolved = jsonref
+            .deref_value_until(&mut partial, Instant::now())
+            .unwrap();
+        let pointers: Vec<_> = unresolved
+            .iter()
+            .map(|site| site.pointer.as_str())
+            .collect();
+        assert_eq!(
+            pointers,
+            ["/properties/a", "/properties/b", "/properties/c/items"]
+        );
+        assert_eq!(unresolved[2].kind, RefKind::File);
+        assert_eq!(partial["properties"], input["properties"]);
+        assert!(matches!(
+            &jsonref.trace()[0].rules[..],
+            [Rule::Unresolved { .. }]
+        ));
+
+        input["properties"].as_object_mut().unwrap().remove("c");
+        // The deadline is for that one deref only.
+        jsonref.deref_value(&mut input.clone()).unwrap();
+        assert!(jsonref.trace().iter().all(|step| step.rules.is_empty()));
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(jsonref
+            .deref_value_until(&mut input, later)
+            .unwrap()
+            .is_empty());
+        assert_eq!(input["properties"]["b"], json!({"title": "b"}));
+    }
+}
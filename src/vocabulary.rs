@@ -0,0 +1,101 @@
+//! `$vocabulary`, whose URIs name vocabularies rather than documents to resolve.
+
+use serde_json::Value;
+use snafu::ResultExt;
+use url::Url;
+
+use crate::recursion::RefChain;
+use crate::{JsonRef, Result, Scope, UrlParseError};
+
+pub(crate) const VOCABULARY_KEYWORD: &str = "$vocabulary";
+
+/// Whether `value`, found under `$vocabulary`, is a vocabulary declaration: an object of
+/// URIs to booleans, rather than e.g. a property that happens to be called `$vocabulary`.
+pub(crate) fn is_declaration(value: &Value) -> bool {
+    value
+        .as_object()
+        .is_some_and(|vocabularies| vocabularies.values().all(Value::is_boolean))
+}
+
+impl JsonRef {
+    /// Load the document behind each `$vocabulary` URI of meta-schemas, for vocabularies that
+    /// are published at their URI. Off by default, as vocabulary URIs are identifiers and
+    /// often lead nowhere.
+    ///
+    /// The documents are only loaded, into the cache and [`JsonRef::loaded_documents`]; the
+    /// `$vocabulary` itself is left as written either way. A required vocabulary (`true`)
+    /// that can not be loaded fails like a `$ref` would, an optional one is skipped.
+    pub fn set_fetch_vocabularies(&mut self, fetch: bool) {
+        self.fetch_vocabularies = fetch;
+    }
+
+    /// Load the vocabularies declared by `vocabularies`, the `$vocabulary` at `scope`.
+    pub(crate) fn load_vocabularies(
+        &mut self,
+        vocabularies: &Value,
+        scope: &Scope,
+        used_refs: &RefChain,
+    ) -> Result<()> {
+        let vocabularies = match vocabularies.as_object() {
+            Some(vocabularies) => vocabularies,
+            None => return Ok(()),
+        };
+        for (uri, required) in vocabularies {
+            let loaded = Url::parse(uri)
+                .context(UrlParseError { url: uri.clone() })
+                .and_then(|mut url| {
+                    url.set_fragment(None);
+                    self.document(&url)
+                });
+            if let Err(error) = loaded {
+                if *required == Value::Bool(true) {
+                    self.collect(scope, used_refs, uri, error)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Error, JsonRef};
+    use serde_json::json;
+
+    #[test]
+    fn vocabularies_are_only_fetched_when_asked() {
+        const CORE: &str = "https://example.com/vocab/core";
+        let meta = json!({
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "$vocabulary": {CORE: true, "https://example.com/vocab/extra": false},
+            "properties": {"$vocabulary": {"$ref": "#/$defs/list"}},
+            "$defs": {"list": {"type": "array"}}
+        });
+        let resolver = |url: &url::Url| Ok((url.as_str() == CORE).then(|| json!({})));
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolver(Box::new(resolver));
+        jsonref.set_offline(true);
+        jsonref.deref_value(&mut meta.clone()).unwrap();
+        assert!(jsonref.loaded_documents().is_empty());
+
+        jsonref.set_fetch_vocabularies(true);
+        let mut schema = meta.clone();
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(jsonref.loaded_documents(), [CORE]);
+        assert_eq!(schema["$vocabulary"], meta["$vocabulary"]);
+        assert_eq!(
+            schema["properties"]["$vocabulary"],
+            json!({"type": "array"})
+        );
+
+        let mut jsonref = JsonRef::new();
+        jsonref.set_fetch_vocabularies(true);
+        jsonref.set_offline(true);
+        let error = jsonref.deref_value(&mut meta.clone()).unwrap_err();
+        assert!(matches!(
+            error.root_cause(),
+            Error::RemoteFetchDisabled { url } if url == CORE
+        ));
+    }
+}
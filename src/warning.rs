@@ -0,0 +1,236 @@
+//! Things a dereference got past without failing, but that its caller may want to know about.
+
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::{Draft, JsonRef, Scope};
+
+/// A problem found during the last deref that did not stop it, listed by
+/// [`JsonRef::warnings`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// The document loaded from `url` declares the `$id` `id`, but another document is
+    /// already cached under that URL, and stays there.
+    IdConflict { id: String, url: String },
+    /// The schema at `pointer` of the document at `url` uses `keyword`, which dereferencing
+    /// does not act on, so the output may not mean what the document does. Listed once per
+    /// keyword and document.
+    UnsupportedKeyword {
+        keyword: String,
+        url: String,
+        pointer: String,
+        reason: Unsupported,
+    },
+    /// The document at `url` could not be fetched, failing with `error`, so a copy from the
+    /// disk cache that is no longer fresh was used instead, as
+    /// [`DiskPolicy::stale_if_error`](crate::DiskPolicy::stale_if_error) allows.
+    StaleCopy { url: String, error: String },
+}
+
+/// Why a keyword listed in a [`Warning::UnsupportedKeyword`] was not acted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unsupported {
+    /// It means nothing in `draft`, which the document is read as, e.g. `$defs` before
+    /// 2019-09 or `id` after draft-04.
+    OtherDraft { draft: Draft },
+    /// It comes from a specification built on JSON Schema, e.g. OpenAPI's `discriminator`,
+    /// whose references to schemas are not followed.
+    OtherSpecification,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::IdConflict { id, url } => write!(
+                f,
+                "{} declares the $id {}, which already names another document",
+                url, id
+            ),
+            Warning::UnsupportedKeyword {
+                keyword,
+                url,
+                pointer,
+                reason,
+            } => {
+                write!(f, "{}#{}: `{}` ", url, pointer, keyword)?;
+                match reason {
+                    Unsupported::OtherDraft { draft } => {
+                        write!(f, "means nothing in {}, left as it is", draft)
+                    }
+                    Unsupported::OtherSpecification => {
+                        write!(f, "is not JSON Schema, its schema names are not resolved")
+                    }
+                }
+            }
+            Warning::StaleCopy { url, error } => write!(
+                f,
+                "{}: fetching failed ({}), a stale cached copy was used",
+                url, error
+            ),
+        }
+    }
+}
+
+/// Keywords the walk checks for in each schema, see [`unsupported`].
+const CHECKED_KEYWORDS: &[&str] = &[
+    "$dynamicRef",
+    "$recursiveRef",
+    "$defs",
+    "$id",
+    "id",
+    "discriminator",
+];
+
+/// Why `keyword`, with `value`, is not acted on in a schema read as `draft`, if it is not.
+fn unsupported(keyword: &str, value: &Value, draft: Option<Draft>) -> Option<Unsupported> {
+    let other_draft = draft.map(|draft| Unsupported::OtherDraft { draft });
+    match keyword {
+        "$dynamicRef" if draft.is_some_and(|draft| draft < Draft::Draft202012) => other_draft,
+        "$recursiveRef" if draft.is_some_and(|draft| draft != Draft::Draft201909) => other_draft,
+        "$defs" if draft.is_some_and(|draft| draft < Draft::Draft201909) => other_draft,
+        "$id" if draft == Some(Draft::Draft4) => other_draft,
+        "id" if value.is_string() && draft.is_some_and(|draft| draft > Draft::Draft4) => {
+            other_draft
+        }
+        "discriminator" => Some(Unsupported::OtherSpecification),
+        _ => None,
+    }
+}
+
+impl JsonRef {
+    /// Warnings of the last deref, in the order they came up.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Warn about the keywords of the schema `obj`, at `scope`, that are not acted on.
+    pub(crate) fn check_keywords(&mut self, obj: &Map<String, Value>, scope: &Scope) {
+        for keyword in CHECKED_KEYWORDS {
+            let reason = match obj.get(*keyword) {
+                Some(value) => match unsupported(keyword, value, scope.draft) {
+                    Some(reason) => reason,
+                    None => continue,
+                },
+                None => continue,
+            };
+            let known = self.warnings.iter().any(|warning| {
+                matches!(warning, Warning::UnsupportedKeyword { keyword: known, url, .. }
+                    if known == keyword && **url == *scope.document)
+            });
+            if !known {
+                self.warnings.push(Warning::UnsupportedKeyword {
+                    keyword: (*keyword).to_owned(),
+                    url: scope.document.to_string(),
+                    pointer: scope.document_pointer.clone(),
+                    reason,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Unsupported, Warning};
+    use crate::{Draft, JsonRef};
+    use serde_json::json;
+
+    #[test]
+    fn documents_are_cached_under_their_id() {
+        let resolver = |url: &url::Url| {
+            Ok(match url.as_str() {
+                "https://mirror.example.com/owner.json" => Some(json!({
+                    "$id": "https://example.com/schemas/owner.json",
+                    "title": "owner"
+                })),
+                "https://mirror.example.com/pet.json" => Some(json!({
+                    "$id": "https://example.com/schemas/pet.json",
+                    "title": "pet"
+                })),
+                _ => None,
+            })
+        };
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolver(Box::new(resolver));
+        jsonref.set_offline(true);
+        jsonref
+            .add_schema("https://example.com/schemas/pet.json", json!({}))
+            .unwrap();
+
+        // `owner.json` is only found through the `$id` of the document at the mirror.
+        let mut schema = json!({
+            "$id": "https://example.com/schemas/root.json",
+            "properties": {
+                "mirrored": {"$ref": "https://mirror.example.com/owner.json"},
+                "owner": {"$ref": "owner.json"},
+                "pet": {"$ref": "https://mirror.example.com/pet.json"}
+            }
+        });
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(schema["properties"]["owner"]["title"], "owner");
+        assert_eq!(schema["properties"]["pet"]["title"], "pet");
+        assert_eq!(
+            jsonref.warnings(),
+            [Warning::IdConflict {
+                id: "https://example.com/schemas/pet.json".to_owned(),
+                url: "https://mirror.example.com/pet.json".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unsupported_keywords_are_listed_once_per_document() {
+        let mut schema = json!({
+            "$schema": "http://json-schema.org/draft-07/schema#",
+            "properties": {
+                "a": {"$dynamicRef": "#meta"},
+                "b": {"$dynamicRef": "#meta"},
+                "c": {"oneOf": [{"$ref": "#/$defs/c"}], "discriminator": {"propertyName": "kind"}},
+                "$defs": {"title": "a property, not definitions"}
+            },
+            "$defs": {"c": {"type": "object"}}
+        });
+        let mut jsonref = JsonRef::new();
+        jsonref.deref_value(&mut schema).unwrap();
+        let found: Vec<_> = jsonref
+            .warnings()
+            .iter()
+            .map(|warning| match warning {
+                Warning::UnsupportedKeyword {
+                    keyword,
+                    pointer,
+                    reason,
+                    ..
+                } => (keyword.as_str(), pointer.as_str(), *reason),
+                other => panic!("unexpected warning {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    "$defs",
+                    "",
+                    Unsupported::OtherDraft {
+                        draft: Draft::Draft7
+                    }
+                ),
+                (
+                    "$dynamicRef",
+                    "/properties/a",
+                    Unsupported::OtherDraft {
+                        draft: Draft::Draft7
+                    }
+                ),
+                (
+                    "discriminator",
+                    "/properties/c",
+                    Unsupported::OtherSpecification
+                ),
+            ]
+        );
+        assert!(jsonref.warnings()[0]
+            .to_string()
+            .ends_with("#: `$defs` means nothing in draft-07, left as it is"));
+    }
+}
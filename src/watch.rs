@@ -0,0 +1,159 @@
+//! Noticing when upstream documents change, so services can dereference again.
+
+use std::fmt;
+
+use crate::{Error, JsonRef, Refreshed, Result};
+
+type Callback = Box<dyn FnMut(&Refreshed) + Send>;
+
+/// Remote documents refreshed on demand, with callbacks for those whose content changed.
+///
+/// Give it a clone of the `JsonRef` a service dereferences with: clones share the cache, so
+/// each refresh replaces the copy the service sees. Call [`Watcher::poll`] on a timer, e.g.
+/// from a thread of its own, or [`Watcher::notify`] when a webhook says a document changed;
+/// the callbacks are then told of each document that changed, with the SHA-256 of its new
+/// content, and can dereference the schemas that depend on it again.
+///
+/// ```
+/// # use polywrap_jsonref::{JsonRef, Watcher};
+/// use serde_json::{json, Value};
+/// use std::sync::mpsc;
+/// use url::Url;
+///
+/// let mut jsonref = JsonRef::new();
+/// jsonref.set_resolver(Box::new(|_: &Url| -> Result<Option<Value>, _> {
+///     Ok(Some(json!({"title": "pet v2"})))
+/// }));
+/// jsonref.add_schema("https://example.com/pet.json", json!({"title": "pet"})).unwrap();
+///
+/// let (changes, changed) = mpsc::channel();
+/// let mut watcher = Watcher::new(jsonref.clone());
+/// watcher.watch("https://example.com/pet.json");
+/// watcher.on_change(move |refreshed| changes.send(refreshed.url.clone()).unwrap());
+/// assert!(watcher.poll().is_empty());
+/// assert_eq!(changed.try_recv().unwrap(), "https://example.com/pet.json");
+///
+/// let mut schema = json!({"items": {"$ref": "https://example.com/pet.json"}});
+/// jsonref.deref_value(&mut schema).unwrap();
+/// assert_eq!(schema["items"]["title"], "pet v2");
+/// ```
+pub struct Watcher {
+    jsonref: JsonRef,
+    urls: Vec<String>,
+    callbacks: Vec<Callback>,
+}
+
+impl Watcher {
+    /// Refresh documents with `jsonref` and whatever it was configured with.
+    pub fn new(jsonref: JsonRef) -> Watcher {
+        Watcher {
+            jsonref,
+            urls: Vec::new(),
+            callbacks: Vec::new(),
+        }
+    }
+
+    /// Refresh `url` on each [`Watcher::poll`].
+    pub fn watch(&mut self, url: &str) {
+        if !self.urls.iter().any(|watched| watched == url) {
+            self.urls.push(url.to_owned());
+        }
+    }
+
+    /// Stop refreshing `url`.
+    pub fn unwatch(&mut self, url: &str) {
+        self.urls.retain(|watched| watched != url);
+    }
+
+    /// Call `callback` with each document found to have changed, in the order the callbacks
+    /// were added.
+    pub fn on_change(&mut self, callback: impl FnMut(&Refreshed) + Send + 'static) {
+        self.callbacks.push(Box::new(callback));
+    }
+
+    /// Refresh every watched document, and return the errors of those that could not be
+    /// loaded, whose cached copies are kept.
+    ///
+    /// A document counts as changed when its content differs from the cached copy, or
+    /// nothing was cached, as with [`JsonRef::refresh`].
+    pub fn poll(&mut self) -> Vec<Error> {
+        let mut errors = vec![];
+        for url in self.urls.clone() {
+            if let Err(error) = self.notify(&url) {
+                errors.push(error);
+            }
+        }
+        errors
+    }
+
+    /// Refresh the document at `url` now, watched or not, e.g. when a webhook says it
+    /// changed, and return whether it did.
+    pub fn notify(&mut self, url: &str) -> Result<bool> {
+        let refreshed = self.jsonref.refresh(&[url])?.remove(0);
+        if refreshed.changed {
+            for callback in &mut self.callbacks {
+                callback(&refreshed);
+            }
+        }
+        Ok(refreshed.changed)
+    }
+}
+
+impl fmt::Debug for Watcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watcher")
+            .field("jsonref", &self.jsonref)
+            .field("urls", &self.urls)
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Watcher;
+    use crate::{Error, JsonRef};
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use url::Url;
+
+    #[test]
+    fn only_changed_documents_are_reported() {
+        let upstream = Arc::new(Mutex::new(json!({"title": "a"})));
+        let served = upstream.clone();
+        let mut jsonref = JsonRef::new();
+        jsonref.set_resolver(Box::new(move |url: &Url| -> Result<Option<Value>, _> {
+            match url.path() {
+                "/a.json" => Ok(Some(served.lock().unwrap().clone())),
+                "/b.json" => Ok(Some(json!({"title": "b"}))),
+                _ => Err("unreachable".into()),
+            }
+        }));
+        let mut schema = json!({"items": [
+            {"$ref": "https://example.com/a.json"},
+            {"$ref": "https://example.com/b.json"}
+        ]});
+        jsonref.deref_value(&mut schema.clone()).unwrap();
+
+        let changes = Arc::new(Mutex::new(vec![]));
+        let seen = changes.clone();
+        let mut watcher = Watcher::new(jsonref.clone());
+        for url in ["a", "b", "down"] {
+            watcher.watch(&format!("https://example.com/{}.json", url));
+        }
+        watcher.on_change(move |refreshed| seen.lock().unwrap().push(refreshed.clone()));
+        let errors = watcher.poll();
+        assert!(matches!(errors[..], [Error::ResolverFailed { .. }]));
+        assert!(changes.lock().unwrap().is_empty());
+
+        *upstream.lock().unwrap() = json!({"title": "a2"});
+        watcher.unwatch("https://example.com/down.json");
+        assert!(watcher.poll().is_empty());
+        let changes = changes.lock().unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].url, "https://example.com/a.json");
+        assert_eq!(changes[0].sha256.len(), 64);
+        jsonref.deref_value(&mut schema).unwrap();
+        assert_eq!(schema["items"][0]["title"], "a2");
+    }
+}
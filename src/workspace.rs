@@ -0,0 +1,220 @@
+//! Resolution against editor buffers, for language servers.
+
+use serde_json::Value;
+use snafu::ResultExt;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use url::Url;
+
+use crate::span::value_spans;
+use crate::{
+    file_url, ref_sites, Hover, JsonRef, RefSite, Result, SchemaFromFile, SchemaNotJsonSerde,
+};
+
+/// Where a `$ref` leads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// URL of the target document, without fragment.
+    pub uri: Url,
+    /// JSON pointer of the target within the document.
+    pub pointer: String,
+    /// Byte range of the target in the document's text, if it could be read and the fragment
+    /// is a JSON pointer.
+    pub span: Option<Range<usize>>,
+}
+
+/// Open documents layered over the files on disk.
+///
+/// The text of an open document is used in place of the file it was opened for, both when
+/// resolving refs and when answering [`Workspace::definition`]. Each change replaces only
+/// that document, so other documents stay cached between requests.
+///
+/// ```
+/// # use polywrap_jsonref::{JsonRef, Workspace};
+/// use serde_json::json;
+/// use url::Url;
+///
+/// let mut workspace = Workspace::new(JsonRef::new());
+/// let root = Url::parse("file:///project/root.json").unwrap();
+/// let pet = Url::parse("file:///project/pet.json").unwrap();
+/// workspace.open(&pet, r#"{"title": "pet"}"#).unwrap();
+/// workspace.open(&root, r#"{"items": {"$ref": "pet.json"}}"#).unwrap();
+///
+/// assert_eq!(workspace.resolve(&root).unwrap(), json!({"items": {"title": "pet"}}));
+///
+/// let target = workspace.definition(&root, 22).unwrap().unwrap();
+/// assert_eq!(target.uri, pet);
+/// assert_eq!(target.span, Some(0..16));
+/// ```
+#[derive(Debug)]
+pub struct Workspace {
+    jsonref: JsonRef,
+    overlays: HashMap<Url, String>,
+}
+
+impl Workspace {
+    /// Resolve with `jsonref` and whatever it was configured with.
+    pub fn new(jsonref: JsonRef) -> Workspace {
+        Workspace {
+            jsonref,
+            overlays: HashMap::new(),
+        }
+    }
+
+    /// Open `uri` with `text`, or replace the text of an open document.
+    ///
+    /// If `text` is not valid JSON the error is returned, and refs into the document keep
+    /// resolving against its last valid text.
+    pub fn open(&mut self, uri: &Url, text: &str) -> Result<()> {
+        let uri = document_url(uri);
+        self.overlays.insert(uri.clone(), text.to_owned());
+        let value: Value = serde_json::from_str(text).context(SchemaNotJsonSerde {
+            url: uri.to_string(),
+        })?;
+        self.jsonref.add_schema(uri.as_str(), value)
+    }
+
+    /// Close `uri`, going back to the file on disk.
+    pub fn close(&mut self, uri: &Url) {
+        let uri = document_url(uri);
+        self.overlays.remove(&uri);
+        self.jsonref.forget(&uri);
+    }
+
+    /// Dereference the document at `uri`.
+    pub fn resolve(&mut self, uri: &Url) -> Result<Value> {
+        self.jsonref.deref_url(document_url(uri).as_str())
+    }
+
+    /// The target of the `$ref` whose value contains the byte `offset` of `uri`'s text.
+    ///
+    /// Returns `None` if there is no `$ref` at `offset`.
+    pub fn definition(&self, uri: &Url, offset: usize) -> Result<Option<Location>> {
+        let resolved = match self
+            .site_at(uri, offset)?
+            .and_then(|site| site.resolved_url)
+        {
+            Some(resolved) => Url::parse(&resolved).ok(),
+            None => None,
+        };
+        let resolved = match resolved {
+            Some(resolved) => resolved,
+            None => return Ok(None),
+        };
+
+        let target = document_url(&resolved);
+        let pointer = resolved.fragment().unwrap_or("").to_owned();
+        let span = self
+            .text(&target)
+            .ok()
+            .and_then(|text| value_spans(&text))
+            .and_then(|spans| spans.get(&pointer).cloned());
+        Ok(Some(Location {
+            uri: target,
+            pointer,
+            span,
+        }))
+    }
+
+    /// The documentation of the target of the `$ref` at the byte `offset` of `uri`'s text.
+    ///
+    /// Returns `None` if there is no `$ref` at `offset`.
+    pub fn hover(&mut self, uri: &Url, offset: usize) -> Result<Option<Hover>> {
+        match self.site_at(uri, offset)? {
+            Some(site) => self.jsonref.hover(&site).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The `$ref` whose value contains the byte `offset` of `uri`'s text.
+    fn site_at(&self, uri: &Url, offset: usize) -> Result<Option<RefSite>> {
+        let uri = document_url(uri);
+        let text = self.text(&uri)?;
+        let value: Value = serde_json::from_str(&text).context(SchemaNotJsonSerde {
+            url: uri.to_string(),
+        })?;
+        let spans = match value_spans(&text) {
+            Some(spans) => spans,
+            None => return Ok(None),
+        };
+        let ref_pointer = spans
+            .iter()
+            .filter(|(pointer, span)| pointer.ends_with("/$ref") && span.contains(&offset))
+            .map(|(pointer, _)| &pointer[..pointer.len() - "/$ref".len()])
+            .next_back();
+        let ref_pointer = match ref_pointer {
+            Some(pointer) => pointer,
+            None => return Ok(None),
+        };
+        Ok(ref_sites(&value, &uri)
+            .into_iter()
+            .find(|site| site.pointer == ref_pointer))
+    }
+
+    /// The text of `uri`: its open buffer, or the file on disk.
+    fn text(&self, uri: &Url) -> Result<String> {
+        if let Some(text) = self.overlays.get(uri) {
+            return Ok(text.clone());
+        }
+        fs::read_to_string(file_url::to_path(uri)).context(SchemaFromFile {
+            filename: uri.to_string(),
+        })
+    }
+}
+
+fn document_url(uri: &Url) -> Url {
+    let mut uri = uri.clone();
+    uri.set_fragment(None);
+    uri
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Workspace;
+    use crate::JsonRef;
+    use serde_json::json;
+    use std::fs;
+    use url::Url;
+
+    #[test]
+    fn overlay_wins_over_disk_until_closed() {
+        let base = fs::canonicalize("fixtures/nested_relative/base.json").unwrap();
+        let other = fs::canonicalize("fixtures/nested_relative/other.json").unwrap();
+        let base = Url::from_file_path(base).unwrap();
+        let other = Url::from_file_path(other).unwrap();
+
+        let mut workspace = Workspace::new(JsonRef::new());
+        workspace
+            .open(
+                &other,
+                r#"{"properties": {"prop1": {"title": "edited"}, "prop2": {}}}"#,
+            )
+            .unwrap();
+        let resolved = workspace.resolve(&base).unwrap();
+        assert_eq!(resolved["properties"]["prop4"], json!({"title": "edited"}));
+
+        let text = fs::read_to_string(base.to_file_path().unwrap()).unwrap();
+        let offset = text.find("other.json#/properties/prop1").unwrap();
+        let target = workspace.definition(&base, offset).unwrap().unwrap();
+        assert_eq!(target.uri, other);
+        assert_eq!(target.pointer, "/properties/prop1");
+        assert_eq!(target.span, Some(25..44));
+        let hover = workspace.hover(&base, offset).unwrap().unwrap();
+        assert_eq!(hover.title.as_deref(), Some("edited"));
+
+        assert!(workspace.open(&other, "{").is_err());
+        assert_eq!(
+            workspace.resolve(&base).unwrap()["properties"]["prop4"],
+            json!({"title": "edited"})
+        );
+
+        workspace.close(&other);
+        let resolved = workspace.resolve(&base).unwrap();
+        assert_eq!(
+            resolved["properties"]["prop4"],
+            json!({"title": "sub property title in other.json"})
+        );
+        assert_eq!(workspace.definition(&base, 0).unwrap(), None);
+    }
+}
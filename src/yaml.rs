@@ -0,0 +1,73 @@
+//! YAML input and output, behind the `yaml` feature.
+
+use serde_json::Value;
+use snafu::ResultExt;
+use std::io;
+
+use crate::{JsonRef, Result, SchemaNotYaml, YamlOutput};
+
+impl JsonRef {
+    /// deref a YAML file, whatever its extension.
+    ///
+    /// `$ref`s to other `.yaml` or `.yml` files are parsed as YAML, any other file as JSON.
+    ///
+    /// ```
+    /// # use polywrap_jsonref::JsonRef;
+    /// let mut jsonref = JsonRef::new();
+    /// let schema = jsonref.deref_yaml_file("fixtures/yaml/base.yaml").unwrap();
+    /// assert_eq!(schema["properties"]["prop1"]["title"], "title from other.yml");
+    /// ```
+    pub fn deref_yaml_file(&mut self, file_path: &str) -> Result<Value> {
+        self.deref_path(file_path, |url, _path, bytes| from_reader(url, bytes))
+    }
+
+    /// deref a YAML string, resolving relative `$ref`s against the working directory.
+    pub fn deref_yaml_str(&mut self, yaml: &str) -> Result<Value> {
+        let mut value = serde_yaml::from_str(yaml).context(SchemaNotYaml { url: "<string>" })?;
+        self.deref_value(&mut value)?;
+        Ok(value)
+    }
+}
+
+/// Serialize a (dereferenced) schema as YAML.
+pub fn to_yaml_string(value: &Value) -> Result<String> {
+    serde_yaml::to_string(value).context(YamlOutput {})
+}
+
+pub(crate) fn is_yaml_path(path: &str) -> bool {
+    path.ends_with(".yaml") || path.ends_with(".yml")
+}
+
+pub(crate) fn from_reader(url: &str, reader: impl io::Read) -> Result<Value> {
+    serde_yaml::from_reader(reader).context(SchemaNotYaml { url })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_yaml_string;
+    use crate::JsonRef;
+    use serde_json::json;
+
+    #[test]
+    fn yaml_refs_and_output() {
+        let mut jsonref = JsonRef::new();
+        let schema = jsonref
+            .deref_yaml_str(
+                "properties:\n  prop1:\n    $ref: fixtures/yaml/base.yaml#/properties/prop2\n",
+            )
+            .unwrap();
+        assert_eq!(
+            schema,
+            json!({"properties": {"prop1": {"title": "sub property title in other.json"}}})
+        );
+
+        let from_file = jsonref.deref_file("fixtures/yaml/base.yaml").unwrap();
+        assert_eq!(from_file["properties"]["prop1"]["type"], "string");
+
+        assert_eq!(
+            to_yaml_string(&json!({"title": "a", "type": "string"})).unwrap(),
+            "title: a\ntype: string\n"
+        );
+        assert!(jsonref.deref_yaml_str("properties: [").is_err());
+    }
+}